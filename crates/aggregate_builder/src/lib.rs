@@ -38,6 +38,7 @@ struct Aggregate {
     state_ty: AggregateTy,
 
     parallel_safe: Option<syn::LitBool>,
+    batch_transition: Option<syn::LitBool>,
 
     transition_fn: AggregateFn,
     final_fn: AggregateFn,
@@ -50,7 +51,7 @@ struct Aggregate {
 enum AggregateItem {
     State(AggregateTy),
     Fn(AggregateFn),
-    ParallelSafe(AggregateParallelSafe),
+    Const(AggregateConst),
 }
 
 struct AggregateTy {
@@ -58,7 +59,8 @@ struct AggregateTy {
     ty: Box<syn::Type>,
 }
 
-struct AggregateParallelSafe {
+struct AggregateConst {
+    name: syn::Ident,
     value: syn::LitBool,
 }
 
@@ -112,6 +114,7 @@ impl Parse for Aggregate {
         let mut state_ty = None;
 
         let mut parallel_safe = None;
+        let mut batch_transition = None;
 
         let mut fns: Vec<AggregateFn> = vec![];
         while !body.is_empty() {
@@ -130,7 +133,8 @@ impl Parse for Aggregate {
                     }
                     state_ty = Some(ty);
                 }
-                ParallelSafe(safe) => parallel_safe = Some(safe.value),
+                Const(c) if c.name == "PARALLEL_SAFE" => parallel_safe = Some(c.value),
+                Const(c) => batch_transition = Some(c.value),
                 Fn(f) => {
                     fns.push(f);
                 }
@@ -242,6 +246,7 @@ impl Parse for Aggregate {
             name,
             state_ty,
             parallel_safe,
+            batch_transition,
             transition_fn,
             final_fn,
             serialize_fn,
@@ -261,7 +266,7 @@ impl Parse for AggregateItem {
         } else if lookahead.peek(Token![type]) {
             input.parse().map(AggregateItem::State)
         } else if lookahead.peek(Token![const]) {
-            input.parse().map(AggregateItem::ParallelSafe)
+            input.parse().map(AggregateItem::Const)
         } else {
             Err(lookahead.error())
         }
@@ -279,14 +284,14 @@ impl Parse for AggregateTy {
     }
 }
 
-impl Parse for AggregateParallelSafe {
+impl Parse for AggregateConst {
     fn parse(input: ParseStream) -> syn::Result<Self> {
         let _: Token![const] = input.parse()?;
         let name: syn::Ident = input.parse()?;
-        if name != "PARALLEL_SAFE" {
+        if name != "PARALLEL_SAFE" && name != "BATCH_TRANSITION" {
             error!(
                 name.span(),
-                "unexpected const `{}` expected `PARALLEL_SAFE`", name
+                "unexpected const `{}` expected `PARALLEL_SAFE` or `BATCH_TRANSITION`", name
             )
         }
         let _: Token![:] = input.parse()?;
@@ -297,7 +302,7 @@ impl Parse for AggregateParallelSafe {
         let _: Token![=] = input.parse()?;
         let value = input.parse()?;
         let _: Token![;] = input.parse()?;
-        Ok(Self { value })
+        Ok(Self { name, value })
     }
 }
 
@@ -405,6 +410,7 @@ fn expand(agg: Aggregate) -> TokenStream2 {
         name,
         state_ty,
         parallel_safe,
+        batch_transition,
         transition_fn,
         final_fn,
         serialize_fn,
@@ -426,24 +432,10 @@ fn expand(agg: Aggregate) -> TokenStream2 {
         Some(schema) => format!("{}.", schema),
         None => String::new(),
     };
-    let mut create = format!("\nCREATE AGGREGATE {}{} (", schema_qualifier, name);
-    for (i, (name, arg)) in transition_fn.sql_args().enumerate() {
-        if i != 0 {
-            let _ = write!(&mut create, ", ");
-        }
-        if let Some(name) = name {
-            let _ = write!(&mut create, "{} ", name);
-        }
-        let _ = write!(&mut create, "{}", arg);
-    }
-    let _ = write!(
-        &mut create,
-        ") (\n    \
-            stype = internal,\n    \
-            sfunc = {}{},\n    \
-            finalfunc = {}{}",
-        schema_qualifier,
-        transition_fn.outer_ident(&name),
+    // everything after the `sfunc`, shared with the batch version of the
+    // aggregate if there is one
+    let mut create_options = format!(
+        "finalfunc = {}{}",
         schema_qualifier,
         final_fn.outer_ident(&name),
     );
@@ -451,7 +443,7 @@ fn expand(agg: Aggregate) -> TokenStream2 {
     let parallel_safe = parallel_safe.map(|p| {
         let value = p.value();
         let _ = write!(
-            &mut create,
+            &mut create_options,
             ",\n    parallel = {}",
             if value { "safe" } else { "unsafe" }
         );
@@ -498,7 +490,7 @@ fn expand(agg: Aggregate) -> TokenStream2 {
          make_tokens: fn(&AggregateFn, &Option<syn::Ident>, &syn::Ident) -> TokenStream2| {
             extension_sql_reqs.push(f.outer_ident(&name));
             let _ = write!(
-                &mut create,
+                &mut create_options,
                 ",\n    {} = {}{}",
                 field,
                 schema_qualifier,
@@ -556,7 +548,30 @@ fn expand(agg: Aggregate) -> TokenStream2 {
     let combine_fns =
         combine_fn.map(|f| add_function(f, "combinefunc", AggregateFn::combine_fn_tokens));
 
-    let _ = write!(&mut create, "\n);\n");
+    create_options.push_str("\n);\n");
+
+    let mut create = create_aggregate_sql(
+        &schema_qualifier,
+        &name,
+        &transition_fn,
+        "",
+        &transition_fn.outer_ident(&name),
+        &create_options,
+    );
+
+    let batch_transition_fns = batch_transition.filter(|b| b.value()).map(|_| {
+        let batch_ident = transition_fn.batch_outer_ident(&name);
+        create.push_str(&create_aggregate_sql(
+            &schema_qualifier,
+            &name,
+            &transition_fn,
+            "[]",
+            &batch_ident,
+            &create_options,
+        ));
+        extension_sql_reqs.push(batch_ident);
+        transition_fn.batch_transition_fn_tokens(&schema, &name)
+    });
 
     let extension_sql_name = format!("{}_extension_sql", name);
 
@@ -575,6 +590,7 @@ fn expand(agg: Aggregate) -> TokenStream2 {
             #parallel_safe
 
             #transition_fns
+            #batch_transition_fns
 
             #final_fns
             #serialize_fns
@@ -704,6 +720,116 @@ impl AggregateFn {
         }
     }
 
+    // Transition function taking an array for each argument, feeding the
+    // elements to the transition function in order.  Rows where an argument
+    // that isn't an `Option` is NULL are skipped, as they would be for a
+    // strict transition function.
+    fn batch_transition_fn_tokens(
+        &self,
+        schema: &Option<syn::Ident>,
+        aggregate_name: &syn::Ident,
+    ) -> TokenStream2 {
+        let outer_ident = self.batch_outer_ident(aggregate_name);
+        let Self {
+            ident,
+            args,
+            fcinfo,
+            ..
+        } = self;
+
+        let schema = schema.as_ref().map(|s| {
+            let s = format!("{}", s);
+            quote!(, schema = #s)
+        });
+
+        let mut names = vec![];
+        let mut elem_tys = vec![];
+        let mut iters = vec![];
+        let mut null_checks = vec![];
+        for arg in args.iter().skip(1) {
+            let name = match &*arg.rust.pat {
+                syn::Pat::Ident(id) => id.ident.clone(),
+                _ => {
+                    return quote_spanned!(arg.rust.span()=>
+                        compile_error!("batch transition functions require named arguments");
+                    )
+                }
+            };
+            let ty = &*arg.rust.ty;
+            match option_inner_type(ty) {
+                Some(inner) => elem_tys.push(inner),
+                None => {
+                    elem_tys.push(ty);
+                    null_checks.push(quote! {
+                        let #name = match #name {
+                            Some(#name) => #name,
+                            None => continue,
+                        };
+                    });
+                }
+            }
+            iters.push(syn::Ident::new(&format!("__{}_values", name), name.span()));
+            names.push(name);
+        }
+        let first_name = match names.first() {
+            Some(name) => name,
+            None => {
+                return quote_spanned!(self.parens.span=>
+                    compile_error!("batch transition functions require at least one argument");
+                )
+            }
+        };
+        let fcinfo_val = fcinfo.as_ref().map(|_| quote!(, __fcinfo));
+        let length_error = format!(
+            "all arrays passed to {} must have the same length",
+            aggregate_name
+        );
+
+        quote! {
+            #[pgx::pg_extern(immutable, parallel_safe #schema)]
+            pub fn #outer_ident<'__batch>(
+                __inner: pgx::Internal,
+                #(#names: pgx::Array<'__batch, #elem_tys>,)*
+                __fcinfo: pg_sys::FunctionCallInfo,
+            ) -> Option<Internal> {
+                use crate::palloc::{Inner, InternalAsValue, ToInternal};
+                let __len = #first_name.len();
+                #(
+                    if #names.len() != __len {
+                        pgx::error!(#length_error)
+                    }
+                )*
+                unsafe {
+                    let mut __inner: Option<Inner<Option<State>>> = __inner.to_inner();
+                    let mut state: Option<State> = match &mut __inner {
+                        None => None,
+                        Some(inner) => Option::take(&mut **inner),
+                    };
+                    crate::aggregate_utils::in_aggregate_context(__fcinfo, || {
+                        #(let mut #iters = #names.iter();)*
+                        for _ in 0..__len {
+                            #(let #names = #iters.next().unwrap();)*
+                            #(#null_checks)*
+                            state = #ident(state, #(#names),* #fcinfo_val);
+                        }
+
+                        __inner = match (__inner, state) {
+                            (None, None) => None,
+                            (None, state @ Some(..)) => {
+                                Some(state.into())
+                            },
+                            (Some(mut inner), state) => {
+                                *inner = state;
+                                Some(inner)
+                            },
+                        };
+                        __inner.internal()
+                    })
+                }
+            }
+        }
+    }
+
     fn final_fn_tokens(
         &self,
         schema: &Option<syn::Ident>,
@@ -965,6 +1091,11 @@ impl AggregateFn {
         syn::Ident::new(&name, Span::call_site())
     }
 
+    fn batch_outer_ident(&self, aggregate_name: &syn::Ident) -> syn::Ident {
+        let name = format!("{}_batch_{}_fn_outer", aggregate_name, self.ident);
+        syn::Ident::new(&name, Span::call_site())
+    }
+
     fn sql_args(&self) -> impl Iterator<Item = (Option<&syn::Ident>, String)> {
         self.args.iter().skip(1).map(|arg| {
             let ident = match &*arg.rust.pat {
@@ -976,6 +1107,55 @@ impl AggregateFn {
     }
 }
 
+fn create_aggregate_sql(
+    schema_qualifier: &str,
+    name: &syn::Ident,
+    transition_fn: &AggregateFn,
+    arg_suffix: &str,
+    sfunc: &syn::Ident,
+    options: &str,
+) -> String {
+    use std::fmt::Write;
+    let mut create = format!("\nCREATE AGGREGATE {}{} (", schema_qualifier, name);
+    for (i, (name, arg)) in transition_fn.sql_args().enumerate() {
+        if i != 0 {
+            let _ = write!(&mut create, ", ");
+        }
+        if let Some(name) = name {
+            let _ = write!(&mut create, "{} ", name);
+        }
+        let _ = write!(&mut create, "{}{}", arg, arg_suffix);
+    }
+    let _ = write!(
+        &mut create,
+        ") (\n    \
+            stype = internal,\n    \
+            sfunc = {}{},\n    \
+            {}",
+        schema_qualifier, sfunc, options,
+    );
+    create
+}
+
+// `Some(T)` if `ty` is syntactically `Option<T>`
+fn option_inner_type(ty: &syn::Type) -> Option<&syn::Type> {
+    let path = match ty {
+        syn::Type::Path(p) if p.qself.is_none() => &p.path,
+        _ => return None,
+    };
+    let last = path.segments.last()?;
+    if last.ident != "Option" {
+        return None;
+    }
+    match &last.arguments {
+        syn::PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+            syn::GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 fn arg_ident(arg: &AggregateArg) -> syn::Pat {
     syn::Pat::clone(&*arg.rust.pat)
 }
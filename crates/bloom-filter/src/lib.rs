@@ -0,0 +1,205 @@
+//! Bloom Filter implementation in Rust
+//!
+//! Based on the paper:
+//! <https://dl.acm.org/doi/10.1145/362686.362692>
+//!
+//! using the double hashing of:
+//! <https://www.eecs.harvard.edu/~michaelm/postscripts/rsa2008.pdf>
+
+use serde::{Deserialize, Serialize};
+
+/// The Bloom Filter is a set of bits which answers whether an item might have
+/// been added to it. Each item added sets the bits at a fixed number of
+/// positions derived from its hash; an item whose positions are all set might
+/// have been added, and one with any position unset certainly wasn't. Items
+/// are never missed, but an item which wasn't added is reported as possibly
+/// present with a false positive rate which depends on the number of bits,
+/// the number of positions per item, and the number of items added.[1]
+///
+/// Items are added by their 64-bit hash, so the filter itself is independent
+/// of the type of the items and of the hash function used, as long as every
+/// filter which is unioned or queried uses the same one.
+///
+/// [1]: <https://dl.acm.org/doi/10.1145/362686.362692>
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BloomFilter {
+    num_bits: u64,
+    num_hashes: u32,
+    bits: Vec<u64>,
+}
+
+impl BloomFilter {
+    /// Constructs a new, empty Bloom Filter with `num_bits` bits and
+    /// `num_hashes` positions per item.
+    pub fn new(num_bits: u64, num_hashes: u32) -> Self {
+        assert!(num_bits > 0);
+        assert!(num_hashes > 0);
+        let words = ((num_bits + 63) / 64) as usize;
+        Self {
+            num_bits,
+            num_hashes,
+            bits: vec![0; words],
+        }
+    }
+
+    /// Constructs a new, empty Bloom Filter sized so that, once
+    /// `expected_items` distinct items have been added, the false positive
+    /// rate is about `fp_rate`.
+    pub fn with_capacity(expected_items: u64, fp_rate: f64) -> Self {
+        let (num_bits, num_hashes) = Self::optimal_params(expected_items, fp_rate);
+        Self::new(num_bits, num_hashes)
+    }
+
+    /// Constructs a Bloom Filter from its bits, stored 64 to a word starting
+    /// from the least significant bit of the first word.
+    pub fn from_parts(num_bits: u64, num_hashes: u32, bits: Vec<u64>) -> Self {
+        assert!(num_bits > 0);
+        assert!(num_hashes > 0);
+        assert_eq!(bits.len() as u64, (num_bits + 63) / 64);
+        Self {
+            num_bits,
+            num_hashes,
+            bits,
+        }
+    }
+
+    /// Returns the number of bits and of positions per item that give a
+    /// false positive rate of `fp_rate` after `expected_items` distinct items
+    /// have been added, using as few bits as possible.
+    ///
+    /// `expected_items` must be positive and `fp_rate` must be between 0 and
+    /// 1, exclusive.
+    pub fn optimal_params(expected_items: u64, fp_rate: f64) -> (u64, u32) {
+        assert!(expected_items > 0);
+        assert!(fp_rate > 0.0 && fp_rate < 1.0);
+        let n = expected_items as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-n * fp_rate.ln() / (ln2 * ln2)).ceil().max(1.0);
+        let num_hashes = (num_bits / n * ln2).round().max(1.0);
+        (num_bits as u64, num_hashes as u32)
+    }
+
+    /// Returns the number of bits in the filter.
+    pub fn num_bits(&self) -> u64 {
+        self.num_bits
+    }
+
+    /// Returns the number of positions set for each item.
+    pub fn num_hashes(&self) -> u32 {
+        self.num_hashes
+    }
+
+    /// Returns the bits of the filter, stored 64 to a word.
+    pub fn bits(&self) -> &[u64] {
+        &self.bits
+    }
+
+    /// Adds the item with the given `hash` to the filter.
+    pub fn insert_hash(&mut self, hash: u64) {
+        for position in self.positions(hash) {
+            self.bits[(position / 64) as usize] |= 1 << (position % 64);
+        }
+    }
+
+    /// Returns whether the item with the given `hash` might have been added
+    /// to the filter. `false` means it certainly wasn't.
+    pub fn might_contain_hash(&self, hash: u64) -> bool {
+        self.positions(hash)
+            .all(|position| self.bits[(position / 64) as usize] & (1 << (position % 64)) != 0)
+    }
+
+    /// Includes the items added to `other` into `self`, so that `self` might
+    /// contain every item either of them might.
+    ///
+    /// Both filters must have the same number of bits and of positions per
+    /// item.
+    pub fn union(&mut self, other: &BloomFilter) {
+        assert_eq!(self.num_bits, other.num_bits);
+        assert_eq!(self.num_hashes, other.num_hashes);
+        for (word, other_word) in self.bits.iter_mut().zip(&other.bits) {
+            *word |= other_word;
+        }
+    }
+
+    /// Returns an estimate of the false positive rate of the filter, from the
+    /// fraction of its bits which are set.
+    pub fn estimated_fp_rate(&self) -> f64 {
+        let set: u32 = self.bits.iter().map(|word| word.count_ones()).sum();
+        (set as f64 / self.num_bits as f64).powi(self.num_hashes as i32)
+    }
+
+    /// The positions of the bits for the item with the given `hash`, derived
+    /// from its two halves as `h1 + i * h2`.
+    fn positions(&self, hash: u64) -> impl Iterator<Item = u64> {
+        let h1 = hash & 0xffff_ffff;
+        let h2 = hash >> 32;
+        let num_bits = self.num_bits;
+        (0..self.num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // splitmix64, to give the test items well-distributed hashes
+    fn hash(item: u64) -> u64 {
+        let mut z = item.wrapping_add(0x9e3779b97f4a7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    #[test]
+    fn test_optimal_params() {
+        assert_eq!(BloomFilter::optimal_params(1000, 0.01), (9586, 7));
+        assert_eq!(BloomFilter::optimal_params(1, 0.5), (2, 1));
+    }
+
+    #[test]
+    fn test_no_false_negatives() {
+        let mut filter = BloomFilter::with_capacity(1000, 0.01);
+        for item in 0..1000 {
+            filter.insert_hash(hash(item));
+        }
+        for item in 0..1000 {
+            assert!(filter.might_contain_hash(hash(item)));
+        }
+    }
+
+    #[test]
+    fn test_false_positive_rate() {
+        let mut filter = BloomFilter::with_capacity(10_000, 0.01);
+        for item in 0..10_000 {
+            filter.insert_hash(hash(item));
+        }
+        let false_positives = (10_000..110_000)
+            .filter(|item| filter.might_contain_hash(hash(*item)))
+            .count();
+        let rate = false_positives as f64 / 100_000.0;
+        assert!(rate < 0.02, "false positive rate {}", rate);
+        assert!((filter.estimated_fp_rate() - 0.01).abs() < 0.005);
+    }
+
+    #[test]
+    fn test_union() {
+        let mut evens = BloomFilter::with_capacity(1000, 0.01);
+        let mut odds = BloomFilter::with_capacity(1000, 0.01);
+        for item in 0..1000 {
+            if item % 2 == 0 {
+                evens.insert_hash(hash(item));
+            } else {
+                odds.insert_hash(hash(item));
+            }
+        }
+        let mut union = evens.clone();
+        union.union(&odds);
+        for item in 0..1000 {
+            assert!(union.might_contain_hash(hash(item)));
+        }
+
+        let rebuilt =
+            BloomFilter::from_parts(union.num_bits(), union.num_hashes(), union.bits().to_vec());
+        assert_eq!(rebuilt, union);
+    }
+}
@@ -32,7 +32,7 @@ pub struct MetricSummary {
     // - num_changes > 0 if num_resets > 0
     // - num_resets > 0 if num_changes > 0
     // - reset_sum > 0 if num_resets > 0
-    // - num_resets > 0 if reset_sum > 0
+    // - num_resets > 0 if reset_sum > 0, unless a fixed-width counter wrapped around
     pub reset_sum: f64,
     pub num_resets: u64,
     pub num_changes: u64,
@@ -80,10 +80,17 @@ impl MetricSummary {
         n
     }
 
-    fn reset(&mut self, incoming: &TSPoint) {
+    fn reset(&mut self, incoming: &TSPoint, wraps_at: Option<f64>) {
         if incoming.val < self.last.val {
-            self.reset_sum += self.last.val;
-            self.num_resets += 1;
+            match wraps_at {
+                // a fixed-width counter counts up to its maximum and then
+                // continues from 0, which isn't a reset
+                Some(wraps_at) => self.reset_sum += wraps_at,
+                None => {
+                    self.reset_sum += self.last.val;
+                    self.num_resets += 1;
+                }
+            }
         }
     }
 
@@ -351,45 +358,65 @@ impl From<MetricSummary> for GaugeSummaryBuilder {
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
-pub struct CounterSummaryBuilder(MetricSummary);
+pub struct CounterSummaryBuilder {
+    summary: MetricSummary,
+    // the value at which a fixed-width counter wraps around to 0, if the
+    // counter is one
+    wraps_at: Option<f64>,
+}
 
 impl CounterSummaryBuilder {
     pub fn new(pt: &TSPoint, bounds: Option<range::I64Range>) -> Self {
-        Self(MetricSummary::new(pt, bounds))
+        Self {
+            summary: MetricSummary::new(pt, bounds),
+            wraps_at: None,
+        }
     }
 
     /// expects time-ordered input
     pub fn add_point(&mut self, incoming: &TSPoint) -> Result<(), CounterError> {
-        self.0.reset(incoming);
-        self.0.add_point(incoming)
+        self.summary.reset(incoming, self.wraps_at);
+        self.summary.add_point(incoming)
     }
 
     /// combining can only happen for disjoint time ranges
     pub fn combine(&mut self, incoming: &MetricSummary) -> Result<(), CounterError> {
-        self.0.reset(&incoming.first);
-        self.0.combine(incoming)
+        self.summary.reset(&incoming.first, self.wraps_at);
+        self.summary.combine(incoming)
     }
 
     pub fn set_bounds(&mut self, bounds: Option<range::I64Range>) {
-        self.0.bounds = bounds;
+        self.summary.bounds = bounds;
+    }
+
+    /// Treats the counter as a fixed-width one of `width` bits, so that when
+    /// its value decreases, it's taken to have wrapped around past its
+    /// maximum rather than to have reset, e.g. a 32-bit counter going from
+    /// 4294967290 to 5 has increased by 11.  `None` treats every decrease as
+    /// a reset.
+    pub fn set_counter_width(&mut self, width: Option<u8>) {
+        self.wraps_at = width.map(|width| 2f64.powi(width as i32));
     }
 
     pub fn build(self) -> MetricSummary {
-        self.0
+        self.summary
     }
 
     pub fn first(&self) -> &TSPoint {
-        &self.0.first
+        &self.summary.first
     }
 
     // TODO build method should check validity rather than caller
     pub fn bounds_valid(&self) -> bool {
-        self.0.bounds_valid()
+        self.summary.bounds_valid()
     }
 }
 
 impl From<MetricSummary> for CounterSummaryBuilder {
     fn from(summary: MetricSummary) -> Self {
-        Self(summary)
+        Self {
+            summary,
+            wraps_at: None,
+        }
     }
 }
@@ -610,3 +610,55 @@ fn test_prometheus_extrapolation_bound_size() {
         to_micro(70.0 / 44000.0)
     );
 }
+
+#[test]
+fn test_counter_width() {
+    let mut summary = CounterSummaryBuilder::new(
+        &TSPoint {
+            ts: 0,
+            val: 4294967290.0,
+        },
+        None,
+    );
+    summary.set_counter_width(Some(32));
+    summary
+        .add_point(&TSPoint {
+            ts: 5,
+            val: 4294967295.0,
+        })
+        .unwrap();
+    summary.add_point(&TSPoint { ts: 10, val: 5.0 }).unwrap();
+    let summary = summary.build();
+    assert_relative_eq!(summary.delta(), 11.0);
+    assert_eq!(summary.num_resets, 0);
+
+    // wrapping between summaries is corrected too
+    let mut before = CounterSummaryBuilder::new(
+        &TSPoint {
+            ts: 0,
+            val: 4294967290.0,
+        },
+        None,
+    );
+    before
+        .add_point(&TSPoint {
+            ts: 5,
+            val: 4294967295.0,
+        })
+        .unwrap();
+    let mut after = CounterSummaryBuilder::new(&TSPoint { ts: 10, val: 5.0 }, None);
+    after.add_point(&TSPoint { ts: 15, val: 8.0 }).unwrap();
+    let mut combined = CounterSummaryBuilder::from(before.build());
+    combined.set_counter_width(Some(32));
+    combined.combine(&after.build()).unwrap();
+    let combined = combined.build();
+    assert_relative_eq!(combined.delta(), 14.0);
+    assert_eq!(combined.num_resets, 0);
+
+    // without a width the same decrease is a reset
+    let mut reset = CounterSummaryBuilder::from(summary);
+    reset.add_point(&TSPoint { ts: 15, val: 3.0 }).unwrap();
+    let reset = reset.build();
+    assert_relative_eq!(reset.delta(), 14.0);
+    assert_eq!(reset.num_resets, 1);
+}
@@ -0,0 +1,193 @@
+//! Additive Holt-Winters (triple exponential smoothing) forecasting
+//!
+//! See:
+//! <https://otexts.com/fpp3/holt-winters.html>
+
+/// The smoothing parameters tried when fitting a model; each of alpha, beta
+/// and gamma is picked from these.
+const PARAMETER_GRID: [f64; 9] = [0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9];
+
+/// A series smoothed into a level, a trend, and a seasonal component which
+/// repeats every `season_length` points, each of them adding to the others.
+/// After each point, the level moves `alpha` of the way towards the point
+/// less its season, the trend `beta` of the way towards the latest change in
+/// level, and the point's season `gamma` of the way towards the point less
+/// the level.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HoltWinters {
+    pub alpha: f64,
+    pub beta: f64,
+    pub gamma: f64,
+    pub level: f64,
+    pub trend: f64,
+    // `seasonal[i]` is the seasonal component of the points `i` after a
+    // multiple of `season_length`, counting from the first point
+    pub seasonal: Vec<f64>,
+    // the number of points the model was fit to
+    pub num_points: usize,
+    // the prediction for each point from the ones before it, from the
+    // `season_length`th point on
+    pub fitted: Vec<f64>,
+}
+
+impl HoltWinters {
+    /// Fits a model to `values` with the smoothing parameters from a grid
+    /// minimizing the squared error of its one-step-ahead predictions.
+    /// Returns `None` if there are fewer than two seasons of values.
+    pub fn fit(values: &[f64], season_length: usize) -> Option<Self> {
+        let mut best: Option<(f64, Self)> = None;
+        for &alpha in &PARAMETER_GRID {
+            for &beta in &PARAMETER_GRID {
+                for &gamma in &PARAMETER_GRID {
+                    let model = Self::fit_with(values, season_length, alpha, beta, gamma)?;
+                    let error = model.squared_error(values);
+                    let better = match &best {
+                        None => true,
+                        Some((best_error, _)) => error < *best_error,
+                    };
+                    if better {
+                        best = Some((error, model));
+                    }
+                }
+            }
+        }
+        best.map(|(_, model)| model)
+    }
+
+    /// Fits a model to `values` with the given smoothing parameters.  The
+    /// level and trend start from the means of the first two seasons, and
+    /// the seasonal components from the first season's differences from
+    /// them.  Returns `None` if there are fewer than two seasons of values.
+    pub fn fit_with(
+        values: &[f64],
+        season_length: usize,
+        alpha: f64,
+        beta: f64,
+        gamma: f64,
+    ) -> Option<Self> {
+        assert!(season_length > 0);
+        if values.len() < 2 * season_length {
+            return None;
+        }
+
+        let m = season_length as f64;
+        let first_mean = values[..season_length].iter().sum::<f64>() / m;
+        let second_mean = values[season_length..2 * season_length].iter().sum::<f64>() / m;
+        let mut trend = (second_mean - first_mean) / m;
+        // the first season's mean is its level halfway through it, so the
+        // level at its last point is half a season of trend further on
+        let middle = (m - 1.0) / 2.0;
+        let mut level = first_mean + trend * middle;
+        let mut seasonal: Vec<f64> = values[..season_length]
+            .iter()
+            .enumerate()
+            .map(|(i, value)| value - (first_mean + trend * (i as f64 - middle)))
+            .collect();
+
+        let mut fitted = Vec::with_capacity(values.len() - season_length);
+        for (i, &value) in values.iter().enumerate().skip(season_length) {
+            let season = &mut seasonal[i % season_length];
+            fitted.push(level + trend + *season);
+            let previous_level = level;
+            level = alpha * (value - *season) + (1.0 - alpha) * (level + trend);
+            trend = beta * (level - previous_level) + (1.0 - beta) * trend;
+            *season = gamma * (value - level) + (1.0 - gamma) * *season;
+        }
+
+        Some(Self {
+            alpha,
+            beta,
+            gamma,
+            level,
+            trend,
+            seasonal,
+            num_points: values.len(),
+            fitted,
+        })
+    }
+
+    pub fn season_length(&self) -> usize {
+        self.seasonal.len()
+    }
+
+    /// The prediction for the point `horizon` points after the last one the
+    /// model was fit to.
+    pub fn forecast(&self, horizon: usize) -> f64 {
+        assert!(horizon > 0);
+        let season = self.seasonal[(self.num_points - 1 + horizon) % self.season_length()];
+        self.level + horizon as f64 * self.trend + season
+    }
+
+    fn squared_error(&self, values: &[f64]) -> f64 {
+        values[self.season_length()..]
+            .iter()
+            .zip(&self.fitted)
+            .map(|(value, fitted)| (value - fitted) * (value - fitted))
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    // a line rising by 2 each point, plus a season of 4
+    fn seasonal_line(n: usize) -> Vec<f64> {
+        let season = [5.0, -3.0, 1.0, -3.0];
+        (0..n).map(|i| 2.0 * i as f64 + season[i % 4]).collect()
+    }
+
+    #[test]
+    fn too_short() {
+        assert_eq!(HoltWinters::fit(&seasonal_line(7), 4), None);
+        assert!(HoltWinters::fit(&seasonal_line(8), 4).is_some());
+    }
+
+    #[test]
+    fn exact_fit() {
+        let values = seasonal_line(12);
+        let model = HoltWinters::fit_with(&values, 4, 0.5, 0.5, 0.5).unwrap();
+        assert_close(model.level, 22.0);
+        assert_close(model.trend, 2.0);
+        for (s, expected) in model.seasonal.iter().zip([5.0, -3.0, 1.0, -3.0]) {
+            assert_close(*s, expected);
+        }
+        assert_eq!(model.fitted.len(), 8);
+        for (fitted, value) in model.fitted.iter().zip(&values[4..]) {
+            assert_close(*fitted, *value);
+        }
+
+        let continued = seasonal_line(20);
+        for horizon in 1..=8 {
+            assert_close(model.forecast(horizon), continued[11 + horizon]);
+        }
+    }
+
+    #[test]
+    fn fit_picks_parameters() {
+        // a season which shifts partway through should be followed by the
+        // fitted model, so that it forecasts the new season
+        let mut values = seasonal_line(40);
+        for (i, value) in values.iter_mut().enumerate().skip(20) {
+            if i % 4 == 0 {
+                *value += 4.0;
+            }
+        }
+        let model = HoltWinters::fit(&values, 4).unwrap();
+        let fixed = HoltWinters::fit_with(&values, 4, 0.1, 0.1, 0.1).unwrap();
+        assert!(model.squared_error(&values) <= fixed.squared_error(&values));
+        assert!((model.forecast(1) - (2.0 * 40.0 + 9.0)).abs() < 1.0);
+    }
+
+    #[test]
+    fn season_of_one() {
+        let values: Vec<f64> = (0..10).map(|i| 3.0 * i as f64 + 1.0).collect();
+        let model = HoltWinters::fit(&values, 1).unwrap();
+        assert_close(model.forecast(1), 31.0);
+        assert_close(model.forecast(5), 43.0);
+    }
+}
@@ -0,0 +1,328 @@
+//! KLL Sketch implementation in Rust
+//!
+//! Based on the paper:
+//! <https://arxiv.org/abs/1603.05346>
+
+use serde::{Deserialize, Serialize};
+
+/// The factor by which the capacity of each level shrinks from that of the
+/// level above it.
+const CAPACITY_DECAY: f64 = 2.0 / 3.0;
+
+/// The smallest capacity of any level, so that each level can be compacted.
+const MIN_CAPACITY: usize = 2;
+
+/// The KLL Sketch summarizes a stream of values in a hierarchy of compactors,
+/// or levels. Values enter the lowest level, and when a level fills up, it's
+/// compacted: its values are sorted and every other one of them is promoted
+/// to the level above, where each value stands for twice as many of the
+/// values seen. The capacities of the levels shrink geometrically going down
+/// from the top one, so the sketch holds O(k) values however many it's seen.
+///
+/// The rank of any value estimated from the sketch is within εn of its true
+/// rank with high probability, where n is the number of values seen and ε
+/// shrinks as about 1/k, regardless of the number or distribution of the
+/// values.[1]
+///
+/// [1]: <https://arxiv.org/abs/1603.05346>
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct KllSketch {
+    k: u32,
+    count: u64,
+    min: f64,
+    max: f64,
+    // `levels[h]` holds values which each stand for 2^h of the values seen
+    levels: Vec<Vec<f64>>,
+}
+
+impl KllSketch {
+    /// Constructs a new, empty KLL Sketch, the capacity of whose top level is
+    /// `k`.
+    pub fn new(k: u32) -> Self {
+        assert!(k as usize >= MIN_CAPACITY);
+        Self {
+            k,
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            levels: vec![vec![]],
+        }
+    }
+
+    /// Constructs a KLL Sketch from the values held at each of its levels,
+    /// which must stand for `count` values in total.
+    pub fn new_from_data(k: u32, count: u64, min: f64, max: f64, levels: Vec<Vec<f64>>) -> Self {
+        assert!(k as usize >= MIN_CAPACITY);
+        assert!(!levels.is_empty());
+        let weight: u64 = levels
+            .iter()
+            .enumerate()
+            .map(|(level, values)| (values.len() as u64) << level)
+            .sum();
+        assert_eq!(weight, count);
+        Self {
+            k,
+            count,
+            min,
+            max,
+            levels,
+        }
+    }
+
+    /// Returns the capacity of the sketch's top level.
+    pub fn k(&self) -> u32 {
+        self.k
+    }
+
+    /// Returns the number of values the sketch has seen.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the smallest value the sketch has seen.
+    pub fn min(&self) -> f64 {
+        self.min
+    }
+
+    /// Returns the largest value the sketch has seen.
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+
+    /// Returns the values held at each level of the sketch, from the lowest
+    /// one up.
+    pub fn levels(&self) -> &[Vec<f64>] {
+        &self.levels
+    }
+
+    /// Adds the given `value` to the sketch. NaN values have no rank among
+    /// the others, so they're ignored.
+    pub fn add_value(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.levels[0].push(value);
+        self.compress();
+    }
+
+    /// Includes the values summarized by `other` into `self`, level by level.
+    ///
+    /// Both sketches must have the same `k`.
+    pub fn merge(&mut self, other: &KllSketch) {
+        assert_eq!(self.k, other.k);
+        if self.levels.len() < other.levels.len() {
+            self.levels.resize(other.levels.len(), vec![]);
+        }
+        for (values, other_values) in self.levels.iter_mut().zip(&other.levels) {
+            values.extend_from_slice(other_values);
+        }
+        self.count += other.count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.compress();
+    }
+
+    /// Returns an estimate of the value at the given quantile (0.0-1.0) of
+    /// the values seen, or NaN if the sketch hasn't seen any.
+    pub fn estimate_quantile(&self, quantile: f64) -> f64 {
+        if self.count == 0 {
+            return f64::NAN;
+        }
+        if quantile <= 0.0 {
+            return self.min;
+        }
+        if quantile >= 1.0 {
+            return self.max;
+        }
+        let target = quantile * self.count as f64;
+        let mut seen = 0;
+        for (value, weight) in self.weighted_values() {
+            seen += weight;
+            if seen as f64 >= target {
+                return value;
+            }
+        }
+        self.max
+    }
+
+    /// Returns an estimate of the fraction (0.0-1.0) of the values seen which
+    /// are at or below `value`, or NaN if the sketch hasn't seen any.
+    pub fn estimate_rank(&self, value: f64) -> f64 {
+        if self.count == 0 {
+            return f64::NAN;
+        }
+        if value < self.min {
+            return 0.0;
+        }
+        if value >= self.max {
+            return 1.0;
+        }
+        let below: u64 = self
+            .weighted_values()
+            .into_iter()
+            .take_while(|(held, _)| *held <= value)
+            .map(|(_, weight)| weight)
+            .sum();
+        below as f64 / self.count as f64
+    }
+
+    /// The values held, each with the number of values seen it stands for,
+    /// in ascending order.
+    fn weighted_values(&self) -> Vec<(f64, u64)> {
+        let mut values: Vec<(f64, u64)> = self
+            .levels
+            .iter()
+            .enumerate()
+            .flat_map(|(level, values)| values.iter().map(move |value| (*value, 1 << level)))
+            .collect();
+        values.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        values
+    }
+
+    /// The capacity of the given level, which depends on how far it is below
+    /// the top one.
+    fn capacity(&self, level: usize) -> usize {
+        let depth = self.levels.len() - 1 - level;
+        let capacity = (self.k as f64 * CAPACITY_DECAY.powi(depth as i32)).ceil() as usize;
+        capacity.max(MIN_CAPACITY)
+    }
+
+    /// Compacts the lowest level at or over its capacity, until the sketch
+    /// holds no more values than the total capacity of its levels.
+    fn compress(&mut self) {
+        loop {
+            let size: usize = self.levels.iter().map(Vec::len).sum();
+            let capacity: usize = (0..self.levels.len())
+                .map(|level| self.capacity(level))
+                .sum();
+            if size <= capacity {
+                return;
+            }
+            let level = (0..self.levels.len())
+                .find(|&level| self.levels[level].len() >= self.capacity(level))
+                .unwrap();
+            self.compact(level);
+        }
+    }
+
+    /// Promotes every other value of the given level, in sorted order,
+    /// starting from the first or second one at random, to the level above.
+    /// With an odd number of values, the smallest is left behind, so that the
+    /// values still stand for as many values seen.
+    fn compact(&mut self, level: usize) {
+        if level + 1 == self.levels.len() {
+            self.levels.push(vec![]);
+        }
+        let mut values = std::mem::take(&mut self.levels[level]);
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let left = if values.len() % 2 == 1 {
+            vec![values.remove(0)]
+        } else {
+            vec![]
+        };
+        let offset = self.coin(level) as usize;
+        let promoted = values.into_iter().skip(offset).step_by(2);
+        self.levels[level + 1].extend(promoted);
+        self.levels[level] = left;
+    }
+
+    /// A pseudo-random bit for a compaction of the given level, derived from
+    /// the number of values seen, so that sketches of the same values in the
+    /// same order are the same.
+    fn coin(&self, level: usize) -> bool {
+        // splitmix64
+        let mut z = self
+            .count
+            .wrapping_add((level as u64 + 1).wrapping_mul(0x9e3779b97f4a7c15));
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        (z ^ (z >> 31)) & 1 == 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Checks that the estimated quantiles of the values 1 to `n` are within
+    // `error` of their true rank.
+    fn check_quantiles(sketch: &KllSketch, n: u64, error: f64) {
+        for i in 1..100 {
+            let quantile = i as f64 / 100.0;
+            let estimate = sketch.estimate_quantile(quantile);
+            let rank = estimate / n as f64;
+            assert!(
+                (rank - quantile).abs() <= error,
+                "quantile {} estimated as {}",
+                quantile,
+                estimate
+            );
+        }
+    }
+
+    #[test]
+    fn test_small_sketch_is_exact() {
+        let mut sketch = KllSketch::new(200);
+        for value in (1..=100).rev() {
+            sketch.add_value(value as f64);
+        }
+        assert_eq!(sketch.levels().len(), 1);
+        assert_eq!(sketch.estimate_quantile(0.5), 50.0);
+        assert_eq!(sketch.estimate_quantile(0.0), 1.0);
+        assert_eq!(sketch.estimate_quantile(1.0), 100.0);
+        assert_eq!(sketch.estimate_rank(25.0), 0.25);
+        assert_eq!(sketch.estimate_rank(0.0), 0.0);
+        assert_eq!(sketch.estimate_rank(200.0), 1.0);
+    }
+
+    #[test]
+    fn test_quantiles() {
+        let n = 100_000;
+        let mut sketch = KllSketch::new(200);
+        // a permutation of 1 to n
+        for i in 0..n {
+            sketch.add_value((i * 7919 % n + 1) as f64);
+        }
+        assert_eq!(sketch.count(), n);
+        assert_eq!(sketch.min(), 1.0);
+        assert_eq!(sketch.max(), n as f64);
+        let held: usize = sketch.levels().iter().map(Vec::len).sum();
+        assert!(held < 1000, "{} values held", held);
+        check_quantiles(&sketch, n, 0.02);
+    }
+
+    #[test]
+    fn test_merge() {
+        let n = 50_000;
+        let mut sketches = [KllSketch::new(200), KllSketch::new(200)];
+        for i in 0..n {
+            sketches[(i % 2) as usize].add_value((i * 7919 % n + 1) as f64);
+        }
+        let mut merged = sketches[0].clone();
+        merged.merge(&sketches[1]);
+        assert_eq!(merged.count(), n);
+        assert_eq!(merged.min(), 1.0);
+        assert_eq!(merged.max(), n as f64);
+        check_quantiles(&merged, n, 0.02);
+
+        let rebuilt = KllSketch::new_from_data(
+            merged.k(),
+            merged.count(),
+            merged.min(),
+            merged.max(),
+            merged.levels().to_vec(),
+        );
+        assert_eq!(rebuilt, merged);
+    }
+
+    #[test]
+    fn test_nan_is_ignored() {
+        let mut sketch = KllSketch::new(8);
+        sketch.add_value(f64::NAN);
+        assert_eq!(sketch.count(), 0);
+        assert!(sketch.estimate_quantile(0.5).is_nan());
+    }
+}
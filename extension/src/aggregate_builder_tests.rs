@@ -14,6 +14,7 @@ use crate::{palloc::Inner, raw::bytea};
 //     but not parallel-safe) outputs the expected config.
 //  3. `parallel_anything()` tests that the parallel version outputs the expected
 //      config.
+//  4. `batch_anything()` tests the additional array-accepting aggregate.
 #[aggregate]
 impl toolkit_experimental::anything {
     type State = String;
@@ -79,6 +80,21 @@ impl toolkit_experimental::parallel_anything {
     }
 }
 
+#[aggregate]
+impl toolkit_experimental::batch_anything {
+    type State = String;
+
+    fn transition(state: Option<State>, #[sql_type("text")] value: String) -> Option<State> {
+        state.or(Some(value))
+    }
+
+    fn finally(state: Option<&mut State>) -> Option<String> {
+        state.as_deref().cloned()
+    }
+
+    const BATCH_TRANSITION: bool = true;
+}
+
 #[cfg(any(test, feature = "pg_test"))]
 #[pg_schema]
 mod tests {
@@ -185,6 +201,50 @@ mod tests {
         });
     }
 
+    #[pg_test]
+    fn test_batch_anything_skips_nulls_and_returns_first() {
+        Spi::execute(|client| {
+            let output = client
+                .select(
+                    "SELECT toolkit_experimental.batch_anything(vals) \
+                FROM (VALUES (ARRAY[NULL, 'foo']), (ARRAY['bar', 'baz'])) as v(vals)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(output.as_deref(), Some("foo"));
+        })
+    }
+
+    #[pg_test]
+    fn test_batch_anything_has_correct_fn_names_and_def() {
+        Spi::execute(|client| {
+            let mut specs = client.select(
+                "SELECT pg_get_function_arguments(aggfnoid), aggtransfn::TEXT \
+                FROM pg_proc, pg_aggregate \
+                WHERE proname = 'batch_anything' \
+                  AND pg_proc.oid = aggfnoid \
+                ORDER BY 1",
+                None,
+                None,
+            );
+            let spec = specs.next().unwrap();
+            assert_eq!(spec[1].value(), Some("value text"));
+            assert_eq!(
+                spec[2].value(),
+                Some("toolkit_experimental.batch_anything_transition_fn_outer")
+            );
+            let spec = specs.next().unwrap();
+            assert_eq!(spec[1].value(), Some("value text[]"));
+            assert_eq!(
+                spec[2].value(),
+                Some("toolkit_experimental.batch_anything_batch_transition_fn_outer")
+            );
+            assert!(specs.next().is_none());
+        });
+    }
+
     // It gets annoying, and segfaulty to handle many arguments from the Spi.
     // For simplicity, we just return a single string representing the tuple
     // and use string-comparison.
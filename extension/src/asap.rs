@@ -126,6 +126,7 @@ fn asap_final_inner(
                     internal_padding: [0; 3],
                     points: points.into(),
                     null_val: std::vec::from_elem(0_u8, nulls_len).into(),
+                    quality: vec![].into(),
                 }
             })
         })
@@ -174,6 +175,7 @@ pub fn asap_on_timevector(
             internal_padding: [0; 3],
             points: points.into(),
             null_val: std::vec::from_elem(0_u8, nulls_len).into(),
+            quality: vec![].into(),
         }
     })
 }
@@ -1,57 +1,3883 @@
-use pgx::*;
+use std::ffi::{CStr, CString};
+
 use pgx::prelude::*;
+use pgx::*;
+
+use crate::{datum_utils::ms_to_interval, palloc::in_memory_context, raw::regclass};
+
+use cursor::{Cursor, Plans, Row};
+use merge::{merge_recent, merge_references, merge_rows, nearest, Keyed};
+use spill::Spill;
+use window::merge_windows;
+
+mod cursor;
+mod merge;
+mod spill;
+mod window;
 
+// Kept as a wrapper of `toolkit_experimental.asof_join` for existing callers.
 #[pg_extern]
-fn asof(t1:String,
-        t2:String,
-        time_column:String,
-        value_column:String) -> TableIterator<'static, (name!(time, Option<TimestampWithTimeZone>), name!(value, Option<f64>))> {
-
-    let mut table_one_query:String = "select ".to_owned();
-    table_one_query.push_str(&time_column);
-    table_one_query.push_str(",null as ");
-    table_one_query.push_str(&value_column);
-    table_one_query.push_str(" from ");
-    table_one_query.push_str(&t1);
-
-    // let table_two_query = "select time,val from sample_data_second";
-    let mut table_two_query:String ="select ".to_owned();
-    table_two_query.push_str(&time_column);
-    table_two_query.push_str(",");
-    table_two_query.push_str(&value_column);
-    table_two_query.push_str(" from  ");
-    table_two_query.push_str(&t2);
-    let table_two_query = &table_two_query;
-    let table_one_query = &table_one_query;
-
-    let mut results = Vec::new();
+fn asof(
+    t1: regclass,
+    t2: regclass,
+    time_column: String,
+    value_column: String,
+) -> TableIterator<
+    'static,
+    (
+        name!(time, Option<TimestampWithTimeZone>),
+        name!(value, Option<f64>),
+    ),
+> {
+    asof_join(t1, t2, time_column, value_column, "prior".to_owned(), None)
+}
+
+// Returns the time of every row of `left` along with the value of its match
+// in `right`, converted to a double: the last row at or before it, the first
+// one at or after it, or whichever of those is closest, depending on
+// `direction`. Rows of `right` with NULL values are passed over, and matches
+// further than `tolerance` from the row don't count. It's a front end of
+// `toolkit_experimental.asof_query`, which does the join: the sides are
+// narrowed to the time and the value, as a double, along with the tolerance.
+#[pg_extern(stable, parallel_restricted, schema = "toolkit_experimental")]
+pub fn asof_join(
+    left: regclass,
+    right: regclass,
+    on_time: String,
+    value: String,
+    direction: default!(String, "'prior'"),
+    tolerance: default!(Option<crate::raw::Interval>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(time, Option<TimestampWithTimeZone>),
+        name!(value, Option<f64>),
+    ),
+> {
+    if direction_kind(&direction) == Direction::Both {
+        pgx::error!("asof_join can't return both matches");
+    }
+    let (left, right) = (relation_oid(left), relation_oid(right));
+    let time_type = column_type(left, &on_time);
+    if ![pg_sys::TIMESTAMPOID, pg_sys::TIMESTAMPTZOID].contains(&time_type) {
+        pgx::error!("asof_join time columns must be of type timestamp or timestamp with time zone");
+    }
+    if column_type(right, &on_time) != time_type {
+        pgx::error!("asof_join time columns of both tables must have the same type");
+    }
+    let time_type = unsafe { CStr::from_ptr(pg_sys::format_type_be(time_type)) }
+        .to_str()
+        .unwrap()
+        .to_owned();
+    let (left, right) = (relation_name(left), relation_name(right));
+    let (on_time, value) = (quote_ident(&on_time), quote_ident(&value));
+
+    // the tolerance is a column of every row of `left`
+    let (left_query, tolerance_argument, tolerance_column) = match tolerance {
+        None => (
+            format!("SELECT l.{} AS time FROM {} l", on_time, left),
+            "",
+            "",
+        ),
+        Some(tolerance) => {
+            let query = format!(
+                "SELECT l.{} AS time, $1 AS tolerance FROM {} l",
+                on_time, left
+            );
+            let query = unsafe { inline_args(&query, &[(pg_sys::INTERVALOID, tolerance.0)]) };
+            (
+                query,
+                ", tolerance_column => 'tolerance'",
+                ", tolerance interval",
+            )
+        }
+    };
+    let right_query = format!(
+        "SELECT r.{} AS time, r.{}::double precision AS value FROM {} r",
+        on_time, value, right
+    );
+    let query = format!(
+        "SELECT j.time::timestamptz, j.value FROM toolkit_experimental.asof_query(\
+            $1, $2, 'time', 'value', direction => $3, null_values => 'skip'{}) \
+        AS j(time {}{}, value double precision)",
+        tolerance_argument, time_type, tolerance_column,
+    );
+    let args = vec![
+        (PgBuiltInOids::TEXTOID.oid(), left_query.into_datum()),
+        (PgBuiltInOids::TEXTOID.oid(), right_query.into_datum()),
+        (PgBuiltInOids::TEXTOID.oid(), direction.into_datum()),
+    ];
+
+    let mut results = vec![];
     Spi::connect(|client| {
-        client
-            .select(table_one_query, None, None)
-            .map(|row| (row[1].value(), row[2].value()))
-            .for_each(|tuple| results.push(tuple));
-        client
-            .select(table_two_query, None, None)
+        results = client
+            .select(&query, None, Some(args))
             .map(|row| (row[1].value(), row[2].value()))
-            .for_each(|tuple| results.push(tuple));
+            .collect();
+        Ok(Some(()))
+    });
+    TableIterator::new(results.into_iter())
+}
+
+// `toolkit_experimental.asof` returns the columns of `t1` followed by the
+// values, of whatever types they are, so it's declared as returning
+// `SETOF record` and called with a column definition list. pgx can't declare
+// such a function, so it's a plain C function declared by hand. It shares its
+// arguments, and its implementation, with `toolkit_experimental.asof_query`,
+// which joins the results of two queries instead of two tables,
+// `toolkit_experimental.asof_summary`, which returns a single row of counts of
+// how well the rows matched, ignoring the arguments that only affect how the
+// rows are returned, and `toolkit_experimental.asof_explain`, which returns the
+// queries the join would run on each side, in the order it would run them.
+// Each has an overload taking a single value column and one taking an array
+// of them, with a fill to match, so the eight are all declared from the one
+// list of arguments below; `JoinArgs` reads them by name. They all read their
+// inputs through SPI, which parallel workers can't do, so they're only
+// parallel restricted.
+extension_sql!(
+    "\n\
+    DO $$\n\
+    DECLARE\n\
+        f record;\n\
+        v record;\n\
+        arguments text := $arguments$\n\
+            direction text DEFAULT 'backward',\n\
+            by_columns text[] DEFAULT NULL,\n\
+            inner boolean DEFAULT false,\n\
+            range_start timestamptz DEFAULT NULL,\n\
+            range_end timestamptz DEFAULT NULL,\n\
+            tie_break text DEFAULT 'last',\n\
+            fill %1$s DEFAULT NULL,\n\
+            ordering text DEFAULT 'asc',\n\
+            null_values text DEFAULT 'keep',\n\
+            tolerance_column text DEFAULT NULL,\n\
+            sorted boolean DEFAULT false,\n\
+            left_where text DEFAULT NULL,\n\
+            right_where text DEFAULT NULL,\n\
+            bucket_width interval DEFAULT NULL\n\
+        $arguments$;\n\
+    BEGIN\n\
+        FOR f IN SELECT * FROM (VALUES\n\
+            ('asof', 't1 regclass, t2 regclass', 'SETOF record', 'asof_records'),\n\
+            ('asof_query', 'q1 text, q2 text', 'SETOF record', 'asof_query_records'),\n\
+            ('asof_summary', 't1 regclass, t2 regclass',\n\
+                'TABLE(matched bigint, unmatched bigint, dropped_by_tolerance bigint, '\n\
+                    'max_staleness interval, avg_staleness interval)',\n\
+                'asof_summary'),\n\
+            ('asof_explain', 't1 regclass, t2 regclass', 'TABLE(side text, query text)', 'asof_explain')\n\
+        ) f(name, sources, returns, symbol) LOOP\n\
+            FOR v IN SELECT * FROM (VALUES ('value_column', 'text'), ('value_columns', 'text[]')) v(name, type) LOOP\n\
+                EXECUTE format('CREATE FUNCTION toolkit_experimental.%I('\n\
+                        '%s, time_column text, %I %s, %s'\n\
+                    ') RETURNS %s AS %L, %L LANGUAGE C STABLE PARALLEL RESTRICTED',\n\
+                    f.name, f.sources, v.name, v.type, format(arguments, v.type),\n\
+                    f.returns, 'MODULE_PATHNAME', f.symbol);\n\
+            END LOOP;\n\
+        END LOOP;\n\
+    END\n\
+    $$;\n\
+",
+    name = "asof_records",
+);
+
+// `toolkit_experimental.asof_stats` likewise returns the columns of `t1`,
+// followed by the statistics. The window's width is an interval for
+// timestamp time columns, or a number in the time column's units for bigint
+// ones.
+extension_sql!(
+    "\n\
+    CREATE FUNCTION toolkit_experimental.asof_stats(\n\
+        t1 regclass,\n\
+        t2 regclass,\n\
+        time_column text,\n\
+        value_column text,\n\
+        width interval,\n\
+        by_columns text[] DEFAULT NULL\n\
+    ) RETURNS SETOF record\n\
+    AS 'MODULE_PATHNAME', 'asof_stats_records'\n\
+    LANGUAGE C STABLE PARALLEL RESTRICTED;\n\
+    CREATE FUNCTION toolkit_experimental.asof_stats(\n\
+        t1 regclass,\n\
+        t2 regclass,\n\
+        time_column text,\n\
+        value_column text,\n\
+        width bigint,\n\
+        by_columns text[] DEFAULT NULL\n\
+    ) RETURNS SETOF record\n\
+    AS 'MODULE_PATHNAME', 'asof_stats_records'\n\
+    LANGUAGE C STABLE PARALLEL RESTRICTED;\n\
+",
+    name = "asof_stats_records",
+);
+
+// `toolkit_experimental.asof_multi` returns the columns of `t1` followed by a
+// value from each of several tables, given as an array. The value column is
+// either the same in each table, or given as an array of one per table.
+extension_sql!(
+    "\n\
+    CREATE FUNCTION toolkit_experimental.asof_multi(\n\
+        t1 regclass,\n\
+        t2 regclass[],\n\
+        time_column text,\n\
+        value_column text,\n\
+        by_columns text[] DEFAULT NULL\n\
+    ) RETURNS SETOF record\n\
+    AS 'MODULE_PATHNAME', 'asof_multi_records'\n\
+    LANGUAGE C STABLE PARALLEL RESTRICTED;\n\
+    CREATE FUNCTION toolkit_experimental.asof_multi(\n\
+        t1 regclass,\n\
+        t2 regclass[],\n\
+        time_column text,\n\
+        value_columns text[],\n\
+        by_columns text[] DEFAULT NULL\n\
+    ) RETURNS SETOF record\n\
+    AS 'MODULE_PATHNAME', 'asof_multi_records'\n\
+    LANGUAGE C STABLE PARALLEL RESTRICTED;\n\
+",
+    name = "asof_multi_records",
+);
+
+// `toolkit_experimental.asof_topk` returns the columns of `t1` followed by a
+// value, its rank, and its staleness, once for each of up to `k` matches.
+// The staleness is an interval for timestamp time columns, or a bigint for
+// bigint ones.
+extension_sql!(
+    "\n\
+    CREATE FUNCTION toolkit_experimental.asof_topk(\n\
+        t1 regclass,\n\
+        t2 regclass,\n\
+        time_column text,\n\
+        value_column text,\n\
+        k integer,\n\
+        by_columns text[] DEFAULT NULL\n\
+    ) RETURNS SETOF record\n\
+    AS 'MODULE_PATHNAME', 'asof_topk_records'\n\
+    LANGUAGE C STABLE PARALLEL RESTRICTED;\n\
+",
+    name = "asof_topk_records",
+);
+
+#[no_mangle]
+pub extern "C" fn pg_finfo_asof_records() -> &'static pg_sys::Pg_finfo_record {
+    const V1_API: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1_API
+}
+
+#[no_mangle]
+pub extern "C" fn pg_finfo_asof_query_records() -> &'static pg_sys::Pg_finfo_record {
+    const V1_API: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1_API
+}
+
+#[no_mangle]
+pub extern "C" fn pg_finfo_asof_summary() -> &'static pg_sys::Pg_finfo_record {
+    const V1_API: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1_API
+}
+
+#[no_mangle]
+pub extern "C" fn pg_finfo_asof_explain() -> &'static pg_sys::Pg_finfo_record {
+    const V1_API: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1_API
+}
+
+#[pg_guard]
+#[no_mangle]
+pub unsafe extern "C" fn asof_records(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum {
+    join_sources(fcinfo, false, JoinOutput::Rows)
+}
+
+#[pg_guard]
+#[no_mangle]
+pub unsafe extern "C" fn asof_query_records(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum {
+    join_sources(fcinfo, true, JoinOutput::Rows)
+}
+
+#[pg_guard]
+#[no_mangle]
+pub unsafe extern "C" fn asof_summary(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum {
+    join_sources(fcinfo, false, JoinOutput::Summary)
+}
+
+#[pg_guard]
+#[no_mangle]
+pub unsafe extern "C" fn asof_explain(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum {
+    join_sources(fcinfo, false, JoinOutput::Explain)
+}
+
+// What a join returns: the joined rows, a `MatchSummary` of them, or the
+// queries it would run to find them, without running them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum JoinOutput {
+    Rows,
+    Summary,
+    Explain,
+}
+
+// How well the rows of `t1` matched: how many did and didn't, how many only
+// had matches further than their tolerance, and how far the matches were
+// from the rows they matched, in microseconds.
+#[derive(Default)]
+struct MatchSummary {
+    matched: i64,
+    unmatched: i64,
+    dropped_by_tolerance: i64,
+    max_staleness: Option<i64>,
+    total_staleness: i128,
+}
+
+impl MatchSummary {
+    fn values(&self) -> [Option<pg_sys::Datum>; 5] {
+        let interval = |staleness: i64| ms_to_interval(staleness).0;
+        let avg_staleness = (self.matched > 0)
+            .then(|| interval((self.total_staleness / self.matched as i128) as i64));
+        [
+            Some(pg_sys::Datum::from(self.matched)),
+            Some(pg_sys::Datum::from(self.unmatched)),
+            Some(pg_sys::Datum::from(self.dropped_by_tolerance)),
+            self.max_staleness.map(interval),
+            avg_staleness,
+        ]
+    }
+}
+
+// The arguments of the join functions declared above, read by name.
+struct JoinArgs {
+    // the tables, or the text of queries, to join
+    t1: Source,
+    t2: Source,
+    time_column: String,
+    // the value columns of `t2` to return, given as a single column or an array
+    value_columns: Vec<String>,
+    // the last row of `t2` at or before each row of `t1`, the first one at or
+    // after it, whichever of those is closest, or both of them along with how
+    // far the row is from the first towards the second
+    direction: Direction,
+    // columns whose values rows must share to match, so each series is joined
+    // separately
+    by_columns: Vec<String>,
+    // whether rows without a match are left out rather than returned with NULLs
+    inner: bool,
+    // only rows of either table from `range_start` up to `range_end` are read
+    range: (Option<pg_sys::Datum>, Option<pg_sys::Datum>),
+    // which of several rows of `t2` at the matching time is the match, the
+    // first or last in physical order, or whether that's an error
+    tie_break: TieBreak,
+    // the values, as text, rows without a match get instead of NULLs
+    fill: Option<Vec<Option<String>>>,
+    // whether rows are returned in descending time order, with rows of `t1` at
+    // the same time in physical order either way
+    descending: bool,
+    // whether rows of `t2` with NULL values are matched like any other,
+    // skipped, or an error
+    null_policy: NullValues,
+    // a column of `t1` limiting how far each row's match may be from it
+    tolerance_column: Option<String>,
+    // whether both are already in ascending time order, so that they can be
+    // read without sorting them, checking that they are
+    sorted: bool,
+    // conditions on the columns of `t1` and `t2` restricting which of their
+    // rows are read
+    left_where: Option<String>,
+    right_where: Option<String>,
+    // the width of the `time_bucket` buckets rows must share to match
+    bucket_width: Option<i64>,
+}
+
+impl JoinArgs {
+    // The arguments of the call, or `None` if any of those without a default
+    // are NULL. `t1` and `t2` are queries when `queries` is true.
+    unsafe fn read(fcinfo: pg_sys::FunctionCallInfo, queries: bool) -> Option<Self> {
+        let args = NamedArgs::of(fcinfo);
+        let (t1, t2) = if queries {
+            (
+                Source::query(&args.get::<String>("q1")?),
+                Source::query(&args.get::<String>("q2")?),
+            )
+        } else {
+            let relation = |name| args.get::<regclass>(name).map(relation_oid);
+            (
+                Source::Relation(relation("t1")?),
+                Source::Relation(relation("t2")?),
+            )
+        };
+        let value_columns = if args.has("value_columns") {
+            args.get::<Vec<String>>("value_columns")?
+        } else {
+            vec![args.get::<String>("value_column")?]
+        };
+        let fill = if args.type_of("fill") == pg_sys::TEXTARRAYOID {
+            args.get::<Vec<Option<String>>>("fill")
+        } else {
+            args.get::<String>("fill").map(|value| vec![Some(value)])
+        };
+        let bucket_width = args
+            .get::<crate::raw::Interval>("bucket_width")
+            .map(|width| {
+                let width = &*(width.0.cast_mut_ptr() as *const pg_sys::Interval);
+                if width.month != 0 {
+                    pgx::error!("asof's bucket_width can't be given in months");
+                }
+                let width = interval_length(width);
+                if width <= 0 {
+                    pgx::error!("asof's bucket_width must be positive");
+                }
+                width
+            });
+        Some(JoinArgs {
+            t1,
+            t2,
+            time_column: args.get("time_column")?,
+            value_columns,
+            direction: direction_kind(&args.get::<String>("direction")?),
+            by_columns: args.get("by_columns").unwrap_or_default(),
+            inner: args.get("inner").unwrap_or(false),
+            range: (args.datum("range_start"), args.datum("range_end")),
+            tie_break: tie_break_kind(args.get::<String>("tie_break").as_deref().unwrap_or("last")),
+            fill,
+            descending: ordering_kind(args.get::<String>("ordering").as_deref().unwrap_or("asc")),
+            null_policy: null_values_kind(
+                args.get::<String>("null_values")
+                    .as_deref()
+                    .unwrap_or("keep"),
+            ),
+            tolerance_column: args.get("tolerance_column"),
+            sorted: args.get("sorted").unwrap_or(false),
+            left_where: args.get("left_where"),
+            right_where: args.get("right_where"),
+            bucket_width,
+        })
+    }
+}
+
+// The arguments of a call, looked up by the names they're declared with.
+struct NamedArgs {
+    fcinfo: pg_sys::FunctionCallInfo,
+    names: Vec<String>,
+}
+
+impl NamedArgs {
+    unsafe fn of(fcinfo: pg_sys::FunctionCallInfo) -> Self {
+        let function = (*(*fcinfo).flinfo).fn_oid;
+        let tuple = pg_sys::SearchSysCache1(
+            pg_sys::SysCacheIdentifier_PROCOID as _,
+            pgx::Datum::from(function),
+        );
+        if tuple.is_null() {
+            pgx::error!("no function info for oid {}", function);
+        }
+        let mut types = std::ptr::null_mut();
+        let mut names = std::ptr::null_mut();
+        let mut modes = std::ptr::null_mut();
+        let count = pg_sys::get_func_arg_info(tuple, &mut types, &mut names, &mut modes);
+        pg_sys::ReleaseSysCache(tuple);
+        // the input arguments come first, followed by the columns of any
+        // `RETURNS TABLE`, which are left out
+        let names = (0..count as usize)
+            .filter(|&i| modes.is_null() || !matches!(*modes.add(i) as u8, b'o' | b't'))
+            .map(|i| match names.is_null() {
+                true => String::new(),
+                false => CStr::from_ptr(*names.add(i)).to_string_lossy().into_owned(),
+            })
+            .collect();
+        NamedArgs { fcinfo, names }
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.names.iter().any(|arg| arg == name)
+    }
+
+    fn position(&self, name: &str) -> usize {
+        match self.names.iter().position(|arg| arg == name) {
+            Some(position) => position,
+            None => pgx::error!("asof has no argument named {}", name),
+        }
+    }
+
+    unsafe fn get<T: FromDatum>(&self, name: &str) -> Option<T> {
+        pg_getarg::<T>(self.fcinfo, self.position(name))
+    }
+
+    unsafe fn datum(&self, name: &str) -> Option<pg_sys::Datum> {
+        pg_getarg_datum(self.fcinfo, self.position(name))
+    }
+
+    unsafe fn type_of(&self, name: &str) -> pg_sys::Oid {
+        pg_sys::get_fn_expr_argtype((*self.fcinfo).flinfo, self.position(name) as i32)
+    }
+}
+
+// Joins every row of `t1` to the value columns of a row of `t2`, as described
+// by `JoinArgs`, returning the joined rows, a single row of a `MatchSummary`,
+// or the queries the join would run on each side, with their parameters
+// written into them, depending on `output`.
+unsafe fn join_sources(
+    fcinfo: pg_sys::FunctionCallInfo,
+    queries: bool,
+    output: JoinOutput,
+) -> pg_sys::Datum {
+    let summary = output == JoinOutput::Summary;
+    let explain = output == JoinOutput::Explain;
+    let function = match output {
+        JoinOutput::Rows => "asof",
+        JoinOutput::Summary => "asof_summary",
+        JoinOutput::Explain => "asof_explain",
+    };
+    let rsinfo = result_info(fcinfo, function);
+
+    let JoinArgs {
+        t1,
+        t2,
+        time_column,
+        value_columns,
+        direction,
+        by_columns,
+        inner,
+        range,
+        tie_break,
+        fill,
+        descending,
+        null_policy,
+        tolerance_column,
+        sorted,
+        left_where,
+        right_where,
+        bucket_width,
+    } = match JoinArgs::read(fcinfo, queries) {
+        Some(args) => args,
+        // like a strict function, there's no result for NULL arguments
+        None => return no_result(rsinfo),
+    };
+    // a summary is the same whichever order the rows are found in
+    let descending = descending && !summary;
+
+    let left_types = t1.column_types();
+    let value_types: Vec<pg_sys::Oid> = value_columns
+        .iter()
+        .map(|column| t2.column_type(column))
+        .collect();
+    // with both matches, the values of each, followed by the weight
+    let output_types: Vec<pg_sys::Oid> = if direction == Direction::Both {
+        let weight = [pg_sys::FLOAT8OID];
+        let values = value_types.iter().chain(&value_types).chain(&weight);
+        left_types.iter().chain(values).copied().collect()
+    } else {
+        left_types.iter().chain(&value_types).copied().collect()
+    };
+    if summary && direction == Direction::Both {
+        pgx::error!("asof_summary can't summarize both matches");
+    }
+    if output == JoinOutput::Rows {
+        let followed_by = if direction == Direction::Both {
+            "the value columns of the previous and the next match, and the weight"
+        } else {
+            "the value columns"
+        };
+        check_column_definitions((*rsinfo).expectedDesc, &output_types, "asof", followed_by);
+    }
+    let time_type = time_type(&t1, &t2, &time_column);
+    if summary && time_type == pg_sys::INT8OID {
+        pgx::error!(
+            "asof_summary's staleness is an interval, so its time column must be a timestamp"
+        );
+    }
+    if time_type == pg_sys::INT8OID && (range.0.is_some() || range.1.is_some()) {
+        pgx::error!("asof can't restrict a bigint time column to a range of timestamps");
+    }
+    if time_type == pg_sys::INT8OID && bucket_width.is_some() {
+        pgx::error!("asof can't put a bigint time column in buckets of an interval");
+    }
+
+    // tolerances are compared as microseconds, or in the time column's units
+    let tolerance = match &tolerance_column {
+        None => String::new(),
+        Some(column) => {
+            let typoid = t1.column_type(column);
+            let column = quote_ident(column);
+            match (time_type, typoid) {
+                (pg_sys::INT8OID, pg_sys::INT2OID | pg_sys::INT4OID | pg_sys::INT8OID) => {
+                    format!(", l.{}::bigint", column)
+                }
+                (pg_sys::INT8OID, _) => pgx::error!(
+                    "asof's tolerance column must be an integer when the time column is a bigint"
+                ),
+                (_, pg_sys::INTERVALOID) => {
+                    format!(", (extract(epoch FROM l.{}) * 1000000)::bigint", column)
+                }
+                _ => pgx::error!("asof's tolerance column must be an interval"),
+            }
+        }
+    };
+    // the columns read from `t1`: its own, followed by the tolerance, if any
+    let left_query_types: Vec<pg_sys::Oid> = match tolerance_column {
+        Some(_) => left_types
+            .iter()
+            .chain(&[pg_sys::INT8OID])
+            .copied()
+            .collect(),
+        None => left_types.clone(),
+    };
+
+    let time_column = quote_ident(&time_column);
+    let values: Vec<String> = value_columns
+        .iter()
+        .map(|column| format!("r.{}", quote_ident(column)))
+        .collect();
+    let by_columns: Vec<String> = by_columns
+        .iter()
+        .map(|column| quote_ident(column))
+        .collect();
+    let left_key = partition_key("l", &by_columns);
+    let right_key = partition_key("r", &by_columns);
+    let range = [
+        (">=", pg_sys::TIMESTAMPTZOID, range.0),
+        ("<", pg_sys::TIMESTAMPTZOID, range.1),
+    ];
+    let (mut left_conditions, mut right_conditions) = (vec![], vec![]);
+    if let Some(condition) = left_where {
+        left_conditions.push(where_condition(&t1, "l", &condition, "left_where"));
+    }
+    if let Some(condition) = right_where {
+        right_conditions.push(where_condition(&t2, "r", &condition, "right_where"));
+    }
+    let (left_range, range_args) = time_bounds("l", &time_column, &range, left_conditions);
+
+    let (desc, store) = result_store(rsinfo);
+    let plans = Plans::of_function((*fcinfo).flinfo);
+    let null_values = vec![None; value_types.len()];
+    let fill = fill_values(fill, &value_types);
+    let put = |output: &[Option<pg_sys::Datum>]| put_values(store, desc, output);
+    // Rows are found in ascending order, so in descending order they're
+    // spilled and put in the result in reverse afterwards.
+    let mut reversed = descending.then(|| Spill::new(&output_types));
+    let left_len = left_types.len();
+    let mut match_summary = MatchSummary::default();
+    let mut emit = |time: Option<i64>,
+                    left: &[Option<pg_sys::Datum>],
+                    matches: &[Option<(i64, &[Option<pg_sys::Datum>])>]| {
+        let tolerance = left
+            .get(left_len)
+            .copied()
+            .flatten()
+            .map(|tolerance| tolerance.value() as i64);
+        if summary {
+            let (matched, dropped) = checked_match(time, matches[0], tolerance, bucket_width);
+            let staleness = time
+                .zip(matched)
+                .map(|(time, (matched, _))| (matched - time).abs());
+            match staleness {
+                _ if dropped => match_summary.dropped_by_tolerance += 1,
+                Some(staleness) => {
+                    match_summary.matched += 1;
+                    match_summary.total_staleness += staleness as i128;
+                    match_summary.max_staleness = match_summary.max_staleness.max(Some(staleness));
+                }
+                None => match_summary.unmatched += 1,
+            }
+            return;
+        }
+        let matches: Vec<Option<(i64, &[Option<pg_sys::Datum>])>> = matches
+            .iter()
+            .map(|matched| checked_match(time, *matched, tolerance, bucket_width).0)
+            .collect();
+        if inner && matches.iter().any(Option::is_none) {
+            return;
+        }
+        let mut output: Vec<Option<pg_sys::Datum>> = left[..left_len].to_vec();
+        for matched in &matches {
+            output.extend_from_slice(matched.map_or(&fill[..], |(_, values)| values));
+        }
+        if direction == Direction::Both {
+            // how far the row is from the previous match towards the next
+            let weight = match (time, matches[0], matches[1]) {
+                (Some(time), Some((before, _)), Some((after, _))) if after > before => {
+                    Some((time - before) as f64 / (after - before) as f64)
+                }
+                (Some(_), Some(_), Some(_)) => Some(0.0),
+                _ => None,
+            };
+            output.push(weight.and_then(IntoDatum::into_datum));
+        }
+        match &mut reversed {
+            Some(reversed) => reversed.push(&output),
+            None => put(&output),
+        }
+    };
+
+    // ties are broken by reading the chosen row last
+    let right_ties = t2.tie_order("r", tie_break == TieBreak::First);
+    let unique = tie_break == TieBreak::Error;
+    // Rows of `t1` at the same time are read in physical order, or the
+    // reverse when the rows read are reversed once more before they're
+    // returned: the first match after each row is found by reading
+    // backwards, and descending order is returned reversed.
+    let left_ties = t1.tie_order("l", (direction != Direction::Backward) != descending);
+    // No row of `t2` before the last one at or before the first row of `t1`,
+    // or after the first one at or after its last row, can be a match, so
+    // `t2` is only read between those. With series, those are the earliest
+    // and latest such rows of any series, or the series' own first or last
+    // row of `t1` when it has none. Hypertables' chunks outside them are
+    // then excluded instead of scanned. Queries would have to be run again to
+    // find them, so they're only found for tables, and finding them in
+    // sorted tables would mean reading them whole.
+    let bounded = !sorted && matches!((&t1, &t2), (Source::Relation(_), Source::Relation(_)));
+    let series = !by_columns.is_empty();
+    let (t1, t2) = (t1.from_item(), t2.from_item());
+    let values = values.join(", ");
+    if null_policy == NullValues::Skip {
+        right_conditions.push(format!("num_nulls({}) = 0", values));
+    }
+    let right_filter: String = right_conditions
+        .iter()
+        .map(|condition| format!(" AND {}", condition))
+        .collect();
+    let left_query = |order: &str| {
+        let order = if sorted && order.is_empty() {
+            String::new()
+        } else {
+            format!(" ORDER BY 1 {}{}", order, left_ties)
+        };
+        format!(
+            "SELECT l.{}, {}, l.*{} FROM {} l{}{}",
+            time_column, left_key, tolerance, t1, left_range, order
+        )
+    };
+    let open_left = |order: &str| {
+        let presorted = sorted && order.is_empty();
+        Cursor::open(plans, &left_query(order), &range_args, &left_query_types)
+            .inspect(check_sorted(presorted, "t1"))
+    };
+    let bound_query = |operator: &str, aggregate: &str, order: &str| {
+        if series {
+            return format!(
+                "SELECT {3}(coalesce((SELECT r.{0} FROM {1} r WHERE r.{0} {2} l.bound AND {8} = l.key{6} ORDER BY 1 {7} LIMIT 1), l.bound)), NULL::text \
+                FROM (SELECT {9} AS key, {3}(l.{0}) AS bound FROM {4} l{5} GROUP BY 1) l",
+                time_column, t2, operator, aggregate, t1, left_range, right_filter, order, right_key, left_key
+            );
+        }
+        format!(
+            "SELECT r.{0}, NULL::text FROM {1} r WHERE r.{0} {2} (SELECT {3}(l.{0}) FROM {4} l{5}){6} ORDER BY 1 {7} LIMIT 1",
+            time_column, t2, operator, aggregate, t1, left_range, right_filter, order
+        )
+    };
+    let bound = |operator: &str, aggregate: &str, order: &str| {
+        let query = bound_query(operator, aggregate, order);
+        let time = Cursor::open(plans, &query, &range_args, &[]).next();
+        time.and_then(|(_, time, _)| time).map(pg_sys::Datum::from)
+    };
+    // the sides and orders of the queries the join reads, in the order it
+    // reads them
+    let reads: &[(&str, &str)] = match direction {
+        Direction::Backward => &[("t1", ""), ("t2", "")],
+        Direction::Forward => &[("t1", "DESC NULLS FIRST"), ("t2", "DESC")],
+        Direction::Nearest | Direction::Both => {
+            &[("t1", "DESC NULLS FIRST"), ("t2", "DESC"), ("t2", "")]
+        }
+    };
+
+    // Both sides are read in time order and merged as they're read, so
+    // neither needs to fit in memory. The first match after each row is
+    // found by reading both backwards; the rows are spilled along with their
+    // time, key, and the match's time and values, and read back in order.
+    let spilled_len = left_query_types.len();
+    let spill_types: Vec<pg_sys::Oid> = [pg_sys::INT8OID, pg_sys::TEXTOID]
+        .iter()
+        .chain(&left_query_types)
+        .chain(&[pg_sys::INT8OID])
+        .chain(&value_types)
+        .copied()
+        .collect();
+    Spi::connect(|_client| {
+        let (lower, upper) = if bounded {
+            (bound("<=", "min", "DESC"), bound(">=", "max", ""))
+        } else {
+            (None, None)
+        };
+        let right_bounds = [
+            range[0],
+            range[1],
+            (">=", time_type, lower),
+            ("<=", time_type, upper),
+        ];
+        let (right_range, right_args) =
+            time_bounds("r", &time_column, &right_bounds, right_conditions.clone());
+        let right_query = |order: &str| {
+            let order = if sorted && order.is_empty() {
+                String::new()
+            } else {
+                format!(" ORDER BY 1 {}{}", order, right_ties)
+            };
+            format!(
+                "SELECT r.{}, {}, {} FROM {} r{}{}",
+                time_column, right_key, values, t2, right_range, order
+            )
+        };
+
+        if explain {
+            let mut explained = vec![];
+            if bounded {
+                explained.push(("t2", bound_query("<=", "min", "DESC"), &range_args));
+                explained.push(("t2", bound_query(">=", "max", ""), &range_args));
+            }
+            for &(side, order) in reads {
+                if side == "t1" {
+                    explained.push((side, left_query(order), &range_args));
+                } else {
+                    explained.push((side, right_query(order), &right_args));
+                }
+            }
+            for (side, query, args) in explained {
+                let query = inline_args(&query, args);
+                put(&[side.into_datum(), query.into_datum()]);
+            }
+            return Ok(Some(()));
+        }
+
+        let open_right = |order: &str| {
+            let presorted = sorted && order.is_empty();
+            Cursor::open(plans, &right_query(order), &right_args, &value_types)
+                .inspect(check_sorted(presorted, "t2"))
+                .inspect(|(_, _, row)| {
+                    if null_policy == NullValues::Error && row.datums.iter().any(Option::is_none) {
+                        pgx::error!("asof found NULL values in t2");
+                    }
+                })
+        };
+
+        if direction == Direction::Backward {
+            let left = open_left(reads[0].1).map(|(key, time, row)| (key, time, (time, row)));
+            merge_rows(
+                left,
+                open_right(""),
+                false,
+                unique,
+                |(time, row), matched| {
+                    let matched = matched.map(|(time, values)| (time, &values.datums[..]));
+                    emit(time, &row.datums, &[matched])
+                },
+            );
+            return Ok(Some(()));
+        }
+
+        let mut spill = Spill::new(&spill_types);
+        let left =
+            open_left(reads[0].1).map(|(key, time, row)| (key.clone(), time, (key, time, row)));
+        merge_rows(
+            left,
+            open_right(reads[1].1),
+            true,
+            unique,
+            |(key, time, row), matched| {
+                let key = key.and_then(IntoDatum::into_datum);
+                let values = matched.map_or(&null_values, |(_, values)| &values.datums);
+                let datums: Vec<Option<pg_sys::Datum>> = [time.map(pg_sys::Datum::from), key]
+                    .iter()
+                    .chain(&row.datums)
+                    .chain(&[matched.map(|(time, _)| pg_sys::Datum::from(time))])
+                    .chain(values)
+                    .copied()
+                    .collect();
+                spill.push(&datums);
+                if let Some(key) = key {
+                    pg_sys::pfree(key.cast_mut_ptr());
+                }
+            },
+        );
+        let spilled = spill.into_reversed();
+
+        if direction == Direction::Forward {
+            for row in spilled {
+                let time = row.datums[0].map(|time| time.value() as i64);
+                let matched = spilled_match(&row, spilled_len);
+                emit(time, &row.datums[2..2 + spilled_len], &[matched]);
+            }
+            return Ok(Some(()));
+        }
+
+        let left = spilled.map(|row| {
+            let time = row.datums[0].map(|time| time.value() as i64);
+            let key = row.datums[1]
+                .and_then(|key| String::from_polymorphic_datum(key, false, pg_sys::TEXTOID));
+            (key, time, row)
+        });
+        merge_rows(left, open_right(""), false, unique, |row, before| {
+            let time = row.datums[0].map(|time| time.value() as i64);
+            let before = before.map(|(time, values)| (time, (time, &values.datums[..])));
+            let after = spilled_match(&row, spilled_len).map(|matched| (matched.0, matched));
+            if direction == Direction::Both {
+                let matches = [
+                    before.map(|(_, before)| before),
+                    after.map(|(_, after)| after),
+                ];
+                return emit(time, &row.datums[2..2 + spilled_len], &matches);
+            }
+            let matched = time.and_then(|time| {
+                // the closest match is the closest one in the row's bucket
+                let in_bucket = |at: i64| {
+                    bucket_width.map_or(true, |width| bucket(at, width) == bucket(time, width))
+                };
+                let before = before.filter(|&(at, _)| in_bucket(at));
+                let after = after.filter(|&(at, _)| in_bucket(at));
+                nearest(time, before, after)
+            });
+            emit(time, &row.datums[2..2 + spilled_len], &[matched])
+        });
         Ok(Some(()))
     });
-   results.sort_by(|a, b| a.0.cmp(&b.0));
-    let mut results2 = Vec::new();
-    let mut curr_val = None;
-// TODO: build a fast locf logic. The current implementation is based on looping.
-    for mut res in results{
-        if res.1 == None{
-            if curr_val == None{
-            }else{
-                res.1 = curr_val;
+    if let Some(reversed) = reversed {
+        for row in reversed.into_reversed() {
+            put(&row.datums);
+        }
+    }
+    if summary {
+        put(&match_summary.values());
+    }
+
+    return_result(rsinfo, desc, store)
+}
+
+// The result info of a call of `function` returning its result in a
+// tuplestore, erroring unless it's been called with a column definition
+// list.
+unsafe fn result_info(
+    fcinfo: pg_sys::FunctionCallInfo,
+    function: &str,
+) -> *mut pg_sys::ReturnSetInfo {
+    let rsinfo = (*fcinfo).resultinfo as *mut pg_sys::ReturnSetInfo;
+    if rsinfo.is_null()
+        || (*rsinfo).allowedModes & pg_sys::SetFunctionReturnMode_SFRM_Materialize as i32 == 0
+        || (*rsinfo).expectedDesc.is_null()
+    {
+        pgx::error!(
+            "{} must be called in the FROM clause with a column definition list",
+            function
+        );
+    }
+    rsinfo
+}
+
+// The tuple descriptor and tuplestore of the result, which must outlive the
+// call.
+unsafe fn result_store(
+    rsinfo: *mut pg_sys::ReturnSetInfo,
+) -> (pg_sys::TupleDesc, *mut pg_sys::Tuplestorestate) {
+    let per_query_context = (*(*rsinfo).econtext).ecxt_per_query_memory;
+    in_memory_context(per_query_context, || {
+        (
+            pg_sys::CreateTupleDescCopy((*rsinfo).expectedDesc),
+            pg_sys::tuplestore_begin_heap(true, false, pg_sys::work_mem),
+        )
+    })
+}
+
+unsafe fn put_values(
+    store: *mut pg_sys::Tuplestorestate,
+    desc: pg_sys::TupleDesc,
+    values: &[Option<pg_sys::Datum>],
+) {
+    let mut datums: Vec<pg_sys::Datum> = values
+        .iter()
+        .map(|datum| datum.unwrap_or_else(|| pg_sys::Datum::from(0usize)))
+        .collect();
+    let mut nulls: Vec<bool> = values.iter().map(Option::is_none).collect();
+    pg_sys::tuplestore_putvalues(store, desc, datums.as_mut_ptr(), nulls.as_mut_ptr());
+}
+
+unsafe fn return_result(
+    rsinfo: *mut pg_sys::ReturnSetInfo,
+    desc: pg_sys::TupleDesc,
+    store: *mut pg_sys::Tuplestorestate,
+) -> pg_sys::Datum {
+    (*rsinfo).returnMode = pg_sys::SetFunctionReturnMode_SFRM_Materialize;
+    (*rsinfo).setResult = store;
+    (*rsinfo).setDesc = desc;
+    pg_sys::Datum::from(0usize)
+}
+
+unsafe fn no_result(rsinfo: *mut pg_sys::ReturnSetInfo) -> pg_sys::Datum {
+    (*rsinfo).returnMode = pg_sys::SetFunctionReturnMode_SFRM_Materialize;
+    pg_sys::Datum::from(0usize)
+}
+
+// Looks up the value of the row of `t` at or before `at`, at or after it, or
+// whichever of those is closer, depending on `direction`. Each is a single
+// row read in time order, which an index on the time column can find without
+// reading the rest. The value is returned as text, since it can be of any
+// type.
+#[pg_extern(stable, parallel_restricted, schema = "toolkit_experimental")]
+pub fn asof_value(
+    t: regclass,
+    time_column: String,
+    value_column: String,
+    at: TimestampWithTimeZone,
+    direction: default!(String, "'backward'"),
+) -> Option<String> {
+    let relation = relation_oid(t);
+    let time_type = column_type(relation, &time_column);
+    if ![pg_sys::TIMESTAMPOID, pg_sys::TIMESTAMPTZOID].contains(&time_type) {
+        pgx::error!(
+            "asof_value time columns must be of type timestamp or timestamp with time zone"
+        );
+    }
+    let table = relation_name(relation);
+    let (time_column, value_column) = (quote_ident(&time_column), quote_ident(&value_column));
+
+    let row = |condition: &str, order: &str| {
+        format!(
+            "(SELECT t.{0} AS time, t.{1}::text AS value FROM {2} t WHERE t.{0} {3} $1 ORDER BY t.{0} {4} LIMIT 1)",
+            time_column, value_column, table, condition, order
+        )
+    };
+    let query = match direction_kind(&direction) {
+        Direction::Backward => format!("SELECT value FROM {} s", row("<=", "DESC")),
+        Direction::Forward => format!("SELECT value FROM {} s", row(">=", "")),
+        Direction::Both => pgx::error!("asof_value can't return both matches"),
+        // equally far rows resolve to the earlier one
+        Direction::Nearest => format!(
+            "SELECT value FROM ({} UNION ALL {}) s ORDER BY abs(extract(epoch FROM s.time - $1)), s.time LIMIT 1",
+            row("<=", "DESC"),
+            row(">=", ""),
+        ),
+    };
+    Spi::get_one_with_args(
+        &query,
+        vec![(PgBuiltInOids::TIMESTAMPTZOID.oid(), at.into_datum())],
+    )
+}
+
+// `toolkit_experimental.last_known` returns the value as the type of
+// `value_type`, which is only there for its type, so it's declared as
+// returning `anyelement`, and isn't strict, since `value_type` is usually
+// NULL.
+extension_sql!(
+    "\n\
+    CREATE FUNCTION toolkit_experimental.last_known(\n\
+        t2 regclass,\n\
+        time_column text,\n\
+        value_column text,\n\
+        probe_time timestamptz,\n\
+        value_type anyelement,\n\
+        by_columns text[] DEFAULT NULL,\n\
+        by_values text[] DEFAULT NULL\n\
+    ) RETURNS anyelement\n\
+    AS 'MODULE_PATHNAME', 'last_known'\n\
+    LANGUAGE C STABLE PARALLEL RESTRICTED;\n\
+",
+    name = "last_known",
+);
+
+#[no_mangle]
+pub extern "C" fn pg_finfo_last_known() -> &'static pg_sys::Pg_finfo_record {
+    const V1_API: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1_API
+}
+
+// Looks up the value of the last row of `t2` at or before `probe_time`, like
+// `asof_value`, but returns it as the type of `value_type`, so that it can be
+// used in place of a join. When `by_columns` are given, only rows with the
+// corresponding `by_values`, given as text, in those columns are looked at.
+#[pg_guard]
+#[no_mangle]
+pub unsafe extern "C" fn last_known(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum {
+    let args = (
+        pg_getarg::<regclass>(fcinfo, 0),
+        pg_getarg::<String>(fcinfo, 1),
+        pg_getarg::<String>(fcinfo, 2),
+        pg_getarg_datum(fcinfo, 3),
+    );
+    let (t2, time_column, value_column, probe_time) = match args {
+        (Some(t2), Some(time_column), Some(value_column), Some(probe_time)) => {
+            (t2, time_column, value_column, probe_time)
+        }
+        _ => return pg_return_null(fcinfo),
+    };
+    let value_type = pg_sys::get_fn_expr_argtype((*fcinfo).flinfo, 4);
+    let by_columns = pg_getarg::<Vec<String>>(fcinfo, 5).unwrap_or_default();
+    let by_values = pg_getarg::<Vec<Option<String>>>(fcinfo, 6).unwrap_or_default();
+    if by_columns.len() != by_values.len() {
+        pgx::error!("last_known must have a value for each of its by_columns");
+    }
+
+    let relation = relation_oid(t2);
+    let time_type = column_type(relation, &time_column);
+    if ![pg_sys::TIMESTAMPOID, pg_sys::TIMESTAMPTZOID].contains(&time_type) {
+        pgx::error!(
+            "last_known time columns must be of type timestamp or timestamp with time zone"
+        );
+    }
+    let mut args = vec![(PgBuiltInOids::TIMESTAMPTZOID.oid(), Some(probe_time))];
+    let mut conditions = String::new();
+    for (column, value) in by_columns.iter().zip(by_values) {
+        let value = match value {
+            Some(value) => value,
+            // rows with NULL in a by column aren't part of any series
+            None => return pg_return_null(fcinfo),
+        };
+        let typoid = column_type(relation, column);
+        let type_name = CStr::from_ptr(pg_sys::format_type_be(typoid))
+            .to_str()
+            .unwrap();
+        args.push((PgBuiltInOids::TEXTOID.oid(), value.into_datum()));
+        conditions.push_str(&format!(
+            " AND t.{} = ${}::{}",
+            quote_ident(column),
+            args.len(),
+            type_name
+        ));
+    }
+    let query = format!(
+        "SELECT t.{1}::text FROM {2} t WHERE t.{0} <= $1{3} ORDER BY t.{0} DESC LIMIT 1",
+        quote_ident(&time_column),
+        quote_ident(&value_column),
+        relation_name(relation),
+        conditions
+    );
+    match Spi::get_one_with_args::<String>(&query, args) {
+        Some(value) => input_value(&value, value_type),
+        None => pg_return_null(fcinfo),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn pg_finfo_asof_stats_records() -> &'static pg_sys::Pg_finfo_record {
+    const V1_API: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1_API
+}
+
+// Joins every row of `t1` to the count, average, minimum, and maximum of the
+// values of the rows of `t2` after `width` before it, up to and including
+// its time. When `by_columns` are given, only rows with the same values in
+// those columns are counted.
+#[pg_guard]
+#[no_mangle]
+pub unsafe extern "C" fn asof_stats_records(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum {
+    let rsinfo = result_info(fcinfo, "asof_stats");
+    let args = (
+        pg_getarg::<regclass>(fcinfo, 0),
+        pg_getarg::<regclass>(fcinfo, 1),
+        pg_getarg::<String>(fcinfo, 2),
+        pg_getarg::<String>(fcinfo, 3),
+        pg_getarg_datum(fcinfo, 4),
+    );
+    let (t1, t2, time_column, value_column, width) = match args {
+        (Some(t1), Some(t2), Some(time_column), Some(value_column), Some(width)) => {
+            (t1, t2, time_column, value_column, width)
+        }
+        // like a strict function, there's no result for NULL arguments
+        _ => return no_result(rsinfo),
+    };
+    let by_columns = pg_getarg::<Vec<String>>(fcinfo, 5).unwrap_or_default();
+
+    let (t1, t2) = (
+        Source::Relation(relation_oid(t1)),
+        Source::Relation(relation_oid(t2)),
+    );
+    let left_types = t1.column_types();
+    let stats_types = [
+        pg_sys::INT8OID,
+        pg_sys::FLOAT8OID,
+        pg_sys::FLOAT8OID,
+        pg_sys::FLOAT8OID,
+    ];
+    let output_types: Vec<pg_sys::Oid> = left_types.iter().chain(&stats_types).copied().collect();
+    check_column_definitions(
+        (*rsinfo).expectedDesc,
+        &output_types,
+        "asof_stats",
+        "count, average, minimum, and maximum",
+    );
+    let time_type = time_type(&t1, &t2, &time_column);
+    let width_type = pg_sys::get_fn_expr_argtype((*fcinfo).flinfo, 4);
+    let width = match (time_type, width_type) {
+        (pg_sys::INT8OID, pg_sys::INT8OID) => width.value() as i64,
+        (pg_sys::INT8OID, _) => {
+            pgx::error!("asof_stats's width must be a bigint when the time column is a bigint")
+        }
+        (_, pg_sys::INTERVALOID) => interval_length(&*(width.cast_mut_ptr() as *const _)),
+        _ => pgx::error!("asof_stats's width must be an interval"),
+    };
+
+    let time_column = quote_ident(&time_column);
+    let value_column = quote_ident(&value_column);
+    let by_columns: Vec<String> = by_columns
+        .iter()
+        .map(|column| quote_ident(column))
+        .collect();
+    let left_key = partition_key("l", &by_columns);
+    let right_key = partition_key("r", &by_columns);
+    let (t1, t2) = (t1.from_item(), t2.from_item());
+
+    let (desc, store) = result_store(rsinfo);
+    let plans = Plans::of_function((*fcinfo).flinfo);
+    Spi::connect(|_client| {
+        let left = Cursor::open(
+            plans,
+            &format!(
+                "SELECT l.{}, {}, l.* FROM {} l ORDER BY 1",
+                time_column, left_key, t1
+            ),
+            &[],
+            &left_types,
+        );
+        let right = Cursor::open(
+            plans,
+            &format!(
+                "SELECT r.{}, {}, r.{}::double precision FROM {} r ORDER BY 1",
+                time_column, right_key, value_column, t2
+            ),
+            &[],
+            &[pg_sys::FLOAT8OID],
+        )
+        .map(|(key, time, row)| {
+            let value = row.datums[0]
+                .and_then(|value| f64::from_polymorphic_datum(value, false, pg_sys::FLOAT8OID));
+            (key, time, value)
+        });
+        merge_windows(left, right, width, |row, stats| {
+            let stats = match stats {
+                Some(stats) => [
+                    Some(pg_sys::Datum::from(stats.count)),
+                    stats.avg.into_datum(),
+                    stats.min.into_datum(),
+                    stats.max.into_datum(),
+                ],
+                None => [Some(pg_sys::Datum::from(0i64)), None, None, None],
+            };
+            let output: Vec<Option<pg_sys::Datum>> =
+                row.datums.iter().chain(&stats).copied().collect();
+            put_values(store, desc, &output);
+        });
+        Ok(Some(()))
+    });
+    return_result(rsinfo, desc, store)
+}
+
+#[no_mangle]
+pub extern "C" fn pg_finfo_asof_multi_records() -> &'static pg_sys::Pg_finfo_record {
+    const V1_API: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1_API
+}
+
+// Joins every row of `t1` to the value of the last row at or before it of
+// each of the tables of `t2`, reading `t1` once for all of them. When
+// `by_columns` are given, rows are only matched to rows with the same values
+// in those columns.
+#[pg_guard]
+#[no_mangle]
+pub unsafe extern "C" fn asof_multi_records(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum {
+    let rsinfo = result_info(fcinfo, "asof_multi");
+    let args = (
+        pg_getarg::<regclass>(fcinfo, 0),
+        pg_getarg::<Vec<regclass>>(fcinfo, 1),
+        pg_getarg::<String>(fcinfo, 2),
+        value_columns(fcinfo),
+    );
+    let (t1, t2, time_column, value_columns) = match args {
+        (Some(t1), Some(t2), Some(time_column), Some(value_columns)) => {
+            (t1, t2, time_column, value_columns)
+        }
+        // like a strict function, there's no result for NULL arguments
+        _ => return no_result(rsinfo),
+    };
+    let by_columns = pg_getarg::<Vec<String>>(fcinfo, 4).unwrap_or_default();
+
+    let value_columns = match value_columns.len() {
+        1 => vec![value_columns[0].clone(); t2.len()],
+        len if len == t2.len() => value_columns,
+        _ => pgx::error!("asof_multi must have a value column for each table of t2"),
+    };
+    let t1 = Source::Relation(relation_oid(t1));
+    let references: Vec<Source> = t2
+        .into_iter()
+        .map(|t2| Source::Relation(relation_oid(t2)))
+        .collect();
+    let left_types = t1.column_types();
+    let value_types: Vec<pg_sys::Oid> = references
+        .iter()
+        .zip(&value_columns)
+        .map(|(t2, column)| t2.column_type(column))
+        .collect();
+    let output_types: Vec<pg_sys::Oid> = left_types.iter().chain(&value_types).copied().collect();
+    check_column_definitions(
+        (*rsinfo).expectedDesc,
+        &output_types,
+        "asof_multi",
+        "a value column of each table of t2",
+    );
+    for t2 in &references {
+        time_type(&t1, t2, &time_column);
+    }
+
+    let time_column = quote_ident(&time_column);
+    let by_columns: Vec<String> = by_columns
+        .iter()
+        .map(|column| quote_ident(column))
+        .collect();
+    let left_key = partition_key("l", &by_columns);
+    let right_key = partition_key("r", &by_columns);
+
+    let (desc, store) = result_store(rsinfo);
+    let plans = Plans::of_function((*fcinfo).flinfo);
+    Spi::connect(|_client| {
+        let left = Cursor::open(
+            plans,
+            &format!(
+                "SELECT l.{}, {}, l.* FROM {} l ORDER BY 1{}",
+                time_column,
+                left_key,
+                t1.from_item(),
+                t1.tie_order("l", false)
+            ),
+            &[],
+            &left_types,
+        );
+        let rights: Vec<Cursor> = references
+            .iter()
+            .zip(&value_columns)
+            .zip(&value_types)
+            .map(|((t2, column), typoid)| {
+                let query = format!(
+                    "SELECT r.{}, {}, r.{} FROM {} r ORDER BY 1{}",
+                    time_column,
+                    right_key,
+                    quote_ident(column),
+                    t2.from_item(),
+                    t2.tie_order("r", false)
+                );
+                Cursor::open(plans, &query, &[], &[*typoid])
+            })
+            .collect();
+        merge_references(left, rights, |row, matched| {
+            let values = matched
+                .iter()
+                .map(|matched| matched.and_then(|(_, value)| value.datums[0]));
+            let output: Vec<Option<pg_sys::Datum>> =
+                row.datums.iter().copied().chain(values).collect();
+            put_values(store, desc, &output);
+        });
+        Ok(Some(()))
+    });
+    return_result(rsinfo, desc, store)
+}
+
+#[no_mangle]
+pub extern "C" fn pg_finfo_asof_topk_records() -> &'static pg_sys::Pg_finfo_record {
+    const V1_API: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1_API
+}
+
+// Joins every row of `t1` to the values of up to `k` of the last rows at or
+// before it of `t2`, as one output row for each, ranked from 1 for the most
+// recent. Rows without any match are returned once, with NULL values. When
+// `by_columns` are given, rows are only matched to rows with the same values
+// in those columns.
+#[pg_guard]
+#[no_mangle]
+pub unsafe extern "C" fn asof_topk_records(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum {
+    let rsinfo = result_info(fcinfo, "asof_topk");
+    let args = (
+        pg_getarg::<regclass>(fcinfo, 0),
+        pg_getarg::<regclass>(fcinfo, 1),
+        pg_getarg::<String>(fcinfo, 2),
+        pg_getarg::<String>(fcinfo, 3),
+        pg_getarg::<i32>(fcinfo, 4),
+    );
+    let (t1, t2, time_column, value_column, k) = match args {
+        (Some(t1), Some(t2), Some(time_column), Some(value_column), Some(k)) => {
+            (t1, t2, time_column, value_column, k)
+        }
+        // like a strict function, there's no result for NULL arguments
+        _ => return no_result(rsinfo),
+    };
+    let by_columns = pg_getarg::<Vec<String>>(fcinfo, 5).unwrap_or_default();
+    if k < 1 {
+        pgx::error!("asof_topk's k must be at least 1")
+    }
+
+    let (t1, t2) = (
+        Source::Relation(relation_oid(t1)),
+        Source::Relation(relation_oid(t2)),
+    );
+    let time_type = time_type(&t1, &t2, &time_column);
+    let staleness_type = if time_type == pg_sys::INT8OID {
+        pg_sys::INT8OID
+    } else {
+        pg_sys::INTERVALOID
+    };
+    let left_types = t1.column_types();
+    let value_type = t2.column_type(&value_column);
+    let output_types: Vec<pg_sys::Oid> = left_types
+        .iter()
+        .chain(&[value_type, pg_sys::INT8OID, staleness_type])
+        .copied()
+        .collect();
+    check_column_definitions(
+        (*rsinfo).expectedDesc,
+        &output_types,
+        "asof_topk",
+        "the value column, rank, and staleness",
+    );
+
+    let time_column = quote_ident(&time_column);
+    let by_columns: Vec<String> = by_columns
+        .iter()
+        .map(|column| quote_ident(column))
+        .collect();
+    let left_key = partition_key("l", &by_columns);
+    let right_key = partition_key("r", &by_columns);
+
+    let (desc, store) = result_store(rsinfo);
+    let plans = Plans::of_function((*fcinfo).flinfo);
+    Spi::connect(|_client| {
+        let left = Cursor::open(
+            plans,
+            &format!(
+                "SELECT l.{}, {}, l.* FROM {} l ORDER BY 1{}",
+                time_column,
+                left_key,
+                t1.from_item(),
+                t1.tie_order("l", false)
+            ),
+            &[],
+            &left_types,
+        );
+        let right = Cursor::open(
+            plans,
+            &format!(
+                "SELECT r.{}, {}, r.{} FROM {} r ORDER BY 1{}",
+                time_column,
+                right_key,
+                quote_ident(&value_column),
+                t2.from_item(),
+                t2.tie_order("r", false)
+            ),
+            &[],
+            &[value_type],
+        );
+        // the time of each row of t1 goes along with it, for its staleness
+        let left = left.map(|(key, time, row)| (key, time, (time, row)));
+        merge_recent(left, right, k as usize, |(time, row), matches| {
+            if matches.is_empty() {
+                let output: Vec<Option<pg_sys::Datum>> = row
+                    .datums
+                    .iter()
+                    .copied()
+                    .chain([None, None, None])
+                    .collect();
+                put_values(store, desc, &output);
+                return;
+            }
+            let time = time.unwrap();
+            for (rank, (matched_time, value)) in matches.into_iter().enumerate() {
+                let staleness = time - matched_time;
+                let staleness = if staleness_type == pg_sys::INT8OID {
+                    pg_sys::Datum::from(staleness)
+                } else {
+                    ms_to_interval(staleness).0
+                };
+                let output: Vec<Option<pg_sys::Datum>> = row
+                    .datums
+                    .iter()
+                    .copied()
+                    .chain([
+                        value.datums[0],
+                        Some(pg_sys::Datum::from(rank as i64 + 1)),
+                        Some(staleness),
+                    ])
+                    .collect();
+                put_values(store, desc, &output);
+            }
+        });
+        Ok(Some(()))
+    });
+    return_result(rsinfo, desc, store)
+}
+
+// Checks the rows of a source asserted to be sorted as they're read, when
+// `presorted` is true, erroring unless their times are in ascending order,
+// with NULL times last.
+fn check_sorted<T>(presorted: bool, source: &'static str) -> impl FnMut(&Keyed<T>) {
+    let mut last: Option<(bool, Option<i64>)> = None;
+    move |(_, time, _)| {
+        if !presorted {
+            return;
+        }
+        let time = (time.is_none(), *time);
+        if matches!(last, Some(last) if last > time) {
+            pgx::error!("asof's {} isn't in ascending time order", source);
+        }
+        last = Some(time);
+    }
+}
+
+// A condition on the columns of `source`, for the parameter `name`. It's
+// spliced into the queries reading `source`, so it's parsed first to check
+// that it's a single expression, which can't reach outside of its
+// parentheses, and then checked to be boolean when read with the given alias.
+fn where_condition(source: &Source, alias: &str, condition: &str, name: &str) -> String {
+    // the newline ends any trailing comment before the closing parenthesis
+    let condition = format!("({}\n)", condition);
+    let statement = CString::new(format!("SELECT {}", condition)).unwrap();
+    unsafe {
+        let parsed = PgList::<pg_sys::RawStmt>::from_pg(pg_sys::pg_parse_query(statement.as_ptr()));
+        let select = match parsed.get_ptr(0) {
+            Some(raw) if parsed.len() == 1 && is_a((*raw).stmt, pg_sys::NodeTag_T_SelectStmt) => {
+                (*raw).stmt as *mut pg_sys::SelectStmt
             }
-            results2.push((res.0,res.1));
-        }else{
-            curr_val = res.1;
+            _ => pgx::error!("asof's {} must be a single expression", name),
+        };
+        let targets = PgList::<pg_sys::ResTarget>::from_pg((*select).targetList);
+        let single_expression = targets.len() == 1
+            && targets
+                .get_ptr(0)
+                .map_or(false, |target| (*target).name.is_null())
+            && (*select).op == pg_sys::SetOperation_SETOP_NONE
+            && [
+                (*select).distinctClause,
+                (*select).fromClause,
+                (*select).groupClause,
+                (*select).windowClause,
+                (*select).valuesLists,
+                (*select).sortClause,
+                (*select).lockingClause,
+            ]
+            .iter()
+            .all(|list| list.is_null())
+            && [
+                (*select).intoClause as *mut pg_sys::Node,
+                (*select).whereClause,
+                (*select).havingClause,
+                (*select).limitOffset,
+                (*select).limitCount,
+                (*select).withClause as *mut pg_sys::Node,
+            ]
+            .iter()
+            .all(|node| node.is_null());
+        if !single_expression {
+            pgx::error!("asof's {} must be a single expression", name);
+        }
+    }
+    let query = format!("SELECT {} FROM {} {}", condition, source.from_item(), alias);
+    let columns = describe_query(&query);
+    if columns.len() != 1 || columns[0].1 != pg_sys::BOOLOID {
+        pgx::error!("asof's {} must be a boolean expression", name);
+    }
+    condition
+}
+
+// A match of a row at `time`, unless it's in another bucket than the row, or
+// further from it than its tolerance, along with whether it's the latter.
+fn checked_match(
+    time: Option<i64>,
+    matched: Option<(i64, &[Option<pg_sys::Datum>])>,
+    tolerance: Option<i64>,
+    bucket_width: Option<i64>,
+) -> (Option<(i64, &[Option<pg_sys::Datum>])>, bool) {
+    let (time, (at, _)) = match time.zip(matched) {
+        Some(pair) => pair,
+        None => return (None, false),
+    };
+    // matches in another bucket than the row's aren't matches at all
+    if matches!(bucket_width, Some(width) if bucket(time, width) != bucket(at, width)) {
+        return (None, false);
+    }
+    // matches further from the row than its tolerance don't count
+    if matches!(tolerance, Some(tolerance) if (at - time).abs() > tolerance) {
+        return (None, true);
+    }
+    (matched, false)
+}
+
+// The bucket a time is in, numbered from the one starting at `time_bucket`'s
+// default origin, Monday 2000-01-03, which is two days after the time that's
+// stored as 0.
+fn bucket(time: i64, width: i64) -> i64 {
+    const ORIGIN: i64 = 2 * 24 * 60 * 60 * 1_000_000;
+    (time - ORIGIN).div_euclid(width)
+}
+
+// The length of an interval in microseconds, with months taken to be 30 days,
+// as when extracting an interval's epoch.
+fn interval_length(interval: &pg_sys::Interval) -> i64 {
+    interval.time + (interval.day as i64 + interval.month as i64 * 30) * 24 * 60 * 60 * 1_000_000
+}
+
+// The time and values of the match spilled along with a row of `left_len`
+// columns.
+fn spilled_match(row: &Row, left_len: usize) -> Option<(i64, &[Option<pg_sys::Datum>])> {
+    let time = row.datums[2 + left_len].map(|time| time.value() as i64);
+    time.map(|time| (time, &row.datums[3 + left_len..]))
+}
+
+// A WHERE clause restricting `table`'s rows to those meeting the given
+// conditions and with times compared to each of the given bounds by its
+// operator, skipping those not given, and the parameters it refers to.
+fn time_bounds(
+    table: &str,
+    time_column: &str,
+    bounds: &[(&str, pg_sys::Oid, Option<pg_sys::Datum>)],
+    mut conditions: Vec<String>,
+) -> (String, Vec<(pg_sys::Oid, pg_sys::Datum)>) {
+    let mut args = vec![];
+    for (operator, typoid, bound) in bounds {
+        if let Some(bound) = bound {
+            args.push((*typoid, *bound));
+            conditions.push(format!(
+                "{}.{} {} ${}",
+                table,
+                time_column,
+                operator,
+                args.len()
+            ));
         }
+    }
+    if conditions.is_empty() {
+        return (String::new(), args);
+    }
+    (format!(" WHERE {}", conditions.join(" AND ")), args)
+}
+
+// A query with the parameters it refers to written into it as literals, so
+// that it can be run on its own.
+unsafe fn inline_args(query: &str, args: &[(pg_sys::Oid, pg_sys::Datum)]) -> String {
+    let mut query = query.to_owned();
+    // from the last one, so that $1 isn't replaced within $10
+    for (n, (typoid, arg)) in args.iter().enumerate().rev() {
+        let (mut output, mut is_varlena) = (0, false);
+        pg_sys::getTypeOutputInfo(*typoid, &mut output, &mut is_varlena);
+        let value = pg_sys::OidOutputFunctionCall(output, *arg);
+        let literal = CStr::from_ptr(pg_sys::quote_literal_cstr(value))
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let type_name = CStr::from_ptr(pg_sys::format_type_be(*typoid))
+            .to_str()
+            .unwrap()
+            .to_owned();
+        query = query.replace(
+            &format!("${}", n + 1),
+            &format!("{}::{}", literal, type_name),
+        );
+    }
+    query
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Backward,
+    Forward,
+    Nearest,
+    // both the matches before and after the row
+    Both,
+}
+
+#[track_caller]
+pub fn direction_kind(direction: &str) -> Direction {
+    match direction.trim().to_lowercase().as_str() {
+        "backward" | "prior" => Direction::Backward,
+        "forward" | "next" => Direction::Forward,
+        "nearest" => Direction::Nearest,
+        "both" => Direction::Both,
+        _ => pgx::error!(
+            "unknown asof direction. Valid directions are 'backward' (or 'prior'), 'forward' (or 'next'), 'nearest', and 'both'"
+        ),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TieBreak {
+    First,
+    Last,
+    Error,
+}
+
+#[track_caller]
+pub fn tie_break_kind(tie_break: &str) -> TieBreak {
+    match tie_break.trim().to_lowercase().as_str() {
+        "first" => TieBreak::First,
+        "last" => TieBreak::Last,
+        "error" => TieBreak::Error,
+        _ => {
+            pgx::error!("unknown asof tie_break. Valid tie_breaks are 'first', 'last', and 'error'")
+        }
+    }
+}
 
+// Whether rows are returned in descending order.
+#[track_caller]
+pub fn ordering_kind(ordering: &str) -> bool {
+    match ordering.trim().to_lowercase().as_str() {
+        "asc" => false,
+        "desc" => true,
+        _ => pgx::error!("unknown asof ordering. Valid orderings are 'asc' and 'desc'"),
     }
+}
 
-    TableIterator::new(results2.into_iter())
-}
\ No newline at end of file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NullValues {
+    Keep,
+    Skip,
+    Error,
+}
+
+#[track_caller]
+pub fn null_values_kind(null_values: &str) -> NullValues {
+    match null_values.trim().to_lowercase().as_str() {
+        "keep" => NullValues::Keep,
+        "skip" => NullValues::Skip,
+        "error" => NullValues::Error,
+        _ => pgx::error!(
+            "unknown asof null_values. Valid null_values are 'keep', 'skip', and 'error'"
+        ),
+    }
+}
+
+// One side of the join: a table, or a query read as if it were one.
+enum Source {
+    Relation(pg_sys::Oid),
+    // the query's text and the names and types of its columns
+    Query(String, Vec<(String, pg_sys::Oid)>),
+}
+
+impl Source {
+    fn query(query: &str) -> Self {
+        Source::Query(query.to_owned(), describe_query(query))
+    }
+
+    // Additional ORDER BY terms putting rows at the same time in physical
+    // order, or the reverse. Queries' rows have no physical order to use.
+    fn tie_order(&self, alias: &str, reverse: bool) -> String {
+        match self {
+            Source::Relation(_) if reverse => format!(", {}.ctid DESC", alias),
+            Source::Relation(_) => format!(", {}.ctid", alias),
+            Source::Query(..) => String::new(),
+        }
+    }
+
+    // The source as it's written in a FROM clause.
+    fn from_item(&self) -> String {
+        match self {
+            Source::Relation(relation) => relation_name(*relation),
+            Source::Query(query, _) => format!("({})", query),
+        }
+    }
+
+    fn column_types(&self) -> Vec<pg_sys::Oid> {
+        match self {
+            Source::Relation(relation) => column_types(*relation),
+            Source::Query(_, columns) => columns.iter().map(|(_, typoid)| *typoid).collect(),
+        }
+    }
+
+    fn column_type(&self, column: &str) -> pg_sys::Oid {
+        match self {
+            Source::Relation(relation) => column_type(*relation, column),
+            Source::Query(_, columns) => match columns.iter().find(|(name, _)| name == column) {
+                Some((_, typoid)) => *typoid,
+                None => pgx::error!("column \"{}\" of asof query does not exist", column),
+            },
+        }
+    }
+}
+
+// The names and types of the columns a query returns. Running it as a
+// subquery also checks that it's a single query that can be used as one.
+fn describe_query(query: &str) -> Vec<(String, pg_sys::Oid)> {
+    let query = CString::new(format!("SELECT * FROM ({}) q LIMIT 0", query)).unwrap();
+    let mut columns = vec![];
+    Spi::connect(|_client| {
+        unsafe {
+            if pg_sys::SPI_execute(query.as_ptr(), true, 0) != pg_sys::SPI_OK_SELECT as i32 {
+                pgx::error!("asof queries must be SELECT queries");
+            }
+            let desc = (*pg_sys::SPI_tuptable).tupdesc;
+            columns = (*desc)
+                .attrs
+                .as_slice((*desc).natts as usize)
+                .iter()
+                .map(|attribute| {
+                    (
+                        CStr::from_ptr(attribute.attname.data.as_ptr())
+                            .to_string_lossy()
+                            .into_owned(),
+                        attribute.atttypid,
+                    )
+                })
+                .collect();
+        }
+        Ok(Some(()))
+    });
+    columns
+}
+
+fn relation_oid(relation: regclass) -> pg_sys::Oid {
+    relation.0.value() as pg_sys::Oid
+}
+
+// The schema-qualified name of a relation, quoted for use in a query.
+fn relation_name(relation: pg_sys::Oid) -> String {
+    unsafe {
+        let name = pg_sys::get_rel_name(relation);
+        if name.is_null() {
+            pgx::error!("relation with OID {} does not exist", relation);
+        }
+        let namespace = pg_sys::get_namespace_name(pg_sys::get_rel_namespace(relation));
+        CStr::from_ptr(pg_sys::quote_qualified_identifier(namespace, name))
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+}
+
+// The types of the columns `SELECT *` returns for a relation.
+fn column_types(relation: pg_sys::Oid) -> Vec<pg_sys::Oid> {
+    unsafe {
+        let desc = pg_sys::lookup_rowtype_tupdesc_copy(pg_sys::get_rel_type_id(relation), -1);
+        (*desc)
+            .attrs
+            .as_slice((*desc).natts as usize)
+            .iter()
+            .filter(|attribute| !attribute.attisdropped)
+            .map(|attribute| attribute.atttypid)
+            .collect()
+    }
+}
+
+fn column_type(relation: pg_sys::Oid, column: &str) -> pg_sys::Oid {
+    let name = CString::new(column).unwrap();
+    unsafe {
+        let number = pg_sys::get_attnum(relation, name.as_ptr());
+        if number == pg_sys::InvalidAttrNumber as pg_sys::AttrNumber {
+            pgx::error!(
+                "column \"{}\" of relation {} does not exist",
+                column,
+                relation_name(relation)
+            );
+        }
+        pg_sys::get_atttype(relation, number)
+    }
+}
+
+// The type of both tables' time column. All three of bigint, timestamp, and
+// timestamptz are stored as 64-bit integers that sort in time order, so rows
+// are compared by those integers whichever it is, but both must agree.
+fn time_type(t1: &Source, t2: &Source, time_column: &str) -> pg_sys::Oid {
+    let types = (t1.column_type(time_column), t2.column_type(time_column));
+    for typoid in [types.0, types.1] {
+        if ![
+            pg_sys::INT8OID,
+            pg_sys::TIMESTAMPOID,
+            pg_sys::TIMESTAMPTZOID,
+        ]
+        .contains(&typoid)
+        {
+            pgx::error!(
+                "asof time columns must be of type bigint, timestamp, or timestamp with time zone"
+            );
+        }
+    }
+    if types.0 != types.1 {
+        pgx::error!("asof time columns of both tables must have the same type");
+    }
+    types.0
+}
+
+// The value columns, given either as a single column or an array of them.
+unsafe fn value_columns(fcinfo: pg_sys::FunctionCallInfo) -> Option<Vec<String>> {
+    if pg_sys::get_fn_expr_argtype((*fcinfo).flinfo, 3) == pg_sys::TEXTARRAYOID {
+        pg_getarg::<Vec<String>>(fcinfo, 3)
+    } else {
+        pg_getarg::<String>(fcinfo, 3).map(|column| vec![column])
+    }
+}
+
+// The values to give rows without a match, given as text, either a single
+// value or an array of one per value column, and converted to those columns'
+// types.
+unsafe fn fill_values(
+    fill: Option<Vec<Option<String>>>,
+    value_types: &[pg_sys::Oid],
+) -> Vec<Option<pg_sys::Datum>> {
+    let fill = match fill {
+        Some(fill) => fill,
+        None => return vec![None; value_types.len()],
+    };
+    if fill.len() != value_types.len() {
+        pgx::error!("asof's fill must have a value for each value column");
+    }
+    fill.iter()
+        .zip(value_types)
+        .map(|(value, typoid)| value.as_ref().map(|value| input_value(value, *typoid)))
+        .collect()
+}
+
+// Converts the text of a value to the given type.
+unsafe fn input_value(value: &str, typoid: pg_sys::Oid) -> pg_sys::Datum {
+    let (mut input, mut io_param) = (0, 0);
+    pg_sys::getTypeInputInfo(typoid, &mut input, &mut io_param);
+    let value = CString::new(value).unwrap();
+    pg_sys::OidInputFunctionCall(input, value.as_ptr() as *mut _, io_param, -1)
+}
+
+// Errors unless a column definition list has the given types.
+fn check_column_definitions(
+    desc: pg_sys::TupleDesc,
+    types: &[pg_sys::Oid],
+    function: &str,
+    followed_by: &str,
+) {
+    let defined: Vec<pg_sys::Oid> = unsafe {
+        (*desc)
+            .attrs
+            .as_slice((*desc).natts as usize)
+            .iter()
+            .map(|attribute| attribute.atttypid)
+            .collect()
+    };
+    if defined != types {
+        let type_names: Vec<String> = types
+            .iter()
+            .map(|typoid| unsafe {
+                CStr::from_ptr(pg_sys::format_type_be(*typoid))
+                    .to_str()
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect();
+        pgx::error!(
+            "{}'s column definition list must have the types of the columns of t1 followed by {}: {}",
+            function,
+            followed_by,
+            type_names.join(", ")
+        );
+    }
+}
+
+fn quote_ident(identifier: &str) -> String {
+    let identifier = CString::new(identifier).unwrap();
+    unsafe {
+        CStr::from_ptr(pg_sys::quote_identifier(identifier.as_ptr()))
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+}
+
+// SQL for the key identifying which series a row of `table` belongs to, given
+// already quoted columns. Rows with NULL in any of the columns get a NULL key
+// and aren't part of any.
+fn partition_key(table: &str, by_columns: &[String]) -> String {
+    if by_columns.is_empty() {
+        return "''".to_owned();
+    }
+    let columns: Vec<String> = by_columns
+        .iter()
+        .map(|column| format!("{}.{}", table, column))
+        .collect();
+    let columns = columns.join(", ");
+    format!(
+        "CASE WHEN num_nulls({columns}) = 0 THEN ROW({columns})::text END",
+        columns = columns
+    )
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_asof_full_rows() {
+        Spi::execute(|client| {
+            client.select("SET TIMEZONE to UTC", None, None);
+            client.select(
+                "CREATE TABLE trades(time TIMESTAMPTZ, symbol TEXT, qty INT)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES
+                    ('2020-01-01 00:00:30', 'AAA', 10),
+                    ('2020-01-01 00:01:00', 'BBB', 20),
+                    ('2020-01-01 00:00:00', 'CCC', 30)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES ('2020-01-01 00:00:10', 1.5), ('2020-01-01 00:01:00', 2.5)",
+                None,
+                None,
+            );
+
+            let joined = client
+                .select(
+                    "SELECT string_agg(format('%s %s %s %s', time::time, symbol, qty, price), ', ' ORDER BY time)
+                    FROM toolkit_experimental.asof('trades', 'quotes', 'time', 'price')
+                        AS (time TIMESTAMPTZ, symbol TEXT, qty INT, price DOUBLE PRECISION)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(
+                joined,
+                "00:00:00 CCC 30 , 00:00:30 AAA 10 1.5, 00:01:00 BBB 20 2.5"
+            );
+
+            let joined = client
+                .select(
+                    "SELECT string_agg(format('%s %s', symbol, price), ', ' ORDER BY time)
+                    FROM toolkit_experimental.asof('trades', 'quotes', 'time', 'price', direction => 'forward')
+                        AS (time TIMESTAMPTZ, symbol TEXT, qty INT, price DOUBLE PRECISION)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(joined, "CCC 1.5, AAA 2.5, BBB 2.5");
+
+            let joined = client
+                .select(
+                    "SELECT string_agg(format('%s %s', symbol, price), ', ' ORDER BY time)
+                    FROM toolkit_experimental.asof('trades', 'quotes', 'time', 'price', direction => 'nearest')
+                        AS (time TIMESTAMPTZ, symbol TEXT, qty INT, price DOUBLE PRECISION)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(joined, "CCC 1.5, AAA 1.5, BBB 2.5");
+
+            let joined = client
+                .select(
+                    "SELECT string_agg(format('%s %s', symbol, price), ', ' ORDER BY time)
+                    FROM toolkit_experimental.asof('trades', 'quotes', 'time', 'price', inner => true)
+                        AS (time TIMESTAMPTZ, symbol TEXT, qty INT, price DOUBLE PRECISION)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(joined, "AAA 1.5, BBB 2.5");
+
+            // the quote before the range isn't read, so AAA has no match
+            let joined = client
+                .select(
+                    "SELECT string_agg(format('%s %s', symbol, price), ', ' ORDER BY time)
+                    FROM toolkit_experimental.asof('trades', 'quotes', 'time', 'price',
+                        range_start => '2020-01-01 00:00:20', range_end => '2020-01-01 00:01:01')
+                        AS (time TIMESTAMPTZ, symbol TEXT, qty INT, price DOUBLE PRECISION)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(joined, "AAA , BBB 2.5");
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_by_columns() {
+        Spi::execute(|client| {
+            client.select("SET TIMEZONE to UTC", None, None);
+            client.select(
+                "CREATE TABLE trades(time TIMESTAMPTZ, symbol TEXT)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, symbol TEXT, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES
+                    ('2020-01-01 00:01:00', 'AAA'),
+                    ('2020-01-01 00:01:00', 'BBB'),
+                    ('2020-01-01 00:01:00', 'CCC'),
+                    ('2020-01-01 00:01:00', NULL)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES
+                    ('2020-01-01 00:00:00', 'AAA', 1.5),
+                    ('2020-01-01 00:00:10', 'BBB', 2.5),
+                    ('2020-01-01 00:00:20', 'AAA', 3.5),
+                    ('2020-01-01 00:00:30', NULL, 4.5)",
+                None,
+                None,
+            );
+
+            let joined = client
+                .select(
+                    "SELECT string_agg(format('%s %s', coalesce(symbol, 'none'), price), ', ' ORDER BY symbol)
+                    FROM toolkit_experimental.asof('trades', 'quotes', 'time', 'price', by_columns => '{symbol}')
+                        AS (time TIMESTAMPTZ, symbol TEXT, price DOUBLE PRECISION)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(joined, "AAA 3.5, BBB 2.5, CCC , none ");
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_quotes_names() {
+        Spi::execute(|client| {
+            client.select("CREATE SCHEMA \"Market Data\"", None, None);
+            client.select(
+                "CREATE TABLE \"Market Data\".\"Trades\"(\"Time\" TIMESTAMPTZ, \"Symbol\" TEXT)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE \"Market Data\".\"Quotes\"(\"Time\" TIMESTAMPTZ, \"Symbol\" TEXT, \"Price; DROP TABLE x\" DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO \"Market Data\".\"Trades\" VALUES ('2020-01-01 00:01:00', 'AAA')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO \"Market Data\".\"Quotes\" VALUES ('2020-01-01 00:00:00', 'AAA', 1.5)",
+                None,
+                None,
+            );
+
+            let value = client
+                .select(
+                    "SELECT price FROM toolkit_experimental.asof(
+                        '\"Market Data\".\"Trades\"', '\"Market Data\".\"Quotes\"', 'Time', 'Price; DROP TABLE x',
+                        by_columns => '{Symbol}'
+                    ) AS (time TIMESTAMPTZ, symbol TEXT, price DOUBLE PRECISION)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<f64>();
+            assert_eq!(value, Some(1.5));
+
+            let value = client
+                .select(
+                    "SELECT value FROM asof('\"Market Data\".\"Trades\"', '\"Market Data\".\"Quotes\"', 'Time', 'Price; DROP TABLE x')",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<f64>();
+            assert_eq!(value, Some(1.5));
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_batches() {
+        Spi::execute(|client| {
+            client.select("SET TIMEZONE to UTC", None, None);
+            client.select(
+                "CREATE TABLE trades AS SELECT '2020-01-01'::timestamptz + i * interval '1 minute' AS time \
+                FROM generate_series(1, 2500) i",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE quotes AS SELECT '2020-01-01'::timestamptz + i * interval '2 minutes' AS time, \
+                CASE WHEN i % 100 = 0 THEN NULL ELSE i END::double precision AS value \
+                FROM generate_series(1, 1250) i",
+                None,
+                None,
+            );
+
+            // each trade after the first gets the last quote at or before
+            // it, passing over the NULL ones, across several cursor batches
+            let (count, sum) = client
+                .select(
+                    "SELECT count(value), sum(value)::bigint FROM asof('trades', 'quotes', 'time', 'value')",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<i64, i64>();
+            let expected: i64 = (2..=2500)
+                .map(|minute| match minute / 2 {
+                    i if i % 100 == 0 => i - 1,
+                    i => i,
+                })
+                .sum();
+            assert_eq!(count, Some(2499));
+            assert_eq!(sum, Some(expected));
+
+            let (first, unordered) = client
+                .select(
+                    "SELECT min(time) FILTER (WHERE value IS NULL) = '2020-01-01 00:01:00', \
+                    count(*) FILTER (WHERE time < prev) \
+                    FROM (SELECT *, lag(time) OVER () AS prev FROM asof('trades', 'quotes', 'time', 'value')) a",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<bool, i64>();
+            assert_eq!(first, Some(true));
+            assert_eq!(unordered, Some(0));
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_join() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE trades(time TIMESTAMPTZ)", None, None);
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES ('2020-01-01 00:00:00'), ('2020-01-01 00:00:30'), ('2020-01-01 00:01:00'), (NULL)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES ('2020-01-01 00:00:10', 1.5), ('2020-01-01 00:00:50', 2.5)",
+                None,
+                None,
+            );
+
+            for (direction, tolerance, expected) in [
+                ("prior", "NULL", ", 1.5, 2.5, "),
+                ("next", "NULL", "1.5, 2.5, , "),
+                ("backward", "NULL", ", 1.5, 2.5, "),
+                ("forward", "NULL", "1.5, 2.5, , "),
+                ("nearest", "NULL", "1.5, 1.5, 2.5, "),
+                ("prior", "'15 seconds'", ", , 2.5, "),
+                ("nearest", "'15 seconds'", "1.5, , 2.5, "),
+            ] {
+                let joined = client
+                    .select(
+                        &format!(
+                            "SELECT string_agg(format('%s', value), ', ')
+                            FROM toolkit_experimental.asof_join(
+                                left => 'trades', right => 'quotes', on_time => 'time', value => 'price',
+                                direction => '{}', tolerance => {}
+                            )",
+                            direction, tolerance
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<String>()
+                    .unwrap();
+                assert_eq!(joined, expected, "{} {}", direction, tolerance);
+            }
+        });
+    }
+
+    #[pg_test(error = "asof_join time columns of both tables must have the same type")]
+    fn test_asof_join_time_types() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE trades(time TIMESTAMPTZ)", None, None);
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMP, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "SELECT * FROM toolkit_experimental.asof_join('trades', 'quotes', 'time', 'price')",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_value_types() {
+        Spi::execute(|client| {
+            client.select("SET TIMEZONE to UTC", None, None);
+            client.select("CREATE TABLE trades(time TIMESTAMPTZ)", None, None);
+            client.select(
+                "CREATE TABLE statuses(time TIMESTAMPTZ, status TEXT, price NUMERIC, info JSONB)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES ('2020-01-01 00:00:00'), ('2020-01-01 00:01:00')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO statuses VALUES ('2020-01-01 00:00:30', 'open', 1.50, '{\"venue\": \"x\"}')",
+                None,
+                None,
+            );
+
+            let joined = client
+                .select(
+                    "SELECT string_agg(format('%s %s', time::time, status), ', ' ORDER BY time)
+                    FROM toolkit_experimental.asof('trades', 'statuses', 'time', 'status')
+                        AS (time TIMESTAMPTZ, status TEXT)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(joined, "00:00:00 , 00:01:00 open");
+
+            let price = client
+                .select(
+                    "SELECT price::text FROM toolkit_experimental.asof('trades', 'statuses', 'time', 'price')
+                        AS (time TIMESTAMPTZ, price NUMERIC)
+                    WHERE price IS NOT NULL",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(price.as_deref(), Some("1.50"));
+
+            let venue = client
+                .select(
+                    "SELECT info->>'venue' FROM toolkit_experimental.asof('trades', 'statuses', 'time', 'info')
+                        AS (time TIMESTAMPTZ, info JSONB)
+                    WHERE info IS NOT NULL",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(venue.as_deref(), Some("x"));
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_multiple_values() {
+        Spi::execute(|client| {
+            client.select("SET TIMEZONE to UTC", None, None);
+            client.select("CREATE TABLE trades(time TIMESTAMPTZ)", None, None);
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, bid DOUBLE PRECISION, ask DOUBLE PRECISION, venue TEXT)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES ('2020-01-01 00:00:00'), ('2020-01-01 00:01:00')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES ('2020-01-01 00:00:30', 1.5, 1.75, 'x')",
+                None,
+                None,
+            );
+
+            let joined = client
+                .select(
+                    "SELECT string_agg(format('%s %s %s %s', time::time, bid, ask, venue), ', ' ORDER BY time)
+                    FROM toolkit_experimental.asof('trades', 'quotes', 'time', ARRAY['bid', 'ask', 'venue'])
+                        AS (time TIMESTAMPTZ, bid DOUBLE PRECISION, ask DOUBLE PRECISION, venue TEXT)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(joined, "00:00:00   , 00:01:00 1.5 1.75 x");
+        });
+    }
+
+    #[pg_test(
+        error = "asof's column definition list must have the types of the columns of t1 followed by the value columns: timestamp with time zone, double precision"
+    )]
+    fn test_asof_checks_column_definitions() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE trades(time TIMESTAMPTZ)", None, None);
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "SELECT * FROM toolkit_experimental.asof('trades', 'quotes', 'time', 'price')
+                    AS (time TIMESTAMPTZ, price NUMERIC)",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_time_types() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE trades(time BIGINT, stamp TIMESTAMP, symbol TEXT)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE quotes(time BIGINT, stamp TIMESTAMP, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES
+                    (30000000000, '2020-01-01 00:00:30', 'AAA'),
+                    (60000000000, '2020-01-01 00:01:00', 'BBB'),
+                    (0, '2020-01-01 00:00:00', 'CCC')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES
+                    (10000000000, '2020-01-01 00:00:10', 1.5),
+                    (60000000000, '2020-01-01 00:01:00', 2.5)",
+                None,
+                None,
+            );
+
+            for time_column in ["time", "stamp"] {
+                let joined = client
+                    .select(
+                        &format!(
+                            "SELECT string_agg(format('%s %s', symbol, price), ', ' ORDER BY time)
+                            FROM toolkit_experimental.asof('trades', 'quotes', '{}', 'price', direction => 'nearest')
+                                AS (time BIGINT, stamp TIMESTAMP, symbol TEXT, price DOUBLE PRECISION)",
+                            time_column
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<String>()
+                    .unwrap();
+                assert_eq!(joined, "CCC 1.5, AAA 1.5, BBB 2.5");
+            }
+        });
+    }
+
+    #[pg_test(error = "asof time columns of both tables must have the same type")]
+    fn test_asof_checks_time_types() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE trades(time TIMESTAMP)", None, None);
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "SELECT * FROM toolkit_experimental.asof('trades', 'quotes', 'time', 'price')
+                    AS (time TIMESTAMP, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_query() {
+        Spi::execute(|client| {
+            client.select("SET TIMEZONE to UTC", None, None);
+            client.select(
+                "CREATE TABLE trades(time TIMESTAMPTZ, symbol TEXT, qty INT)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, symbol TEXT, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES
+                    ('2020-01-01 00:00:30', 'AAA', 10),
+                    ('2020-01-01 00:01:00', 'BBB', 20),
+                    ('2020-01-01 00:01:30', 'AAA', 30)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES
+                    ('2020-01-01 00:00:10', 'AAA', 1.5),
+                    ('2020-01-01 00:00:20', 'BBB', 2.5),
+                    ('2020-01-01 00:01:00', 'AAA', 3.5)",
+                None,
+                None,
+            );
+
+            let joined = client
+                .select(
+                    "SELECT string_agg(format('%s %s %s', time::time, total, price), ', ' ORDER BY time)
+                    FROM toolkit_experimental.asof_query(
+                        $$SELECT time, sum(qty) OVER (ORDER BY time) AS total FROM trades WHERE symbol = 'AAA'$$,
+                        $$SELECT time, price * 2 AS price FROM quotes WHERE symbol = 'AAA'$$,
+                        'time', 'price'
+                    ) AS (time TIMESTAMPTZ, total BIGINT, price DOUBLE PRECISION)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(joined, "00:00:30 10 3, 00:01:30 40 7");
+        });
+    }
+
+    #[pg_test(error = "syntax error at or near \";\"")]
+    fn test_asof_query_checks_query() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "SELECT * FROM toolkit_experimental.asof_query(
+                    'SELECT * FROM quotes; DROP TABLE quotes', 'SELECT * FROM quotes', 'time', 'price'
+                ) AS (time TIMESTAMPTZ, price DOUBLE PRECISION, price2 DOUBLE PRECISION)",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_tie_break() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE trades(time TIMESTAMPTZ)", None, None);
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES ('2020-01-01 00:00:30')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES
+                    ('2020-01-01 00:00:10', 1.5),
+                    ('2020-01-01 00:00:10', 2.5),
+                    ('2020-01-01 00:00:50', 3.5),
+                    ('2020-01-01 00:00:50', 4.5)",
+                None,
+                None,
+            );
+
+            for (direction, tie_break, expected) in [
+                ("backward", "last", 2.5),
+                ("backward", "first", 1.5),
+                ("forward", "last", 4.5),
+                ("forward", "first", 3.5),
+            ] {
+                let price = client
+                    .select(
+                        &format!(
+                            "SELECT price FROM toolkit_experimental.asof('trades', 'quotes', 'time', 'price',
+                                direction => '{}', tie_break => '{}')
+                                AS (time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                            direction, tie_break
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<f64>();
+                assert_eq!(price, Some(expected), "{} {}", direction, tie_break);
+            }
+        });
+    }
+
+    #[pg_test(error = "asof found several rows of a series of t2 at the same time")]
+    fn test_asof_tie_break_error() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE trades(time TIMESTAMPTZ)", None, None);
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES ('2020-01-01 00:00:30')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES ('2020-01-01 00:00:10', 1.5), ('2020-01-01 00:00:10', 2.5)",
+                None,
+                None,
+            );
+            client.select(
+                "SELECT * FROM toolkit_experimental.asof('trades', 'quotes', 'time', 'price', tie_break => 'error')
+                    AS (time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_fill() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE trades(time TIMESTAMPTZ, symbol TEXT)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE spreads(time TIMESTAMPTZ, bid NUMERIC, ask NUMERIC)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES ('2020-01-01 00:00:00', 'AAA'), ('2020-01-01 00:01:00', 'BBB')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO spreads VALUES ('2020-01-01 00:00:30', 1.5, 1.75)",
+                None,
+                None,
+            );
+
+            let joined = client
+                .select(
+                    "SELECT string_agg(format('%s %s', symbol, bid), ', ' ORDER BY time)
+                    FROM toolkit_experimental.asof('trades', 'spreads', 'time', 'bid', fill => '0')
+                        AS (time TIMESTAMPTZ, symbol TEXT, bid NUMERIC)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(joined, "AAA 0, BBB 1.5");
+
+            let joined = client
+                .select(
+                    "SELECT string_agg(format('%s %s %s', symbol, bid, coalesce(ask, -1)), ', ' ORDER BY time)
+                    FROM toolkit_experimental.asof('trades', 'spreads', 'time', ARRAY['bid', 'ask'],
+                        fill => ARRAY['0.5', NULL])
+                        AS (time TIMESTAMPTZ, symbol TEXT, bid NUMERIC, ask NUMERIC)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(joined, "AAA 0.5 -1, BBB 1.5 1.75");
+        });
+    }
+
+    #[pg_test(error = "asof's fill must have a value for each value column")]
+    fn test_asof_checks_fill() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE trades(time TIMESTAMPTZ)", None, None);
+            client.select(
+                "CREATE TABLE spreads(time TIMESTAMPTZ, bid NUMERIC, ask NUMERIC)",
+                None,
+                None,
+            );
+            client.select(
+                "SELECT * FROM toolkit_experimental.asof('trades', 'spreads', 'time', ARRAY['bid', 'ask'],
+                    fill => ARRAY['0'])
+                    AS (time TIMESTAMPTZ, bid NUMERIC, ask NUMERIC)",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_lateral() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE trades(time TIMESTAMPTZ, symbol TEXT)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, symbol TEXT, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE symbols(symbol TEXT, since TIMESTAMPTZ)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES
+                    ('2020-01-01 00:00:30', 'AAA'),
+                    ('2020-01-01 00:01:00', 'BBB')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES
+                    ('2020-01-01 00:00:10', 'AAA', 1.5),
+                    ('2020-01-01 00:00:20', 'BBB', 2.5)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO symbols VALUES ('AAA', '2020-01-01 00:00:00'), ('BBB', '2020-01-01 00:00:50')",
+                None,
+                None,
+            );
+
+            let joined = client
+                .select(
+                    "SELECT string_agg(format('%s %s %s', s.symbol, t.symbol, t.price), ', ' ORDER BY s.symbol, t.time)
+                    FROM symbols s,
+                        LATERAL toolkit_experimental.asof('trades', 'quotes', 'time', 'price',
+                            by_columns => '{symbol}', range_start => s.since)
+                            AS t(time TIMESTAMPTZ, symbol TEXT, price DOUBLE PRECISION)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            // BBB's quote is before its symbol's range
+            assert_eq!(joined, "AAA AAA 1.5, AAA BBB 2.5, BBB BBB ");
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_lateral_plans() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE trades(time TIMESTAMPTZ)", None, None);
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE sessions(name TEXT, start_time TIMESTAMPTZ)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES ('2020-01-01 00:00:30'), ('2020-01-01 00:01:00')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES ('2020-01-01 00:00:15', 1.5), ('2020-01-01 00:00:50', 2.5)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO sessions VALUES ('a', NULL), ('b', '2020-01-01 00:00:40'), ('c', NULL)",
+                None,
+                None,
+            );
+
+            // the calls share their plans, of queries with and without a
+            // range, and with parameters of each call's own bounds
+            let joined = client
+                .select(
+                    "SELECT string_agg(format('%s %s', s.name, t.price), ', ' ORDER BY s.name, t.time)
+                    FROM sessions s,
+                        LATERAL toolkit_experimental.asof('trades', 'quotes', 'time', 'price',
+                            direction => 'nearest', range_start => s.start_time)
+                            AS t(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(joined, "a 1.5, a 2.5, b 2.5, c 1.5, c 2.5");
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_value() {
+        Spi::execute(|client| {
+            client.select("SET TIMEZONE to UTC", None, None);
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price NUMERIC)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES ('2020-01-01 00:00:10', 1.5), ('2020-01-01 00:01:00', 2.5)",
+                None,
+                None,
+            );
+
+            let value = |at: &str, direction: &str| {
+                client
+                    .select(
+                        &format!(
+                            "SELECT toolkit_experimental.asof_value('quotes', 'time', 'price', '{}', '{}')",
+                            at, direction
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<String>()
+            };
+            assert_eq!(value("2020-01-01 00:00:30", "backward"), Some("1.5".into()));
+            assert_eq!(value("2020-01-01 00:00:30", "forward"), Some("2.5".into()));
+            assert_eq!(value("2020-01-01 00:00:30", "nearest"), Some("1.5".into()));
+            assert_eq!(value("2020-01-01 00:00:40", "nearest"), Some("2.5".into()));
+            // equally far rows resolve to the earlier one
+            assert_eq!(value("2020-01-01 00:00:35", "nearest"), Some("1.5".into()));
+            assert_eq!(value("2020-01-01 00:01:00", "forward"), Some("2.5".into()));
+            assert_eq!(value("2020-01-01 00:00:00", "backward"), None);
+            assert_eq!(value("2020-01-01 00:02:00", "forward"), None);
+
+            let value = client
+                .select(
+                    "SELECT toolkit_experimental.asof_value('quotes', 'time', 'price', '2020-01-01 00:00:30')::numeric = 1.5",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<bool>();
+            assert_eq!(value, Some(true));
+        });
+    }
+
+    #[pg_test]
+    fn test_last_known() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE trades(time TIMESTAMPTZ, symbol TEXT, qty INTEGER)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, symbol TEXT, price NUMERIC)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES
+                    ('2020-01-01 00:00:00', 'AAA', 1),
+                    ('2020-01-01 00:00:30', 'AAA', 2),
+                    ('2020-01-01 00:00:30', 'BBB', 3),
+                    ('2020-01-01 00:01:00', 'AAA', 4)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES
+                    ('2020-01-01 00:00:10', 'AAA', 1.5),
+                    ('2020-01-01 00:00:20', 'BBB', 7.5),
+                    ('2020-01-01 00:01:00', 'AAA', 2.5)",
+                None,
+                None,
+            );
+
+            let joined = client
+                .select(
+                    "SELECT string_agg(format('%s %s', qty,
+                        toolkit_experimental.last_known('quotes', 'time', 'price', time, NULL::double precision,
+                            by_columns => '{symbol}', by_values => ARRAY[symbol]) * qty), ', ' ORDER BY qty)
+                    FROM trades",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(joined, "1 , 2 3, 3 22.5, 4 10");
+
+            let value = client
+                .select(
+                    "SELECT toolkit_experimental.last_known('quotes', 'time', 'price', '2020-01-01 00:00:30', NULL::numeric) = 7.5",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<bool>();
+            assert_eq!(value, Some(true));
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_bounds() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE trades(time TIMESTAMPTZ)", None, None);
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES ('2020-01-01 00:00:20'), ('2020-01-01 00:00:30')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES
+                    ('2020-01-01 00:00:00', 1),
+                    ('2020-01-01 00:00:10', 2),
+                    ('2020-01-01 00:00:20', 3),
+                    ('2020-01-01 00:00:40', 4),
+                    ('2020-01-01 00:01:00', 5)",
+                None,
+                None,
+            );
+
+            // only the quotes from 00:00:20 to 00:00:40 are read
+            for (direction, expected) in [
+                ("backward", "3, 3"),
+                ("forward", "3, 4"),
+                ("nearest", "3, 3"),
+            ] {
+                let joined = client
+                    .select(
+                        &format!(
+                            "SELECT string_agg(price::text, ', ' ORDER BY time)
+                            FROM toolkit_experimental.asof('trades', 'quotes', 'time', 'price', direction => '{}')
+                                AS (time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                            direction
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<String>()
+                    .unwrap();
+                assert_eq!(joined, expected, "{}", direction);
+            }
+            let queries: Vec<(String, String)> = client
+                .select(
+                    "SELECT side, query FROM toolkit_experimental.asof_explain('trades', 'quotes', 'time', 'price')",
+                    None,
+                    None,
+                )
+                .map(|row| (row[1].value().unwrap(), row[2].value().unwrap()))
+                .collect();
+            let sides: Vec<&str> = queries.iter().map(|(side, _)| &side[..]).collect();
+            assert_eq!(sides, ["t2", "t2", "t1", "t2"]);
+            let read = &queries[3].1;
+            assert!(read.contains(" >= '") && read.contains(" <= '"), "{}", read);
+            let count = client
+                .select(&format!("SELECT count(*) FROM ({}) q", read), None, None)
+                .first()
+                .get_one::<i64>();
+            assert_eq!(count, Some(2));
+
+            // With series, each series' own last quote at or before its first
+            // trade counts, so series `a`'s quote at 00:00:10 is still read,
+            // though series `b` has a later one at or before the first trade.
+            client.select(
+                "CREATE TABLE symbol_trades(time TIMESTAMPTZ, symbol TEXT)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE symbol_quotes(time TIMESTAMPTZ, symbol TEXT, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO symbol_trades VALUES ('2020-01-01 00:00:20', 'a'), ('2020-01-01 00:00:30', 'b')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO symbol_quotes VALUES
+                    ('2020-01-01 00:00:00', 'b', 1),
+                    ('2020-01-01 00:00:10', 'a', 2),
+                    ('2020-01-01 00:00:20', 'b', 3),
+                    ('2020-01-01 00:00:40', 'a', 4),
+                    ('2020-01-01 00:01:00', 'b', 5)",
+                None,
+                None,
+            );
+            for (direction, expected) in [
+                ("backward", "2, 3"),
+                ("forward", "4, 5"),
+                ("nearest", "2, 3"),
+            ] {
+                let joined = client
+                    .select(
+                        &format!(
+                            "SELECT string_agg(price::text, ', ' ORDER BY time)
+                            FROM toolkit_experimental.asof('symbol_trades', 'symbol_quotes', 'time', 'price',
+                                direction => '{}', by_columns => '{{symbol}}')
+                                AS (time TIMESTAMPTZ, symbol TEXT, price DOUBLE PRECISION)",
+                            direction
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<String>()
+                    .unwrap();
+                assert_eq!(joined, expected, "{}", direction);
+            }
+            let queries: Vec<(String, String)> = client
+                .select(
+                    "SELECT side, query FROM toolkit_experimental.asof_explain(
+                        'symbol_trades', 'symbol_quotes', 'time', 'price', by_columns => '{symbol}')",
+                    None,
+                    None,
+                )
+                .map(|row| (row[1].value().unwrap(), row[2].value().unwrap()))
+                .collect();
+            let sides: Vec<&str> = queries.iter().map(|(side, _)| &side[..]).collect();
+            assert_eq!(sides, ["t2", "t2", "t1", "t2"]);
+            let read = &queries[3].1;
+            assert!(read.contains(" >= '") && read.contains(" <= '"), "{}", read);
+            let count = client
+                .select(&format!("SELECT count(*) FROM ({}) q", read), None, None)
+                .first()
+                .get_one::<i64>();
+            assert_eq!(count, Some(4));
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_order() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE trades(time TIMESTAMPTZ, symbol TEXT)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES
+                    ('2020-01-01 00:00:30', 'AAA'),
+                    ('2020-01-01 00:00:30', 'BBB'),
+                    ('2020-01-01 00:00:00', 'CCC')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES ('2020-01-01 00:00:10', 1.5), ('2020-01-01 00:01:00', 2.5)",
+                None,
+                None,
+            );
+
+            // rows at the same time stay in physical order either way
+            for (direction, ordering, expected) in [
+                ("backward", "asc", "CCC , AAA 1.5, BBB 1.5"),
+                ("backward", "desc", "AAA 1.5, BBB 1.5, CCC "),
+                ("forward", "asc", "CCC 1.5, AAA 2.5, BBB 2.5"),
+                ("forward", "desc", "AAA 2.5, BBB 2.5, CCC 1.5"),
+                ("nearest", "asc", "CCC 1.5, AAA 1.5, BBB 1.5"),
+                ("nearest", "desc", "AAA 1.5, BBB 1.5, CCC 1.5"),
+            ] {
+                let joined = client
+                    .select(
+                        &format!(
+                            "SELECT string_agg(format('%s %s', symbol, price), ', ')
+                            FROM toolkit_experimental.asof('trades', 'quotes', 'time', 'price',
+                                direction => '{}', ordering => '{}')
+                                AS (time TIMESTAMPTZ, symbol TEXT, price DOUBLE PRECISION)",
+                            direction, ordering
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<String>()
+                    .unwrap();
+                assert_eq!(joined, expected, "{} {}", direction, ordering);
+            }
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_sorted() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE trades(time TIMESTAMPTZ, symbol TEXT)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES
+                    ('2020-01-01 00:00:00', 'AAA'),
+                    ('2020-01-01 00:00:30', 'BBB'),
+                    ('2020-01-01 00:01:00', 'CCC'),
+                    (NULL, 'DDD')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES ('2020-01-01 00:00:10', 1.5), ('2020-01-01 00:01:00', 2.5)",
+                None,
+                None,
+            );
+
+            for (direction, expected) in [
+                ("backward", "AAA , BBB 1.5, CCC 2.5, DDD "),
+                ("forward", "AAA 1.5, BBB 2.5, CCC 2.5, DDD "),
+                ("nearest", "AAA 1.5, BBB 1.5, CCC 2.5, DDD "),
+            ] {
+                let joined = client
+                    .select(
+                        &format!(
+                            "SELECT string_agg(format('%s %s', symbol, price), ', ')
+                            FROM toolkit_experimental.asof('trades', 'quotes', 'time', 'price',
+                                direction => '{}', sorted => true)
+                                AS (time TIMESTAMPTZ, symbol TEXT, price DOUBLE PRECISION)",
+                            direction
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<String>()
+                    .unwrap();
+                assert_eq!(joined, expected, "{}", direction);
+            }
+        });
+    }
+
+    #[pg_test(error = "asof's t1 isn't in ascending time order")]
+    fn test_asof_sorted_checks_order() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT * FROM toolkit_experimental.asof_query(
+                    $$SELECT * FROM (VALUES (2::bigint), (1)) v(time)$$,
+                    $$SELECT * FROM (VALUES (1::bigint, 1.5)) v(time, price)$$,
+                    'time', 'price', sorted => true
+                ) AS (time BIGINT, price NUMERIC)",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_where() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE readings(time TIMESTAMPTZ, device_id INTEGER)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE statuses(time TIMESTAMPTZ, device_id INTEGER, status TEXT)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO readings VALUES
+                    ('2020-01-01 00:00:10', 7),
+                    ('2020-01-01 00:00:20', 8),
+                    ('2020-01-01 00:00:30', 7)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO statuses VALUES
+                    ('2020-01-01 00:00:00', 7, 'ok'),
+                    ('2020-01-01 00:00:25', 8, 'down'),
+                    ('2020-01-01 00:00:05', 7, 'busy')",
+                None,
+                None,
+            );
+
+            let joined = client
+                .select(
+                    "SELECT string_agg(format('%s %s', device_id, status), ', ')
+                    FROM toolkit_experimental.asof('readings', 'statuses', 'time', 'status',
+                        left_where => 'device_id = 7', right_where => $$status <> 'busy'$$)
+                        AS (time TIMESTAMPTZ, device_id INTEGER, status TEXT)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(joined, "7 ok, 7 down");
+        });
+    }
+
+    #[pg_test(error = "asof's right_where must be a single expression")]
+    fn test_asof_checks_where_expression() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE trades(time TIMESTAMPTZ)", None, None);
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "SELECT * FROM toolkit_experimental.asof('trades', 'quotes', 'time', 'price', right_where => 'true) AS x, (price > 0')
+                    AS (time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test(error = "asof's right_where must be a boolean expression")]
+    fn test_asof_checks_where() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE trades(time TIMESTAMPTZ)", None, None);
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "SELECT * FROM toolkit_experimental.asof('trades', 'quotes', 'time', 'price', right_where => 'price + 1')
+                    AS (time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_bucket_width() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE trades(time TIMESTAMPTZ)", None, None);
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES
+                    ('2020-01-01 00:59:50+00'),
+                    ('2020-01-01 00:59:59+00'),
+                    ('2020-01-01 01:00:02+00'),
+                    ('2020-01-01 01:00:10+00')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES ('2020-01-01 00:59:40+00', 1.5), ('2020-01-01 01:00:05+00', 2.5)",
+                None,
+                None,
+            );
+
+            for (direction, width, expected) in [
+                ("backward", "1 hour", "1.5, 1.5, , 2.5"),
+                ("forward", "1 hour", ", , 2.5, "),
+                ("nearest", "1 hour", "1.5, 1.5, 2.5, 2.5"),
+                ("backward", "1 day", "1.5, 1.5, 1.5, 2.5"),
+            ] {
+                let joined = client
+                    .select(
+                        &format!(
+                            "SELECT string_agg(format('%s', price), ', ')
+                            FROM toolkit_experimental.asof('trades', 'quotes', 'time', 'price',
+                                direction => '{}', bucket_width => '{}')
+                                AS (time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                            direction, width
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<String>()
+                    .unwrap();
+                assert_eq!(joined, expected, "{} {}", direction, width);
+            }
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_both() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE trades(time TIMESTAMPTZ)", None, None);
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES
+                    ('2020-01-01 00:00:00'),
+                    ('2020-01-01 00:00:25'),
+                    ('2020-01-01 00:00:50'),
+                    ('2020-01-01 00:01:00')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES ('2020-01-01 00:00:10', 1.5), ('2020-01-01 00:00:50', 2.5)",
+                None,
+                None,
+            );
+
+            let joined = client
+                .select(
+                    "SELECT string_agg(format('%s %s %s', prev, next, weight), ', ')
+                    FROM toolkit_experimental.asof('trades', 'quotes', 'time', 'price', direction => 'both')
+                        AS (time TIMESTAMPTZ, prev DOUBLE PRECISION, next DOUBLE PRECISION, weight DOUBLE PRECISION)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(joined, " 1.5 , 1.5 2.5 0.375, 2.5 2.5 0, 2.5  ");
+
+            let joined = client
+                .select(
+                    "SELECT string_agg(format('%s %s %s', prev, next, weight), ', ')
+                    FROM toolkit_experimental.asof('trades', 'quotes', 'time', 'price', direction => 'both', inner => true)
+                        AS (time TIMESTAMPTZ, prev DOUBLE PRECISION, next DOUBLE PRECISION, weight DOUBLE PRECISION)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(joined, "1.5 2.5 0.375, 2.5 2.5 0");
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_null_values() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE trades(time TIMESTAMPTZ)", None, None);
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES ('2020-01-01 00:00:30')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES ('2020-01-01 00:00:10', 1.5), ('2020-01-01 00:00:20', NULL)",
+                None,
+                None,
+            );
+
+            for (null_values, expected) in [("keep", None), ("skip", Some(1.5))] {
+                let price = client
+                    .select(
+                        &format!(
+                            "SELECT price FROM toolkit_experimental.asof('trades', 'quotes', 'time', 'price',
+                                null_values => '{}')
+                                AS (time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                            null_values
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<f64>();
+                assert_eq!(price, expected, "{}", null_values);
+            }
+        });
+    }
+
+    #[pg_test(error = "asof found NULL values in t2")]
+    fn test_asof_null_values_error() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE trades(time TIMESTAMPTZ)", None, None);
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES ('2020-01-01 00:00:30')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES ('2020-01-01 00:00:10', 1.5), ('2020-01-01 00:00:20', NULL)",
+                None,
+                None,
+            );
+            client.select(
+                "SELECT * FROM toolkit_experimental.asof('trades', 'quotes', 'time', 'price', null_values => 'error')
+                    AS (time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_row_identity() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE trades(time TIMESTAMPTZ)", None, None);
+            client.select(
+                "CREATE TABLE quotes(id INT PRIMARY KEY, time TIMESTAMPTZ, price DOUBLE PRECISION, venue TEXT)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES ('2020-01-01 00:00:00'), ('2020-01-01 00:00:30')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES (7, '2020-01-01 00:00:10', 1.5, 'x')",
+                None,
+                None,
+            );
+
+            let joined = client
+                .select(
+                    "SELECT string_agg(format('%s %s %s', a.id, q.id, q.venue), ', ' ORDER BY a.time)
+                    FROM toolkit_experimental.asof('trades', 'quotes', 'time', ARRAY['id', 'ctid'])
+                        AS a(time TIMESTAMPTZ, id INT, row_id TID)
+                    LEFT JOIN quotes q ON q.ctid = a.row_id",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(joined, "  , 7 7 x");
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_tolerance_column() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE readings(time TIMESTAMPTZ, max_staleness INTERVAL)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO readings VALUES
+                    ('2020-01-01 00:00:15', NULL),
+                    ('2020-01-01 00:00:30', '10 seconds'),
+                    ('2020-01-01 00:01:00', '1 minute')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES ('2020-01-01 00:00:10', 1.5), ('2020-01-01 00:00:45', 2.5)",
+                None,
+                None,
+            );
+
+            for (direction, expected) in [
+                ("backward", "1.5, , 2.5"),
+                ("forward", "2.5, , "),
+                ("nearest", "1.5, , 2.5"),
+            ] {
+                let joined = client
+                    .select(
+                        &format!(
+                            "SELECT string_agg(format('%s', price), ', ' ORDER BY time)
+                            FROM toolkit_experimental.asof('readings', 'quotes', 'time', 'price',
+                                direction => '{}', tolerance_column => 'max_staleness')
+                                AS (time TIMESTAMPTZ, max_staleness INTERVAL, price DOUBLE PRECISION)",
+                            direction
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<String>()
+                    .unwrap();
+                assert_eq!(joined, expected, "{}", direction);
+            }
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_summary() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE readings(time TIMESTAMPTZ, max_staleness INTERVAL)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO readings VALUES
+                    ('2020-01-01 00:00:05', NULL),
+                    ('2020-01-01 00:00:15', NULL),
+                    ('2020-01-01 00:00:30', '10 seconds'),
+                    ('2020-01-01 00:01:00', '1 minute')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES ('2020-01-01 00:00:10', 1.5), ('2020-01-01 00:00:45', 2.5)",
+                None,
+                None,
+            );
+
+            for (direction, expected) in [
+                ("backward", "2 1 1 00:00:15 00:00:10"),
+                ("forward", "2 1 1 00:00:30 00:00:17.5"),
+            ] {
+                let summary = client
+                    .select(
+                        &format!(
+                            "SELECT format('%s %s %s %s %s', matched, unmatched, dropped_by_tolerance, max_staleness, avg_staleness)
+                            FROM toolkit_experimental.asof_summary('readings', 'quotes', 'time', 'price',
+                                direction => '{}', tolerance_column => 'max_staleness')",
+                            direction
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<String>()
+                    .unwrap();
+                assert_eq!(summary, expected, "{}", direction);
+            }
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_explain() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE trades(time TIMESTAMPTZ, symbol TEXT)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, symbol TEXT, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES ('2020-01-01 00:00:00', 'a'), ('2020-01-01 00:01:00', 'a')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES ('2020-01-01 00:00:10', 'a', 1.5), ('2020-01-01 00:00:50', 'b', 2.5)",
+                None,
+                None,
+            );
+
+            let sides = client
+                .select(
+                    "SELECT string_agg(side, ' ') FROM toolkit_experimental.asof_explain(
+                        'trades', 'quotes', 'time', 'price', direction => 'nearest', by_columns => '{symbol}')",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(sides, "t2 t2 t1 t2 t2");
+
+            // the queries can be run on their own, with their parameters
+            // written into them
+            let queries: Vec<(String, String)> = client
+                .select(
+                    "SELECT side, query FROM toolkit_experimental.asof_explain(
+                        'trades', 'quotes', 'time', 'price',
+                        range_start => '2020-01-01 00:00:30', right_where => $$symbol = 'b'$$)",
+                    None,
+                    None,
+                )
+                .map(|row| (row[1].value().unwrap(), row[2].value().unwrap()))
+                .collect();
+            let sides: Vec<&str> = queries.iter().map(|(side, _)| &side[..]).collect();
+            assert_eq!(sides, ["t2", "t2", "t1", "t2"]);
+            let counts: Vec<i64> = queries
+                .iter()
+                .map(|(_, query)| {
+                    client
+                        .select(&format!("SELECT count(*) FROM ({}) q", query), None, None)
+                        .first()
+                        .get_one::<i64>()
+                        .unwrap()
+                })
+                .collect();
+            assert_eq!(counts, [1, 0, 1, 1]);
+        });
+    }
+
+    #[pg_test(error = "asof's tolerance column must be an interval")]
+    fn test_asof_checks_tolerance_column() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE readings(time TIMESTAMPTZ, max_staleness TEXT)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "SELECT * FROM toolkit_experimental.asof('readings', 'quotes', 'time', 'price',
+                    tolerance_column => 'max_staleness')
+                    AS (time TIMESTAMPTZ, max_staleness TEXT, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_multi() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE trades(time TIMESTAMPTZ, qty INTEGER)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE prices(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE rates(time TIMESTAMPTZ, rate NUMERIC)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES
+                    ('2020-01-01 00:00:00', 1),
+                    ('2020-01-01 00:00:30', 2),
+                    ('2020-01-01 00:01:00', 3)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO prices VALUES ('2020-01-01 00:00:10', 1.5), ('2020-01-01 00:01:00', 2.5)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO rates VALUES ('2020-01-01 00:00:00', 0.5), ('2020-01-01 00:00:45', 0.75)",
+                None,
+                None,
+            );
+
+            let joined = client
+                .select(
+                    "SELECT string_agg(format('%s %s %s', qty, price, rate), ', ' ORDER BY time)
+                    FROM toolkit_experimental.asof_multi('trades', '{prices,rates}', 'time', '{price,rate}')
+                        AS (time TIMESTAMPTZ, qty INTEGER, price DOUBLE PRECISION, rate NUMERIC)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(joined, "1  0.5, 2 1.5 0.5, 3 2.5 0.75");
+
+            client.select("ALTER TABLE rates RENAME rate TO price", None, None);
+            let joined = client
+                .select(
+                    "SELECT string_agg(format('%s %s', p, r), ', ' ORDER BY time)
+                    FROM toolkit_experimental.asof_multi('trades', '{prices,rates}', 'time', 'price')
+                        AS (time TIMESTAMPTZ, qty INTEGER, p DOUBLE PRECISION, r NUMERIC)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(joined, " 0.5, 1.5 0.5, 2.5 0.75");
+        });
+    }
+
+    #[pg_test(error = "asof_multi must have a value column for each table of t2")]
+    fn test_asof_multi_checks_value_columns() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE trades(time TIMESTAMPTZ)", None, None);
+            client.select(
+                "CREATE TABLE prices(time TIMESTAMPTZ, price FLOAT)",
+                None,
+                None,
+            );
+            client.select(
+                "SELECT * FROM toolkit_experimental.asof_multi(
+                    'trades', '{prices,prices,prices}', 'time', '{price,price}'
+                ) AS (time TIMESTAMPTZ, a FLOAT, b FLOAT)",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_topk() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE trades(time TIMESTAMPTZ, symbol TEXT)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, symbol TEXT, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES
+                    ('2020-01-01 00:00:00', 'a'),
+                    ('2020-01-01 00:01:00', 'a'),
+                    ('2020-01-01 00:01:00', 'b')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES
+                    ('2020-01-01 00:00:10', 'a', 1.5),
+                    ('2020-01-01 00:00:20', 'b', 4.5),
+                    ('2020-01-01 00:00:30', 'a', 2.5),
+                    ('2020-01-01 00:00:50', 'a', 3.5)",
+                None,
+                None,
+            );
+
+            let joined = client
+                .select(
+                    "SELECT string_agg(format('%s %s %s %s', symbol, price, rank, staleness), ', '
+                        ORDER BY time, symbol, rank)
+                    FROM toolkit_experimental.asof_topk('trades', 'quotes', 'time', 'price', 2, '{symbol}')
+                        AS (time TIMESTAMPTZ, symbol TEXT, price DOUBLE PRECISION, rank BIGINT, staleness INTERVAL)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            assert_eq!(
+                joined,
+                "a   , a 3.5 1 00:00:10, a 2.5 2 00:00:30, b 4.5 1 00:00:40"
+            );
+        });
+    }
+
+    #[pg_test(
+        error = "asof_topk's column definition list must have the types of the columns of t1 followed by the value column, rank, and staleness: bigint, double precision, bigint, bigint"
+    )]
+    fn test_asof_topk_checks_column_definitions() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE trades(time BIGINT)", None, None);
+            client.select("CREATE TABLE quotes(time BIGINT, price FLOAT)", None, None);
+            client.select(
+                "SELECT * FROM toolkit_experimental.asof_topk('trades', 'quotes', 'time', 'price', 3)
+                    AS (time BIGINT, price FLOAT, rank BIGINT, staleness INTERVAL)",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_asof_stats() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE trades(time TIMESTAMPTZ, symbol TEXT)",
+                None,
+                None,
+            );
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, symbol TEXT, price NUMERIC)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO trades VALUES
+                    ('2020-01-01 00:01:00', 'AAA'),
+                    ('2020-01-01 00:01:00', 'BBB'),
+                    ('2020-01-01 00:01:45', 'AAA'),
+                    ('2020-01-01 00:00:00', 'CCC')",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO quotes VALUES
+                    ('2020-01-01 00:00:00', 'AAA', 1),
+                    ('2020-01-01 00:00:30', 'AAA', 3),
+                    ('2020-01-01 00:00:50', 'BBB', 10),
+                    ('2020-01-01 00:01:00', 'AAA', 5)",
+                None,
+                None,
+            );
+
+            let stats = |by_columns: &str| {
+                client
+                    .select(
+                        &format!(
+                            "SELECT string_agg(format('%s %s %s %s %s', symbol, count, avg, min, max), ', ' ORDER BY time, symbol)
+                            FROM toolkit_experimental.asof_stats('trades', 'quotes', 'time', 'price', '1 minute', {})
+                                AS (time TIMESTAMPTZ, symbol TEXT, count BIGINT, avg DOUBLE PRECISION, min DOUBLE PRECISION, max DOUBLE PRECISION)",
+                            by_columns
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<String>()
+                    .unwrap()
+            };
+            assert_eq!(
+                stats("NULL"),
+                "CCC 1 1 1 1, AAA 3 6 3 10, BBB 3 6 3 10, AAA 2 7.5 5 10"
+            );
+            assert_eq!(
+                stats("'{symbol}'"),
+                "CCC 0   , AAA 2 4 3 5, BBB 1 10 10 10, AAA 1 5 5 5"
+            );
+        });
+    }
+
+    #[pg_test(
+        error = "asof_stats's column definition list must have the types of the columns of t1 followed by count, average, minimum, and maximum: timestamp with time zone, bigint, double precision, double precision, double precision"
+    )]
+    fn test_asof_stats_checks_column_definitions() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE trades(time TIMESTAMPTZ)", None, None);
+            client.select(
+                "CREATE TABLE quotes(time TIMESTAMPTZ, price DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "SELECT * FROM toolkit_experimental.asof_stats('trades', 'quotes', 'time', 'price', '1 minute')
+                    AS (time TIMESTAMPTZ, avg DOUBLE PRECISION)",
+                None,
+                None,
+            );
+        });
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    ffi::CString,
+    mem::size_of,
+    os::raw::c_void,
+    rc::Rc,
+};
+
+use pgx::*;
+
+use crate::datum_utils::deep_copy_datum;
+
+use super::merge::Keyed;
+
+// Rows fetched from a cursor at a time.
+const BATCH_SIZE: i64 = 1000;
+
+// The types of a row's columns, along with whether each is passed by value,
+// so that the type cache is consulted once per column rather than once per
+// datum, and by-value datums are neither copied nor freed.
+pub struct ColumnTypes {
+    oids: Vec<pg_sys::Oid>,
+    by_value: Vec<bool>,
+}
+
+impl ColumnTypes {
+    pub fn new(oids: &[pg_sys::Oid]) -> Rc<Self> {
+        let by_value = oids
+            .iter()
+            .map(|typoid| unsafe { (*pg_sys::lookup_type_cache(*typoid, 0)).typbyval })
+            .collect();
+        Rc::new(Self {
+            oids: oids.to_vec(),
+            by_value,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.oids.len()
+    }
+}
+
+// Column values copied out of a cursor's batch or a spill, freed when the
+// row is dropped. Rows must be dropped before the SPI connection they were
+// read in is closed.
+pub struct Row {
+    pub datums: Vec<Option<pg_sys::Datum>>,
+    types: Rc<ColumnTypes>,
+}
+
+impl Row {
+    // Copies the datums, which must be of the given types.
+    pub unsafe fn copied(
+        datums: impl Iterator<Item = Option<pg_sys::Datum>>,
+        types: Rc<ColumnTypes>,
+    ) -> Self {
+        let datums = datums
+            .zip(types.oids.iter().zip(&types.by_value))
+            .map(|(datum, (typoid, by_value))| match datum {
+                Some(datum) if !by_value => Some(deep_copy_datum(datum, *typoid)),
+                datum => datum,
+            })
+            .collect();
+        Self { datums, types }
+    }
+}
+
+impl Drop for Row {
+    fn drop(&mut self) {
+        for (datum, by_value) in self.datums.iter().zip(&self.types.by_value) {
+            match datum {
+                Some(datum) if !by_value => unsafe { pg_sys::pfree(datum.cast_mut_ptr()) },
+                _ => (),
+            }
+        }
+    }
+}
+
+// The plans of queries, by their text and the types of their parameters,
+// kept until they're dropped, so that a query run again isn't planned again.
+#[derive(Default)]
+pub struct Plans(RefCell<HashMap<(String, Vec<pg_sys::Oid>), pg_sys::SPIPlanPtr>>);
+
+impl Plans {
+    // The plans kept for the calls of a function in its `fn_extra`, until
+    // the memory context of its `FmgrInfo` is reset at the end of the query
+    // calling it. Calls with the same arguments, such as those of a lateral
+    // join, run the same queries, so each is only planned on the first of
+    // them. Only for functions not otherwise using their `fn_extra`.
+    pub unsafe fn of_function<'a>(flinfo: *mut pg_sys::FmgrInfo) -> &'a Self {
+        if (*flinfo).fn_extra.is_null() {
+            let plans = Box::into_raw(Box::new(Self::default()));
+            let callback = pg_sys::MemoryContextAlloc(
+                (*flinfo).fn_mcxt,
+                size_of::<pg_sys::MemoryContextCallback>() as _,
+            ) as *mut pg_sys::MemoryContextCallback;
+            (*callback).func = Some(drop_plans);
+            (*callback).arg = plans as *mut c_void;
+            pg_sys::MemoryContextRegisterResetCallback((*flinfo).fn_mcxt, callback);
+            (*flinfo).fn_extra = plans as *mut c_void;
+        }
+        &*((*flinfo).fn_extra as *const Self)
+    }
+
+    // The plan of a query, prepared the first time it's needed. Must be
+    // called within an SPI connection.
+    unsafe fn plan(&self, query: &str, arg_types: &[pg_sys::Oid]) -> pg_sys::SPIPlanPtr {
+        let key = (query.to_owned(), arg_types.to_vec());
+        if let Some(plan) = self.0.borrow().get(&key) {
+            return *plan;
+        }
+        let query = CString::new(query).unwrap();
+        let mut arg_types = arg_types.to_vec();
+        let plan = pg_sys::SPI_prepare(
+            query.as_ptr(),
+            arg_types.len() as i32,
+            arg_types.as_mut_ptr(),
+        );
+        if plan.is_null() {
+            pgx::error!("could not prepare asof query");
+        }
+        // kept beyond the SPI connection it's prepared in
+        pg_sys::SPI_keepplan(plan);
+        self.0.borrow_mut().insert(key, plan);
+        plan
+    }
+}
+
+impl Drop for Plans {
+    fn drop(&mut self) {
+        for plan in self.0.get_mut().values() {
+            unsafe { pg_sys::SPI_freeplan(*plan) };
+        }
+    }
+}
+
+#[pg_guard]
+unsafe extern "C" fn drop_plans(plans: *mut c_void) {
+    drop(Box::from_raw(plans as *mut Plans));
+}
+
+// Reads the rows of a query a batch at a time, so that only one batch needs
+// to be in memory, and the query only needs to be planned once. The query's
+// first column is the time, its second the series key, and the rest have the
+// given types.
+pub struct Cursor {
+    portal: pg_sys::Portal,
+    types: Rc<ColumnTypes>,
+    batch: VecDeque<Keyed<Row>>,
+    exhausted: bool,
+}
+
+impl Cursor {
+    // Must be called within an SPI connection. `args` are the types and
+    // values of the query's parameters. The query's plan is taken from
+    // `plans`, or prepared and kept there.
+    pub unsafe fn open(
+        plans: &Plans,
+        query: &str,
+        args: &[(pg_sys::Oid, pg_sys::Datum)],
+        types: &[pg_sys::Oid],
+    ) -> Self {
+        let (arg_types, mut arg_values): (Vec<pg_sys::Oid>, Vec<pg_sys::Datum>) =
+            args.iter().copied().unzip();
+        let plan = plans.plan(query, &arg_types);
+        let portal = pg_sys::SPI_cursor_open(
+            std::ptr::null(),
+            plan,
+            arg_values.as_mut_ptr(),
+            std::ptr::null(),
+            true,
+        );
+        if portal.is_null() {
+            pgx::error!("could not open cursor for asof query");
+        }
+        Self {
+            portal,
+            types: ColumnTypes::new(types),
+            batch: VecDeque::new(),
+            exhausted: false,
+        }
+    }
+
+    unsafe fn fetch(&mut self) {
+        pg_sys::SPI_cursor_fetch(self.portal, true, BATCH_SIZE);
+        let table = pg_sys::SPI_tuptable;
+        let fetched = pg_sys::SPI_processed as usize;
+        self.exhausted = fetched < BATCH_SIZE as usize;
+        // each tuple is deformed in one go, instead of walking it from the
+        // start for every column, into buffers shared by the whole batch
+        let natts = (*(*table).tupdesc).natts as usize;
+        let mut values = vec![pg_sys::Datum::from(0usize); natts];
+        let mut nulls = vec![true; natts];
+        for i in 0..fetched {
+            let tuple = *(*table).vals.add(i);
+            pg_sys::heap_deform_tuple(
+                tuple,
+                (*table).tupdesc,
+                values.as_mut_ptr(),
+                nulls.as_mut_ptr(),
+            );
+            let column = |j: usize| (!nulls[j]).then(|| values[j]);
+            let time = column(0).map(|time| time.value() as i64);
+            let key = column(1)
+                .and_then(|key| String::from_polymorphic_datum(key, false, pg_sys::TEXTOID));
+            let row = Row::copied((2..2 + self.types.len()).map(column), self.types.clone());
+            self.batch.push_back((key, time, row));
+        }
+        pg_sys::SPI_freetuptable(table);
+    }
+}
+
+impl Iterator for Cursor {
+    type Item = Keyed<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.batch.is_empty() && !self.exhausted {
+            unsafe { self.fetch() }
+        }
+        self.batch.pop_front()
+    }
+}
+
+impl Drop for Cursor {
+    fn drop(&mut self) {
+        unsafe { pg_sys::SPI_cursor_close(self.portal) }
+    }
+}
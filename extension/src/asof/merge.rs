@@ -0,0 +1,363 @@
+use std::collections::{HashMap, VecDeque};
+
+// A row of either side: the series it belongs to, its time, and its contents.
+// Rows without a series or a time are never matched.
+pub type Keyed<T> = (Option<String>, Option<i64>, T);
+
+// Merges left and right rows, both ordered by time, calling `emit` with each left row and the last right row of the same
+// series read at or before it, along with that row's time. When the rows are
+// in descending order that's the first right row at or after the left row.
+// Only the last right row of each series is kept in memory. Rows with NULL
+// times may come first or last; they're emitted or skipped as they're read.
+//
+// At equal times right rows are merged first, so of several right rows at
+// the left row's time the one read last is its match. With `unique` it's an
+// error for a series to have several right rows at the same time instead.
+pub fn merge_rows<T, V>(
+    left: impl Iterator<Item = Keyed<T>>,
+    right: impl Iterator<Item = Keyed<V>>,
+    descending: bool,
+    unique: bool,
+    mut emit: impl FnMut(T, Option<(i64, &V)>),
+) {
+    let mut left = left.peekable();
+    let mut right = right.peekable();
+    let mut last: HashMap<String, (i64, V)> = HashMap::new();
+
+    loop {
+        let right_first = match (left.peek(), right.peek()) {
+            // the rest of the right rows have nothing to match
+            (None, _) => break,
+            (Some(_), None) => false,
+            (Some((_, left_time, _)), Some((_, right_time, _))) => match (left_time, right_time) {
+                (_, None) => true,
+                (None, _) => false,
+                (Some(left_time), Some(right_time)) if descending => right_time >= left_time,
+                (Some(left_time), Some(right_time)) => right_time <= left_time,
+            },
+        };
+
+        if right_first {
+            if let (Some(key), Some(time), value) = right.next().unwrap() {
+                if let Some((previous, _)) = last.insert(key, (time, value)) {
+                    if unique && previous == time {
+                        pgx::error!("asof found several rows of a series of t2 at the same time");
+                    }
+                }
+            }
+            continue;
+        }
+
+        let (key, time, row) = left.next().unwrap();
+        let matched = match (key, time) {
+            (Some(key), Some(_)) => last.get(&key).map(|(time, value)| (*time, value)),
+            _ => None,
+        };
+        emit(row, matched);
+    }
+}
+
+// Merges left rows with the right rows of several references, all in
+// ascending time order with NULL times last, calling `emit` with each left
+// row and, for each reference, the last right row of the same series read at
+// or before it, like `merge_rows` does for one. The left rows are read once
+// however many references there are.
+pub fn merge_references<T, V>(
+    left: impl Iterator<Item = Keyed<T>>,
+    references: Vec<impl Iterator<Item = Keyed<V>>>,
+    mut emit: impl FnMut(T, &[Option<(i64, &V)>]),
+) {
+    let mut references: Vec<_> = references
+        .into_iter()
+        .map(|right| (right.peekable(), HashMap::<String, (i64, V)>::new()))
+        .collect();
+
+    for (key, time, row) in left {
+        if let Some(time) = time {
+            for (right, last) in &mut references {
+                while let Some(&(_, Some(right_time), _)) = right.peek() {
+                    if right_time > time {
+                        break;
+                    }
+                    if let (Some(key), Some(right_time), value) = right.next().unwrap() {
+                        last.insert(key, (right_time, value));
+                    }
+                }
+            }
+        }
+
+        let matched: Vec<Option<(i64, &V)>> = references
+            .iter()
+            .map(|(_, last)| match (&key, time) {
+                (Some(key), Some(_)) => last.get(key).map(|(time, value)| (*time, value)),
+                _ => None,
+            })
+            .collect();
+        emit(row, &matched);
+    }
+}
+
+// Merges left and right rows, both in ascending time order with NULL times
+// last, calling `emit` with each left row and up to the last `k` right rows
+// of the same series read at or before it, the last one read first. Only
+// those `k` rows of each series are kept in memory.
+pub fn merge_recent<T, V>(
+    left: impl Iterator<Item = Keyed<T>>,
+    right: impl Iterator<Item = Keyed<V>>,
+    k: usize,
+    mut emit: impl FnMut(T, Vec<(i64, &V)>),
+) {
+    let mut right = right.peekable();
+    let mut recent: HashMap<String, VecDeque<(i64, V)>> = HashMap::new();
+
+    for (key, time, row) in left {
+        if let Some(time) = time {
+            while let Some(&(_, Some(right_time), _)) = right.peek() {
+                if right_time > time {
+                    break;
+                }
+                if let (Some(key), Some(right_time), value) = right.next().unwrap() {
+                    let rows = recent.entry(key).or_default();
+                    if rows.len() == k {
+                        rows.pop_front();
+                    }
+                    if k > 0 {
+                        rows.push_back((right_time, value));
+                    }
+                }
+            }
+        }
+
+        let matches = match (&key, time) {
+            (Some(key), Some(_)) => recent.get(key).map_or_else(Vec::new, |rows| {
+                rows.iter()
+                    .rev()
+                    .map(|(time, value)| (*time, value))
+                    .collect()
+            }),
+            _ => vec![],
+        };
+        emit(row, matches);
+    }
+}
+
+// Chooses whichever of the matches before and after `time` is closer,
+// preferring the earlier one when both are equally far.
+pub fn nearest<V>(time: i64, before: Option<(i64, V)>, after: Option<(i64, V)>) -> Option<V> {
+    match (before, after) {
+        (Some((before_time, before)), Some((after_time, after))) => {
+            if after_time - time < time - before_time {
+                Some(after)
+            } else {
+                Some(before)
+            }
+        }
+        (before, after) => before.or(after).map(|(_, value)| value),
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    use super::Keyed;
+
+    // Orders the rows like the queries feeding the merge do, and collects
+    // what it emits.
+    fn merge(
+        mut left: Vec<Keyed<char>>,
+        mut right: Vec<Keyed<f64>>,
+        descending: bool,
+        unique: bool,
+    ) -> Vec<(char, Option<(i64, f64)>)> {
+        let order = |time: &Option<i64>| {
+            let time = if descending { time.map(|t| -t) } else { *time };
+            (time.is_none(), time)
+        };
+        left.sort_by_key(|(_, time, _)| order(time));
+        right.sort_by_key(|(_, time, _)| order(time));
+        let mut emitted = vec![];
+        super::merge_rows(
+            left.into_iter(),
+            right.into_iter(),
+            descending,
+            unique,
+            |row, matched| emitted.push((row, matched.map(|(time, value)| (time, *value)))),
+        );
+        emitted
+    }
+
+    #[pg_test]
+    fn test_merge_rows() {
+        let key = || Some(String::new());
+        let left = || {
+            vec![
+                (key(), Some(5), 'a'),
+                (key(), None, 'b'),
+                (key(), Some(1), 'c'),
+                (key(), Some(20), 'd'),
+            ]
+        };
+        let right = || {
+            vec![
+                (key(), Some(2), 1.0),
+                (key(), Some(5), 2.0),
+                (key(), Some(9), 3.0),
+                (key(), None, 4.0),
+            ]
+        };
+        assert_eq!(
+            merge(left(), right(), false, false),
+            vec![
+                ('c', None),
+                ('a', Some((5, 2.0))),
+                ('d', Some((9, 3.0))),
+                ('b', None)
+            ]
+        );
+        assert_eq!(
+            merge(left(), right(), true, false),
+            vec![
+                ('d', None),
+                ('a', Some((5, 2.0))),
+                ('c', Some((2, 1.0))),
+                ('b', None)
+            ]
+        );
+    }
+
+    #[pg_test]
+    fn test_merge_rows_partitioned() {
+        let key = |k: &str| Some(k.to_owned());
+        let left = vec![
+            (key("x"), Some(5), 'a'),
+            (key("y"), Some(5), 'b'),
+            (None, Some(5), 'c'),
+            (key("z"), Some(5), 'd'),
+        ];
+        let right = vec![
+            (key("x"), Some(1), 1.0),
+            (key("y"), Some(2), 2.0),
+            (key("x"), Some(3), 3.0),
+            (None, Some(4), 4.0),
+        ];
+        assert_eq!(
+            merge(left, right, false, false),
+            vec![
+                ('a', Some((3, 3.0))),
+                ('b', Some((2, 2.0))),
+                ('c', None),
+                ('d', None)
+            ]
+        );
+    }
+
+    #[pg_test]
+    fn test_merge_references() {
+        let key = |k: &str| Some(k.to_owned());
+        let left = vec![
+            (key("x"), Some(2), 'a'),
+            (key("y"), Some(5), 'b'),
+            (key("x"), Some(6), 'c'),
+            (key("x"), None, 'd'),
+        ];
+        let prices = vec![
+            (key("x"), Some(1), 1.0),
+            (key("y"), Some(3), 3.0),
+            (key("x"), Some(6), 6.0),
+            (key("x"), None, 9.0),
+        ];
+        let rates = vec![(key("x"), Some(4), 4.0), (None, Some(5), 5.0)];
+        let mut emitted = vec![];
+        super::merge_references(
+            left.into_iter(),
+            vec![prices.into_iter(), rates.into_iter()],
+            |row, matched| {
+                let matched: Vec<Option<f64>> = matched
+                    .iter()
+                    .map(|matched| matched.map(|(_, value)| *value))
+                    .collect();
+                emitted.push((row, matched))
+            },
+        );
+        assert_eq!(
+            emitted,
+            vec![
+                ('a', vec![Some(1.0), None]),
+                ('b', vec![Some(3.0), None]),
+                ('c', vec![Some(6.0), Some(4.0)]),
+                ('d', vec![None, None]),
+            ]
+        );
+    }
+
+    #[pg_test]
+    fn test_merge_recent() {
+        let key = |k: &str| Some(k.to_owned());
+        let left = vec![
+            (key("x"), Some(2), 'a'),
+            (key("x"), Some(5), 'b'),
+            (key("y"), Some(5), 'c'),
+            (key("x"), None, 'd'),
+        ];
+        let right = vec![
+            (key("x"), Some(1), 1.0),
+            (key("x"), Some(3), 3.0),
+            (key("y"), Some(3), 7.0),
+            (key("x"), Some(4), 4.0),
+            (key("x"), Some(5), 5.0),
+            (key("x"), Some(6), 6.0),
+        ];
+        let mut emitted = vec![];
+        super::merge_recent(left.into_iter(), right.into_iter(), 2, |row, matches| {
+            let times: Vec<i64> = matches.iter().map(|(time, _)| *time).collect();
+            emitted.push((row, times))
+        });
+        assert_eq!(
+            emitted,
+            vec![
+                ('a', vec![1]),
+                ('b', vec![5, 4]),
+                ('c', vec![3]),
+                ('d', vec![]),
+            ]
+        );
+    }
+
+    #[pg_test]
+    fn test_nearest() {
+        use super::nearest;
+        assert_eq!(nearest(5, Some((2, 'a')), Some((9, 'b'))), Some('a'));
+        assert_eq!(nearest(5, Some((2, 'a')), Some((7, 'b'))), Some('b'));
+        // equally far rows resolve to the earlier one
+        assert_eq!(nearest(5, Some((3, 'a')), Some((7, 'b'))), Some('a'));
+        assert_eq!(nearest(5, None, Some((7, 'b'))), Some('b'));
+        assert_eq!(nearest::<char>(5, None, None), None);
+    }
+
+    #[pg_test]
+    fn test_merge_rows_ties() {
+        let key = || Some(String::new());
+        let left = || vec![(key(), Some(5), 'a')];
+        let right = || vec![(key(), Some(5), 1.0), (key(), Some(5), 2.0)];
+        // sorting is stable, so the row listed last is read last
+        assert_eq!(
+            merge(left(), right(), false, false),
+            vec![('a', Some((5, 2.0)))]
+        );
+        assert_eq!(
+            merge(left(), right(), true, false),
+            vec![('a', Some((5, 2.0)))]
+        );
+    }
+
+    #[pg_test(error = "asof found several rows of a series of t2 at the same time")]
+    fn test_merge_rows_unique() {
+        let key = || Some(String::new());
+        let left = vec![(key(), Some(5), 'a')];
+        let right = vec![(key(), Some(5), 1.0), (key(), Some(5), 2.0)];
+        merge(left, right, false, true);
+    }
+}
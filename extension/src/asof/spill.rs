@@ -0,0 +1,101 @@
+use std::{ffi::CString, rc::Rc};
+
+use pgx::*;
+
+use super::cursor::{ColumnTypes, Row};
+
+// Rows kept in a tuplestore, so that beyond `work_mem` they're written to
+// temporary files instead of being held in memory, and read back in reverse.
+pub struct Spill {
+    store: *mut pg_sys::Tuplestorestate,
+    desc: pg_sys::TupleDesc,
+    types: Rc<ColumnTypes>,
+    len: i64,
+}
+
+impl Spill {
+    pub unsafe fn new(types: &[pg_sys::Oid]) -> Self {
+        let desc = pg_sys::CreateTemplateTupleDesc(types.len() as i32);
+        for (i, typoid) in types.iter().enumerate() {
+            let name = CString::new(format!("c{}", i + 1)).unwrap();
+            pg_sys::TupleDescInitEntry(
+                desc,
+                (i + 1) as pg_sys::AttrNumber,
+                name.as_ptr(),
+                *typoid,
+                -1,
+                0,
+            );
+        }
+        Self {
+            store: pg_sys::tuplestore_begin_heap(true, false, pg_sys::work_mem),
+            desc,
+            types: ColumnTypes::new(types),
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, datums: &[Option<pg_sys::Datum>]) {
+        let mut values: Vec<pg_sys::Datum> = datums
+            .iter()
+            .map(|datum| datum.unwrap_or_else(|| pg_sys::Datum::from(0usize)))
+            .collect();
+        let mut nulls: Vec<bool> = datums.iter().map(Option::is_none).collect();
+        unsafe {
+            pg_sys::tuplestore_putvalues(
+                self.store,
+                self.desc,
+                values.as_mut_ptr(),
+                nulls.as_mut_ptr(),
+            )
+        };
+        self.len += 1;
+    }
+
+    // Reads the rows back, last first.
+    pub fn into_reversed(self) -> ReversedSpill {
+        unsafe {
+            pg_sys::tuplestore_skiptuples(self.store, self.len, true);
+            let slot = pg_sys::MakeSingleTupleTableSlot(self.desc, &pg_sys::TTSOpsMinimalTuple);
+            ReversedSpill { spill: self, slot }
+        }
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        unsafe { pg_sys::tuplestore_end(self.store) }
+    }
+}
+
+pub struct ReversedSpill {
+    spill: Spill,
+    slot: *mut pg_sys::TupleTableSlot,
+}
+
+impl Iterator for ReversedSpill {
+    type Item = Row;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        unsafe {
+            if !pg_sys::tuplestore_gettupleslot(self.spill.store, false, false, self.slot) {
+                return None;
+            }
+            let natts = self.spill.types.len();
+            pg_sys::slot_getsomeattrs_int(self.slot, natts as i32);
+            let values = std::slice::from_raw_parts((*self.slot).tts_values, natts);
+            let nulls = std::slice::from_raw_parts((*self.slot).tts_isnull, natts);
+            let datums = values
+                .iter()
+                .zip(nulls)
+                .map(|(value, is_null)| (!is_null).then(|| *value));
+            Some(Row::copied(datums, self.spill.types.clone()))
+        }
+    }
+}
+
+impl Drop for ReversedSpill {
+    fn drop(&mut self) {
+        unsafe { pg_sys::ExecDropSingleTupleTableSlot(self.slot) }
+    }
+}
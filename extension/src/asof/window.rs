@@ -0,0 +1,184 @@
+use std::collections::{HashMap, VecDeque};
+
+use super::merge::Keyed;
+
+// The count, average, minimum, and maximum of the values in a window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stats {
+    pub count: i64,
+    pub avg: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+// The values of a series in a trailing window, along with their sum and the
+// values that may yet be the window's minimum or maximum, so that each value
+// only needs to be looked at when it enters and leaves the window.
+#[derive(Default)]
+pub struct Window {
+    values: VecDeque<(i64, f64)>,
+    sum: f64,
+    // increasing, and decreasing, respectively
+    mins: VecDeque<(i64, f64)>,
+    maxes: VecDeque<(i64, f64)>,
+}
+
+impl Window {
+    // Adds a value, which mustn't be earlier than any already added.
+    pub fn push(&mut self, time: i64, value: f64) {
+        self.values.push_back((time, value));
+        self.sum += value;
+        while matches!(self.mins.back(), Some(&(_, min)) if min >= value) {
+            self.mins.pop_back();
+        }
+        self.mins.push_back((time, value));
+        while matches!(self.maxes.back(), Some(&(_, max)) if max <= value) {
+            self.maxes.pop_back();
+        }
+        self.maxes.push_back((time, value));
+    }
+
+    // Drops the values at or before `time`.
+    pub fn expire(&mut self, time: i64) {
+        while let Some(&(value_time, value)) = self.values.front() {
+            if value_time > time {
+                break;
+            }
+            self.values.pop_front();
+            self.sum -= value;
+        }
+        if self.values.is_empty() {
+            // don't carry rounding errors over to the next values
+            self.sum = 0.0;
+        }
+        while matches!(self.mins.front(), Some(&(min_time, _)) if min_time <= time) {
+            self.mins.pop_front();
+        }
+        while matches!(self.maxes.front(), Some(&(max_time, _)) if max_time <= time) {
+            self.maxes.pop_front();
+        }
+    }
+
+    pub fn stats(&self) -> Option<Stats> {
+        let count = self.values.len();
+        if count == 0 {
+            return None;
+        }
+        Some(Stats {
+            count: count as i64,
+            avg: self.sum / count as f64,
+            min: self.mins.front()?.1,
+            max: self.maxes.front()?.1,
+        })
+    }
+}
+
+// Merges left and right rows, both in ascending time order with NULL times
+// last, calling `emit` with each left row and the statistics of the right
+// values of the same series after `width` before it, up to and including its
+// time. Only the values of each series within `width` of the last row read
+// are kept in memory. NULL values are skipped, like aggregates skip them.
+pub fn merge_windows<T>(
+    left: impl Iterator<Item = Keyed<T>>,
+    right: impl Iterator<Item = Keyed<Option<f64>>>,
+    width: i64,
+    mut emit: impl FnMut(T, Option<Stats>),
+) {
+    let mut left = left.peekable();
+    let mut right = right.peekable();
+    let mut windows: HashMap<String, Window> = HashMap::new();
+
+    loop {
+        let right_first = match (left.peek(), right.peek()) {
+            // the rest of the right rows are after every left row
+            (None, _) => break,
+            (Some(_), None) => false,
+            (Some((_, left_time, _)), Some((_, right_time, _))) => match (left_time, right_time) {
+                (_, None) => true,
+                (None, _) => false,
+                (Some(left_time), Some(right_time)) => right_time <= left_time,
+            },
+        };
+
+        if right_first {
+            if let (Some(key), Some(time), Some(value)) = right.next().unwrap() {
+                // the left rows still to come are no earlier than this one,
+                // so nothing `width` before it is in any of their windows
+                let window = windows.entry(key).or_default();
+                window.expire(time.saturating_sub(width));
+                window.push(time, value);
+            }
+            continue;
+        }
+
+        let (key, time, row) = left.next().unwrap();
+        let stats = match (key, time) {
+            (Some(key), Some(time)) => windows.get_mut(&key).and_then(|window| {
+                window.expire(time.saturating_sub(width));
+                window.stats()
+            }),
+            _ => None,
+        };
+        emit(row, stats);
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    use super::{Stats, Window};
+
+    #[pg_test]
+    fn test_window() {
+        let mut window = Window::default();
+        assert_eq!(window.stats(), None);
+        for (time, value) in [(1, 3.0), (2, 1.0), (3, 4.0), (4, 2.0)] {
+            window.push(time, value);
+        }
+        let stats = |count, avg, min, max| {
+            Some(Stats {
+                count,
+                avg,
+                min,
+                max,
+            })
+        };
+        assert_eq!(window.stats(), stats(4, 2.5, 1.0, 4.0));
+        window.expire(2);
+        assert_eq!(window.stats(), stats(2, 3.0, 2.0, 4.0));
+        window.expire(3);
+        assert_eq!(window.stats(), stats(1, 2.0, 2.0, 2.0));
+        window.expire(4);
+        assert_eq!(window.stats(), None);
+    }
+
+    #[pg_test]
+    fn test_merge_windows() {
+        let key = |k: &str| Some(k.to_owned());
+        let left = vec![
+            (key("x"), Some(5), 'a'),
+            (key("y"), Some(5), 'b'),
+            (key("x"), Some(20), 'c'),
+            (key("x"), None, 'd'),
+        ];
+        let right = vec![
+            (key("x"), Some(1), Some(1.0)),
+            (key("x"), Some(3), Some(3.0)),
+            (key("x"), Some(4), None),
+            (key("y"), Some(5), Some(5.0)),
+            (key("x"), Some(18), Some(2.0)),
+            (key("x"), None, Some(9.0)),
+        ];
+        let mut emitted = vec![];
+        super::merge_windows(left.into_iter(), right.into_iter(), 3, |row, stats| {
+            emitted.push((row, stats.map(|stats| stats.count)))
+        });
+        assert_eq!(
+            emitted,
+            vec![('a', Some(1)), ('b', Some(1)), ('c', Some(1)), ('d', None)]
+        );
+    }
+}
@@ -0,0 +1,493 @@
+use std::hash::{BuildHasher, Hasher};
+
+use serde::{Deserialize, Serialize};
+
+use pg_sys::Datum;
+use pgx::*;
+
+use bloomfilter::BloomFilter as BloomFilterInternal;
+
+use crate::{
+    aggregate_utils::{get_collation, in_aggregate_context},
+    datum_utils::DatumHashBuilder,
+    flatten,
+    palloc::{Inner, Internal, InternalAsValue, ToInternal},
+    pg_type,
+    raw::bytea,
+    ron_inout_funcs,
+    serialization::{PgCollationId, ShortTypeId},
+};
+
+// The filter is built over the extended hashes of the values, so it also
+// records the type and collation they were hashed with, so that values
+// looked up in it, and other filters unioned with it, are hashed the same way.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BloomFilterTrans {
+    filter: BloomFilterInternal,
+    hasher: DatumHashBuilder,
+}
+
+impl BloomFilterTrans {
+    fn add(&mut self, value: Datum) {
+        let hash = hash_datum(&self.hasher, value);
+        self.filter.insert_hash(hash);
+    }
+
+    fn union(&mut self, other: &BloomFilterTrans) {
+        if self.hasher.type_id != other.hasher.type_id {
+            pgx::error!("cannot roll up bloom filters of different types")
+        }
+        if self.filter.num_bits() != other.filter.num_bits()
+            || self.filter.num_hashes() != other.filter.num_hashes()
+        {
+            pgx::error!("cannot roll up bloom filters of different sizes")
+        }
+        self.filter.union(&other.filter)
+    }
+}
+
+// Filters are stored as varlenas, which can be at most 1GB, so that bounds
+// the bits they can have, leaving some room for the rest of the type.
+const MAX_NUM_BITS: u64 = (0x3FFF_FFFF - 1024) * 8;
+// The optimal number of hashes for the smallest positive fp_rate.
+const MAX_NUM_HASHES: u32 = 1075;
+
+fn hash_datum(hasher: &DatumHashBuilder, value: Datum) -> u64 {
+    let mut hasher = hasher.build_hasher();
+    hasher.write_usize(value.value());
+    hasher.finish()
+}
+
+#[pg_schema]
+pub mod toolkit_experimental {
+    use super::*;
+
+    pg_type! {
+        #[derive(Debug)]
+        struct BloomFilter<'input> {
+            num_bits: u64,
+            // Oids are stored in postgres arrays, so it should be safe to store them
+            // in our types as long as we do send/recv and in/out correctly
+            // see https://github.com/postgres/postgres/blob/b8d0cda53377515ac61357ec4a60e85ca873f486/src/include/utils/array.h#L90
+            element_type: ShortTypeId,
+            collation: PgCollationId,
+            num_hashes: u32,
+            num_words: u32,
+            bits: [u64; self.num_words],
+        }
+    }
+
+    impl BloomFilter<'_> {
+        // Filters can be read from text, so their sizes are checked before
+        // they're used.
+        pub fn to_trans(&self) -> BloomFilterTrans {
+            if self.num_bits < 1 || self.num_bits > MAX_NUM_BITS {
+                pgx::error!(
+                    "invalid bloom filter: it must have between 1 and {} bits",
+                    MAX_NUM_BITS
+                )
+            }
+            if self.num_hashes < 1 || self.num_hashes > MAX_NUM_HASHES {
+                pgx::error!(
+                    "invalid bloom filter: it must have between 1 and {} hashes",
+                    MAX_NUM_HASHES
+                )
+            }
+            if u64::from(self.num_words) != (self.num_bits + 63) / 64 {
+                pgx::error!(
+                    "invalid bloom filter: {} bits need {} words, but it has {}",
+                    self.num_bits,
+                    (self.num_bits + 63) / 64,
+                    self.num_words
+                )
+            }
+            let filter = BloomFilterInternal::from_parts(
+                self.num_bits,
+                self.num_hashes,
+                self.bits.iter().collect(),
+            );
+            let hasher = unsafe {
+                DatumHashBuilder::from_type_id(self.element_type.0, Some(self.collation.0))
+            };
+            BloomFilterTrans { filter, hasher }
+        }
+
+        pub fn from_trans(trans: &BloomFilterTrans) -> Self {
+            let filter = &trans.filter;
+            unsafe {
+                flatten!(BloomFilter {
+                    num_bits: filter.num_bits(),
+                    element_type: ShortTypeId(trans.hasher.type_id),
+                    collation: PgCollationId(trans.hasher.collation),
+                    num_hashes: filter.num_hashes(),
+                    num_words: filter.bits().len() as u32,
+                    bits: filter.bits().into(),
+                })
+            }
+        }
+    }
+
+    ron_inout_funcs!(BloomFilter);
+}
+
+use toolkit_experimental::BloomFilter;
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn bloom_agg_trans(
+    state: Internal,
+    value: Option<AnyElement>,
+    expected_items: i64,
+    fp_rate: f64,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    bloom_agg_trans_inner(
+        unsafe { state.to_inner() },
+        value,
+        expected_items,
+        fp_rate,
+        fcinfo,
+    )
+    .internal()
+}
+
+pub fn bloom_agg_trans_inner(
+    state: Option<Inner<BloomFilterTrans>>,
+    value: Option<AnyElement>,
+    expected_items: i64,
+    fp_rate: f64,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<BloomFilterTrans>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value,
+            };
+            let mut state = match state {
+                None => {
+                    if expected_items < 1 {
+                        pgx::error!("bloom_agg requires expected_items to be at least 1")
+                    }
+                    if fp_rate <= 0.0 || fp_rate >= 1.0 {
+                        pgx::error!("bloom_agg requires a fp_rate in the range (0.0, 1.0)")
+                    }
+                    let (num_bits, num_hashes) =
+                        BloomFilterInternal::optimal_params(expected_items as u64, fp_rate);
+                    if num_bits > MAX_NUM_BITS {
+                        pgx::error!(
+                            "bloom_agg cannot hold {} expected_items at a fp_rate of {} in a filter of at most 1GB",
+                            expected_items,
+                            fp_rate
+                        )
+                    }
+                    let filter = BloomFilterInternal::new(num_bits, num_hashes);
+                    let hasher = DatumHashBuilder::from_type_id(value.oid(), get_collation(fcinfo));
+                    BloomFilterTrans { filter, hasher }.into()
+                }
+                Some(state) => state,
+            };
+            state.add(value.datum());
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn bloom_agg_combine(
+    state1: Internal,
+    state2: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    unsafe { bloom_agg_combine_inner(state1.to_inner(), state2.to_inner(), fcinfo).internal() }
+}
+pub fn bloom_agg_combine_inner(
+    state1: Option<Inner<BloomFilterTrans>>,
+    state2: Option<Inner<BloomFilterTrans>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<BloomFilterTrans>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || match (state1, state2) {
+            (None, None) => None,
+            (None, Some(state2)) => Some(state2.clone().into()),
+            (Some(state1), None) => Some(state1.clone().into()),
+            (Some(state1), Some(state2)) => {
+                let mut state = state1.clone();
+                state.union(&state2);
+                Some(state.into())
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn bloom_agg_serialize(state: Internal) -> bytea {
+    let state: Inner<BloomFilterTrans> = unsafe { state.to_inner().unwrap() };
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn bloom_agg_deserialize(bytes: bytea, _internal: Internal) -> Option<Internal> {
+    let state: BloomFilterTrans = crate::do_deserialize!(bytes, BloomFilterTrans);
+    Inner::from(state).internal()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn bloom_agg_final(
+    state: Internal,
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<BloomFilter<'static>> {
+    let state: Option<&BloomFilterTrans> = unsafe { state.get() };
+    state.map(BloomFilter::from_trans)
+}
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.bloom_agg(\n\
+        value AnyElement, expected_items bigint, fp_rate double precision\n\
+    ) (\n\
+        sfunc = toolkit_experimental.bloom_agg_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.bloom_agg_final,\n\
+        combinefunc = toolkit_experimental.bloom_agg_combine,\n\
+        serialfunc = toolkit_experimental.bloom_agg_serialize,\n\
+        deserialfunc = toolkit_experimental.bloom_agg_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "bloom_agg",
+    requires = [
+        bloom_agg_trans,
+        bloom_agg_final,
+        bloom_agg_combine,
+        bloom_agg_serialize,
+        bloom_agg_deserialize
+    ],
+);
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn bloom_agg_union<'a>(
+    state: Internal,
+    other: Option<BloomFilter<'a>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    bloom_agg_union_inner(unsafe { state.to_inner() }, other, fcinfo).internal()
+}
+pub fn bloom_agg_union_inner(
+    state: Option<Inner<BloomFilterTrans>>,
+    other: Option<BloomFilter>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<BloomFilterTrans>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let other = match other {
+                None => return state,
+                Some(other) => other.to_trans(),
+            };
+            match state {
+                None => Some(other.into()),
+                Some(mut state) => {
+                    state.union(&other);
+                    Some(state)
+                }
+            }
+        })
+    }
+}
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.rollup(\n\
+        filter toolkit_experimental.BloomFilter\n\
+    ) (\n\
+        sfunc = toolkit_experimental.bloom_agg_union,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.bloom_agg_final,\n\
+        combinefunc = toolkit_experimental.bloom_agg_combine,\n\
+        serialfunc = toolkit_experimental.bloom_agg_serialize,\n\
+        deserialfunc = toolkit_experimental.bloom_agg_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "bloom_rollup",
+    requires = [
+        bloom_agg_union,
+        bloom_agg_final,
+        bloom_agg_combine,
+        bloom_agg_serialize,
+        bloom_agg_deserialize
+    ],
+);
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn might_contain<'a>(filter: BloomFilter<'a>, value: AnyElement) -> bool {
+    if value.oid() != filter.element_type.0 {
+        pgx::error!("mismatched types")
+    }
+    let trans = filter.to_trans();
+    let hash = hash_datum(&trans.hasher, value.datum());
+    trans.filter.might_contain_hash(hash)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_bloom_agg() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE filters AS \
+                SELECT toolkit_experimental.bloom_agg(v, 1000, 0.01) AS filter \
+                FROM generate_series(1, 1000) v",
+                None,
+                None,
+            );
+
+            let missing = client
+                .select(
+                    "SELECT count(*) FROM filters, generate_series(1, 1000) v \
+                    WHERE NOT toolkit_experimental.might_contain(filter, v)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<i64>();
+            assert_eq!(missing, Some(0));
+
+            let false_positives = client
+                .select(
+                    "SELECT count(*) FROM filters, generate_series(1001, 11000) v \
+                    WHERE toolkit_experimental.might_contain(filter, v)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            assert!(false_positives < 200, "{} false positives", false_positives);
+
+            let round_trip = client
+                .select(
+                    "SELECT filter::text::toolkit_experimental.BloomFilter::text = filter::text \
+                    FROM filters",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<bool>();
+            assert_eq!(round_trip, Some(true));
+        });
+    }
+
+    #[pg_test]
+    fn test_bloom_agg_text() {
+        Spi::execute(|client| {
+            let (present, absent) = client
+                .select(
+                    "SELECT \
+                        toolkit_experimental.might_contain(filter, 'host-42'::text), \
+                        toolkit_experimental.might_contain(filter, 'host-1042'::text) \
+                    FROM (\
+                        SELECT toolkit_experimental.bloom_agg('host-' || v, 100, 0.0001) AS filter \
+                        FROM generate_series(1, 100) v\
+                    ) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<bool, bool>();
+            assert_eq!(present, Some(true));
+            assert_eq!(absent, Some(false));
+        });
+    }
+
+    #[pg_test]
+    fn test_bloom_agg_rollup() {
+        Spi::execute(|client| {
+            let (missing, rolled_up) = client
+                .select(
+                    "WITH rolled_up AS (\
+                        SELECT toolkit_experimental.rollup(filter) AS filter FROM (\
+                            SELECT toolkit_experimental.bloom_agg(v, 1000, 0.01) AS filter \
+                            FROM generate_series(1, 1000) v \
+                            GROUP BY v % 10\
+                        ) s\
+                    ) \
+                    SELECT \
+                        (SELECT count(*) FROM rolled_up, generate_series(1, 1000) v \
+                            WHERE NOT toolkit_experimental.might_contain(filter, v)), \
+                        (SELECT filter::text FROM rolled_up) = \
+                            (SELECT toolkit_experimental.bloom_agg(v, 1000, 0.01)::text \
+                                FROM generate_series(1, 1000) v)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<i64, bool>();
+            assert_eq!(missing, Some(0));
+            // the union of filters has exactly the bits of one built over
+            // all of their values
+            assert_eq!(rolled_up, Some(true));
+        });
+    }
+
+    #[pg_test(
+        error = "bloom_agg cannot hold 9223372036854775807 expected_items at a fp_rate of 0.01 in a filter of at most 1GB"
+    )]
+    fn test_bloom_agg_too_large() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.bloom_agg(1, 9223372036854775807, 0.01)",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test(error = "invalid bloom filter: it must have between 1 and 1075 hashes")]
+    fn test_bloom_filter_checks_hashes() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.might_contain(\
+                    replace(toolkit_experimental.bloom_agg(v, 10, 0.01)::text, 'num_hashes:7', 'num_hashes:4000000000')\
+                        ::toolkit_experimental.BloomFilter, \
+                    1) \
+                FROM generate_series(1, 10) v",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test(error = "invalid bloom filter: 200 bits need 4 words, but it has 2")]
+    fn test_bloom_filter_checks_words() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.might_contain(\
+                    replace(toolkit_experimental.bloom_agg(v, 10, 0.01)::text, 'num_bits:96', 'num_bits:200')\
+                        ::toolkit_experimental.BloomFilter, \
+                    1) \
+                FROM generate_series(1, 10) v",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_bloom_agg_null_input_yields_null_output() {
+        Spi::execute(|client| {
+            let output = client
+                .select(
+                    "SELECT toolkit_experimental.bloom_agg(NULL::int, 10, 0.01)::TEXT",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(output, None)
+        })
+    }
+}
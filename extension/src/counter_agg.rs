@@ -156,6 +156,11 @@ pub struct CounterSummaryTransState {
     // must first build up a buffer of InternalMetricSummaries, then sort them, then call the combine function in
     // the correct order.
     summary_buffer: Vec<MetricSummary>,
+    // The width in bits of a fixed-width counter, whose decreases are wraparounds rather than resets.
+    // This isn't part of the stable serialized form, the width aggregates serialize it separately
+    // through CounterWidthTransState.
+    #[serde(skip)]
+    counter_width: Option<u8>,
 }
 
 impl CounterSummaryTransState {
@@ -164,6 +169,7 @@ impl CounterSummaryTransState {
             point_buffer: vec![],
             bounds: None,
             summary_buffer: vec![],
+            counter_width: None,
         }
     }
 
@@ -182,6 +188,7 @@ impl CounterSummaryTransState {
         self.point_buffer.sort_unstable_by_key(|p| p.ts);
         let mut iter = self.point_buffer.iter();
         let mut summary = CounterSummaryBuilder::new(iter.next().unwrap(), self.bounds);
+        summary.set_counter_width(self.counter_width);
         for p in iter {
             summary
                 .add_point(p)
@@ -213,6 +220,7 @@ impl CounterSummaryTransState {
         self.summary_buffer.sort_unstable_by_key(|s| s.first.ts);
         let mut sum_iter = self.summary_buffer.iter();
         let mut new_summary = CounterSummaryBuilder::from(sum_iter.next().unwrap().clone());
+        new_summary.set_counter_width(self.counter_width);
         for sum in sum_iter {
             new_summary
                 .combine(sum)
@@ -238,6 +246,35 @@ pub fn counter_summary_trans_deserialize_inner(bytes: bytea) -> Inner<CounterSum
     c.into()
 }
 
+// The serialized partial state of the toolkit_experimental counter_width aggregates.
+#[derive(Serialize, Deserialize)]
+struct CounterWidthTransState {
+    counter_width: Option<u8>,
+    state: CounterSummaryTransState,
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn counter_width_trans_serialize(state: Internal) -> bytea {
+    let state: &mut CounterSummaryTransState = unsafe { state.get_mut().unwrap() };
+    state.combine_summaries();
+    let state = CounterWidthTransState {
+        counter_width: state.counter_width,
+        state: state.clone(),
+    };
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(strict, immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn counter_width_trans_deserialize(bytes: bytea, _internal: Internal) -> Option<Internal> {
+    counter_width_trans_deserialize_inner(bytes).internal()
+}
+pub fn counter_width_trans_deserialize_inner(bytes: bytea) -> Inner<CounterSummaryTransState> {
+    let c: CounterWidthTransState = crate::do_deserialize!(bytes, CounterWidthTransState);
+    let mut state = c.state;
+    state.counter_width = c.counter_width;
+    state.into()
+}
+
 #[pg_extern(immutable, parallel_safe)]
 pub fn counter_agg_trans(
     state: Internal,
@@ -254,6 +291,16 @@ pub fn counter_agg_trans_inner(
     val: Option<f64>,
     bounds: Option<tstzrange>,
     fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<CounterSummaryTransState>> {
+    counter_agg_width_trans_inner(state, ts, val, bounds, None, fcinfo)
+}
+pub fn counter_agg_width_trans_inner(
+    state: Option<Inner<CounterSummaryTransState>>,
+    ts: Option<crate::raw::TimestampTz>,
+    val: Option<f64>,
+    bounds: Option<tstzrange>,
+    counter_width: Option<i32>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Inner<CounterSummaryTransState>> {
     unsafe {
         in_aggregate_context(fcinfo, || {
@@ -262,12 +309,23 @@ pub fn counter_agg_trans_inner(
                 (None, _) => return state,
                 (Some(ts), Some(val)) => TSPoint { ts: ts.into(), val },
             };
+            let counter_width = counter_width.map(validate_counter_width);
+            if let Some(width) = counter_width {
+                if p.val < 0.0 || p.val >= 2f64.powi(width as i32) {
+                    pgx::error!(
+                        "counter value {} does not fit in a {}-bit counter",
+                        p.val,
+                        width
+                    )
+                }
+            }
             match state {
                 None => {
                     let mut s = CounterSummaryTransState::new();
                     if let Some(r) = bounds {
                         s.bounds = get_range(r.0.cast_mut_ptr());
                     }
+                    s.counter_width = counter_width;
                     s.push_point(p);
                     Some(s.into())
                 }
@@ -290,6 +348,52 @@ pub fn counter_agg_trans_no_bounds(
     counter_agg_trans_inner(unsafe { state.to_inner() }, ts, val, None, fcinfo).internal()
 }
 
+fn validate_counter_width(width: i32) -> u8 {
+    if !(1..=64).contains(&width) {
+        pgx::error!("counter width must be between 1 and 64 bits")
+    }
+    width as u8
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn counter_agg_width_trans(
+    state: Internal,
+    ts: Option<crate::raw::TimestampTz>,
+    val: Option<f64>,
+    bounds: Option<tstzrange>,
+    counter_width: Option<i32>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    counter_agg_width_trans_inner(
+        unsafe { state.to_inner() },
+        ts,
+        val,
+        bounds,
+        counter_width,
+        fcinfo,
+    )
+    .internal()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn counter_agg_width_trans_no_bounds(
+    state: Internal,
+    ts: Option<crate::raw::TimestampTz>,
+    val: Option<f64>,
+    counter_width: Option<i32>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    counter_agg_width_trans_inner(
+        unsafe { state.to_inner() },
+        ts,
+        val,
+        None,
+        counter_width,
+        fcinfo,
+    )
+    .internal()
+}
+
 #[pg_extern(immutable, parallel_safe)]
 pub fn counter_agg_summary_trans<'a>(
     state: Internal,
@@ -302,12 +406,21 @@ pub fn counter_agg_summary_trans_inner(
     state: Option<Inner<CounterSummaryTransState>>,
     value: Option<CounterSummary>,
     fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<CounterSummaryTransState>> {
+    counter_agg_summary_width_trans_inner(state, value, None, fcinfo)
+}
+pub fn counter_agg_summary_width_trans_inner(
+    state: Option<Inner<CounterSummaryTransState>>,
+    value: Option<CounterSummary>,
+    counter_width: Option<i32>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Inner<CounterSummaryTransState>> {
     unsafe {
         in_aggregate_context(fcinfo, || match (state, value) {
             (state, None) => state,
             (None, Some(value)) => {
                 let mut state = CounterSummaryTransState::new();
+                state.counter_width = counter_width.map(validate_counter_width);
                 state
                     .summary_buffer
                     .push(value.to_internal_counter_summary());
@@ -323,6 +436,17 @@ pub fn counter_agg_summary_trans_inner(
     }
 }
 
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn counter_agg_summary_width_trans<'a>(
+    state: Internal,
+    value: Option<CounterSummary<'a>>,
+    counter_width: Option<i32>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    counter_agg_summary_width_trans_inner(unsafe { state.to_inner() }, value, counter_width, fcinfo)
+        .internal()
+}
+
 #[pg_extern(immutable, parallel_safe)]
 pub fn counter_agg_combine(
     state1: Internal,
@@ -465,6 +589,75 @@ extension_sql!(
     ],
 );
 
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.counter_agg( ts timestamptz, value DOUBLE PRECISION, bounds tstzrange, counter_width INTEGER )\n\
+    (\n\
+        sfunc = toolkit_experimental.counter_agg_width_trans,\n\
+        stype = internal,\n\
+        finalfunc = counter_agg_final,\n\
+        combinefunc = counter_agg_combine,\n\
+        serialfunc = toolkit_experimental.counter_width_trans_serialize,\n\
+        deserialfunc = toolkit_experimental.counter_width_trans_deserialize,\n\
+        parallel = restricted\n\
+    );\n\
+",
+    name = "counter_agg_width",
+    requires = [
+        counter_agg_width_trans,
+        counter_agg_final,
+        counter_agg_combine,
+        counter_width_trans_serialize,
+        counter_width_trans_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.counter_agg( ts timestamptz, value DOUBLE PRECISION, counter_width INTEGER )\n\
+    (\n\
+        sfunc = toolkit_experimental.counter_agg_width_trans_no_bounds,\n\
+        stype = internal,\n\
+        finalfunc = counter_agg_final,\n\
+        combinefunc = counter_agg_combine,\n\
+        serialfunc = toolkit_experimental.counter_width_trans_serialize,\n\
+        deserialfunc = toolkit_experimental.counter_width_trans_deserialize,\n\
+        parallel = restricted\n\
+    );\n\
+",
+    name = "counter_agg_width_no_bounds",
+    requires = [
+        counter_agg_width_trans_no_bounds,
+        counter_agg_final,
+        counter_agg_combine,
+        counter_width_trans_serialize,
+        counter_width_trans_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.rollup(cs CounterSummary, counter_width INTEGER)\n\
+    (\n\
+        sfunc = toolkit_experimental.counter_agg_summary_width_trans,\n\
+        stype = internal,\n\
+        finalfunc = counter_agg_final,\n\
+        combinefunc = counter_agg_combine,\n\
+        serialfunc = toolkit_experimental.counter_width_trans_serialize,\n\
+        deserialfunc = toolkit_experimental.counter_width_trans_deserialize,\n\
+        parallel = restricted\n\
+    );\n\
+",
+    name = "counter_rollup_width",
+    requires = [
+        counter_agg_summary_width_trans,
+        counter_agg_final,
+        counter_agg_combine,
+        counter_width_trans_serialize,
+        counter_width_trans_deserialize
+    ],
+);
+
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_counter_agg_delta<'a>(
@@ -1164,6 +1357,40 @@ mod tests {
         }
     }
 
+    #[pg_test]
+    fn test_counter_width_byte_io() {
+        unsafe {
+            use std::ptr;
+            const BASE: i64 = 631152000000000;
+            const MIN: i64 = 60000000;
+            let state = counter_agg_width_trans_inner(
+                None,
+                Some(BASE.into()),
+                Some(65530.0),
+                None,
+                Some(16),
+                ptr::null_mut(),
+            );
+            let state = counter_agg_width_trans_inner(
+                state,
+                Some((BASE + MIN).into()),
+                Some(4.0),
+                None,
+                Some(16),
+                ptr::null_mut(),
+            );
+
+            let mut control = state.unwrap();
+            let buffer =
+                counter_width_trans_serialize(Inner::from(control.clone()).internal().unwrap());
+            let new_state = counter_width_trans_deserialize_inner(buffer);
+
+            control.combine_summaries();
+            assert_eq!(new_state.counter_width, Some(16));
+            assert_eq!(&*new_state, &*control);
+        }
+    }
+
     #[pg_test]
     fn delta_after_counter_decrease() {
         Spi::execute(|client| {
@@ -1175,6 +1402,64 @@ mod tests {
         });
     }
 
+    #[pg_test]
+    fn delta_after_counter_wraparound() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select("SET TIME ZONE 'UTC'", None, None);
+            client.select(
+                r#"INSERT INTO test VALUES
+                    ('2020-01-01 00:00:00+00', 4294967290.0),
+                    ('2020-01-01 00:01:00+00', 4294967295.0),
+                    ('2020-01-01 00:02:00+00', 5.0),
+                    ('2020-01-01 00:03:00+00', 8.0)"#,
+                None,
+                None,
+            );
+
+            // a reset adds the whole previous value, so this is 8 + 4294967295 - 4294967290
+            let stmt = "SELECT delta(counter_agg(ts, val)) FROM test";
+            assert_eq!(13.0, select_one!(client, stmt, f64));
+
+            // a 32-bit counter wraps from 4294967295 to 0, so this is 8 + 4294967296 - 4294967290
+            let stmt = "SELECT delta(toolkit_experimental.counter_agg(ts, val, 32)) FROM test";
+            assert_eq!(14.0, select_one!(client, stmt, f64));
+            let stmt = "SELECT num_resets(toolkit_experimental.counter_agg(ts, val, 32)) FROM test";
+            assert_eq!(0, select_one!(client, stmt, i64));
+            let stmt = "SELECT delta(toolkit_experimental.counter_agg(ts, val, '[2020-01-01, 2020-01-02)', 32)) FROM test";
+            assert_eq!(14.0, select_one!(client, stmt, f64));
+
+            // wrapping between summaries is only corrected by a rollup which knows the width
+            let stmt = "SELECT delta(toolkit_experimental.rollup(cs, 32)) FROM (
+                SELECT toolkit_experimental.counter_agg(ts, val, 32) AS cs
+                FROM test
+                GROUP BY ts < '2020-01-01 00:02:00+00'
+            ) summaries";
+            assert_eq!(14.0, select_one!(client, stmt, f64));
+            let stmt = "SELECT delta(rollup(cs)) FROM (
+                SELECT toolkit_experimental.counter_agg(ts, val, 32) AS cs
+                FROM test
+                GROUP BY ts < '2020-01-01 00:02:00+00'
+            ) summaries";
+            assert_eq!(13.0, select_one!(client, stmt, f64));
+        });
+    }
+
+    #[pg_test(error = "counter value 70000 does not fit in a 16-bit counter")]
+    fn counter_value_too_wide() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.counter_agg('2020-01-01 00:00:00+00', 70000, 16)",
+                None,
+                None,
+            );
+        });
+    }
+
     #[pg_test]
     fn delta_after_counter_increase() {
         Spi::execute(|client| {
@@ -4,8 +4,9 @@ use aggregate_builder::aggregate;
 use countminsketch::{CountMinHashFn, CountMinSketch as CountMinSketchInternal};
 
 use crate::{
+    aggregate_utils::in_aggregate_context,
     flatten,
-    palloc::{Inner, Internal},
+    palloc::{Inner, Internal, InternalAsValue, ToInternal},
     pg_type,
     raw::bytea,
     ron_inout_funcs,
@@ -126,6 +127,63 @@ impl toolkit_experimental::count_min_sketch {
     }
 }
 
+// Shares its state, and so its combine, serialize and final functions, with
+// the count_min_sketch aggregate, which wraps the state in an Option.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn count_min_sketch_rollup_trans<'a>(
+    state: Internal,
+    value: Option<CountMinSketch<'a>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    count_min_sketch_rollup_trans_inner(unsafe { state.to_inner() }, value, fcinfo).internal()
+}
+
+pub fn count_min_sketch_rollup_trans_inner(
+    state: Option<Inner<Option<CountMinSketchInternal>>>,
+    value: Option<CountMinSketch>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<Option<CountMinSketchInternal>>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value.to_internal_countminsketch(),
+            };
+            match state {
+                None => Some(Some(value).into()),
+                Some(mut state) => {
+                    match &mut *state {
+                        Some(sketch) => sketch.combine(value),
+                        None => *state = Some(value),
+                    }
+                    Some(state)
+                }
+            }
+        })
+    }
+}
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.rollup(\n\
+        sketch toolkit_experimental.CountMinSketch\n\
+    ) (\n\
+        sfunc = toolkit_experimental.count_min_sketch_rollup_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.count_min_sketch_finally_fn_outer,\n\
+        combinefunc = toolkit_experimental.count_min_sketch_combine_fn_outer,\n\
+        serialfunc = toolkit_experimental.count_min_sketch_serialize_fn_outer,\n\
+        deserialfunc = toolkit_experimental.count_min_sketch_deserialize_fn_outer,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "count_min_sketch_rollup",
+    requires = [
+        count_min_sketch_rollup_trans,
+        "count_min_sketch_extension_sql"
+    ],
+);
+
 #[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
 pub fn approx_count<'a>(item: String, aggregate: Option<CountMinSketch<'a>>) -> Option<i64> {
     aggregate.map(|sketch| CountMinSketch::to_internal_countminsketch(&sketch).estimate(item))
@@ -219,6 +277,29 @@ mod tests {
         });
     }
 
+    #[pg_test]
+    fn test_countminsketch_rollup() {
+        Spi::execute(|client| {
+            let (rolled_up, direct) = client
+                .select(
+                    "SELECT
+                        toolkit_experimental.rollup(sketch)::TEXT,
+                        (SELECT toolkit_experimental.count_min_sketch(v::text, 0.01, 0.01)::TEXT
+                            FROM generate_series(1, 100) v)
+                    FROM (
+                        SELECT toolkit_experimental.count_min_sketch(v::text, 0.01, 0.01) AS sketch
+                        FROM generate_series(1, 100) v
+                        GROUP BY v % 3
+                    ) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(rolled_up, direct);
+        });
+    }
+
     #[pg_test]
     fn countminsketch_io_test() {
         Spi::execute(|client| {
@@ -56,6 +56,47 @@ pub fn interval_to_ms(ref_time: &crate::raw::TimestampTz, interval: &crate::raw:
     bound.value() as i64 - ref_time.0.value() as i64
 }
 
+// Inverse of the above; builds an INTERVAL of `ms` microseconds justified into
+// days, matching what postgres' own timestamp subtraction produces (otherwise
+// large values print as e.g. `8760:02:00` instead of `365 days 00:02:00`).
+pub fn ms_to_interval(ms: i64) -> crate::raw::Interval {
+    let interval = pg_sys::Interval {
+        time: ms,
+        ..Default::default()
+    };
+    let interval = unsafe {
+        let ptr = pg_sys::palloc(size_of::<pg_sys::Interval>()) as *mut pg_sys::Interval;
+        *ptr = interval;
+        ptr
+    };
+    let function_args = vec![Some(pgx::Datum::from(interval))];
+    unsafe { pgx::direct_function_call(pg_sys::interval_justify_hours, function_args) }
+        .expect("interval_justify_hours does not return None")
+}
+
+// The text output of a TIMESTAMPTZ or INTERVAL, as psql would display it in
+// the current session.
+pub fn timestamptz_to_string(ts: i64) -> String {
+    extern "C" {
+        fn timestamptz_out(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
+    }
+    unsafe { datum_out_to_string(Some(timestamptz_out), pgx::Datum::from(ts)) }
+}
+
+pub fn interval_to_string(interval: &crate::raw::Interval) -> String {
+    extern "C" {
+        fn interval_out(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum;
+    }
+    unsafe { datum_out_to_string(Some(interval_out), interval.0) }
+}
+
+unsafe fn datum_out_to_string(out_fn: pg_sys::PGFunction, datum: Datum) -> String {
+    let out = pg_sys::DirectFunctionCall1Coll(out_fn, pg_sys::InvalidOid, datum);
+    std::ffi::CStr::from_ptr(out.cast_mut_ptr())
+        .to_string_lossy()
+        .into_owned()
+}
+
 pub struct TextSerializableDatumWriter {
     flinfo: pg_sys::FmgrInfo,
 }
@@ -233,6 +233,36 @@ impl SpaceSavingTransState {
         }
     }
 
+    // Rebuilds the state an aggregate was created from, so it can be rolled up
+    // with others.  The aggregate doesn't record the collation it was built
+    // with, so the type's default collation is used.
+    fn from_aggregate_parts(
+        typ: pg_sys::Oid,
+        values: impl Iterator<Item = Datum>,
+        counts: impl Iterator<Item = u64>,
+        overcounts: impl Iterator<Item = u64>,
+        total_vals: u64,
+        freq_param: f64,
+        topn: u32,
+    ) -> Self {
+        let mut state = if topn == 0 {
+            SpaceSavingTransState::freq_agg_from_type_id(freq_param, typ, None)
+        } else {
+            SpaceSavingTransState::topn_agg_from_type_id(freq_param, topn, typ, None)
+        };
+        state.total_vals = total_vals;
+        state.entries = values
+            .zip(counts.zip(overcounts))
+            .map(|(value, (count, overcount))| SpaceSavingEntry {
+                value: unsafe { deep_copy_datum(value, typ) },
+                count,
+                overcount,
+            })
+            .collect();
+        state.update_all_map_indices();
+        state
+    }
+
     fn type_oid(&self) -> Oid {
         self.indices.typoid()
     }
@@ -503,6 +533,48 @@ pub mod toolkit_experimental {
     ron_inout_funcs!(SpaceSavingTextAggregate);
 }
 
+impl From<SpaceSavingAggregate<'_>> for SpaceSavingTransState {
+    fn from(agg: SpaceSavingAggregate<'_>) -> Self {
+        SpaceSavingTransState::from_aggregate_parts(
+            agg.type_oid,
+            agg.datums.iter(),
+            agg.counts.iter(),
+            agg.overcounts.iter(),
+            agg.values_seen,
+            agg.freq_param,
+            agg.topn as u32,
+        )
+    }
+}
+
+impl From<SpaceSavingBigIntAggregate<'_>> for SpaceSavingTransState {
+    fn from(agg: SpaceSavingBigIntAggregate<'_>) -> Self {
+        SpaceSavingTransState::from_aggregate_parts(
+            pg_sys::INT8OID,
+            agg.datums.iter().map(Datum::from),
+            agg.counts.iter(),
+            agg.overcounts.iter(),
+            agg.values_seen,
+            agg.freq_param,
+            agg.topn,
+        )
+    }
+}
+
+impl From<SpaceSavingTextAggregate<'_>> for SpaceSavingTransState {
+    fn from(agg: SpaceSavingTextAggregate<'_>) -> Self {
+        SpaceSavingTransState::from_aggregate_parts(
+            pg_sys::TEXTOID,
+            agg.datums.iter(),
+            agg.counts.iter(),
+            agg.overcounts.iter(),
+            agg.values_seen,
+            agg.freq_param,
+            agg.topn,
+        )
+    }
+}
+
 #[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
 pub fn topn_agg_trans(
     state: Internal,
@@ -715,6 +787,60 @@ pub fn space_saving_combine_inner(
     }
 }
 
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn space_saving_rollup_trans(
+    state: Internal,
+    value: Option<SpaceSavingAggregate<'_>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    space_saving_rollup_trans_inner(unsafe { state.to_inner() }, value, fcinfo).internal()
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn space_saving_bigint_rollup_trans(
+    state: Internal,
+    value: Option<SpaceSavingBigIntAggregate<'_>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    space_saving_rollup_trans_inner(unsafe { state.to_inner() }, value, fcinfo).internal()
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn space_saving_text_rollup_trans(
+    state: Internal,
+    value: Option<SpaceSavingTextAggregate<'_>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    space_saving_rollup_trans_inner(unsafe { state.to_inner() }, value, fcinfo).internal()
+}
+
+pub fn space_saving_rollup_trans_inner<A: Into<SpaceSavingTransState>>(
+    state: Option<Inner<SpaceSavingTransState>>,
+    value: Option<A>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<SpaceSavingTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value: SpaceSavingTransState = match value {
+                None => return state,
+                Some(value) => value.into(),
+            };
+            match state {
+                None => Some(value.into()),
+                Some(state) => {
+                    if state.type_oid() != value.type_oid() {
+                        pgx::error!("cannot roll up frequency aggregates of different types")
+                    }
+                    if state.topn != value.topn || state.freq_param != value.freq_param {
+                        pgx::error!("cannot roll up frequency aggregates with different parameters")
+                    }
+                    Some(SpaceSavingTransState::combine(&*state, &value).into())
+                }
+            }
+        })
+    }
+}
+
 #[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
 fn space_saving_final(
     state: Internal,
@@ -970,6 +1096,222 @@ extension_sql!(
     ],
 );
 
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.raw_mcv_agg(\n\
+        count integer, value AnyElement\n\
+    ) (\n\
+        sfunc = toolkit_experimental.topn_agg_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.space_saving_final,\n\
+        combinefunc = toolkit_experimental.space_saving_combine,\n\
+        serialfunc = toolkit_experimental.space_saving_serialize,\n\
+        deserialfunc = toolkit_experimental.space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "mcv_agg",
+    requires = [
+        topn_agg_trans,
+        space_saving_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.mcv_agg(\n\
+        count integer, value INT8\n\
+    ) (\n\
+        sfunc = toolkit_experimental.topn_agg_bigint_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.space_saving_bigint_final,\n\
+        combinefunc = toolkit_experimental.space_saving_combine,\n\
+        serialfunc = toolkit_experimental.space_saving_serialize,\n\
+        deserialfunc = toolkit_experimental.space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "mcv_bigint_agg",
+    requires = [
+        topn_agg_bigint_trans,
+        space_saving_bigint_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.mcv_agg(\n\
+        count integer, value TEXT\n\
+    ) (\n\
+        sfunc = toolkit_experimental.topn_agg_text_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.space_saving_text_final,\n\
+        combinefunc = toolkit_experimental.space_saving_combine,\n\
+        serialfunc = toolkit_experimental.space_saving_serialize,\n\
+        deserialfunc = toolkit_experimental.space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "mcv_text_agg",
+    requires = [
+        topn_agg_text_trans,
+        space_saving_text_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.raw_mcv_agg(\n\
+        count integer, skew double precision, value AnyElement\n\
+    ) (\n\
+        sfunc = toolkit_experimental.topn_agg_with_skew_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.space_saving_final,\n\
+        combinefunc = toolkit_experimental.space_saving_combine,\n\
+        serialfunc = toolkit_experimental.space_saving_serialize,\n\
+        deserialfunc = toolkit_experimental.space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "mcv_agg_with_skew",
+    requires = [
+        topn_agg_with_skew_trans,
+        space_saving_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.mcv_agg(\n\
+        count integer, skew double precision, value INT8\n\
+    ) (\n\
+        sfunc = toolkit_experimental.topn_agg_with_skew_bigint_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.space_saving_bigint_final,\n\
+        combinefunc = toolkit_experimental.space_saving_combine,\n\
+        serialfunc = toolkit_experimental.space_saving_serialize,\n\
+        deserialfunc = toolkit_experimental.space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "mcv_agg_with_skew_bigint",
+    requires = [
+        topn_agg_with_skew_bigint_trans,
+        space_saving_bigint_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.mcv_agg(\n\
+        count integer, skew double precision, value TEXT\n\
+    ) (\n\
+        sfunc = toolkit_experimental.topn_agg_with_skew_text_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.space_saving_text_final,\n\
+        combinefunc = toolkit_experimental.space_saving_combine,\n\
+        serialfunc = toolkit_experimental.space_saving_serialize,\n\
+        deserialfunc = toolkit_experimental.space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "mcv_agg_with_skew_text",
+    requires = [
+        topn_agg_with_skew_text_trans,
+        space_saving_text_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.rollup(\n\
+        agg toolkit_experimental.SpaceSavingAggregate\n\
+    ) (\n\
+        sfunc = toolkit_experimental.space_saving_rollup_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.space_saving_final,\n\
+        combinefunc = toolkit_experimental.space_saving_combine,\n\
+        serialfunc = toolkit_experimental.space_saving_serialize,\n\
+        deserialfunc = toolkit_experimental.space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "space_saving_rollup",
+    requires = [
+        space_saving_rollup_trans,
+        space_saving_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.rollup(\n\
+        agg toolkit_experimental.SpaceSavingBigIntAggregate\n\
+    ) (\n\
+        sfunc = toolkit_experimental.space_saving_bigint_rollup_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.space_saving_bigint_final,\n\
+        combinefunc = toolkit_experimental.space_saving_combine,\n\
+        serialfunc = toolkit_experimental.space_saving_serialize,\n\
+        deserialfunc = toolkit_experimental.space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "space_saving_bigint_rollup",
+    requires = [
+        space_saving_bigint_rollup_trans,
+        space_saving_bigint_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.rollup(\n\
+        agg toolkit_experimental.SpaceSavingTextAggregate\n\
+    ) (\n\
+        sfunc = toolkit_experimental.space_saving_text_rollup_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.space_saving_text_final,\n\
+        combinefunc = toolkit_experimental.space_saving_combine,\n\
+        serialfunc = toolkit_experimental.space_saving_serialize,\n\
+        deserialfunc = toolkit_experimental.space_saving_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "space_saving_text_rollup",
+    requires = [
+        space_saving_text_rollup_trans,
+        space_saving_text_final,
+        space_saving_combine,
+        space_saving_serialize,
+        space_saving_deserialize
+    ],
+);
+
 #[pg_extern(
     immutable,
     parallel_safe,
@@ -1790,6 +2132,81 @@ mod tests {
         });
     }
 
+    #[pg_test]
+    fn test_mcv_agg() {
+        Spi::execute(|client| {
+            setup_with_test_table(&client);
+
+            let (mcv, topn) = client
+                .select(
+                    "SELECT mcv_agg(5, data)::TEXT, topn_agg(5, data)::TEXT FROM test",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(mcv, topn);
+
+            let rows: Vec<i64> = client
+                .select("SELECT topn(mcv_agg(5, data)) FROM test", None, None)
+                .map(|row| row[1].value::<i64>().unwrap())
+                .collect();
+            assert_eq!(rows, vec![19, 18, 17, 16, 15]);
+        });
+    }
+
+    #[pg_test]
+    fn test_frequency_rollup() {
+        Spi::execute(|client| {
+            setup_with_test_table(&client);
+
+            // each half holds few enough distinct values for their counts to
+            // be exact, so rolling them up gives the same aggregate as
+            // building it over all of the values
+            let (rollup, whole) = client
+                .select(
+                    "SELECT \
+                        (SELECT rollup(agg)::TEXT FROM (\
+                            SELECT mcv_agg(5, data) AS agg FROM test GROUP BY data % 2\
+                        ) s), \
+                        (SELECT mcv_agg(5, data)::TEXT FROM test)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(rollup, whole);
+
+            let rows: Vec<String> = client
+                .select(
+                    "SELECT topn(rollup(agg), 3) FROM (\
+                        SELECT mcv_agg(5, data::text) AS agg FROM test GROUP BY data % 3\
+                    ) s",
+                    None,
+                    None,
+                )
+                .map(|row| row[1].value::<String>().unwrap())
+                .collect();
+            assert_eq!(rows, vec!["19", "18", "17"]);
+
+            let (min_freq, max_freq) = client
+                .select(
+                    "SELECT \
+                        min_frequency(rollup(agg), 19), \
+                        max_frequency(rollup(agg), 19) \
+                    FROM (\
+                        SELECT freq_agg(0.05, data) AS agg FROM test GROUP BY data % 4\
+                    ) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(min_freq, Some(20.0 / 210.0));
+            assert_eq!(max_freq, Some(20.0 / 210.0));
+        });
+    }
+
     #[pg_test]
     fn test_frequency_getters() {
         Spi::execute(|client| {
@@ -0,0 +1,2794 @@
+//! SELECT duration_live(health) FROM (
+//!   SELECT heartbeat_agg(ts, '2022-01-01', '1 day', '1 minute') as health FROM ...
+//! );
+//!
+//! The aggregate only stores the ranges of time during which the system was
+//! considered live, where each heartbeat keeps the system live for the
+//! `heartbeat_liveness` interval following it.
+
+use std::cmp::{max, min};
+
+use pgx::{iter::TableIterator, *};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    datum_utils::{interval_to_ms, interval_to_string, ms_to_interval, timestamptz_to_string},
+    flatten,
+    palloc::{Inner, Internal, InternalAsValue, ToInternal},
+    pg_type,
+    range::get_range,
+    raw::{bytea, tstzrange, Interval, TimestampTz},
+    ron_inout_funcs,
+};
+
+// How many heartbeats to absorb before folding them into the liveness ranges
+const BUFFER_SIZE: usize = 1000;
+
+// Intermediate form used to collect the heartbeat times
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeartbeatTransState {
+    start: i64,
+    end: i64,
+    last: i64,
+    interval_len: i64,
+    buffer: Vec<i64>,
+    liveness: Vec<(i64, i64)>, // sorted, non-overlapping, non-adjacent
+    // Ranges between `start` and `end` not covered by any of the aggregates
+    // rolled up into this one, sorted and non-overlapping.
+    uncovered: Vec<(i64, i64)>,
+}
+
+impl HeartbeatTransState {
+    pub fn new(start: i64, end: i64, interval_len: i64) -> Self {
+        if end <= start {
+            pgx::ereport!(
+                PgLogLevel::ERROR,
+                PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                "heartbeat_agg requires a positive agg_duration"
+            );
+        }
+        HeartbeatTransState {
+            start,
+            end,
+            last: i64::MIN,
+            interval_len,
+            buffer: vec![],
+            liveness: vec![],
+            uncovered: vec![],
+        }
+    }
+
+    pub fn insert(&mut self, time: i64) {
+        if time < self.start || time >= self.end {
+            pgx::ereport!(
+                PgLogLevel::ERROR,
+                PgSqlErrorCode::ERRCODE_DATETIME_VALUE_OUT_OF_RANGE,
+                &format!(
+                    "heartbeat at {} is outside of the range covered by heartbeat_agg, all points must occur in the 'agg_duration' interval after 'agg_start'",
+                    timestamptz_to_string(time)
+                )
+            );
+        }
+        if self.buffer.len() >= BUFFER_SIZE {
+            self.process_batch();
+        }
+        self.buffer.push(time);
+    }
+
+    pub fn process_batch(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+
+        self.buffer.sort_unstable();
+        self.last = max(self.last, *self.buffer.last().unwrap());
+
+        let mut new_intervals = vec![];
+        let mut heartbeats = std::mem::take(&mut self.buffer).into_iter();
+
+        let mut start = heartbeats.next().unwrap();
+        let mut bound = start + self.interval_len;
+        for heartbeat in heartbeats {
+            if heartbeat <= bound {
+                bound = heartbeat + self.interval_len;
+            } else {
+                new_intervals.push((start, min(bound, self.end)));
+                start = heartbeat;
+                bound = start + self.interval_len;
+            }
+        }
+        new_intervals.push((start, min(bound, self.end)));
+
+        let old_intervals = std::mem::take(&mut self.liveness);
+        self.liveness = merge_intervals(old_intervals, new_intervals);
+    }
+
+    // In general the covered range is fixed when the aggregate is created, but
+    // when rolling up aggregates we need to grow it to cover both inputs.
+    fn extend_covered_interval(&mut self, new_start: i64, new_end: i64) {
+        debug_assert!(new_start <= self.start && new_end >= self.end);
+
+        // The liveness of the final heartbeat was clipped to the old end of
+        // the range, restore whatever portion now falls inside the range.
+        // If the end of the range was excluded, that portion is restored on
+        // its own, from the old end.
+        if new_end > self.end && self.last != i64::MIN && self.last + self.interval_len > self.end {
+            let restored_end = min(self.last + self.interval_len, new_end);
+            match self.liveness.last_mut() {
+                Some(last_interval) if last_interval.1 == self.end => {
+                    last_interval.1 = restored_end
+                }
+                _ => self.liveness.push((self.end, restored_end)),
+            }
+        }
+
+        self.start = new_start;
+        self.end = new_end;
+    }
+
+    pub fn combine(&mut self, mut other: HeartbeatTransState) {
+        if self.interval_len != other.interval_len {
+            pgx::ereport!(
+                PgLogLevel::ERROR,
+                PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                &format!(
+                    "unable to combine heartbeat aggregates with different liveness intervals ({} and {})",
+                    interval_to_string(&ms_to_interval(self.interval_len)),
+                    interval_to_string(&ms_to_interval(other.interval_len))
+                )
+            );
+        }
+        self.process_batch();
+        other.process_batch();
+
+        let covered = merge_intervals(self.covered_ranges(), other.covered_ranges());
+        let min_start = min(self.start, other.start);
+        let max_end = max(self.end, other.end);
+        self.extend_covered_interval(min_start, max_end);
+        other.extend_covered_interval(min_start, max_end);
+
+        // If the inputs' ranges don't touch we know nothing about the time
+        // between them, so it's tracked separately rather than treated as
+        // dead.  This also drops any liveness carried into that time from
+        // the last heartbeat before it.
+        self.uncovered = subtract_intervals(&[(min_start, max_end)], &covered);
+        let old_intervals = std::mem::take(&mut self.liveness);
+        self.liveness = subtract_intervals(
+            &merge_intervals(old_intervals, other.liveness),
+            &self.uncovered,
+        );
+        self.last = max(self.last, other.last);
+    }
+
+    fn covered_ranges(&self) -> Vec<(i64, i64)> {
+        subtract_intervals(&[(self.start, self.end)], &self.uncovered)
+    }
+
+    // Aggregates over a coverage range with an unbounded end are stored with
+    // `i64::MIN`/`i64::MAX` as that end until they're finalized, at which
+    // point the range is narrowed to start at the first heartbeat and/or end
+    // when the last heartbeat's liveness runs out.
+    fn resolve_unbounded_range(&mut self) {
+        if self.start == i64::MIN {
+            if let Some(&(first_start, _)) = self.liveness.first() {
+                self.start = first_start;
+            }
+        }
+        if self.end == i64::MAX {
+            if let Some(&(_, last_end)) = self.liveness.last() {
+                self.end = last_end;
+            }
+        }
+        let (start, end) = (self.start, self.end);
+        self.uncovered = std::mem::take(&mut self.uncovered)
+            .into_iter()
+            .map(|(gap_start, gap_end)| (max(gap_start, start), min(gap_end, end)))
+            .filter(|(gap_start, gap_end)| gap_start < gap_end)
+            .collect();
+    }
+
+    // Treat any gap between live ranges shorter than `threshold` as live.
+    // Gaps at the edges of the covered range are left alone, since we don't
+    // know how long they actually are.
+    pub fn fill_short_gaps(&mut self, threshold: i64) {
+        self.process_batch();
+        let mut filled: Vec<(i64, i64)> = Vec::with_capacity(self.liveness.len());
+        for (start, end) in std::mem::take(&mut self.liveness) {
+            match filled.last_mut() {
+                Some(last) if start - last.1 < threshold => last.1 = end,
+                _ => filled.push((start, end)),
+            }
+        }
+        // gaps spanning uncovered time aren't known to be short
+        self.liveness = subtract_intervals(&filled, &self.uncovered);
+    }
+
+    // Treat the given windows, e.g. planned maintenance, as uncovered so they
+    // count as neither live nor dead.  The windows may be in any order and
+    // may overlap.
+    pub fn exclude(&mut self, mut windows: Vec<(i64, i64)>) {
+        self.process_batch();
+        let (start, end) = (self.start, self.end);
+        windows.sort_unstable();
+        let windows: Vec<(i64, i64)> = windows
+            .into_iter()
+            .map(|(window_start, window_end)| (max(window_start, start), min(window_end, end)))
+            .filter(|(window_start, window_end)| window_start < window_end)
+            .collect();
+        self.uncovered = merge_intervals(std::mem::take(&mut self.uncovered), windows);
+        self.liveness = subtract_intervals(&self.liveness, &self.uncovered);
+    }
+}
+
+// Intermediate form for health_agg, which derives liveness from a series of
+// healthy/unhealthy samples rather than heartbeats. Each sample's state holds
+// until the next sample, and the range before the first sample is dead.
+//
+// Like heartbeats, samples are buffered and folded in in batches, keeping only
+// the samples where the health changes: a run of samples with the same state
+// is live or dead through to the next change either way. A sample arriving
+// after later ones have been folded in therefore holds until the next change
+// of state rather than until the next sample. Samples at the same time are
+// unhealthy if any of them is.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthTransState {
+    start: i64,
+    end: i64,
+    buffer: Vec<(i64, bool)>,
+    changes: Vec<(i64, bool)>, // sorted, alternating between unhealthy and healthy
+}
+
+impl HealthTransState {
+    pub fn new(start: i64, end: i64) -> Self {
+        if end <= start {
+            pgx::ereport!(
+                PgLogLevel::ERROR,
+                PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                "health_agg requires a positive agg_duration"
+            );
+        }
+        HealthTransState {
+            start,
+            end,
+            buffer: vec![],
+            changes: vec![],
+        }
+    }
+
+    pub fn insert(&mut self, time: i64, healthy: bool) {
+        if time < self.start || time >= self.end {
+            pgx::ereport!(
+                PgLogLevel::ERROR,
+                PgSqlErrorCode::ERRCODE_DATETIME_VALUE_OUT_OF_RANGE,
+                &format!(
+                    "sample at {} is outside of the range covered by health_agg, all points must occur in the 'agg_duration' interval after 'agg_start'",
+                    timestamptz_to_string(time)
+                )
+            );
+        }
+        if self.buffer.len() >= BUFFER_SIZE {
+            self.process_batch();
+        }
+        self.buffer.push((time, healthy));
+    }
+
+    pub fn process_batch(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        // unhealthy samples sort first at the same time
+        self.buffer.sort_unstable();
+        let batch = std::mem::take(&mut self.buffer);
+        let old_changes = std::mem::take(&mut self.changes);
+        self.changes = merge_changes(old_changes, batch);
+    }
+
+    pub fn combine(&mut self, mut other: HealthTransState) {
+        self.process_batch();
+        other.process_batch();
+        self.start = min(self.start, other.start);
+        self.end = max(self.end, other.end);
+        let old_changes = std::mem::take(&mut self.changes);
+        self.changes = merge_changes(old_changes, other.changes);
+    }
+
+    fn liveness(&mut self) -> Vec<(i64, i64)> {
+        self.process_batch();
+        let next_times = self
+            .changes
+            .iter()
+            .skip(1)
+            .map(|(time, _)| *time)
+            .chain(std::iter::once(self.end));
+        self.changes
+            .iter()
+            .zip(next_times)
+            .filter(|(&(_, healthy), _)| healthy)
+            .map(|(&(time, _), next)| (time, next))
+            .collect()
+    }
+}
+
+// Merge two sorted lists of samples, keeping only those where the health
+// changes. Where samples share a time, the unhealthy one wins.
+fn merge_changes(a: Vec<(i64, bool)>, b: Vec<(i64, bool)>) -> Vec<(i64, bool)> {
+    let mut changes: Vec<(i64, bool)> = Vec::with_capacity(a.len() + b.len());
+    let mut a = a.into_iter().peekable();
+    let mut b = b.into_iter().peekable();
+    loop {
+        let (time, healthy) = match (a.peek(), b.peek()) {
+            (None, None) => break,
+            (Some(_), None) => a.next(),
+            (None, Some(_)) => b.next(),
+            (Some(x), Some(y)) => {
+                if x <= y {
+                    a.next()
+                } else {
+                    b.next()
+                }
+            }
+        }
+        .unwrap();
+        match changes.last_mut() {
+            Some(last) if last.0 == time => {
+                last.1 &= healthy;
+                // becoming unhealthy may have made it the same as the one before
+                if changes.len() >= 2
+                    && changes[changes.len() - 2].1 == changes[changes.len() - 1].1
+                {
+                    changes.pop();
+                }
+            }
+            Some(last) if last.1 == healthy => (),
+            _ => changes.push((time, healthy)),
+        }
+    }
+    changes
+}
+
+impl From<HealthTransState> for HeartbeatTransState {
+    fn from(mut state: HealthTransState) -> Self {
+        // there are no heartbeats, so nothing to carry over on rollup
+        HeartbeatTransState {
+            start: state.start,
+            end: state.end,
+            last: i64::MIN,
+            interval_len: 0,
+            buffer: vec![],
+            liveness: state.liveness(),
+            uncovered: vec![],
+        }
+    }
+}
+
+// Merge two sorted lists of non-overlapping ranges into a single sorted list,
+// coalescing any ranges that overlap or touch.
+fn merge_intervals(a: Vec<(i64, i64)>, b: Vec<(i64, i64)>) -> Vec<(i64, i64)> {
+    let mut merged: Vec<(i64, i64)> = Vec::with_capacity(a.len() + b.len());
+    let mut a = a.into_iter().peekable();
+    let mut b = b.into_iter().peekable();
+    loop {
+        let next = match (a.peek(), b.peek()) {
+            (None, None) => break,
+            (Some(_), None) => a.next(),
+            (None, Some(_)) => b.next(),
+            (Some(x), Some(y)) => {
+                if x.0 <= y.0 {
+                    a.next()
+                } else {
+                    b.next()
+                }
+            }
+        };
+        let (start, end) = next.unwrap();
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = max(last.1, end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+// The portions of the ranges in `a` not covered by any range in `b`, both
+// inputs must be sorted and non-overlapping.
+fn subtract_intervals(a: &[(i64, i64)], b: &[(i64, i64)]) -> Vec<(i64, i64)> {
+    let mut remaining = vec![];
+    let mut b = b.iter().peekable();
+    for &(start, end) in a {
+        let mut cursor = start;
+        // skip everything in `b` that ends before this range
+        while b.next_if(|&&(_, b_end)| b_end <= cursor).is_some() {}
+        while let Some(&&(b_start, b_end)) = b.peek() {
+            if b_start >= end {
+                break;
+            }
+            if b_start > cursor {
+                remaining.push((cursor, b_start));
+            }
+            cursor = max(cursor, b_end);
+            if b_end > end {
+                // may still overlap the next range in `a`
+                break;
+            }
+            b.next();
+        }
+        if cursor < end {
+            remaining.push((cursor, end));
+        }
+    }
+    remaining
+}
+
+pg_type! {
+    #[derive(Debug)]
+    struct HeartbeatAgg<'input> {
+        start_time: i64,
+        end_time: i64,
+        last_seen: i64,
+        interval_len: i64,
+        num_intervals: u64,
+        interval_starts: [i64; self.num_intervals],
+        interval_ends: [i64; self.num_intervals],
+        num_uncovered: u64,
+        uncovered_starts: [i64; self.num_uncovered],
+        uncovered_ends: [i64; self.num_uncovered],
+    }
+}
+
+ron_inout_funcs!(HeartbeatAgg);
+
+impl HeartbeatAgg<'_> {
+    fn live_ranges(&self) -> impl Iterator<Item = (i64, i64)> + '_ {
+        self.interval_starts.iter().zip(self.interval_ends.iter())
+    }
+
+    // Ranges between `start_time` and `end_time` not covered by any of the
+    // aggregates rolled up into this one.
+    fn uncovered_ranges(&self) -> impl Iterator<Item = (i64, i64)> + '_ {
+        self.uncovered_starts.iter().zip(self.uncovered_ends.iter())
+    }
+
+    fn covered_ranges(&self) -> Vec<(i64, i64)> {
+        let uncovered: Vec<(i64, i64)> = self.uncovered_ranges().collect();
+        subtract_intervals(&[(self.start_time, self.end_time)], &uncovered)
+    }
+
+    fn dead_ranges(&self) -> Vec<(i64, i64)> {
+        let live: Vec<(i64, i64)> = self.live_ranges().collect();
+        subtract_intervals(&self.covered_ranges(), &live)
+    }
+
+    fn sum_live_intervals(&self) -> i64 {
+        self.live_ranges().map(|(start, end)| end - start).sum()
+    }
+
+    fn sum_uncovered_intervals(&self) -> i64 {
+        self.uncovered_ranges()
+            .map(|(start, end)| end - start)
+            .sum()
+    }
+
+    fn sum_covered_intervals(&self) -> i64 {
+        self.end_time - self.start_time - self.sum_uncovered_intervals()
+    }
+
+    // Uncovered ranges clipped to [start, end).
+    fn uncovered_ranges_in(&self, start: i64, end: i64) -> Vec<(i64, i64)> {
+        self.uncovered_ranges()
+            .map(|(gap_start, gap_end)| (max(gap_start, start), min(gap_end, end)))
+            .filter(|(gap_start, gap_end)| gap_start < gap_end)
+            .collect()
+    }
+
+    fn is_uncovered(&self, time: i64) -> bool {
+        self.uncovered_ranges()
+            .any(|(start, end)| start <= time && time < end)
+    }
+
+    // Live ranges clipped to [start, end).
+    fn live_ranges_in(&self, start: i64, end: i64) -> Vec<(i64, i64)> {
+        self.live_ranges()
+            .map(|(live_start, live_end)| (max(live_start, start), min(live_end, end)))
+            .filter(|(live_start, live_end)| live_start < live_end)
+            .collect()
+    }
+
+    // Compares the logical contents of two aggregates, ignoring how they're
+    // stored.
+    fn same_as(&self, other: &HeartbeatAgg<'_>) -> bool {
+        self.start_time == other.start_time
+            && self.end_time == other.end_time
+            && self.last_seen == other.last_seen
+            && self.interval_len == other.interval_len
+            && self.live_ranges().eq(other.live_ranges())
+            && self.uncovered_ranges().eq(other.uncovered_ranges())
+    }
+
+    // Whether every range during which `other` is live is also live here.
+    fn contains_liveness_of(&self, other: &HeartbeatAgg<'_>) -> bool {
+        let ours: Vec<(i64, i64)> = self.live_ranges().collect();
+        let theirs: Vec<(i64, i64)> = other.live_ranges().collect();
+        subtract_intervals(&theirs, &ours).is_empty()
+    }
+
+    fn uptime_ratio(&self) -> f64 {
+        self.sum_live_intervals() as f64 / self.sum_covered_intervals() as f64
+    }
+
+    fn longest_dead_range(&self) -> i64 {
+        self.dead_ranges()
+            .into_iter()
+            .map(|(start, end)| end - start)
+            .max()
+            .unwrap_or(0)
+    }
+
+    // The part of [start, end) covered by the aggregate, or `None` if they
+    // don't overlap.
+    fn clip_to_coverage(&self, start: i64, end: i64) -> Option<(i64, i64)> {
+        let start = max(start, self.start_time);
+        let end = min(end, self.end_time);
+        if start < end {
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+
+    fn sum_live_intervals_in(&self, start: i64, end: i64) -> i64 {
+        let (start, end) = match self.clip_to_coverage(start, end) {
+            Some(range) => range,
+            None => return 0,
+        };
+        self.live_ranges_in(start, end)
+            .into_iter()
+            .map(|(live_start, live_end)| live_end - live_start)
+            .sum()
+    }
+
+    fn sum_dead_intervals_in(&self, start: i64, end: i64) -> i64 {
+        let (start, end) = match self.clip_to_coverage(start, end) {
+            Some(range) => range,
+            None => return 0,
+        };
+        let uncovered: i64 = self
+            .uncovered_ranges_in(start, end)
+            .into_iter()
+            .map(|(gap_start, gap_end)| gap_end - gap_start)
+            .sum();
+        end - start - uncovered - self.sum_live_intervals_in(start, end)
+    }
+
+    // Total covered and live time falling into each slot of a repeating
+    // cycle of `num_slots` slots of `slot_len` microseconds, where slot 0
+    // starts `offset` slots after the postgres epoch.
+    fn fold_onto_cycle(&self, slot_len: i64, num_slots: i64, offset: i64) -> Vec<(i64, i64)> {
+        let mut slots = vec![(0, 0); num_slots as usize];
+        let mut add = |start: i64, end: i64, live: bool| {
+            let mut cursor = start;
+            while cursor < end {
+                let slot_end = min(end, (cursor.div_euclid(slot_len) + 1) * slot_len);
+                let slot = (cursor.div_euclid(slot_len) + offset).rem_euclid(num_slots);
+                let (covered, live_time) = &mut slots[slot as usize];
+                if live {
+                    *live_time += slot_end - cursor;
+                } else {
+                    *covered += slot_end - cursor;
+                }
+                cursor = slot_end;
+            }
+        };
+        for (start, end) in self.covered_ranges() {
+            add(start, end, false);
+        }
+        for (start, end) in self.live_ranges() {
+            add(start, end, true);
+        }
+        slots
+    }
+
+    fn live_at(&self, time: i64) -> bool {
+        if time < self.start_time || time >= self.end_time || self.is_uncovered(time) {
+            pgx::error!("unable to test for liveness outside of a heartbeat_agg's covered range")
+        }
+        let starts = self.interval_starts.as_slice();
+        // index of the last range starting at or before `time`
+        match starts.partition_point(|&start| start <= time) {
+            0 => false,
+            idx => time < self.interval_ends.as_slice()[idx - 1],
+        }
+    }
+
+    // Like `live_at`, but a probe before this aggregate's first heartbeat is
+    // live if it falls within the liveness of `prev`'s last heartbeat, which
+    // this aggregate has no way of knowing about.
+    fn interpolated_live_at(&self, time: i64, prev: Option<&HeartbeatAgg<'_>>) -> bool {
+        let prev = match prev {
+            Some(prev) if prev.end_time > self.start_time => {
+                pgx::error!("the previous heartbeat_agg must end before the start of this one")
+            }
+            Some(prev) => prev,
+            None => return self.live_at(time),
+        };
+        if self.live_at(time) {
+            return true;
+        }
+        let first_heartbeat = self.interval_starts.as_slice().first().copied();
+        if first_heartbeat.map_or(false, |first| first <= time) || prev.last_seen == i64::MIN {
+            return false;
+        }
+        prev.last_seen <= time && time < prev.last_seen.saturating_add(prev.interval_len)
+    }
+
+    // The live or dead range containing `time`, and whether it's live, or
+    // `None` if `time` is outside of the aggregate's covered range.
+    fn range_at(&self, time: i64) -> Option<(i64, i64, bool)> {
+        if time < self.start_time || time >= self.end_time || self.is_uncovered(time) {
+            return None;
+        }
+        let starts = self.interval_starts.as_slice();
+        let ends = self.interval_ends.as_slice();
+        let idx = starts.partition_point(|&start| start <= time);
+        if idx > 0 && time < ends[idx - 1] {
+            return Some((starts[idx - 1], ends[idx - 1], true));
+        }
+        // otherwise we're in a dead range, which may also be bounded by
+        // uncovered time
+        self.dead_ranges()
+            .into_iter()
+            .find(|&(start, end)| start <= time && time < end)
+            .map(|(start, end)| (start, end, false))
+    }
+}
+
+impl From<HeartbeatTransState> for HeartbeatAgg<'_> {
+    fn from(mut state: HeartbeatTransState) -> Self {
+        state.process_batch();
+        state.resolve_unbounded_range();
+        let starts: Vec<i64> = state.liveness.iter().map(|(start, _)| *start).collect();
+        let ends: Vec<i64> = state.liveness.iter().map(|(_, end)| *end).collect();
+        let uncovered_starts: Vec<i64> = state.uncovered.iter().map(|(start, _)| *start).collect();
+        let uncovered_ends: Vec<i64> = state.uncovered.iter().map(|(_, end)| *end).collect();
+        unsafe {
+            flatten!(HeartbeatAgg {
+                start_time: state.start,
+                end_time: state.end,
+                last_seen: state.last,
+                interval_len: state.interval_len,
+                num_intervals: starts.len() as u64,
+                interval_starts: starts.into(),
+                interval_ends: ends.into(),
+                num_uncovered: uncovered_starts.len() as u64,
+                uncovered_starts: uncovered_starts.into(),
+                uncovered_ends: uncovered_ends.into(),
+            })
+        }
+    }
+}
+
+impl From<HeartbeatAgg<'_>> for HeartbeatTransState {
+    fn from(agg: HeartbeatAgg<'_>) -> Self {
+        HeartbeatTransState {
+            start: agg.start_time,
+            end: agg.end_time,
+            last: agg.last_seen,
+            interval_len: agg.interval_len,
+            buffer: vec![],
+            liveness: agg.live_ranges().collect(),
+            uncovered: agg.uncovered_ranges().collect(),
+        }
+    }
+}
+
+#[pg_extern(immutable, parallel_safe)]
+pub fn heartbeat_trans(
+    state: Internal,
+    heartbeat: Option<TimestampTz>,
+    start: TimestampTz,
+    length: Interval,
+    liveness_duration: Interval,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    heartbeat_trans_inner(
+        unsafe { state.to_inner() },
+        heartbeat,
+        start,
+        length,
+        liveness_duration,
+        fcinfo,
+    )
+    .internal()
+}
+
+pub fn heartbeat_trans_inner(
+    state: Option<Inner<HeartbeatTransState>>,
+    heartbeat: Option<TimestampTz>,
+    start: TimestampTz,
+    length: Interval,
+    liveness_duration: Interval,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<HeartbeatTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let heartbeat = match heartbeat {
+                None => return state,
+                Some(heartbeat) => heartbeat,
+            };
+            let mut state = state.unwrap_or_else(|| {
+                let length = interval_to_ms(&start, &length);
+                let liveness = interval_to_ms(&start, &liveness_duration);
+                let start: i64 = start.into();
+                HeartbeatTransState::new(start, start + length, liveness).into()
+            });
+            state.insert(heartbeat.into());
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn heartbeat_range_trans(
+    state: Internal,
+    heartbeat: Option<TimestampTz>,
+    coverage: tstzrange,
+    liveness_duration: Interval,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    heartbeat_range_trans_inner(
+        unsafe { state.to_inner() },
+        heartbeat,
+        coverage,
+        liveness_duration,
+        fcinfo,
+    )
+    .internal()
+}
+
+pub fn heartbeat_range_trans_inner(
+    state: Option<Inner<HeartbeatTransState>>,
+    heartbeat: Option<TimestampTz>,
+    coverage: tstzrange,
+    liveness_duration: Interval,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<HeartbeatTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let heartbeat: i64 = match heartbeat {
+                None => return state,
+                Some(heartbeat) => heartbeat.into(),
+            };
+            let mut state = state.unwrap_or_else(|| {
+                let coverage = get_range(coverage.0.cast_mut_ptr())
+                    .unwrap_or_else(|| pgx::error!("heartbeat_agg requires a non-empty coverage"));
+                // unbounded ends are resolved when the aggregate is finalized
+                let start = coverage.left.unwrap_or(i64::MIN);
+                let end = coverage.right.unwrap_or(i64::MAX);
+                let reference = TimestampTz::from(coverage.left.unwrap_or(heartbeat));
+                let liveness = interval_to_ms(&reference, &liveness_duration);
+                HeartbeatTransState::new(start, end, liveness).into()
+            });
+            state.insert(heartbeat);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe)]
+pub fn heartbeat_rollup_trans(
+    state: Internal,
+    value: Option<HeartbeatAgg<'static>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    heartbeat_rollup_trans_inner(unsafe { state.to_inner() }, value, fcinfo).internal()
+}
+
+pub fn heartbeat_rollup_trans_inner(
+    state: Option<Inner<HeartbeatTransState>>,
+    value: Option<HeartbeatAgg<'static>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<HeartbeatTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || match (state, value) {
+            (state, None) => state,
+            (None, Some(value)) => Some(HeartbeatTransState::from(value).into()),
+            (Some(mut state), Some(value)) => {
+                state.combine(value.into());
+                Some(state)
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe)]
+pub fn heartbeat_combine(
+    state1: Internal,
+    state2: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    unsafe { heartbeat_combine_inner(state1.to_inner(), state2.to_inner(), fcinfo).internal() }
+}
+
+pub fn heartbeat_combine_inner(
+    state1: Option<Inner<HeartbeatTransState>>,
+    state2: Option<Inner<HeartbeatTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<HeartbeatTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || match (state1, state2) {
+            (None, None) => None,
+            (None, Some(only)) | (Some(only), None) => Some((*only).clone().into()),
+            (Some(a), Some(b)) => {
+                let mut a = (*a).clone();
+                a.combine((*b).clone());
+                Some(a.into())
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, strict)]
+pub fn heartbeat_trans_serialize(state: Internal) -> bytea {
+    let mut state: Inner<HeartbeatTransState> = unsafe { state.to_inner().unwrap() };
+    state.process_batch();
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(immutable, parallel_safe, strict)]
+pub fn heartbeat_trans_deserialize(bytes: bytea, _internal: Internal) -> Option<Internal> {
+    heartbeat_trans_deserialize_inner(bytes).internal()
+}
+
+pub fn heartbeat_trans_deserialize_inner(bytes: bytea) -> Inner<HeartbeatTransState> {
+    let state: HeartbeatTransState = crate::do_deserialize!(bytes, HeartbeatTransState);
+    state.into()
+}
+
+#[pg_extern(immutable, parallel_safe)]
+pub fn heartbeat_final(
+    state: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<HeartbeatAgg<'static>> {
+    heartbeat_final_inner(unsafe { state.to_inner() }, fcinfo)
+}
+
+pub fn heartbeat_final_inner(
+    state: Option<Inner<HeartbeatTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<HeartbeatAgg<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            state.map(|mut state| {
+                state.process_batch();
+                (*state).clone().into()
+            })
+        })
+    }
+}
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE heartbeat_agg(\n\
+        heartbeat TIMESTAMPTZ, agg_start TIMESTAMPTZ, agg_duration INTERVAL, heartbeat_liveness INTERVAL\n\
+    ) (\n\
+        sfunc = heartbeat_trans,\n\
+        stype = internal,\n\
+        finalfunc = heartbeat_final,\n\
+        combinefunc = heartbeat_combine,\n\
+        serialfunc = heartbeat_trans_serialize,\n\
+        deserialfunc = heartbeat_trans_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "heartbeat_agg",
+    requires = [
+        heartbeat_trans,
+        heartbeat_final,
+        heartbeat_combine,
+        heartbeat_trans_serialize,
+        heartbeat_trans_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.heartbeat_agg(\n\
+        heartbeat TIMESTAMPTZ, coverage TSTZRANGE, heartbeat_liveness INTERVAL\n\
+    ) (\n\
+        sfunc = toolkit_experimental.heartbeat_range_trans,\n\
+        stype = internal,\n\
+        finalfunc = heartbeat_final,\n\
+        combinefunc = heartbeat_combine,\n\
+        serialfunc = heartbeat_trans_serialize,\n\
+        deserialfunc = heartbeat_trans_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "heartbeat_agg_range",
+    requires = [
+        heartbeat_range_trans,
+        heartbeat_final,
+        heartbeat_combine,
+        heartbeat_trans_serialize,
+        heartbeat_trans_deserialize
+    ],
+);
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE rollup(\n\
+        HeartbeatAgg\n\
+    ) (\n\
+        sfunc = heartbeat_rollup_trans,\n\
+        stype = internal,\n\
+        finalfunc = heartbeat_final,\n\
+        combinefunc = heartbeat_combine,\n\
+        serialfunc = heartbeat_trans_serialize,\n\
+        deserialfunc = heartbeat_trans_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "heartbeat_agg_rollup",
+    requires = [
+        heartbeat_rollup_trans,
+        heartbeat_final,
+        heartbeat_combine,
+        heartbeat_trans_serialize,
+        heartbeat_trans_deserialize
+    ],
+);
+
+#[pg_extern(immutable, parallel_safe)]
+pub fn live_ranges<'a>(
+    agg: HeartbeatAgg<'a>,
+) -> TableIterator<'static, (name!(start, TimestampTz), name!(end, TimestampTz))> {
+    let ranges: Vec<(TimestampTz, TimestampTz)> = agg
+        .live_ranges()
+        .map(|(start, end)| (start.into(), end.into()))
+        .collect();
+    TableIterator::new(ranges.into_iter())
+}
+
+#[pg_extern(immutable, parallel_safe)]
+pub fn dead_ranges<'a>(
+    agg: HeartbeatAgg<'a>,
+) -> TableIterator<'static, (name!(start, TimestampTz), name!(end, TimestampTz))> {
+    let ranges: Vec<(TimestampTz, TimestampTz)> = agg
+        .dead_ranges()
+        .into_iter()
+        .map(|(start, end)| (start.into(), end.into()))
+        .collect();
+    TableIterator::new(ranges.into_iter())
+}
+
+#[pg_extern(immutable, parallel_safe)]
+pub fn duration_live<'a>(agg: HeartbeatAgg<'a>) -> Interval {
+    ms_to_interval(agg.sum_live_intervals())
+}
+
+#[pg_extern(immutable, parallel_safe)]
+pub fn duration_dead<'a>(agg: HeartbeatAgg<'a>) -> Interval {
+    ms_to_interval(agg.sum_covered_intervals() - agg.sum_live_intervals())
+}
+
+// Time within the aggregate's range which wasn't covered by any of the
+// aggregates rolled up into it, e.g. the time between two non-adjacent
+// buckets.  This time is counted as neither live nor dead.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn uncovered_duration<'a>(agg: HeartbeatAgg<'a>) -> Interval {
+    ms_to_interval(agg.sum_uncovered_intervals())
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "duration_live",
+    schema = "toolkit_experimental"
+)]
+pub fn duration_live_in_range<'a>(
+    agg: HeartbeatAgg<'a>,
+    range_start: TimestampTz,
+    range_end: TimestampTz,
+) -> Interval {
+    ms_to_interval(agg.sum_live_intervals_in(range_start.into(), range_end.into()))
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "duration_dead",
+    schema = "toolkit_experimental"
+)]
+pub fn duration_dead_in_range<'a>(
+    agg: HeartbeatAgg<'a>,
+    range_start: TimestampTz,
+    range_end: TimestampTz,
+) -> Interval {
+    ms_to_interval(agg.sum_dead_intervals_in(range_start.into(), range_end.into()))
+}
+
+// NULL and empty ranges are ignored, unbounded ends extend to the edge of
+// the aggregate.
+fn windows_from_ranges(ranges: Array<tstzrange>) -> Vec<(i64, i64)> {
+    ranges
+        .iter()
+        .flatten()
+        .filter_map(|range| unsafe { get_range(range.0.cast_mut_ptr()) })
+        .map(|range| {
+            (
+                range.left.unwrap_or(i64::MIN),
+                range.right.unwrap_or(i64::MAX),
+            )
+        })
+        .collect()
+}
+
+// Removes maintenance windows, or any other planned downtime, from the
+// aggregate.  Excluded time is treated like time the aggregate doesn't cover:
+// it counts as neither live nor dead.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn exclude<'a>(agg: HeartbeatAgg<'a>, windows: Array<tstzrange>) -> HeartbeatAgg<'static> {
+    let mut state: HeartbeatTransState = agg.into();
+    state.exclude(windows_from_ranges(windows));
+    state.into()
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "duration_live",
+    schema = "toolkit_experimental"
+)]
+pub fn duration_live_excluding<'a>(agg: HeartbeatAgg<'a>, excluding: Array<tstzrange>) -> Interval {
+    duration_live(exclude(agg, excluding))
+}
+
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "duration_dead",
+    schema = "toolkit_experimental"
+)]
+pub fn duration_dead_excluding<'a>(agg: HeartbeatAgg<'a>, excluding: Array<tstzrange>) -> Interval {
+    duration_dead(exclude(agg, excluding))
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn smooth<'a>(
+    agg: HeartbeatAgg<'a>,
+    ignore_gaps_shorter_than: Interval,
+) -> HeartbeatAgg<'static> {
+    let start: TimestampTz = agg.start_time.into();
+    let threshold = interval_to_ms(&start, &ignore_gaps_shorter_than);
+    let mut state: HeartbeatTransState = agg.into();
+    state.fill_short_gaps(threshold);
+    state.into()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn uptime_change<'a>(
+    current: HeartbeatAgg<'a>,
+    previous: HeartbeatAgg<'a>,
+) -> TableIterator<
+    'static,
+    (
+        name!(uptime_ratio_change, f64),
+        name!(outage_count_change, i64),
+        name!(longest_outage_change, Interval),
+    ),
+> {
+    let ratio_change = current.uptime_ratio() - previous.uptime_ratio();
+    let count_change = current.dead_ranges().len() as i64 - previous.dead_ranges().len() as i64;
+    let longest_change = current.longest_dead_range() - previous.longest_dead_range();
+    TableIterator::new(std::iter::once((
+        ratio_change,
+        count_change,
+        ms_to_interval(longest_change),
+    )))
+}
+
+// Ranges, within the range covered by both aggregates, where exactly one of
+// them is live, tagged with which one that is.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn liveness_diff<'a>(
+    agg1: HeartbeatAgg<'a>,
+    agg2: HeartbeatAgg<'a>,
+) -> TableIterator<
+    'static,
+    (
+        name!(start, TimestampTz),
+        name!(end, TimestampTz),
+        name!(live_in, String),
+    ),
+> {
+    let start = max(agg1.start_time, agg2.start_time);
+    let end = min(agg1.end_time, agg2.end_time);
+    let uncovered = merge_intervals(
+        agg1.uncovered_ranges_in(start, end),
+        agg2.uncovered_ranges_in(start, end),
+    );
+    let live1 = subtract_intervals(&agg1.live_ranges_in(start, end), &uncovered);
+    let live2 = subtract_intervals(&agg2.live_ranges_in(start, end), &uncovered);
+
+    let mut diff: Vec<(i64, i64, &str)> = subtract_intervals(&live1, &live2)
+        .into_iter()
+        .map(|(start, end)| (start, end, "agg1"))
+        .chain(
+            subtract_intervals(&live2, &live1)
+                .into_iter()
+                .map(|(start, end)| (start, end, "agg2")),
+        )
+        .collect();
+    diff.sort_unstable_by_key(|(start, _, _)| *start);
+
+    let diff: Vec<(TimestampTz, TimestampTz, String)> = diff
+        .into_iter()
+        .map(|(start, end, live_in)| (start.into(), end.into(), live_in.to_string()))
+        .collect();
+    TableIterator::new(diff.into_iter())
+}
+
+#[pg_extern(immutable, parallel_safe)]
+pub fn live_at<'a>(agg: HeartbeatAgg<'a>, test: TimestampTz) -> bool {
+    agg.live_at(test.into())
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn interpolated_live_at<'a>(
+    agg: HeartbeatAgg<'a>,
+    test: TimestampTz,
+    prev: Option<HeartbeatAgg<'a>>,
+) -> bool {
+    agg.interpolated_live_at(test.into(), prev.as_ref())
+}
+
+// Postgres output functions depend on the session's DateStyle and time zone,
+// so this can't be immutable.
+#[pg_extern(stable, parallel_safe, schema = "toolkit_experimental")]
+pub fn describe<'a>(agg: HeartbeatAgg<'a>) -> String {
+    format!(
+        "coverage: {} to {}\n\
+        uptime: {:.2}%\n\
+        outages: {}\n\
+        longest outage: {}",
+        timestamptz_to_string(agg.start_time),
+        timestamptz_to_string(agg.end_time),
+        agg.uptime_ratio() * 100.0,
+        agg.dead_ranges().len(),
+        interval_to_string(&ms_to_interval(agg.longest_dead_range())),
+    )
+}
+
+// The fraction of covered time that was live in each hour of the day or day
+// of the week (numbered from Sunday, as in `EXTRACT(dow ...)`), in UTC.  Slots
+// the aggregate doesn't cover have a NULL fraction.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn uptime_heatmap<'a>(
+    agg: HeartbeatAgg<'a>,
+    cycle: String,
+) -> TableIterator<'static, (name!(slot, i32), name!(live_fraction, Option<f64>))> {
+    const HOUR: i64 = 60 * 60 * 1_000_000;
+    const DAY: i64 = 24 * HOUR;
+    // the postgres epoch, 2000-01-01, was a Saturday
+    let slots = match cycle.to_lowercase().as_str() {
+        "hour_of_day" => agg.fold_onto_cycle(HOUR, 24, 0),
+        "day_of_week" => agg.fold_onto_cycle(DAY, 7, 6),
+        _ => pgx::error!(
+            "unrecognized heatmap cycle '{}', expected 'hour_of_day' or 'day_of_week'",
+            cycle
+        ),
+    };
+    let heatmap: Vec<(i32, Option<f64>)> = slots
+        .into_iter()
+        .enumerate()
+        .map(|(slot, (covered, live))| {
+            let fraction = if covered == 0 {
+                None
+            } else {
+                Some(live as f64 / covered as f64)
+            };
+            (slot as i32, fraction)
+        })
+        .collect();
+    TableIterator::new(heatmap.into_iter())
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn live_range_at<'a>(
+    agg: HeartbeatAgg<'a>,
+    ts: TimestampTz,
+) -> TableIterator<
+    'static,
+    (
+        name!(start, Option<TimestampTz>),
+        name!(end, Option<TimestampTz>),
+        name!(live, Option<bool>),
+    ),
+> {
+    let range = match agg.range_at(ts.into()) {
+        Some((start, end, live)) => (Some(start.into()), Some(end.into()), Some(live)),
+        None => (None, None, None),
+    };
+    TableIterator::new(std::iter::once(range))
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn heartbeat_agg_eq<'a>(agg1: HeartbeatAgg<'a>, agg2: HeartbeatAgg<'a>) -> bool {
+    agg1.same_as(&agg2)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn heartbeat_agg_ne<'a>(agg1: HeartbeatAgg<'a>, agg2: HeartbeatAgg<'a>) -> bool {
+    !agg1.same_as(&agg2)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn live_contains<'a>(outer: HeartbeatAgg<'a>, inner: HeartbeatAgg<'a>) -> bool {
+    outer.contains_liveness_of(&inner)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn live_contained_by<'a>(inner: HeartbeatAgg<'a>, outer: HeartbeatAgg<'a>) -> bool {
+    outer.contains_liveness_of(&inner)
+}
+
+// using this instead of pg_operator since the latter doesn't support schemas yet
+extension_sql!(
+    "\n\
+    CREATE OPERATOR toolkit_experimental.= (\n\
+        PROCEDURE=toolkit_experimental.heartbeat_agg_eq,\n\
+        LEFTARG=HeartbeatAgg,\n\
+        RIGHTARG=HeartbeatAgg,\n\
+        COMMUTATOR=OPERATOR(toolkit_experimental.=),\n\
+        NEGATOR=OPERATOR(toolkit_experimental.<>)\n\
+    );\n\
+    CREATE OPERATOR toolkit_experimental.<> (\n\
+        PROCEDURE=toolkit_experimental.heartbeat_agg_ne,\n\
+        LEFTARG=HeartbeatAgg,\n\
+        RIGHTARG=HeartbeatAgg,\n\
+        COMMUTATOR=OPERATOR(toolkit_experimental.<>),\n\
+        NEGATOR=OPERATOR(toolkit_experimental.=)\n\
+    );\n\
+    CREATE OPERATOR toolkit_experimental.@> (\n\
+        PROCEDURE=toolkit_experimental.live_contains,\n\
+        LEFTARG=HeartbeatAgg,\n\
+        RIGHTARG=HeartbeatAgg,\n\
+        COMMUTATOR=OPERATOR(toolkit_experimental.<@)\n\
+    );\n\
+    CREATE OPERATOR toolkit_experimental.<@ (\n\
+        PROCEDURE=toolkit_experimental.live_contained_by,\n\
+        LEFTARG=HeartbeatAgg,\n\
+        RIGHTARG=HeartbeatAgg,\n\
+        COMMUTATOR=OPERATOR(toolkit_experimental.@>)\n\
+    );\n\
+",
+    name = "heartbeat_agg_operators",
+    requires = [
+        heartbeat_agg_eq,
+        heartbeat_agg_ne,
+        live_contains,
+        live_contained_by
+    ],
+);
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn health_trans(
+    state: Internal,
+    ts: Option<TimestampTz>,
+    healthy: Option<bool>,
+    start: TimestampTz,
+    length: Interval,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    health_trans_inner(
+        unsafe { state.to_inner() },
+        ts,
+        healthy,
+        start,
+        length,
+        fcinfo,
+    )
+    .internal()
+}
+
+pub fn health_trans_inner(
+    state: Option<Inner<HealthTransState>>,
+    ts: Option<TimestampTz>,
+    healthy: Option<bool>,
+    start: TimestampTz,
+    length: Interval,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<HealthTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let (ts, healthy) = match (ts, healthy) {
+                (Some(ts), Some(healthy)) => (ts, healthy),
+                _ => return state,
+            };
+            let mut state = state.unwrap_or_else(|| {
+                let length = interval_to_ms(&start, &length);
+                let start: i64 = start.into();
+                HealthTransState::new(start, start + length).into()
+            });
+            state.insert(ts.into(), healthy);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn health_combine(
+    state1: Internal,
+    state2: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    unsafe { health_combine_inner(state1.to_inner(), state2.to_inner(), fcinfo).internal() }
+}
+
+pub fn health_combine_inner(
+    state1: Option<Inner<HealthTransState>>,
+    state2: Option<Inner<HealthTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<HealthTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || match (state1, state2) {
+            (None, None) => None,
+            (None, Some(only)) | (Some(only), None) => Some((*only).clone().into()),
+            (Some(a), Some(b)) => {
+                let mut a = (*a).clone();
+                a.combine((*b).clone());
+                Some(a.into())
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn health_trans_serialize(state: Internal) -> bytea {
+    let mut state: Inner<HealthTransState> = unsafe { state.to_inner().unwrap() };
+    state.process_batch();
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn health_trans_deserialize(bytes: bytea, _internal: Internal) -> Option<Internal> {
+    health_trans_deserialize_inner(bytes).internal()
+}
+
+pub fn health_trans_deserialize_inner(bytes: bytea) -> Inner<HealthTransState> {
+    let state: HealthTransState = crate::do_deserialize!(bytes, HealthTransState);
+    state.into()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn health_final(
+    state: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<HeartbeatAgg<'static>> {
+    health_final_inner(unsafe { state.to_inner() }, fcinfo)
+}
+
+pub fn health_final_inner(
+    state: Option<Inner<HealthTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<HeartbeatAgg<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            state.map(|state| HeartbeatTransState::from((*state).clone()).into())
+        })
+    }
+}
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.health_agg(\n\
+        ts TIMESTAMPTZ, healthy BOOLEAN, agg_start TIMESTAMPTZ, agg_duration INTERVAL\n\
+    ) (\n\
+        sfunc = toolkit_experimental.health_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.health_final,\n\
+        combinefunc = toolkit_experimental.health_combine,\n\
+        serialfunc = toolkit_experimental.health_trans_serialize,\n\
+        deserialfunc = toolkit_experimental.health_trans_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "health_agg",
+    requires = [
+        health_trans,
+        health_final,
+        health_combine,
+        health_trans_serialize,
+        health_trans_deserialize
+    ],
+);
+
+// Combine the liveness of two aggregates over the range they both cover.
+fn combine_liveness<'a>(
+    agg1: &HeartbeatAgg<'a>,
+    agg2: &HeartbeatAgg<'a>,
+    op: impl FnOnce(Vec<(i64, i64)>, Vec<(i64, i64)>) -> Vec<(i64, i64)>,
+) -> HeartbeatAgg<'static> {
+    let start = max(agg1.start_time, agg2.start_time);
+    let end = min(agg1.end_time, agg2.end_time);
+    if start >= end {
+        pgx::error!("unable to combine the liveness of aggregates covering disjoint ranges")
+    }
+    // only time covered by both aggregates is covered by the result
+    let uncovered = merge_intervals(
+        agg1.uncovered_ranges_in(start, end),
+        agg2.uncovered_ranges_in(start, end),
+    );
+    let liveness = op(
+        agg1.live_ranges_in(start, end),
+        agg2.live_ranges_in(start, end),
+    );
+    HeartbeatTransState {
+        start,
+        end,
+        last: i64::MIN,
+        interval_len: 0,
+        buffer: vec![],
+        liveness: subtract_intervals(&liveness, &uncovered),
+        uncovered,
+    }
+    .into()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn all_live<'a>(agg1: HeartbeatAgg<'a>, agg2: HeartbeatAgg<'a>) -> HeartbeatAgg<'static> {
+    combine_liveness(&agg1, &agg2, |a, b| {
+        let only_a = subtract_intervals(&a, &b);
+        subtract_intervals(&a, &only_a)
+    })
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn any_live<'a>(agg1: HeartbeatAgg<'a>, agg2: HeartbeatAgg<'a>) -> HeartbeatAgg<'static> {
+    combine_liveness(&agg1, &agg2, merge_intervals)
+}
+
+// Equivalent to `rollup` over two aggregates, but usable outside of an
+// aggregate, e.g. `ON CONFLICT DO UPDATE SET agg = heartbeat_merge(agg, EXCLUDED.agg)`.
+// Since combining is commutative and associative the order in which
+// aggregates are merged doesn't affect the result.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn heartbeat_merge<'a>(
+    a: Option<HeartbeatAgg<'a>>,
+    b: Option<HeartbeatAgg<'a>>,
+) -> Option<HeartbeatAgg<'static>> {
+    match (a, b) {
+        (None, None) => None,
+        (Some(only), None) | (None, Some(only)) => Some(only.in_current_context()),
+        (Some(a), Some(b)) => {
+            let mut state: HeartbeatTransState = a.into();
+            state.combine(b.into());
+            Some(state.into())
+        }
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::*;
+    use pgx_macros::pg_test;
+
+    macro_rules! select_one {
+        ($client:expr, $stmt:expr, $type:ty) => {
+            $client
+                .select($stmt, None, None)
+                .first()
+                .get_one::<$type>()
+                .unwrap()
+        };
+    }
+
+    #[pg_test]
+    fn test_merge_intervals() {
+        assert_eq!(
+            merge_intervals(vec![(0, 10), (20, 30)], vec![(5, 15), (30, 35), (40, 50)]),
+            vec![(0, 15), (20, 35), (40, 50)]
+        );
+        assert_eq!(merge_intervals(vec![], vec![(1, 2)]), vec![(1, 2)]);
+        assert_eq!(
+            merge_intervals(vec![(0, 100)], vec![(10, 20)]),
+            vec![(0, 100)]
+        );
+    }
+
+    #[pg_test]
+    fn test_process_batch_clips_to_end() {
+        let mut state = HeartbeatTransState::new(0, 100, 10);
+        for heartbeat in [95, 0, 5, 50] {
+            state.insert(heartbeat);
+        }
+        state.process_batch();
+        assert!(state.buffer.is_empty());
+        assert_eq!(state.liveness, vec![(0, 15), (50, 60), (95, 100)]);
+        assert_eq!(state.last, 95);
+    }
+
+    #[pg_test]
+    fn test_combine_adjacent_windows() {
+        let mut first = HeartbeatTransState::new(0, 100, 10);
+        first.insert(10);
+        first.insert(95);
+        let mut second = HeartbeatTransState::new(100, 200, 10);
+        second.insert(103);
+        second.insert(150);
+
+        first.combine(second);
+        assert_eq!(first.start, 0);
+        assert_eq!(first.end, 200);
+        assert_eq!(first.liveness, vec![(10, 20), (95, 113), (150, 160)]);
+        assert_eq!(first.last, 150);
+    }
+
+    fn setup_liveness_table(client: &SpiClient) {
+        client.select("SET TIMEZONE to UTC", None, None);
+        client.select("CREATE TABLE liveness(heartbeat TIMESTAMPTZ)", None, None);
+        client.select(
+            "INSERT INTO liveness VALUES
+                ('01-01-2020 0:2:20 UTC'),
+                ('01-01-2020 0:10 UTC'),
+                ('01-01-2020 0:17 UTC'),
+                ('01-01-2020 0:30 UTC'),
+                ('01-01-2020 0:35 UTC'),
+                ('01-01-2020 0:40 UTC'),
+                ('01-01-2020 0:50:30 UTC'),
+                ('01-01-2020 1:00 UTC'),
+                ('01-01-2020 1:08 UTC'),
+                ('01-01-2020 1:18 UTC'),
+                ('01-01-2020 1:28 UTC'),
+                ('01-01-2020 1:38:01 UTC'),
+                ('01-01-2020 1:40 UTC'),
+                ('01-01-2020 1:40:01 UTC'),
+                ('01-01-2020 1:50:01 UTC'),
+                ('01-01-2020 1:57 UTC'),
+                ('01-01-2020 1:59:50 UTC')",
+            None,
+            None,
+        );
+    }
+
+    #[pg_test]
+    pub fn test_heartbeat_agg() {
+        Spi::execute(|client| {
+            setup_liveness_table(&client);
+
+            let mut result = client.select(
+                "SELECT start::TEXT, \"end\"::TEXT FROM live_ranges(
+                    (SELECT heartbeat_agg(heartbeat, '01-01-2020 UTC', '2h', '10m') FROM liveness)
+                )",
+                None,
+                None,
+            );
+
+            let mut next = result.next().unwrap();
+            assert_eq!(next[1].value(), Some("2020-01-01 00:02:20+00"));
+            assert_eq!(next[2].value(), Some("2020-01-01 00:27:00+00"));
+
+            next = result.next().unwrap();
+            assert_eq!(next[1].value(), Some("2020-01-01 00:30:00+00"));
+            assert_eq!(next[2].value(), Some("2020-01-01 00:50:00+00"));
+
+            next = result.next().unwrap();
+            assert_eq!(next[1].value(), Some("2020-01-01 00:50:30+00"));
+            assert_eq!(next[2].value(), Some("2020-01-01 01:38:00+00"));
+
+            next = result.next().unwrap();
+            assert_eq!(next[1].value(), Some("2020-01-01 01:38:01+00"));
+            assert_eq!(next[2].value(), Some("2020-01-01 02:00:00+00"));
+
+            assert!(result.next().is_none());
+
+            assert_eq!(
+                select_one!(
+                    client,
+                    "SELECT duration_live(heartbeat_agg(heartbeat, '01-01-2020 UTC', '2h', '10m'))::TEXT FROM liveness",
+                    &str
+                ),
+                "01:54:09"
+            );
+            assert_eq!(
+                select_one!(
+                    client,
+                    "SELECT duration_dead(heartbeat_agg(heartbeat, '01-01-2020 UTC', '2h', '10m'))::TEXT FROM liveness",
+                    &str
+                ),
+                "00:05:51"
+            );
+        });
+    }
+
+    #[pg_test]
+    pub fn test_heartbeat_duration_in_range() {
+        Spi::execute(|client| {
+            setup_liveness_table(&client);
+            client.select(
+                "CREATE TABLE aggs AS SELECT heartbeat_agg(heartbeat, '01-01-2020 UTC', '2h', '10m') AS agg FROM liveness",
+                None,
+                None,
+            );
+
+            assert_eq!(
+                select_one!(
+                    client,
+                    "SELECT toolkit_experimental.duration_live(agg, '01-01-2020 00:20 UTC', '01-01-2020 01:00 UTC')::TEXT FROM aggs",
+                    &str
+                ),
+                "00:36:30"
+            );
+            assert_eq!(
+                select_one!(
+                    client,
+                    "SELECT toolkit_experimental.duration_dead(agg, '01-01-2020 00:20 UTC', '01-01-2020 01:00 UTC')::TEXT FROM aggs",
+                    &str
+                ),
+                "00:03:30"
+            );
+
+            // ranges extending past the aggregate are clipped to its coverage
+            assert_eq!(
+                select_one!(
+                    client,
+                    "SELECT toolkit_experimental.duration_live(agg, '12-31-2019 UTC', '01-02-2020 UTC')::TEXT FROM aggs",
+                    &str
+                ),
+                "01:54:09"
+            );
+            assert_eq!(
+                select_one!(
+                    client,
+                    "SELECT toolkit_experimental.duration_dead(agg, '12-31-2019 UTC', '01-02-2020 UTC')::TEXT FROM aggs",
+                    &str
+                ),
+                "00:05:51"
+            );
+
+            // as are ranges entirely outside of it
+            assert_eq!(
+                select_one!(
+                    client,
+                    "SELECT toolkit_experimental.duration_dead(agg, '01-02-2020 UTC', '01-03-2020 UTC')::TEXT FROM aggs",
+                    &str
+                ),
+                "00:00:00"
+            );
+        });
+    }
+
+    #[pg_test]
+    pub fn test_fill_short_gaps() {
+        let mut state = HeartbeatTransState::new(0, 100, 10);
+        for time in [5, 16, 40, 52, 75] {
+            state.insert(time);
+        }
+        state.fill_short_gaps(3);
+        assert_eq!(state.liveness, vec![(5, 26), (40, 62), (75, 85)]);
+        state.fill_short_gaps(15);
+        assert_eq!(state.liveness, vec![(5, 85)]);
+    }
+
+    #[pg_test]
+    pub fn test_heartbeat_smooth() {
+        Spi::execute(|client| {
+            setup_liveness_table(&client);
+            client.select(
+                "CREATE TABLE aggs AS SELECT heartbeat_agg(heartbeat, '01-01-2020 UTC', '2h', '10m') AS agg FROM liveness",
+                None,
+                None,
+            );
+
+            let mut result = client.select(
+                "SELECT start::TEXT, \"end\"::TEXT
+                FROM live_ranges((SELECT toolkit_experimental.smooth(agg, '1m') FROM aggs))",
+                None,
+                None,
+            );
+
+            let mut next = result.next().unwrap();
+            assert_eq!(next[1].value(), Some("2020-01-01 00:02:20+00"));
+            assert_eq!(next[2].value(), Some("2020-01-01 00:27:00+00"));
+
+            next = result.next().unwrap();
+            assert_eq!(next[1].value(), Some("2020-01-01 00:30:00+00"));
+            assert_eq!(next[2].value(), Some("2020-01-01 02:00:00+00"));
+
+            assert!(result.next().is_none());
+
+            // the gap before the first heartbeat is never filled
+            assert_eq!(
+                select_one!(
+                    client,
+                    "SELECT duration_dead(toolkit_experimental.smooth(agg, '5m'))::TEXT FROM aggs",
+                    &str
+                ),
+                "00:02:20"
+            );
+        });
+    }
+
+    #[pg_test]
+    pub fn test_heartbeat_uptime_change() {
+        Spi::execute(|client| {
+            setup_liveness_table(&client);
+            client.select(
+                "CREATE TABLE aggs AS
+                SELECT
+                    date_trunc('hour', heartbeat) AS bucket,
+                    heartbeat_agg(heartbeat, date_trunc('hour', heartbeat), '1h', '10m') AS agg
+                FROM liveness
+                GROUP BY date_trunc('hour', heartbeat)",
+                None,
+                None,
+            );
+
+            let mut result = client.select(
+                "SELECT uptime_ratio_change, outage_count_change, longest_outage_change::TEXT
+                FROM toolkit_experimental.uptime_change(
+                    (SELECT agg FROM aggs WHERE bucket = '01-01-2020 01:00 UTC'),
+                    (SELECT agg FROM aggs WHERE bucket = '01-01-2020 00:00 UTC')
+                )",
+                None,
+                None,
+            );
+
+            let row = result.next().unwrap();
+            let ratio_change = row[1].value::<f64>().unwrap();
+            assert!((ratio_change - 349.0 / 3600.0).abs() < 1e-12);
+            assert_eq!(row[2].value::<i64>(), Some(-2));
+            assert_eq!(row[3].value(), Some("-00:02:59"));
+            assert!(result.next().is_none());
+        });
+    }
+
+    #[pg_test]
+    pub fn test_subtract_intervals() {
+        let a = vec![(0, 10), (20, 30), (40, 50)];
+        let b = vec![(5, 25), (28, 29), (45, 60)];
+        assert_eq!(
+            subtract_intervals(&a, &b),
+            vec![(0, 5), (25, 28), (29, 30), (40, 45)]
+        );
+        assert_eq!(subtract_intervals(&b, &a), vec![(10, 20), (50, 60)]);
+        assert_eq!(subtract_intervals(&a, &[]), a);
+        assert_eq!(subtract_intervals(&[], &a), Vec::<(i64, i64)>::new());
+    }
+
+    #[pg_test]
+    pub fn test_heartbeat_liveness_diff() {
+        Spi::execute(|client| {
+            setup_liveness_table(&client);
+            client.select(
+                "CREATE TABLE aggs AS SELECT
+                    (SELECT heartbeat_agg(heartbeat, '01-01-2020 UTC', '2h', '10m') FROM liveness) AS agg1,
+                    (SELECT heartbeat_agg(heartbeat, '01-01-2020 UTC', '2h', '10m') FROM (
+                        SELECT heartbeat FROM liveness WHERE heartbeat < '01-01-2020 1:00 UTC'
+                        UNION ALL VALUES ('01-01-2020 0:25 UTC'::timestamptz), ('01-01-2020 1:45 UTC')
+                    ) h) AS agg2",
+                None,
+                None,
+            );
+
+            let mut result = client.select(
+                "SELECT start::TEXT, \"end\"::TEXT, live_in
+                FROM toolkit_experimental.liveness_diff((SELECT agg1 FROM aggs), (SELECT agg2 FROM aggs))",
+                None,
+                None,
+            );
+
+            let mut next = || {
+                let row = result.next().unwrap();
+                (
+                    row[1].value::<String>().unwrap(),
+                    row[2].value::<String>().unwrap(),
+                    row[3].value::<String>().unwrap(),
+                )
+            };
+            assert_eq!(
+                next(),
+                (
+                    "2020-01-01 00:27:00+00".into(),
+                    "2020-01-01 00:30:00+00".into(),
+                    "agg2".into()
+                )
+            );
+            assert_eq!(
+                next(),
+                (
+                    "2020-01-01 01:00:30+00".into(),
+                    "2020-01-01 01:38:00+00".into(),
+                    "agg1".into()
+                )
+            );
+            assert_eq!(
+                next(),
+                (
+                    "2020-01-01 01:38:01+00".into(),
+                    "2020-01-01 01:45:00+00".into(),
+                    "agg1".into()
+                )
+            );
+            assert_eq!(
+                next(),
+                (
+                    "2020-01-01 01:55:00+00".into(),
+                    "2020-01-01 02:00:00+00".into(),
+                    "agg1".into()
+                )
+            );
+            assert!(result.next().is_none());
+        });
+    }
+
+    #[pg_test]
+    pub fn test_health_state_liveness() {
+        let mut state = HealthTransState::new(0, 100);
+        for (time, healthy) in [(50, true), (10, true), (30, false), (20, true), (90, true)] {
+            state.insert(time, healthy);
+        }
+        let state: HeartbeatTransState = state.into();
+        assert_eq!(state.liveness, vec![(10, 30), (50, 100)]);
+    }
+
+    #[pg_test]
+    pub fn test_health_state_compaction() {
+        let mut state = HealthTransState::new(0, 10 * BUFFER_SIZE as i64);
+        // healthy for the first half, flapping every sample for the second
+        for time in 0..10 * BUFFER_SIZE as i64 {
+            let healthy = time < 5 * BUFFER_SIZE as i64 || time % 2 == 0;
+            state.insert(time, healthy);
+        }
+        assert!(state.buffer.len() <= BUFFER_SIZE);
+        state.process_batch();
+        assert_eq!(state.changes.len(), 5 * BUFFER_SIZE);
+        let liveness = state.liveness();
+        assert_eq!(liveness[0], (0, 5 * BUFFER_SIZE as i64 + 1));
+        assert_eq!(liveness.len(), 5 * BUFFER_SIZE / 2);
+    }
+
+    #[pg_test]
+    pub fn test_health_state_ties() {
+        // the unhealthy sample wins whichever order they're seen in, and
+        // whether they're folded in together or apart
+        for samples in [[(20, true), (20, false)], [(20, false), (20, true)]] {
+            let mut state = HealthTransState::new(0, 100);
+            state.insert(10, true);
+            for (time, healthy) in samples {
+                state.insert(time, healthy);
+            }
+            assert_eq!(state.liveness(), vec![(10, 20)]);
+
+            let mut first = HealthTransState::new(0, 100);
+            first.insert(10, true);
+            first.insert(samples[0].0, samples[0].1);
+            let mut second = HealthTransState::new(0, 100);
+            second.insert(samples[1].0, samples[1].1);
+            first.combine(second);
+            assert_eq!(first.liveness(), vec![(10, 20)]);
+        }
+    }
+
+    fn setup_health_table(client: &SpiClient) {
+        client.select("SET TIMEZONE to UTC", None, None);
+        client.select(
+            "CREATE TABLE health(ts TIMESTAMPTZ, error_rate DOUBLE PRECISION, latency DOUBLE PRECISION)",
+            None,
+            None,
+        );
+        client.select(
+            "INSERT INTO health VALUES
+                ('01-01-2020 0:05 UTC', 0.01, 50),
+                ('01-01-2020 0:10 UTC', 0.5, 50),
+                ('01-01-2020 0:20 UTC', 0.01, 50),
+                ('01-01-2020 0:40 UTC', 0.01, 500),
+                ('01-01-2020 0:50 UTC', 0.01, 50)",
+            None,
+            None,
+        );
+    }
+
+    #[pg_test]
+    pub fn test_health_agg() {
+        Spi::execute(|client| {
+            setup_health_table(&client);
+
+            let mut result = client.select(
+                "SELECT start::TEXT, \"end\"::TEXT FROM live_ranges(
+                    (SELECT toolkit_experimental.health_agg(ts, error_rate < 0.1 AND latency < 100, '01-01-2020 UTC', '1h') FROM health)
+                )",
+                None,
+                None,
+            );
+
+            let mut next = result.next().unwrap();
+            assert_eq!(next[1].value(), Some("2020-01-01 00:05:00+00"));
+            assert_eq!(next[2].value(), Some("2020-01-01 00:10:00+00"));
+
+            next = result.next().unwrap();
+            assert_eq!(next[1].value(), Some("2020-01-01 00:20:00+00"));
+            assert_eq!(next[2].value(), Some("2020-01-01 00:40:00+00"));
+
+            next = result.next().unwrap();
+            assert_eq!(next[1].value(), Some("2020-01-01 00:50:00+00"));
+            assert_eq!(next[2].value(), Some("2020-01-01 01:00:00+00"));
+
+            assert!(result.next().is_none());
+        });
+    }
+
+    #[pg_test]
+    pub fn test_health_all_any_live() {
+        Spi::execute(|client| {
+            setup_health_table(&client);
+            client.select(
+                "CREATE TABLE aggs AS SELECT
+                    toolkit_experimental.health_agg(ts, error_rate < 0.1, '01-01-2020 UTC', '1h') AS errors,
+                    toolkit_experimental.health_agg(ts, latency < 100, '01-01-2020 UTC', '1h') AS latency
+                FROM health",
+                None,
+                None,
+            );
+
+            // combining the separate conditions is equivalent to aggregating
+            // over the combined condition
+            assert_eq!(
+                select_one!(
+                    client,
+                    "SELECT duration_live(toolkit_experimental.all_live(errors, latency))::TEXT FROM aggs",
+                    &str
+                ),
+                "00:35:00"
+            );
+            assert_eq!(
+                select_one!(
+                    client,
+                    "SELECT duration_live(toolkit_experimental.any_live(errors, latency))::TEXT FROM aggs",
+                    &str
+                ),
+                "00:55:00"
+            );
+        });
+    }
+
+    #[pg_test]
+    pub fn test_heartbeat_agg_eq_and_contains() {
+        Spi::execute(|client| {
+            setup_liveness_table(&client);
+            client.select(
+                "CREATE TABLE aggs AS SELECT
+                    (SELECT heartbeat_agg(heartbeat, '01-01-2020 UTC', '2h', '10m') FROM liveness) AS full_agg,
+                    (SELECT rollup(agg) FROM (
+                        SELECT heartbeat_agg(heartbeat, date_trunc('hour', heartbeat), '1h', '10m') AS agg
+                        FROM liveness
+                        GROUP BY date_trunc('hour', heartbeat)
+                    ) hourly) AS rolled_up,
+                    (SELECT heartbeat_agg(heartbeat, '01-01-2020 UTC', '2h', '10m')
+                        FROM liveness WHERE heartbeat < '01-01-2020 1:00 UTC') AS partial_agg",
+                None,
+                None,
+            );
+
+            let check = |query: &str| {
+                client
+                    .select(query, None, None)
+                    .first()
+                    .get_one::<bool>()
+                    .unwrap()
+            };
+
+            // rolling up hourly aggregates must be equivalent to aggregating
+            // over the whole range
+            assert!(check(
+                "SELECT full_agg OPERATOR(toolkit_experimental.=) rolled_up FROM aggs"
+            ));
+            assert!(!check(
+                "SELECT full_agg OPERATOR(toolkit_experimental.=) partial_agg FROM aggs"
+            ));
+            assert!(check(
+                "SELECT full_agg OPERATOR(toolkit_experimental.<>) partial_agg FROM aggs"
+            ));
+            assert!(!check(
+                "SELECT full_agg OPERATOR(toolkit_experimental.<>) rolled_up FROM aggs"
+            ));
+
+            assert!(check(
+                "SELECT full_agg OPERATOR(toolkit_experimental.@>) partial_agg FROM aggs"
+            ));
+            assert!(!check(
+                "SELECT partial_agg OPERATOR(toolkit_experimental.@>) full_agg FROM aggs"
+            ));
+            assert!(check(
+                "SELECT partial_agg OPERATOR(toolkit_experimental.<@) full_agg FROM aggs"
+            ));
+            assert!(!check(
+                "SELECT full_agg OPERATOR(toolkit_experimental.<@) partial_agg FROM aggs"
+            ));
+            assert!(check(
+                "SELECT toolkit_experimental.live_contains(full_agg, full_agg) FROM aggs"
+            ));
+
+            let (commutator, negator) = client
+                .select(
+                    "SELECT oprcom::regoperator::text, oprnegate::regoperator::text FROM pg_operator
+                    WHERE oid = 'toolkit_experimental.=(heartbeatagg, heartbeatagg)'::regoperator",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(
+                commutator.as_deref(),
+                Some("toolkit_experimental.=(heartbeatagg,heartbeatagg)")
+            );
+            assert_eq!(
+                negator.as_deref(),
+                Some("toolkit_experimental.<>(heartbeatagg,heartbeatagg)")
+            );
+        });
+    }
+
+    #[pg_test]
+    pub fn test_heartbeat_dead_ranges_and_live_at() {
+        Spi::execute(|client| {
+            setup_liveness_table(&client);
+            client.select(
+                "CREATE TABLE aggs AS SELECT heartbeat_agg(heartbeat, '01-01-2020 UTC', '2h', '10m') AS agg FROM liveness",
+                None,
+                None,
+            );
+
+            let mut result = client.select(
+                "SELECT start::TEXT, \"end\"::TEXT FROM dead_ranges((SELECT agg FROM aggs))",
+                None,
+                None,
+            );
+
+            let mut next = result.next().unwrap();
+            assert_eq!(next[1].value(), Some("2020-01-01 00:00:00+00"));
+            assert_eq!(next[2].value(), Some("2020-01-01 00:02:20+00"));
+
+            next = result.next().unwrap();
+            assert_eq!(next[1].value(), Some("2020-01-01 00:27:00+00"));
+            assert_eq!(next[2].value(), Some("2020-01-01 00:30:00+00"));
+
+            next = result.next().unwrap();
+            assert_eq!(next[1].value(), Some("2020-01-01 00:50:00+00"));
+            assert_eq!(next[2].value(), Some("2020-01-01 00:50:30+00"));
+
+            next = result.next().unwrap();
+            assert_eq!(next[1].value(), Some("2020-01-01 01:38:00+00"));
+            assert_eq!(next[2].value(), Some("2020-01-01 01:38:01+00"));
+
+            assert!(result.next().is_none());
+
+            assert!(!select_one!(
+                client,
+                "SELECT live_at(agg, '01-01-2020 00:01:00 UTC') FROM aggs",
+                bool
+            ));
+            assert!(select_one!(
+                client,
+                "SELECT live_at(agg, '01-01-2020 00:05:00 UTC') FROM aggs",
+                bool
+            ));
+            assert!(!select_one!(
+                client,
+                "SELECT live_at(agg, '01-01-2020 00:50:00 UTC') FROM aggs",
+                bool
+            ));
+            assert!(select_one!(
+                client,
+                "SELECT live_at(agg, '01-01-2020 01:59:59 UTC') FROM aggs",
+                bool
+            ));
+        });
+    }
+
+    #[pg_test]
+    pub fn test_heartbeat_live_range_at() {
+        Spi::execute(|client| {
+            setup_liveness_table(&client);
+            client.select(
+                "CREATE TABLE aggs AS SELECT heartbeat_agg(heartbeat, '01-01-2020 UTC', '2h', '10m') AS agg FROM liveness",
+                None,
+                None,
+            );
+
+            let range_at = |ts: &str| {
+                let mut result = client.select(
+                    &format!(
+                        "SELECT start::TEXT, \"end\"::TEXT, live
+                        FROM toolkit_experimental.live_range_at((SELECT agg FROM aggs), '{}')",
+                        ts
+                    ),
+                    None,
+                    None,
+                );
+                let row = result.next().unwrap();
+                let range = (
+                    row[1].value::<String>(),
+                    row[2].value::<String>(),
+                    row[3].value::<bool>(),
+                );
+                assert!(result.next().is_none());
+                range
+            };
+
+            assert_eq!(
+                range_at("01-01-2020 00:10:00 UTC"),
+                (
+                    Some("2020-01-01 00:02:20+00".to_string()),
+                    Some("2020-01-01 00:27:00+00".to_string()),
+                    Some(true)
+                )
+            );
+            assert_eq!(
+                range_at("01-01-2020 00:01:00 UTC"),
+                (
+                    Some("2020-01-01 00:00:00+00".to_string()),
+                    Some("2020-01-01 00:02:20+00".to_string()),
+                    Some(false)
+                )
+            );
+            assert_eq!(
+                range_at("01-01-2020 00:50:10 UTC"),
+                (
+                    Some("2020-01-01 00:50:00+00".to_string()),
+                    Some("2020-01-01 00:50:30+00".to_string()),
+                    Some(false)
+                )
+            );
+            assert_eq!(
+                range_at("01-01-2020 01:59:59 UTC"),
+                (
+                    Some("2020-01-01 01:38:01+00".to_string()),
+                    Some("2020-01-01 02:00:00+00".to_string()),
+                    Some(true)
+                )
+            );
+            assert_eq!(range_at("01-01-2020 03:00:00 UTC"), (None, None, None));
+        });
+    }
+
+    #[pg_test]
+    pub fn test_fold_onto_cycle() {
+        let mut state = HeartbeatTransState::new(0, 200, 10);
+        for heartbeat in [0, 25, 110, 180] {
+            state.insert(heartbeat);
+        }
+        let agg: HeartbeatAgg = state.into();
+        // live [0, 10), [25, 35), [110, 120), [180, 190) folded onto 4 slots
+        assert_eq!(
+            agg.fold_onto_cycle(10, 4, 0),
+            vec![(50, 10), (50, 0), (50, 15), (50, 15)]
+        );
+        assert_eq!(
+            agg.fold_onto_cycle(10, 4, 1),
+            vec![(50, 15), (50, 10), (50, 0), (50, 15)]
+        );
+    }
+
+    #[pg_test]
+    pub fn test_heartbeat_uptime_heatmap() {
+        Spi::execute(|client| {
+            setup_liveness_table(&client);
+
+            let mut hours = client.select(
+                "SELECT slot, live_fraction FROM toolkit_experimental.uptime_heatmap(
+                    (SELECT heartbeat_agg(heartbeat, '01-01-2020 UTC', '2h', '10m') FROM liveness),
+                    'hour_of_day')",
+                None,
+                None,
+            );
+            let first = hours.next().unwrap();
+            assert_eq!(first[1].value::<i32>(), Some(0));
+            assert_eq!(
+                first[2].value::<f64>(),
+                Some((3600 - 140 - 180 - 30) as f64 / 3600.0)
+            );
+            let second = hours.next().unwrap();
+            assert_eq!(second[1].value::<i32>(), Some(1));
+            assert_eq!(second[2].value::<f64>(), Some(3599.0 / 3600.0));
+            for _ in 2..24 {
+                assert_eq!(hours.next().unwrap()[2].value::<f64>(), None);
+            }
+            assert!(hours.next().is_none());
+
+            // 2020-01-01 was a Wednesday
+            let days = client.select(
+                "SELECT slot, live_fraction FROM toolkit_experimental.uptime_heatmap(
+                    (SELECT heartbeat_agg(heartbeat, '01-01-2020 UTC', '2h', '10m') FROM liveness),
+                    'day_of_week')
+                WHERE live_fraction IS NOT NULL",
+                None,
+                None,
+            );
+            let days: Vec<Option<i32>> = days.map(|row| row[1].value()).collect();
+            assert_eq!(days, vec![Some(3)]);
+        });
+    }
+
+    #[pg_test(
+        error = "unrecognized heatmap cycle 'minute', expected 'hour_of_day' or 'day_of_week'"
+    )]
+    pub fn test_heartbeat_uptime_heatmap_bad_cycle() {
+        Spi::execute(|client| {
+            setup_liveness_table(&client);
+            client.select(
+                "SELECT toolkit_experimental.uptime_heatmap(
+                    (SELECT heartbeat_agg(heartbeat, '01-01-2020 UTC', '2h', '10m') FROM liveness),
+                    'minute')",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test(
+        error = "heartbeat at 2020-01-01 02:00:00+00 is outside of the range covered by heartbeat_agg, all points must occur in the 'agg_duration' interval after 'agg_start'"
+    )]
+    pub fn test_heartbeat_agg_point_out_of_range() {
+        Spi::execute(|client| {
+            setup_liveness_table(&client);
+            client.select(
+                "SELECT heartbeat_agg(heartbeat, '01-01-2020 UTC', '2h', '10m')
+                FROM (SELECT heartbeat FROM liveness UNION ALL SELECT '01-01-2020 2:00 UTC') h",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test(
+        error = "sample at 2020-01-01 01:00:00+00 is outside of the range covered by health_agg, all points must occur in the 'agg_duration' interval after 'agg_start'"
+    )]
+    pub fn test_health_agg_point_out_of_range() {
+        Spi::execute(|client| {
+            setup_health_table(&client);
+            client.select(
+                "SELECT toolkit_experimental.health_agg(ts, healthy, '01-01-2020 UTC', '1h')
+                FROM (SELECT ts, error_rate < 0.1 AS healthy FROM health
+                    UNION ALL SELECT '01-01-2020 1:00 UTC', true) h",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test(error = "health_agg requires a positive agg_duration")]
+    pub fn test_health_agg_negative_duration() {
+        Spi::execute(|client| {
+            setup_health_table(&client);
+            client.select(
+                "SELECT toolkit_experimental.health_agg(ts, true, '01-01-2020 UTC', '-1h') FROM health",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test(
+        error = "unable to combine heartbeat aggregates with different liveness intervals (00:10:00 and 00:05:00)"
+    )]
+    pub fn test_heartbeat_rollup_liveness_mismatch() {
+        Spi::execute(|client| {
+            setup_liveness_table(&client);
+            client.select(
+                "SELECT rollup(agg) FROM (
+                    SELECT heartbeat_agg(heartbeat, '01-01-2020 UTC', '1h', '10m') AS agg
+                    FROM liveness WHERE heartbeat < '01-01-2020 1:00 UTC'
+                    UNION ALL
+                    SELECT heartbeat_agg(heartbeat, '01-01-2020 1:00 UTC', '1h', '5m')
+                    FROM liveness WHERE heartbeat >= '01-01-2020 1:00 UTC'
+                ) aggs",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test(error = "heartbeat_agg requires a positive agg_duration")]
+    pub fn test_heartbeat_agg_empty_duration() {
+        Spi::execute(|client| {
+            setup_liveness_table(&client);
+            client.select(
+                "SELECT heartbeat_agg(heartbeat, '01-01-2020 UTC', '0', '10m') FROM liveness",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    pub fn test_heartbeat_interpolated_live_at() {
+        Spi::execute(|client| {
+            setup_liveness_table(&client);
+
+            client.select(
+                "CREATE TABLE aggs AS
+                SELECT
+                    (SELECT heartbeat_agg(heartbeat, '01-01-2020 1:00 UTC', '30m', '10m')
+                        FROM liveness WHERE heartbeat < '01-01-2020 1:30 UTC') AS prev,
+                    (SELECT heartbeat_agg(heartbeat, '01-01-2020 1:30 UTC', '30m', '10m')
+                        FROM liveness WHERE heartbeat >= '01-01-2020 1:30 UTC') AS agg",
+                None,
+                None,
+            );
+
+            // the heartbeat at 1:28 keeps things live until 1:38, but the
+            // bucket starting at 1:30 doesn't know about it
+            let (live, interpolated) = client
+                .select(
+                    "SELECT live_at(agg, '01-01-2020 1:35 UTC'),
+                        toolkit_experimental.interpolated_live_at(agg, '01-01-2020 1:35 UTC', prev)
+                    FROM aggs",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<bool, bool>();
+            assert_eq!(live, Some(false));
+            assert_eq!(interpolated, Some(true));
+
+            assert!(!select_one!(
+                client,
+                "SELECT toolkit_experimental.interpolated_live_at(agg, '01-01-2020 1:38 UTC', prev) FROM aggs",
+                bool
+            ));
+            assert!(!select_one!(
+                client,
+                "SELECT toolkit_experimental.interpolated_live_at(agg, '01-01-2020 1:35 UTC', NULL) FROM aggs",
+                bool
+            ));
+            assert!(select_one!(
+                client,
+                "SELECT toolkit_experimental.interpolated_live_at(agg, '01-01-2020 1:40 UTC', prev) FROM aggs",
+                bool
+            ));
+        });
+    }
+
+    #[pg_test(error = "the previous heartbeat_agg must end before the start of this one")]
+    pub fn test_heartbeat_interpolated_live_at_overlapping_prev() {
+        Spi::execute(|client| {
+            setup_liveness_table(&client);
+            client.select(
+                "SELECT toolkit_experimental.interpolated_live_at(agg, '01-01-2020 1:35 UTC', agg)
+                FROM (SELECT heartbeat_agg(heartbeat, '01-01-2020 UTC', '2h', '10m') AS agg FROM liveness) a",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    pub fn test_heartbeat_describe() {
+        Spi::execute(|client| {
+            setup_liveness_table(&client);
+
+            let description = client
+                .select(
+                    "SELECT toolkit_experimental.describe(heartbeat_agg(heartbeat, '01-01-2020 UTC', '2h', '10m')) FROM liveness",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                description.as_deref(),
+                Some(
+                    "coverage: 2020-01-01 00:00:00+00 to 2020-01-01 02:00:00+00\n\
+                    uptime: 95.12%\n\
+                    outages: 4\n\
+                    longest outage: 00:03:00"
+                )
+            );
+        });
+    }
+
+    #[pg_test]
+    pub fn test_heartbeat_rollup() {
+        Spi::execute(|client| {
+            setup_liveness_table(&client);
+
+            // materialize hourly aggregates, then roll them up into the full range
+            client.select(
+                "CREATE TABLE aggs AS
+                SELECT heartbeat_agg(
+                    heartbeat,
+                    date_trunc('hour', heartbeat),
+                    '1h',
+                    '10m'
+                ) AS agg
+                FROM liveness
+                GROUP BY date_trunc('hour', heartbeat)",
+                None,
+                None,
+            );
+
+            let mut result = client.select(
+                "SELECT start::TEXT, \"end\"::TEXT FROM live_ranges(
+                    (SELECT rollup(agg) FROM aggs)
+                )",
+                None,
+                None,
+            );
+
+            let mut next = result.next().unwrap();
+            assert_eq!(next[1].value(), Some("2020-01-01 00:02:20+00"));
+            assert_eq!(next[2].value(), Some("2020-01-01 00:27:00+00"));
+
+            next = result.next().unwrap();
+            assert_eq!(next[1].value(), Some("2020-01-01 00:30:00+00"));
+            assert_eq!(next[2].value(), Some("2020-01-01 00:50:00+00"));
+
+            next = result.next().unwrap();
+            assert_eq!(next[1].value(), Some("2020-01-01 00:50:30+00"));
+            assert_eq!(next[2].value(), Some("2020-01-01 01:38:00+00"));
+
+            next = result.next().unwrap();
+            assert_eq!(next[1].value(), Some("2020-01-01 01:38:01+00"));
+            assert_eq!(next[2].value(), Some("2020-01-01 02:00:00+00"));
+
+            assert!(result.next().is_none());
+
+            assert_eq!(
+                select_one!(
+                    client,
+                    "SELECT duration_live(rollup(agg))::TEXT FROM aggs",
+                    &str
+                ),
+                "01:54:09"
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_combine_disjoint_windows() {
+        let mut first = HeartbeatTransState::new(0, 100, 10);
+        first.insert(10);
+        first.insert(95);
+        let mut second = HeartbeatTransState::new(150, 200, 10);
+        second.insert(160);
+
+        first.combine(second);
+        assert_eq!(first.start, 0);
+        assert_eq!(first.end, 200);
+        assert_eq!(first.uncovered, vec![(100, 150)]);
+        // the liveness from 95 isn't carried into the uncovered range
+        assert_eq!(first.liveness, vec![(10, 20), (95, 100), (160, 170)]);
+
+        // filling in a window covers its part of the gap
+        let mut middle = HeartbeatTransState::new(120, 150, 10);
+        middle.insert(145);
+        first.combine(middle);
+        assert_eq!(first.uncovered, vec![(100, 120)]);
+        assert_eq!(
+            first.liveness,
+            vec![(10, 20), (95, 100), (145, 155), (160, 170)]
+        );
+    }
+
+    #[pg_test]
+    pub fn test_heartbeat_rollup_disjoint() {
+        Spi::execute(|client| {
+            setup_liveness_table(&client);
+
+            client.select(
+                "CREATE TABLE aggs AS
+                SELECT rollup(agg) AS agg FROM (
+                    SELECT heartbeat_agg(heartbeat, '01-01-2020 UTC', '20m', '10m') AS agg
+                    FROM liveness WHERE heartbeat < '01-01-2020 0:20 UTC'
+                    UNION ALL
+                    SELECT heartbeat_agg(heartbeat, '01-01-2020 1:30 UTC', '30m', '10m')
+                    FROM liveness WHERE heartbeat >= '01-01-2020 1:30 UTC'
+                ) a",
+                None,
+                None,
+            );
+
+            let (live, dead, uncovered) = client
+                .select(
+                    "SELECT duration_live(agg)::TEXT,
+                        duration_dead(agg)::TEXT,
+                        toolkit_experimental.uncovered_duration(agg)::TEXT
+                    FROM aggs",
+                    None,
+                    None,
+                )
+                .first()
+                .get_three::<String, String, String>();
+            assert_eq!(live.unwrap(), "00:39:39");
+            assert_eq!(dead.unwrap(), "00:10:21");
+            assert_eq!(uncovered.unwrap(), "01:10:00");
+
+            let mut result = client.select(
+                "SELECT start::TEXT, \"end\"::TEXT FROM dead_ranges((SELECT agg FROM aggs))",
+                None,
+                None,
+            );
+            let mut next = result.next().unwrap();
+            assert_eq!(next[1].value(), Some("2020-01-01 00:00:00+00"));
+            assert_eq!(next[2].value(), Some("2020-01-01 00:02:20+00"));
+            next = result.next().unwrap();
+            assert_eq!(next[1].value(), Some("2020-01-01 01:30:00+00"));
+            assert_eq!(next[2].value(), Some("2020-01-01 01:38:01+00"));
+            assert!(result.next().is_none());
+
+            let live_range = client
+                .select(
+                    "SELECT live FROM toolkit_experimental.live_range_at(
+                        (SELECT agg FROM aggs), '01-01-2020 1:00 UTC')",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<bool>();
+            assert_eq!(live_range, None);
+        });
+    }
+
+    #[pg_test]
+    fn test_exclude_windows() {
+        let mut state = HeartbeatTransState::new(0, 100, 10);
+        state.insert(10);
+        state.insert(50);
+        state.exclude(vec![(40, 55), (-5, 5), (45, 60), (120, 130)]);
+        assert_eq!(state.uncovered, vec![(0, 5), (40, 60)]);
+        assert_eq!(state.liveness, vec![(10, 20)]);
+    }
+
+    #[pg_test]
+    fn test_exclude_then_combine() {
+        let mut first = HeartbeatTransState::new(0, 100, 10);
+        first.insert(10);
+        first.insert(95);
+        first.exclude(vec![(90, 100)]);
+        first.combine(HeartbeatTransState::new(100, 200, 10));
+        assert_eq!(first.liveness, vec![(10, 20), (100, 105)]);
+
+        // no liveness at all is left after the exclusion
+        let mut first = HeartbeatTransState::new(0, 100, 10);
+        first.insert(95);
+        first.exclude(vec![(0, 100)]);
+        first.combine(HeartbeatTransState::new(100, 200, 10));
+        assert_eq!(first.uncovered, vec![(0, 100)]);
+        assert_eq!(first.liveness, vec![(100, 105)]);
+    }
+
+    #[pg_test]
+    pub fn test_heartbeat_exclude_maintenance() {
+        Spi::execute(|client| {
+            setup_liveness_table(&client);
+
+            client.select(
+                "CREATE TABLE aggs AS
+                SELECT heartbeat_agg(heartbeat, '01-01-2020 UTC', '2h', '10m') AS agg
+                FROM liveness",
+                None,
+                None,
+            );
+
+            let (live, dead) = client
+                .select(
+                    "SELECT
+                        toolkit_experimental.duration_live(agg, excluding => windows)::TEXT,
+                        toolkit_experimental.duration_dead(agg, excluding => windows)::TEXT
+                    FROM aggs, (SELECT '{
+                        \"[2020-01-01 00:00+00, 2020-01-01 00:05+00)\",
+                        \"[2020-01-01 00:25+00, 2020-01-01 00:35+00)\"
+                    }'::tstzrange[] AS windows) w",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(live.unwrap(), "01:44:29");
+            assert_eq!(dead.unwrap(), "00:00:31");
+
+            let mut result = client.select(
+                "SELECT start::TEXT, \"end\"::TEXT FROM dead_ranges(
+                    toolkit_experimental.exclude(
+                        (SELECT agg FROM aggs),
+                        ARRAY[
+                            tstzrange('2020-01-01 00:00+00', '2020-01-01 00:05+00'),
+                            NULL,
+                            tstzrange('2020-01-01 00:25+00', '2020-01-01 00:35+00')
+                        ]
+                    )
+                )",
+                None,
+                None,
+            );
+
+            let mut next = result.next().unwrap();
+            assert_eq!(next[1].value(), Some("2020-01-01 00:50:00+00"));
+            assert_eq!(next[2].value(), Some("2020-01-01 00:50:30+00"));
+
+            next = result.next().unwrap();
+            assert_eq!(next[1].value(), Some("2020-01-01 01:38:00+00"));
+            assert_eq!(next[2].value(), Some("2020-01-01 01:38:01+00"));
+
+            assert!(result.next().is_none());
+
+            // unbounded windows extend to the edge of the aggregate
+            let (live, uncovered) = client
+                .select(
+                    "SELECT duration_live(agg)::TEXT,
+                        toolkit_experimental.uncovered_duration(agg)::TEXT
+                    FROM (
+                        SELECT toolkit_experimental.exclude(
+                            agg,
+                            ARRAY[tstzrange('2020-01-01 01:50+00', NULL)]
+                        ) AS agg
+                        FROM aggs
+                    ) a",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<String, String>();
+            assert_eq!(live.unwrap(), "01:44:09");
+            assert_eq!(uncovered.unwrap(), "00:10:00");
+        });
+    }
+
+    #[pg_test]
+    pub fn test_heartbeat_merge_upsert() {
+        Spi::execute(|client| {
+            setup_liveness_table(&client);
+            client.select(
+                "CREATE TABLE daily(day DATE PRIMARY KEY, agg HeartbeatAgg)",
+                None,
+                None,
+            );
+
+            // stream in one small aggregate per heartbeat, out of order
+            client.select(
+                "DO $$
+                DECLARE hb TIMESTAMPTZ;
+                BEGIN
+                    FOR hb IN SELECT heartbeat FROM liveness ORDER BY heartbeat DESC LOOP
+                        INSERT INTO daily
+                            SELECT '2020-01-01', heartbeat_agg(hb, date_trunc('hour', hb), '1h', '10m')
+                        ON CONFLICT (day) DO UPDATE
+                            SET agg = toolkit_experimental.heartbeat_merge(daily.agg, EXCLUDED.agg);
+                    END LOOP;
+                END
+                $$",
+                None,
+                None,
+            );
+
+            // the result is the same as rolling up hourly aggregates
+            assert!(select_one!(
+                client,
+                "SELECT toolkit_experimental.heartbeat_agg_eq(
+                    (SELECT agg FROM daily),
+                    (SELECT rollup(agg) FROM (
+                        SELECT heartbeat_agg(heartbeat, date_trunc('hour', heartbeat), '1h', '10m') AS agg
+                        FROM liveness GROUP BY date_trunc('hour', heartbeat)) hourly)
+                )",
+                bool
+            ));
+
+            assert_eq!(
+                select_one!(client, "SELECT duration_live(agg)::TEXT FROM daily", &str),
+                "01:54:09"
+            );
+
+            // merging is commutative, and NULL acts as an identity
+            assert!(select_one!(
+                client,
+                "SELECT toolkit_experimental.heartbeat_agg_eq(
+                    toolkit_experimental.heartbeat_merge(a, b),
+                    toolkit_experimental.heartbeat_merge(b, a)
+                )
+                FROM (SELECT
+                    heartbeat_agg(heartbeat, '01-01-2020 UTC', '1h', '10m') FILTER (WHERE heartbeat < '01-01-2020 1:00 UTC') AS a,
+                    heartbeat_agg(heartbeat, '01-01-2020 1:00 UTC', '1h', '10m') FILTER (WHERE heartbeat >= '01-01-2020 1:00 UTC') AS b
+                    FROM liveness) aggs",
+                bool
+            ));
+            assert!(select_one!(
+                client,
+                "SELECT toolkit_experimental.heartbeat_agg_eq(
+                    toolkit_experimental.heartbeat_merge(agg, NULL), agg)
+                FROM daily",
+                bool
+            ));
+        });
+    }
+
+    #[pg_test]
+    pub fn test_heartbeat_agg_coverage_range() {
+        Spi::execute(|client| {
+            setup_liveness_table(&client);
+
+            assert!(select_one!(
+                client,
+                "SELECT toolkit_experimental.heartbeat_agg_eq(
+                    toolkit_experimental.heartbeat_agg(heartbeat, '[01-01-2020 UTC, 01-01-2020 2:00 UTC)', '10m'),
+                    heartbeat_agg(heartbeat, '01-01-2020 UTC', '2h', '10m'))
+                FROM liveness",
+                bool
+            ));
+
+            // unbounded ends cover from the first heartbeat until the last one expires
+            client.select(
+                "CREATE TABLE aggs AS
+                SELECT toolkit_experimental.heartbeat_agg(heartbeat, '(,)', '10m') AS agg FROM liveness",
+                None,
+                None,
+            );
+            let range_at = |ts: &str| {
+                client
+                    .select(
+                        &format!(
+                            "SELECT start::TEXT, \"end\"::TEXT
+                            FROM toolkit_experimental.live_range_at((SELECT agg FROM aggs), '{}')",
+                            ts
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_two::<String, String>()
+            };
+            assert_eq!(
+                range_at("01-01-2020 00:02:20 UTC"),
+                (
+                    Some("2020-01-01 00:02:20+00".to_string()),
+                    Some("2020-01-01 00:27:00+00".to_string())
+                )
+            );
+            assert_eq!(
+                range_at("01-01-2020 02:09:49 UTC"),
+                (
+                    Some("2020-01-01 01:38:01+00".to_string()),
+                    Some("2020-01-01 02:09:50+00".to_string())
+                )
+            );
+            assert_eq!(range_at("01-01-2020 00:02:19 UTC"), (None, None));
+            assert_eq!(range_at("01-01-2020 02:09:50 UTC"), (None, None));
+
+            // only the unbounded side is adjusted
+            assert_eq!(
+                select_one!(
+                    client,
+                    "SELECT duration_dead(toolkit_experimental.heartbeat_agg(heartbeat, '[01-01-2020 UTC,)', '10m'))::TEXT
+                    FROM liveness",
+                    &str
+                ),
+                "00:05:51"
+            );
+        });
+    }
+
+    #[pg_test]
+    pub fn test_heartbeat_agg_text_io() {
+        Spi::execute(|client| {
+            client.select("SET TIMEZONE to UTC", None, None);
+
+            let output = select_one!(
+                client,
+                "SELECT heartbeat_agg(ts, '01-01-2020 UTC', '1h', '10m')::TEXT
+                FROM (VALUES ('01-01-2020 0:10 UTC'::timestamptz), ('01-01-2020 0:30 UTC'::timestamptz)) v(ts)",
+                &str
+            );
+
+            let expected = "(version:1,start_time:631152000000000,end_time:631155600000000,last_seen:631153800000000,interval_len:600000000,num_intervals:2,interval_starts:[631152600000000,631153800000000],interval_ends:[631153200000000,631154400000000],num_uncovered:0,uncovered_starts:[],uncovered_ends:[])";
+            assert_eq!(output, expected);
+
+            let round_trip = select_one!(
+                client,
+                &format!("SELECT duration_live('{}'::heartbeatagg)::TEXT", expected),
+                &str
+            );
+            assert_eq!(round_trip, "00:20:00");
+        });
+    }
+}
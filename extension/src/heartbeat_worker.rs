@@ -0,0 +1,310 @@
+//! Incremental maintenance of per-bucket heartbeat aggregates
+//!
+//! INSERT INTO toolkit_experimental.heartbeat_agg_jobs
+//!     (source, time_column, target, bucket_width, heartbeat_liveness)
+//!     VALUES ('heartbeats', 'ts', 'heartbeat_buckets', '1 hour', '1 minute');
+//! SELECT toolkit_experimental.refresh_heartbeat_aggs();
+//!
+//! Each job aggregates the heartbeats in `source` into one `HeartbeatAgg` per
+//! bucket, stored in the `agg` column of `target` keyed by its `bucket`
+//! column.  A refresh only reads the heartbeats from `late_data_window`
+//! before the last one seen by the previous refresh onward, and merges the
+//! result into the existing rows with `heartbeat_merge`.  Merging an
+//! aggregate with one built from some of the same heartbeats doesn't change
+//! it, so heartbeats arriving late, but within `late_data_window`, are picked
+//! up without recomputing whole buckets.
+//!
+//! Refreshes can be run by hand or from a scheduler, or by a background worker
+//! which is started when the library is in `shared_preload_libraries` and
+//! `timescaledb_toolkit.heartbeat_agg_worker_database` is set.
+
+use std::time::Duration;
+
+use pgx::{bgworkers::*, *};
+
+static WORKER_DATABASE: GucSetting<Option<&'static str>> = GucSetting::new(None);
+static REFRESH_INTERVAL: GucSetting<i32> = GucSetting::new(60);
+
+pub(crate) fn init() {
+    GucRegistry::define_int_guc(
+        "timescaledb_toolkit.heartbeat_agg_refresh_interval",
+        "Seconds between refreshes of heartbeat aggregates by the background worker.",
+        "",
+        &REFRESH_INTERVAL,
+        1,
+        i32::MAX,
+        GucContext::Sighup,
+    );
+
+    // postmaster GUCs and background workers can only be defined while
+    // preloading; defining them on a later load is a FATAL error
+    if unsafe { !pg_sys::process_shared_preload_libraries_in_progress } {
+        return;
+    }
+    GucRegistry::define_string_guc(
+        "timescaledb_toolkit.heartbeat_agg_worker_database",
+        "Database in which to maintain heartbeat aggregates.",
+        "When set, and the library is preloaded, a background worker refreshes the jobs in toolkit_experimental.heartbeat_agg_jobs.",
+        &WORKER_DATABASE,
+        GucContext::Postmaster,
+    );
+    if WORKER_DATABASE.get().is_none() {
+        return;
+    }
+    BackgroundWorkerBuilder::new("timescaledb_toolkit heartbeat_agg worker")
+        .set_function("heartbeat_agg_worker_main")
+        .set_library(concat!("timescaledb_toolkit-", env!("CARGO_PKG_VERSION")))
+        .enable_spi_access()
+        .set_restart_time(Some(Duration::from_secs(60)))
+        .load();
+}
+
+#[pg_guard]
+#[no_mangle]
+pub extern "C" fn heartbeat_agg_worker_main(_arg: pg_sys::Datum) {
+    BackgroundWorker::attach_signal_handlers(SignalWakeFlags::SIGHUP | SignalWakeFlags::SIGTERM);
+    BackgroundWorker::connect_worker_to_spi(WORKER_DATABASE.get(), None);
+
+    while BackgroundWorker::wait_latch(Some(Duration::from_secs(REFRESH_INTERVAL.get() as u64))) {
+        BackgroundWorker::transaction(|| {
+            // the library may be preloaded without the extension being
+            // installed in the database
+            let installed = Spi::get_one::<bool>(
+                "SELECT to_regclass('toolkit_experimental.heartbeat_agg_jobs') IS NOT NULL",
+            );
+            if installed == Some(true) {
+                refresh_heartbeat_aggs();
+            }
+        });
+    }
+}
+
+extension_sql!(
+    "\n\
+    CREATE TABLE toolkit_experimental.heartbeat_agg_jobs (\n\
+        job_id SERIAL PRIMARY KEY,\n\
+        source REGCLASS NOT NULL,\n\
+        time_column NAME NOT NULL,\n\
+        target REGCLASS NOT NULL,\n\
+        bucket_width INTERVAL NOT NULL\n\
+            CHECK (bucket_width > '0' AND date_part('month', bucket_width) = 0 AND date_part('year', bucket_width) = 0),\n\
+        heartbeat_liveness INTERVAL NOT NULL CHECK (heartbeat_liveness > '0'),\n\
+        late_data_window INTERVAL NOT NULL DEFAULT '1 hour' CHECK (late_data_window >= '0'),\n\
+        watermark TIMESTAMPTZ\n\
+    );\n\
+    SELECT pg_catalog.pg_extension_config_dump('toolkit_experimental.heartbeat_agg_jobs', '');\n\
+",
+    name = "heartbeat_agg_jobs",
+);
+
+// Each job's settings, already quoted for use in a query.
+struct Job {
+    id: i32,
+    source: String,
+    time_column: String,
+    target: String,
+    bucket_width: String,
+    heartbeat_liveness: String,
+    refresh_from: Option<String>,
+}
+
+// Refreshes every job, returning the number of buckets written.
+#[pg_extern(volatile, parallel_unsafe, schema = "toolkit_experimental")]
+pub fn refresh_heartbeat_aggs() -> i64 {
+    Spi::connect(|client| {
+        let schema: String = client
+            .select(
+                "SELECT quote_ident(n.nspname) FROM pg_catalog.pg_extension e
+                JOIN pg_catalog.pg_namespace n ON n.oid = e.extnamespace
+                WHERE e.extname = 'timescaledb_toolkit'",
+                None,
+                None,
+            )
+            .first()
+            .get_one()
+            .unwrap();
+        // locking the jobs keeps concurrent refreshes from moving a
+        // watermark backwards
+        let jobs: Vec<Job> = client
+            .select(
+                "SELECT job_id, source::text, quote_ident(time_column), target::text,
+                    quote_literal(bucket_width), quote_literal(heartbeat_liveness),
+                    quote_literal(watermark - late_data_window)
+                FROM toolkit_experimental.heartbeat_agg_jobs
+                ORDER BY job_id
+                FOR UPDATE",
+                None,
+                None,
+            )
+            .map(|row| Job {
+                id: row[1].value().unwrap(),
+                source: row[2].value().unwrap(),
+                time_column: row[3].value().unwrap(),
+                target: row[4].value().unwrap(),
+                bucket_width: row[5].value().unwrap(),
+                heartbeat_liveness: row[6].value().unwrap(),
+                refresh_from: row[7].value(),
+            })
+            .collect();
+
+        let mut written = 0;
+        for job in jobs {
+            // Heartbeats up to the current last one are aggregated now, any
+            // inserted while we're running will fall in the late data window
+            // next time.
+            let refresh_until: Option<String> = client
+                .select(
+                    &format!(
+                        "SELECT quote_literal(max({})) FROM {}",
+                        job.time_column, job.source
+                    ),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one();
+            let refresh_until = match refresh_until {
+                Some(until) => until,
+                None => continue,
+            };
+
+            // Buckets are aligned to the same origin as `time_bucket`'s default.
+            let bucket = format!(
+                "('2000-01-03 00:00:00+00'::timestamptz + \
+                    floor(extract(epoch FROM {time} - '2000-01-03 00:00:00+00') / extract(epoch FROM {width}::interval))::float8 \
+                    * {width}::interval)",
+                time = job.time_column,
+                width = job.bucket_width,
+            );
+            let lower_bound = match &job.refresh_from {
+                Some(from) => format!("AND {} >= {}::timestamptz", job.time_column, from),
+                None => String::new(),
+            };
+            written += client
+                .select(
+                    &format!(
+                        "INSERT INTO {target} AS existing (bucket, agg)
+                        SELECT {bucket}, {schema}.heartbeat_agg({time}, {bucket}, {width}::interval, {liveness}::interval)
+                        FROM {source}
+                        WHERE {time} <= {until}::timestamptz {lower_bound}
+                        GROUP BY 1
+                        ON CONFLICT (bucket) DO UPDATE
+                            SET agg = toolkit_experimental.heartbeat_merge(existing.agg, EXCLUDED.agg)
+                        RETURNING bucket",
+                        target = job.target,
+                        bucket = bucket,
+                        schema = schema,
+                        time = job.time_column,
+                        width = job.bucket_width,
+                        liveness = job.heartbeat_liveness,
+                        source = job.source,
+                        until = refresh_until,
+                        lower_bound = lower_bound,
+                    ),
+                    None,
+                    None,
+                )
+                .len() as i64;
+
+            client.select(
+                &format!(
+                    "UPDATE toolkit_experimental.heartbeat_agg_jobs
+                    SET watermark = {}::timestamptz
+                    WHERE job_id = {}",
+                    refresh_until, job.id
+                ),
+                None,
+                None,
+            );
+        }
+        Ok(Some(written))
+    })
+    .unwrap_or(0)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_refresh_heartbeat_aggs() {
+        Spi::execute(|client| {
+            client.select("SET TIMEZONE to UTC", None, None);
+            client.select("CREATE TABLE heartbeats(ts TIMESTAMPTZ)", None, None);
+            client.select(
+                "CREATE TABLE buckets(bucket TIMESTAMPTZ PRIMARY KEY, agg HeartbeatAgg)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO toolkit_experimental.heartbeat_agg_jobs
+                    (source, time_column, target, bucket_width, heartbeat_liveness, late_data_window)
+                VALUES ('heartbeats', 'ts', 'buckets', '1 hour', '10 minutes', '30 minutes')",
+                None,
+                None,
+            );
+            let refresh = || {
+                client
+                    .select(
+                        "SELECT toolkit_experimental.refresh_heartbeat_aggs()",
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<i64>()
+                    .unwrap()
+            };
+            let live = || {
+                client
+                    .select(
+                        "SELECT string_agg(bucket::time || ' ' || duration_live(agg), ', ' ORDER BY bucket)
+                        FROM buckets",
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<String>()
+                    .unwrap()
+            };
+
+            // nothing to do without any heartbeats
+            assert_eq!(refresh(), 0);
+
+            client.select(
+                "INSERT INTO heartbeats VALUES
+                    ('2020-01-01 00:10 UTC'), ('2020-01-01 00:55 UTC'), ('2020-01-01 01:20 UTC')",
+                None,
+                None,
+            );
+            assert_eq!(refresh(), 2);
+            assert_eq!(live(), "00:00:00 00:15:00, 01:00:00 00:10:00");
+
+            // new data in an existing bucket is merged in, buckets from the
+            // late data window on are rewritten
+            client.select(
+                "INSERT INTO heartbeats VALUES ('2020-01-01 01:40 UTC'), ('2020-01-01 02:05 UTC')",
+                None,
+                None,
+            );
+            assert_eq!(refresh(), 3);
+            assert_eq!(
+                live(),
+                "00:00:00 00:15:00, 01:00:00 00:20:00, 02:00:00 00:10:00"
+            );
+
+            // late data within the window is picked up, older data isn't
+            client.select(
+                "INSERT INTO heartbeats VALUES ('2020-01-01 01:50 UTC'), ('2020-01-01 01:00 UTC')",
+                None,
+                None,
+            );
+            assert_eq!(refresh(), 2);
+            assert_eq!(
+                live(),
+                "00:00:00 00:15:00, 01:00:00 00:30:00, 02:00:00 00:10:00"
+            );
+        });
+    }
+}
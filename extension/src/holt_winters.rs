@@ -0,0 +1,290 @@
+use pgx::*;
+use serde::{Deserialize, Serialize};
+
+use aggregate_builder::aggregate;
+use holt_winters::HoltWinters as HoltWintersInternal;
+use tspoint::TSPoint;
+
+use crate::{
+    build, flatten, pg_type,
+    raw::{bytea, TimestampTz},
+    ron_inout_funcs,
+    time_vector::{Timevector_TSTZ_F64, Timevector_TSTZ_F64Data, FLAG_IS_SORTED},
+};
+
+#[pg_schema]
+pub mod toolkit_experimental {
+    use super::*;
+
+    // The points the model was fit to are expected to be evenly spaced,
+    // `step` apart, so the forecast points are spaced the same way after
+    // `last_time`.  The prediction for each point after the first season is
+    // kept in `fitted`.
+    pg_type! {
+        #[derive(Debug)]
+        struct HoltWinters<'input> {
+            alpha: f64,
+            beta: f64,
+            gamma: f64,
+            level: f64,
+            trend: f64,
+            last_time: i64,
+            step: i64,
+            season_length: u32,
+            num_fitted: u32,
+            seasonal: [f64; self.season_length],
+            fitted: [TSPoint; self.num_fitted],
+        }
+    }
+
+    impl HoltWinters<'_> {
+        pub fn to_internal_holt_winters(&self) -> HoltWintersInternal {
+            HoltWintersInternal {
+                alpha: self.alpha,
+                beta: self.beta,
+                gamma: self.gamma,
+                level: self.level,
+                trend: self.trend,
+                seasonal: self.seasonal.iter().collect(),
+                num_points: (self.season_length + self.num_fitted) as usize,
+                fitted: self.fitted.iter().map(|p| p.val).collect(),
+            }
+        }
+
+        fn from_internal_holt_winters(model: &HoltWintersInternal, points: &[TSPoint]) -> Self {
+            let first_time = points.first().unwrap().ts;
+            let last_time = points.last().unwrap().ts;
+            let step = (last_time - first_time) / (points.len() - 1) as i64;
+            let fitted: Vec<TSPoint> = points[model.season_length()..]
+                .iter()
+                .zip(&model.fitted)
+                .map(|(point, &val)| TSPoint { ts: point.ts, val })
+                .collect();
+            unsafe {
+                flatten!(HoltWinters {
+                    alpha: model.alpha,
+                    beta: model.beta,
+                    gamma: model.gamma,
+                    level: model.level,
+                    trend: model.trend,
+                    last_time,
+                    step,
+                    season_length: model.season_length() as u32,
+                    num_fitted: fitted.len() as u32,
+                    seasonal: (&*model.seasonal).into(),
+                    fitted: (&*fitted).into(),
+                })
+            }
+        }
+    }
+
+    ron_inout_funcs!(HoltWinters);
+}
+
+use toolkit_experimental::HoltWinters;
+
+// Intermediate state kept in postgres; the model can only be fit once all the
+// points are in order.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HoltWintersTransState {
+    season_length: u32,
+    points: Vec<TSPoint>,
+}
+
+#[aggregate]
+impl toolkit_experimental::holt_winters {
+    type State = HoltWintersTransState;
+
+    const PARALLEL_SAFE: bool = true;
+
+    fn transition(
+        state: Option<State>,
+        #[sql_type("timestamptz")] ts: Option<TimestampTz>,
+        #[sql_type("double precision")] value: Option<f64>,
+        #[sql_type("integer")] season_length: i32,
+    ) -> Option<State> {
+        let point = match (ts, value) {
+            (Some(ts), Some(val)) => TSPoint { ts: ts.into(), val },
+            _ => return state,
+        };
+        let mut state = match state {
+            None => {
+                if season_length < 1 {
+                    pgx::error!("holt_winters season_length must be at least 1")
+                }
+                HoltWintersTransState {
+                    season_length: season_length as u32,
+                    points: vec![],
+                }
+            }
+            Some(state) => state,
+        };
+        state.points.push(point);
+        Some(state)
+    }
+
+    fn combine(a: Option<&State>, b: Option<&State>) -> Option<State> {
+        match (a, b) {
+            (None, None) => None,
+            (None, Some(only)) | (Some(only), None) => Some(only.clone()),
+            (Some(a), Some(b)) => {
+                let mut a = a.clone();
+                a.points.extend_from_slice(&b.points);
+                Some(a)
+            }
+        }
+    }
+
+    fn serialize(state: &mut State) -> bytea {
+        crate::do_serialize!(state)
+    }
+
+    fn deserialize(bytes: bytea) -> State {
+        crate::do_deserialize!(bytes, HoltWintersTransState)
+    }
+
+    fn finally(state: Option<&mut State>) -> Option<HoltWinters<'static>> {
+        let state = state?;
+        state.points.sort_by_key(|p| p.ts);
+        let values: Vec<f64> = state.points.iter().map(|p| p.val).collect();
+        let model =
+            HoltWintersInternal::fit(&values, state.season_length as usize).unwrap_or_else(|| {
+                pgx::error!("holt_winters needs at least two seasons of points to fit a model")
+            });
+        Some(HoltWinters::from_internal_holt_winters(
+            &model,
+            &state.points,
+        ))
+    }
+}
+
+/// The `horizon` points after the ones the model was fit to, as predicted by
+/// the model.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn forecast<'a>(model: HoltWinters<'a>, horizon: i32) -> Timevector_TSTZ_F64<'static> {
+    if horizon < 1 {
+        pgx::error!("forecast horizon must be at least 1")
+    }
+    let internal = model.to_internal_holt_winters();
+    let points: Vec<TSPoint> = (1..=horizon as usize)
+        .map(|h| TSPoint {
+            ts: model.last_time + h as i64 * model.step,
+            val: internal.forecast(h),
+        })
+        .collect();
+    sorted_timevector(points)
+}
+
+/// The model's prediction for each of the points it was fit to from the one
+/// before it, starting with the first point after the first season.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn fitted<'a>(model: HoltWinters<'a>) -> Timevector_TSTZ_F64<'static> {
+    sorted_timevector(model.fitted.iter().collect())
+}
+
+fn sorted_timevector(points: Vec<TSPoint>) -> Timevector_TSTZ_F64<'static> {
+    let nulls_len = (points.len() + 7) / 8;
+    build! {
+        Timevector_TSTZ_F64 {
+            num_points: points.len() as u32,
+            flags: FLAG_IS_SORTED,
+            internal_padding: [0; 3],
+            points: points.into(),
+            null_val: std::vec::from_elem(0_u8, nulls_len).into(),
+            quality: vec![].into(),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_holt_winters() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // a line rising by 2 a day, plus a season of 4 days
+            client.select(
+                "CREATE VIEW model AS \
+                SELECT toolkit_experimental.holt_winters( \
+                    '2020-01-01 UTC'::timestamptz + i * '1 day'::interval, \
+                    2.0 * i + (ARRAY[5.0, -3.0, 1.0, -3.0])[i % 4 + 1], \
+                    4) AS model \
+                FROM generate_series(0, 11) i",
+                None,
+                None,
+            );
+
+            let mut forecast = client.select(
+                "SELECT time::TEXT, value FROM unnest( \
+                    (SELECT toolkit_experimental.forecast(model, 5) FROM model))",
+                None,
+                None,
+            );
+            let mut next = || {
+                forecast.next().map(|row| {
+                    (
+                        row[1].value::<String>().unwrap(),
+                        row[2].value::<f64>().unwrap(),
+                    )
+                })
+            };
+            for (time, expected) in [
+                ("2020-01-13 00:00:00+00", 29.0),
+                ("2020-01-14 00:00:00+00", 23.0),
+                ("2020-01-15 00:00:00+00", 29.0),
+                ("2020-01-16 00:00:00+00", 27.0),
+                ("2020-01-17 00:00:00+00", 37.0),
+            ] {
+                let (t, value) = next().unwrap();
+                assert_eq!(t, time);
+                assert!((value - expected).abs() < 1e-9, "{} != {}", value, expected);
+            }
+            assert_eq!(next(), None);
+
+            // the series fits exactly, so each prediction matches its point
+            let (count, max_error) = client
+                .select(
+                    "SELECT count(*), max(abs(f.value - s.value)) \
+                    FROM unnest((SELECT toolkit_experimental.fitted(model) FROM model)) f \
+                    JOIN (SELECT '2020-01-01 UTC'::timestamptz + i * '1 day'::interval AS time, \
+                        2.0 * i + (ARRAY[5.0, -3.0, 1.0, -3.0])[i % 4 + 1] AS value \
+                        FROM generate_series(0, 11) i) s \
+                    ON f.time = s.time",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<i64, f64>();
+            assert_eq!(count, Some(8));
+            assert!(max_error.unwrap() < 1e-9);
+
+            // the model survives a round trip through its text form
+            let round_trip = client
+                .select(
+                    "SELECT model::TEXT::toolkit_experimental.HoltWinters::TEXT = model::TEXT FROM model",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<bool>();
+            assert_eq!(round_trip, Some(true));
+        });
+    }
+
+    #[pg_test(error = "holt_winters needs at least two seasons of points to fit a model")]
+    fn test_holt_winters_too_short() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.holt_winters( \
+                    '2020-01-01 UTC'::timestamptz + i * '1 day'::interval, i, 4) \
+                FROM generate_series(0, 6) i",
+                None,
+                None,
+            );
+        });
+    }
+}
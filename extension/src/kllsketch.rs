@@ -0,0 +1,537 @@
+use pgx::*;
+
+use aggregate_builder::aggregate;
+use kllsketch::KllSketch as KllSketchInternal;
+
+use crate::{
+    accessors::{
+        AccessorApproxPercentile, AccessorApproxPercentileRank, AccessorMaxVal, AccessorMinVal,
+        AccessorNumVals,
+    },
+    aggregate_utils::in_aggregate_context,
+    flatten,
+    palloc::{Inner, Internal, InternalAsValue, ToInternal},
+    pg_type,
+    raw::bytea,
+    ron_inout_funcs,
+};
+
+// The smallest size a sketch can be built with; smaller ones would compact
+// their levels so often that they'd have little accuracy left.
+const MIN_SIZE: i32 = 8;
+
+#[pg_schema]
+pub mod toolkit_experimental {
+    use super::*;
+
+    // The values held at every level of the sketch, from the lowest one up,
+    // are stored together, along with how many of them are at each level.
+    pg_type! {
+        #[derive(Debug)]
+        struct KllSketch<'input> {
+            k: u32,
+            num_levels: u32,
+            count: u64,
+            min: f64,
+            max: f64,
+            num_values: u64,
+            values: [f64; self.num_values],
+            level_sizes: [u32; self.num_levels],
+        }
+    }
+
+    impl KllSketch<'_> {
+        // Sketches can be read from text, so their levels are checked before
+        // they're used.
+        pub fn to_internal_kllsketch(&self) -> KllSketchInternal {
+            if self.k < MIN_SIZE as u32 {
+                pgx::error!("kll_sketch size must be at least {}", MIN_SIZE)
+            }
+            if self.level_sizes.is_empty() {
+                pgx::error!("invalid kll_sketch: it must have at least one level")
+            }
+            let held: u64 = self.level_sizes.iter().map(u64::from).sum();
+            if held != self.values.len() as u64 {
+                pgx::error!(
+                    "invalid kll_sketch: its levels hold {} values, but it has {}",
+                    held,
+                    self.values.len()
+                )
+            }
+            // each value of a level stands for twice as many as the level below
+            let weight =
+                self.level_sizes
+                    .iter()
+                    .enumerate()
+                    .try_fold(0u64, |weight, (level, size)| {
+                        let level_weight = 1u64.checked_shl(level as u32)?;
+                        weight.checked_add(u64::from(size).checked_mul(level_weight)?)
+                    });
+            if weight != Some(self.count) {
+                pgx::error!(
+                    "invalid kll_sketch: its levels don't stand for its count of {} values",
+                    self.count
+                )
+            }
+            let mut values = self.values.iter();
+            let levels = self
+                .level_sizes
+                .iter()
+                .map(|size| values.by_ref().take(size as usize).collect())
+                .collect();
+            KllSketchInternal::new_from_data(self.k, self.count, self.min, self.max, levels)
+        }
+
+        pub fn from_internal_kllsketch(sketch: &KllSketchInternal) -> Self {
+            let values: Vec<f64> = sketch.levels().iter().flatten().copied().collect();
+            let level_sizes: Vec<u32> = sketch
+                .levels()
+                .iter()
+                .map(|level| level.len() as u32)
+                .collect();
+            unsafe {
+                flatten!(KllSketch {
+                    k: sketch.k(),
+                    num_levels: level_sizes.len() as u32,
+                    count: sketch.count(),
+                    min: sketch.min(),
+                    max: sketch.max(),
+                    num_values: values.len() as u64,
+                    values: (&*values).into(),
+                    level_sizes: (&*level_sizes).into(),
+                })
+            }
+        }
+    }
+
+    ron_inout_funcs!(KllSketch);
+}
+
+use toolkit_experimental::KllSketch;
+
+#[aggregate]
+impl toolkit_experimental::kll_sketch {
+    type State = KllSketchInternal;
+
+    fn transition(
+        state: Option<State>,
+        #[sql_type("integer")] size: i32,
+        #[sql_type("double precision")] value: Option<f64>,
+    ) -> Option<State> {
+        let value = match value {
+            None => return state,
+            Some(value) => value,
+        };
+
+        let mut state = match state {
+            None => {
+                if size < MIN_SIZE {
+                    pgx::error!("kll_sketch size must be at least {}", MIN_SIZE)
+                }
+                KllSketchInternal::new(size as u32)
+            }
+            Some(state) => state,
+        };
+
+        state.add_value(value);
+        Some(state)
+    }
+
+    fn finally(state: Option<&mut State>) -> Option<KllSketch<'static>> {
+        state.map(|state| KllSketch::from_internal_kllsketch(state))
+    }
+
+    const PARALLEL_SAFE: bool = true;
+
+    fn serialize(state: &mut State) -> bytea {
+        crate::do_serialize!(state)
+    }
+
+    fn deserialize(bytes: bytea) -> State {
+        crate::do_deserialize!(bytes, State)
+    }
+
+    fn combine(state1: Option<&State>, state2: Option<&State>) -> Option<State> {
+        match (state1, state2) {
+            (None, None) => None,
+            (None, Some(only)) | (Some(only), None) => Some(only.clone()),
+            (Some(a), Some(b)) => {
+                if a.k() != b.k() {
+                    pgx::error!("cannot combine kll_sketches of different sizes")
+                }
+                let mut a = a.clone();
+                a.merge(b);
+                Some(a)
+            }
+        }
+    }
+}
+
+// Shares its state, and so its combine, serialize and final functions, with
+// the kll_sketch aggregate, which wraps the state in an Option.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn kll_sketch_rollup_trans<'a>(
+    state: Internal,
+    value: Option<KllSketch<'a>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    kll_sketch_rollup_trans_inner(unsafe { state.to_inner() }, value, fcinfo).internal()
+}
+
+pub fn kll_sketch_rollup_trans_inner(
+    state: Option<Inner<Option<KllSketchInternal>>>,
+    value: Option<KllSketch>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<Option<KllSketchInternal>>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value.to_internal_kllsketch(),
+            };
+            match state {
+                None => Some(Some(value).into()),
+                Some(mut state) => {
+                    match &mut *state {
+                        Some(sketch) => {
+                            if sketch.k() != value.k() {
+                                pgx::error!("cannot roll up kll_sketches of different sizes")
+                            }
+                            sketch.merge(&value)
+                        }
+                        None => *state = Some(value),
+                    }
+                    Some(state)
+                }
+            }
+        })
+    }
+}
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.rollup(\n\
+        sketch toolkit_experimental.KllSketch\n\
+    ) (\n\
+        sfunc = toolkit_experimental.kll_sketch_rollup_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.kll_sketch_finally_fn_outer,\n\
+        combinefunc = toolkit_experimental.kll_sketch_combine_fn_outer,\n\
+        serialfunc = toolkit_experimental.kll_sketch_serialize_fn_outer,\n\
+        deserialfunc = toolkit_experimental.kll_sketch_deserialize_fn_outer,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "kll_sketch_rollup",
+    requires = [kll_sketch_rollup_trans, "kll_sketch_extension_sql"],
+);
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_kll_sketch_approx_percentile<'a>(
+    sketch: KllSketch<'a>,
+    accessor: AccessorApproxPercentile<'a>,
+) -> f64 {
+    kll_sketch_approx_percentile(accessor.percentile, sketch)
+}
+
+// Approximate the value at the given approx_percentile (0.0-1.0)
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "approx_percentile",
+    schema = "toolkit_experimental"
+)]
+pub fn kll_sketch_approx_percentile<'a>(percentile: f64, sketch: KllSketch<'a>) -> f64 {
+    sketch.to_internal_kllsketch().estimate_quantile(percentile)
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_kll_sketch_approx_rank<'a>(
+    sketch: KllSketch<'a>,
+    accessor: AccessorApproxPercentileRank<'a>,
+) -> f64 {
+    kll_sketch_approx_percentile_rank(accessor.value, sketch)
+}
+
+// Approximate the approx_percentile at the given value
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "approx_percentile_rank",
+    schema = "toolkit_experimental"
+)]
+pub fn kll_sketch_approx_percentile_rank<'a>(value: f64, sketch: KllSketch<'a>) -> f64 {
+    sketch.to_internal_kllsketch().estimate_rank(value)
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_kll_sketch_num_vals<'a>(sketch: KllSketch<'a>, _accessor: AccessorNumVals<'a>) -> f64 {
+    kll_sketch_num_vals(sketch)
+}
+
+// Number of elements from which the sketch was built.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "num_vals",
+    schema = "toolkit_experimental"
+)]
+pub fn kll_sketch_num_vals<'a>(sketch: KllSketch<'a>) -> f64 {
+    sketch.count as f64
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_kll_sketch_min_val<'a>(sketch: KllSketch<'a>, _accessor: AccessorMinVal<'a>) -> f64 {
+    kll_sketch_min_val(sketch)
+}
+
+// Smallest of the values entered in the sketch, which is exact.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "min_val",
+    schema = "toolkit_experimental"
+)]
+pub fn kll_sketch_min_val<'a>(sketch: KllSketch<'a>) -> f64 {
+    sketch.min
+}
+
+#[pg_operator(immutable, parallel_safe)]
+#[opname(->)]
+pub fn arrow_kll_sketch_max_val<'a>(sketch: KllSketch<'a>, _accessor: AccessorMaxVal<'a>) -> f64 {
+    kll_sketch_max_val(sketch)
+}
+
+// Largest of the values entered in the sketch, which is exact.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "max_val",
+    schema = "toolkit_experimental"
+)]
+pub fn kll_sketch_max_val<'a>(sketch: KllSketch<'a>) -> f64 {
+    sketch.max
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_kll_sketch() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE VIEW sketch AS \
+                SELECT toolkit_experimental.kll_sketch(200, v::double precision) \
+                FROM generate_series(1, 100000) v",
+                None,
+                None,
+            );
+
+            let (count, min, max) = client
+                .select(
+                    "SELECT \
+                        toolkit_experimental.num_vals(kll_sketch), \
+                        toolkit_experimental.min_val(kll_sketch), \
+                        toolkit_experimental.max_val(kll_sketch) \
+                    FROM sketch",
+                    None,
+                    None,
+                )
+                .first()
+                .get_three::<f64, f64, f64>();
+            assert_eq!(count, Some(100000.0));
+            assert_eq!(min, Some(1.0));
+            assert_eq!(max, Some(100000.0));
+
+            for percentile in [0.01, 0.25, 0.5, 0.9, 0.99] {
+                let (value, rank) = client
+                    .select(
+                        &format!(
+                            "SELECT \
+                                toolkit_experimental.approx_percentile({0}, kll_sketch), \
+                                toolkit_experimental.approx_percentile_rank({0} * 100000, kll_sketch) \
+                            FROM sketch",
+                            percentile
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_two::<f64, f64>();
+                // the ranks of the estimates are within 2% of the true ones
+                let value_rank = value.unwrap() / 100000.0;
+                assert!((value_rank - percentile).abs() < 0.02, "{}", percentile);
+                assert!((rank.unwrap() - percentile).abs() < 0.02, "{}", percentile);
+            }
+        });
+    }
+
+    #[pg_test]
+    fn test_kll_sketch_arrows() {
+        Spi::execute(|client| {
+            let (median, rank, count) = client
+                .select(
+                    "SELECT \
+                        sketch->approx_percentile(0.5), \
+                        sketch->approx_percentile_rank(25), \
+                        sketch->num_vals() \
+                    FROM (\
+                        SELECT toolkit_experimental.kll_sketch(8, v::double precision) AS sketch \
+                        FROM generate_series(1, 100) v\
+                    ) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_three::<f64, f64, f64>();
+            assert!((median.unwrap() - 50.0).abs() < 20.0);
+            assert!((rank.unwrap() - 0.25).abs() < 0.2);
+            assert_eq!(count, Some(100.0));
+
+            let (min, max) = client
+                .select(
+                    "SELECT sketch->min_val(), sketch->max_val() \
+                    FROM (\
+                        SELECT toolkit_experimental.kll_sketch(8, v::double precision) AS sketch \
+                        FROM generate_series(1, 100) v\
+                    ) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(min, Some(1.0));
+            assert_eq!(max, Some(100.0));
+        });
+    }
+
+    #[pg_test]
+    fn test_kll_sketch_rollup() {
+        Spi::execute(|client| {
+            let (count, median) = client
+                .select(
+                    "SELECT \
+                        toolkit_experimental.num_vals(toolkit_experimental.rollup(sketch)), \
+                        toolkit_experimental.approx_percentile(0.5, toolkit_experimental.rollup(sketch)) \
+                    FROM (\
+                        SELECT toolkit_experimental.kll_sketch(200, v::double precision) AS sketch \
+                        FROM generate_series(1, 10000) v \
+                        GROUP BY v % 7\
+                    ) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(count, Some(10000.0));
+            assert!((median.unwrap() - 5000.0).abs() < 200.0);
+        });
+    }
+
+    #[pg_test(error = "cannot roll up kll_sketches of different sizes")]
+    fn test_kll_sketch_rollup_checks_sizes() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.rollup(sketch) \
+                FROM (\
+                    SELECT toolkit_experimental.kll_sketch(8, 1) AS sketch \
+                    UNION ALL \
+                    SELECT toolkit_experimental.kll_sketch(16, 1)\
+                ) s",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test(error = "invalid kll_sketch: its levels don't stand for its count of 6 values")]
+    fn test_kll_sketch_checks_count() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.approx_percentile(0.5, '(\
+                    version:1,k:8,num_levels:1,count:6,min:1,max:5,\
+                    num_values:5,values:[1,2,3,4,5],level_sizes:[5]\
+                )'::toolkit_experimental.kllsketch)",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test(error = "invalid kll_sketch: its levels hold 4 values, but it has 5")]
+    fn test_kll_sketch_checks_levels() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.approx_percentile(0.5, '(\
+                    version:1,k:8,num_levels:1,count:4,min:1,max:5,\
+                    num_values:5,values:[1,2,3,4,5],level_sizes:[4]\
+                )'::toolkit_experimental.kllsketch)",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn kll_sketch_io_test() {
+        Spi::execute(|client| {
+            let sketch = client
+                .select(
+                    "SELECT toolkit_experimental.kll_sketch(8, v::double precision)::text \
+                    FROM generate_series(1, 5) v",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+
+            let expected = "(\
+                version:1,\
+                k:8,\
+                num_levels:1,\
+                count:5,\
+                min:1,\
+                max:5,\
+                num_values:5,\
+                values:[1,2,3,4,5],\
+                level_sizes:[5]\
+                )";
+            assert_eq!(sketch, Some(expected.into()));
+
+            let median = client
+                .select(
+                    &format!(
+                        "SELECT toolkit_experimental.approx_percentile(0.5, '{}'::toolkit_experimental.kllsketch)",
+                        expected
+                    ),
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<f64>();
+            assert_eq!(median, Some(3.0));
+        });
+    }
+
+    #[pg_test]
+    fn test_kll_sketch_null_input_yields_null_output() {
+        Spi::execute(|client| {
+            let output = client
+                .select(
+                    "SELECT toolkit_experimental.kll_sketch(200, NULL::double precision)::TEXT",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(output, None)
+        })
+    }
+}
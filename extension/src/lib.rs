@@ -11,16 +11,22 @@
 
 pub mod accessors;
 pub mod asap;
+pub mod bloomfilter;
 pub mod counter_agg;
 pub mod countminsketch;
 pub mod frequency;
 pub mod gauge_agg;
+pub mod heartbeat_agg;
+pub mod holt_winters;
 pub mod hyperloglog;
+pub mod kllsketch;
+pub mod locf;
 pub mod lttb;
 pub mod asof;
 pub mod nmost;
 pub mod ohlc;
 pub mod range;
+pub mod rollup;
 pub mod saturation;
 pub mod state_aggregate;
 pub mod stats_agg;
@@ -33,6 +39,7 @@ pub mod utilities;
 mod aggregate_utils;
 mod datum_utils;
 mod duration;
+mod heartbeat_worker;
 mod palloc;
 mod pg_any_element;
 mod raw;
@@ -40,6 +47,7 @@ mod serialization;
 mod stabilization_info;
 mod stabilization_tests;
 mod type_builder;
+mod usage_tracking;
 
 #[cfg(any(test, feature = "pg_test"))]
 mod aggregate_builder_tests;
@@ -50,7 +58,8 @@ pgx::pg_module_magic!();
 
 #[pg_guard]
 pub extern "C" fn _PG_init() {
-    // Nothing to do here
+    usage_tracking::init();
+    heartbeat_worker::init();
 }
 
 extension_sql!(
@@ -0,0 +1,127 @@
+//! Last-value-carried-forward over arbitrary columns
+//!
+//! SELECT time, toolkit_experimental.locf(value) OVER (ORDER BY time) FROM ...
+//!
+//! `locf` is an ordinary aggregate whose state is simply the last non-NULL
+//! value seen, so when it's used as a window function with the default frame
+//! each row gets the most recent non-NULL value at or before it.  Since the
+//! result depends on input order it has no combine function and isn't
+//! parallel safe.
+
+use pgx::*;
+
+use crate::raw::AnyElement;
+
+#[pg_extern(immutable, schema = "toolkit_experimental")]
+pub fn locf_trans(state: Option<AnyElement>, value: Option<AnyElement>) -> Option<AnyElement> {
+    // Postgres copies the new state into the aggregate context when it
+    // differs from the old one, so returning `value` directly is safe
+    value.or(state)
+}
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.locf(value AnyElement) (\n\
+        sfunc = toolkit_experimental.locf_trans,\n\
+        stype = AnyElement\n\
+    );\n\
+",
+    name = "locf_agg",
+    requires = [locf_trans],
+);
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    fn setup_gaps_table(client: &SpiClient) {
+        client.select(
+            "CREATE TABLE gaps(time TIMESTAMPTZ, reading DOUBLE PRECISION, label TEXT)",
+            None,
+            None,
+        );
+        client.select(
+            "INSERT INTO gaps VALUES
+                ('2020-01-01 00:00 UTC', NULL, NULL),
+                ('2020-01-01 00:01 UTC', 1.5, 'a'),
+                ('2020-01-01 00:02 UTC', NULL, NULL),
+                ('2020-01-01 00:03 UTC', NULL, 'b'),
+                ('2020-01-01 00:04 UTC', 3.0, NULL),
+                ('2020-01-01 00:05 UTC', NULL, NULL)",
+            None,
+            None,
+        );
+    }
+
+    #[pg_test]
+    fn test_locf_window() {
+        Spi::execute(|client| {
+            setup_gaps_table(&client);
+
+            let mut result = client.select(
+                "SELECT
+                    toolkit_experimental.locf(reading) OVER (ORDER BY time)::TEXT,
+                    toolkit_experimental.locf(label) OVER (ORDER BY time)
+                FROM gaps ORDER BY time",
+                None,
+                None,
+            );
+
+            let mut next = || {
+                let row = result.next().unwrap();
+                (row[1].value::<String>(), row[2].value::<String>())
+            };
+            assert_eq!(next(), (None, None));
+            assert_eq!(next(), (Some("1.5".into()), Some("a".into())));
+            assert_eq!(next(), (Some("1.5".into()), Some("a".into())));
+            assert_eq!(next(), (Some("1.5".into()), Some("b".into())));
+            assert_eq!(next(), (Some("3".into()), Some("b".into())));
+            assert_eq!(next(), (Some("3".into()), Some("b".into())));
+            assert!(result.next().is_none());
+        });
+    }
+
+    #[pg_test]
+    fn test_locf_partitioned() {
+        Spi::execute(|client| {
+            setup_gaps_table(&client);
+
+            // the carried value must not leak across partitions
+            let mut result = client.select(
+                "SELECT toolkit_experimental.locf(reading) OVER (
+                    PARTITION BY time < '2020-01-01 00:03 UTC' ORDER BY time
+                )::TEXT
+                FROM gaps ORDER BY time",
+                None,
+                None,
+            );
+
+            let mut next = || result.next().unwrap()[1].value::<String>();
+            assert_eq!(next(), None);
+            assert_eq!(next(), Some("1.5".into()));
+            assert_eq!(next(), Some("1.5".into()));
+            assert_eq!(next(), None);
+            assert_eq!(next(), Some("3".into()));
+            assert_eq!(next(), Some("3".into()));
+        });
+    }
+
+    #[pg_test]
+    fn test_locf_ordered_aggregate() {
+        Spi::execute(|client| {
+            setup_gaps_table(&client);
+
+            let last = client
+                .select(
+                    "SELECT toolkit_experimental.locf(label ORDER BY time) FROM gaps",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(last.as_deref(), Some("b"));
+        });
+    }
+}
@@ -89,7 +89,8 @@ pub fn lttb_final_inner(
                 flags: time_vector::FLAG_IS_SORTED,
                 internal_padding: [0; 3],
                 points: (&*downsampled).into(),
-                null_val: std::vec::from_elem(0_u8, (downsampled.len() + 7) / 8).into()
+                null_val: std::vec::from_elem(0_u8, (downsampled.len() + 7) / 8).into(),
+                quality: vec![].into(),
             })
             .into()
         })
@@ -214,6 +215,7 @@ pub fn gap_preserving_lttb_final_inner(
                 internal_padding: [0; 3],
                 null_val: std::vec::from_elem(0_u8, (downsampled.len() + 7) / 8).into(),
                 points: downsampled.into(),
+                quality: vec![].into(),
             })
             .into()
         })
@@ -423,6 +425,7 @@ pub fn lttb_ts(data: Timevector_TSTZ_F64, threshold: usize) -> Timevector_TSTZ_F
             internal_padding: [0; 3],
             points: sampled.into(),
             null_val: std::vec::from_elem(0_u8, nulls_len).into(),
+            quality: vec![].into(),
         }
     }
 }
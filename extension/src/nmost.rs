@@ -6,6 +6,7 @@ use crate::{
     aggregate_utils::in_aggregate_context,
     datum_utils::{deep_copy_datum, free_datum, DatumStore},
     palloc::{Inner, Internal, InternalAsValue},
+    raw::bytea,
 };
 
 use std::collections::BinaryHeap;
@@ -136,7 +137,6 @@ fn nmost_trans_combine<T: Clone + Ord + Copy>(
     }
 }
 
-// TODO: serialize and deserialize will need to be implemented with Datum handling code
 #[derive(Clone, Debug)]
 pub struct NMostByTransState<T: Ord> {
     values: NMostTransState<(T, usize)>,
@@ -262,3 +262,64 @@ fn nmost_by_rollup_trans_function<T: Ord + Copy>(
         })
     }
 }
+
+fn nmost_by_trans_combine<T: Ord + Copy>(
+    first: Option<Inner<NMostByTransState<T>>>,
+    second: Option<Inner<NMostByTransState<T>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<NMostByTransState<T>>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || match (first, second) {
+            (None, None) => None,
+            (Some(only), None) => Some(only),
+            // The datums may not live in the aggregate's memory context, so
+            // they're copied into a new state
+            (None, Some(only)) => {
+                let (capacity, vals, data) = only.clone().into_sorted_parts();
+                Internal::new::<NMostByTransState<T>>((&vals[..], &data, capacity).into())
+                    .to_inner()
+            }
+            (Some(mut a), Some(b)) => {
+                let (_, vals, data) = b.clone().into_sorted_parts();
+                for (val, element) in vals.into_iter().zip(data.into_anyelement_iter()) {
+                    a.new_entry(val, element);
+                }
+                Some(a)
+            }
+        })
+    }
+}
+
+// The datums in a NMostByTransState can't be serialized directly, so it's
+// serialized in the same sorted form as the aggregate it produces.
+#[derive(Serialize, Deserialize)]
+struct NMostBySerializedState<'a, T> {
+    capacity: usize,
+    values: Vec<T>,
+    data: DatumStore<'a>,
+}
+
+impl<T: Ord + Copy> From<NMostBySerializedState<'_, T>> for NMostByTransState<T> {
+    fn from(state: NMostBySerializedState<'_, T>) -> Self {
+        (&state.values[..], &state.data, state.capacity).into()
+    }
+}
+
+fn nmost_by_trans_serialize<T: Ord + Clone + Serialize>(
+    state: Inner<NMostByTransState<T>>,
+) -> bytea {
+    let (capacity, values, data) = state.clone().into_sorted_parts();
+    let state = &NMostBySerializedState {
+        capacity,
+        values,
+        data,
+    };
+    crate::do_serialize!(state)
+}
+
+fn nmost_by_trans_deserialize<T>(bytes: bytea) -> NMostByTransState<T>
+where
+    T: Ord + Copy + serde::de::DeserializeOwned,
+{
+    crate::do_deserialize!(bytes, NMostBySerializedState<T>)
+}
@@ -5,8 +5,10 @@ use crate::nmost::*;
 
 use crate::{
     build, flatten,
-    palloc::{Internal, InternalAsValue, ToInternal},
-    pg_type, ron_inout_funcs,
+    palloc::{Inner, Internal, InternalAsValue, ToInternal},
+    pg_type,
+    raw::bytea,
+    ron_inout_funcs,
 };
 
 use ordered_float::NotNan;
@@ -90,6 +92,32 @@ pub fn max_n_by_float_rollup_trans(
     .internal()
 }
 
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn max_n_by_float_combine(
+    state1: Internal,
+    state2: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    nmost_by_trans_combine(
+        unsafe { state1.to_inner::<MaxByFloatTransType>() },
+        unsafe { state2.to_inner::<MaxByFloatTransType>() },
+        fcinfo,
+    )
+    .internal()
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn max_n_by_float_serialize(state: Internal) -> bytea {
+    let state: Inner<MaxByFloatTransType> = unsafe { state.to_inner().unwrap() };
+    nmost_by_trans_serialize(state)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn max_n_by_float_deserialize(bytes: bytea, _internal: Internal) -> Option<Internal> {
+    let i: MaxByFloatTransType = nmost_by_trans_deserialize(bytes);
+    Internal::new(i).into()
+}
+
 #[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
 pub fn max_n_by_float_final(state: Internal) -> toolkit_experimental::MaxByFloats<'static> {
     unsafe { state.to_inner::<MaxByFloatTransType>().unwrap().clone() }.into()
@@ -121,11 +149,21 @@ extension_sql!(
     ) (\n\
         sfunc = toolkit_experimental.max_n_by_float_trans,\n\
         stype = internal,\n\
+        combinefunc = toolkit_experimental.max_n_by_float_combine,\n\
+        parallel = safe,\n\
+        serialfunc = toolkit_experimental.max_n_by_float_serialize,\n\
+        deserialfunc = toolkit_experimental.max_n_by_float_deserialize,\n\
         finalfunc = toolkit_experimental.max_n_by_float_final\n\
     );\n\
 ",
     name = "max_n_by_float",
-    requires = [max_n_by_float_trans, max_n_by_float_final],
+    requires = [
+        max_n_by_float_trans,
+        max_n_by_float_final,
+        max_n_by_float_combine,
+        max_n_by_float_serialize,
+        max_n_by_float_deserialize
+    ],
 );
 
 extension_sql!(
@@ -135,11 +173,21 @@ extension_sql!(
     ) (\n\
         sfunc = toolkit_experimental.max_n_by_float_rollup_trans,\n\
         stype = internal,\n\
+        combinefunc = toolkit_experimental.max_n_by_float_combine,\n\
+        parallel = safe,\n\
+        serialfunc = toolkit_experimental.max_n_by_float_serialize,\n\
+        deserialfunc = toolkit_experimental.max_n_by_float_deserialize,\n\
         finalfunc = toolkit_experimental.max_n_by_float_final\n\
     );\n\
 ",
     name = "max_n_by_float_rollup",
-    requires = [max_n_by_float_rollup_trans, min_n_by_float_final],
+    requires = [
+        max_n_by_float_rollup_trans,
+        max_n_by_float_final,
+        max_n_by_float_combine,
+        max_n_by_float_serialize,
+        max_n_by_float_deserialize
+    ],
 );
 
 #[cfg(any(test, feature = "pg_test"))]
@@ -5,8 +5,10 @@ use crate::nmost::*;
 
 use crate::{
     build, flatten,
-    palloc::{Internal, InternalAsValue, ToInternal},
-    pg_type, ron_inout_funcs,
+    palloc::{Inner, Internal, InternalAsValue, ToInternal},
+    pg_type,
+    raw::bytea,
+    ron_inout_funcs,
 };
 
 use std::cmp::Reverse;
@@ -89,6 +91,32 @@ pub fn max_n_by_time_rollup_trans(
     .internal()
 }
 
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn max_n_by_time_combine(
+    state1: Internal,
+    state2: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    nmost_by_trans_combine(
+        unsafe { state1.to_inner::<MaxByTimeTransType>() },
+        unsafe { state2.to_inner::<MaxByTimeTransType>() },
+        fcinfo,
+    )
+    .internal()
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn max_n_by_time_serialize(state: Internal) -> bytea {
+    let state: Inner<MaxByTimeTransType> = unsafe { state.to_inner().unwrap() };
+    nmost_by_trans_serialize(state)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn max_n_by_time_deserialize(bytes: bytea, _internal: Internal) -> Option<Internal> {
+    let i: MaxByTimeTransType = nmost_by_trans_deserialize(bytes);
+    Internal::new(i).into()
+}
+
 #[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
 pub fn max_n_by_time_final(state: Internal) -> toolkit_experimental::MaxByTimes<'static> {
     unsafe { state.to_inner::<MaxByTimeTransType>().unwrap().clone() }.into()
@@ -127,11 +155,21 @@ extension_sql!(
     ) (\n\
         sfunc = toolkit_experimental.max_n_by_time_trans,\n\
         stype = internal,\n\
+        combinefunc = toolkit_experimental.max_n_by_time_combine,\n\
+        parallel = safe,\n\
+        serialfunc = toolkit_experimental.max_n_by_time_serialize,\n\
+        deserialfunc = toolkit_experimental.max_n_by_time_deserialize,\n\
         finalfunc = toolkit_experimental.max_n_by_time_final\n\
     );\n\
 ",
     name = "max_n_by_time",
-    requires = [max_n_by_time_trans, max_n_by_time_final],
+    requires = [
+        max_n_by_time_trans,
+        max_n_by_time_final,
+        max_n_by_time_combine,
+        max_n_by_time_serialize,
+        max_n_by_time_deserialize
+    ],
 );
 
 extension_sql!(
@@ -141,11 +179,21 @@ extension_sql!(
     ) (\n\
         sfunc = toolkit_experimental.max_n_by_time_rollup_trans,\n\
         stype = internal,\n\
+        combinefunc = toolkit_experimental.max_n_by_time_combine,\n\
+        parallel = safe,\n\
+        serialfunc = toolkit_experimental.max_n_by_time_serialize,\n\
+        deserialfunc = toolkit_experimental.max_n_by_time_deserialize,\n\
         finalfunc = toolkit_experimental.max_n_by_time_final\n\
     );\n\
 ",
     name = "max_n_by_time_rollup",
-    requires = [max_n_by_time_rollup_trans, min_n_by_time_final],
+    requires = [
+        max_n_by_time_rollup_trans,
+        max_n_by_time_final,
+        max_n_by_time_combine,
+        max_n_by_time_serialize,
+        max_n_by_time_deserialize
+    ],
 );
 
 #[cfg(any(test, feature = "pg_test"))]
@@ -5,8 +5,10 @@ use crate::nmost::*;
 
 use crate::{
     build, flatten,
-    palloc::{Internal, InternalAsValue, ToInternal},
-    pg_type, ron_inout_funcs,
+    palloc::{Inner, Internal, InternalAsValue, ToInternal},
+    pg_type,
+    raw::bytea,
+    ron_inout_funcs,
 };
 
 use ordered_float::NotNan;
@@ -89,6 +91,32 @@ pub fn min_n_by_float_rollup_trans(
     .internal()
 }
 
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn min_n_by_float_combine(
+    state1: Internal,
+    state2: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    nmost_by_trans_combine(
+        unsafe { state1.to_inner::<MinByFloatTransType>() },
+        unsafe { state2.to_inner::<MinByFloatTransType>() },
+        fcinfo,
+    )
+    .internal()
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn min_n_by_float_serialize(state: Internal) -> bytea {
+    let state: Inner<MinByFloatTransType> = unsafe { state.to_inner().unwrap() };
+    nmost_by_trans_serialize(state)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn min_n_by_float_deserialize(bytes: bytea, _internal: Internal) -> Option<Internal> {
+    let i: MinByFloatTransType = nmost_by_trans_deserialize(bytes);
+    Internal::new(i).into()
+}
+
 #[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
 pub fn min_n_by_float_final(state: Internal) -> toolkit_experimental::MinByFloats<'static> {
     unsafe { state.to_inner::<MinByFloatTransType>().unwrap().clone() }.into()
@@ -120,11 +148,21 @@ extension_sql!(
     ) (\n\
         sfunc = toolkit_experimental.min_n_by_float_trans,\n\
         stype = internal,\n\
+        combinefunc = toolkit_experimental.min_n_by_float_combine,\n\
+        parallel = safe,\n\
+        serialfunc = toolkit_experimental.min_n_by_float_serialize,\n\
+        deserialfunc = toolkit_experimental.min_n_by_float_deserialize,\n\
         finalfunc = toolkit_experimental.min_n_by_float_final\n\
     );\n\
 ",
     name = "min_n_by_float",
-    requires = [min_n_by_float_trans, min_n_by_float_final],
+    requires = [
+        min_n_by_float_trans,
+        min_n_by_float_final,
+        min_n_by_float_combine,
+        min_n_by_float_serialize,
+        min_n_by_float_deserialize
+    ],
 );
 
 extension_sql!(
@@ -134,11 +172,21 @@ extension_sql!(
     ) (\n\
         sfunc = toolkit_experimental.min_n_by_float_rollup_trans,\n\
         stype = internal,\n\
+        combinefunc = toolkit_experimental.min_n_by_float_combine,\n\
+        parallel = safe,\n\
+        serialfunc = toolkit_experimental.min_n_by_float_serialize,\n\
+        deserialfunc = toolkit_experimental.min_n_by_float_deserialize,\n\
         finalfunc = toolkit_experimental.min_n_by_float_final\n\
     );\n\
 ",
     name = "min_n_by_float_rollup",
-    requires = [min_n_by_float_rollup_trans, min_n_by_float_final],
+    requires = [
+        min_n_by_float_rollup_trans,
+        min_n_by_float_final,
+        min_n_by_float_combine,
+        min_n_by_float_serialize,
+        min_n_by_float_deserialize
+    ],
 );
 
 #[cfg(any(test, feature = "pg_test"))]
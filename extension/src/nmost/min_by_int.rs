@@ -5,8 +5,10 @@ use crate::nmost::*;
 
 use crate::{
     build, flatten,
-    palloc::{Internal, InternalAsValue, ToInternal},
-    pg_type, ron_inout_funcs,
+    palloc::{Inner, Internal, InternalAsValue, ToInternal},
+    pg_type,
+    raw::bytea,
+    ron_inout_funcs,
 };
 
 type MinByIntTransType = NMostByTransState<i64>;
@@ -76,6 +78,32 @@ pub fn min_n_by_int_rollup_trans(
     .internal()
 }
 
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn min_n_by_int_combine(
+    state1: Internal,
+    state2: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    nmost_by_trans_combine(
+        unsafe { state1.to_inner::<MinByIntTransType>() },
+        unsafe { state2.to_inner::<MinByIntTransType>() },
+        fcinfo,
+    )
+    .internal()
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn min_n_by_int_serialize(state: Internal) -> bytea {
+    let state: Inner<MinByIntTransType> = unsafe { state.to_inner().unwrap() };
+    nmost_by_trans_serialize(state)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn min_n_by_int_deserialize(bytes: bytea, _internal: Internal) -> Option<Internal> {
+    let i: MinByIntTransType = nmost_by_trans_deserialize(bytes);
+    Internal::new(i).into()
+}
+
 #[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
 pub fn min_n_by_int_final(state: Internal) -> toolkit_experimental::MinByInts<'static> {
     unsafe { state.to_inner::<MinByIntTransType>().unwrap().clone() }.into()
@@ -107,11 +135,21 @@ extension_sql!(
     ) (\n\
         sfunc = toolkit_experimental.min_n_by_int_trans,\n\
         stype = internal,\n\
+        combinefunc = toolkit_experimental.min_n_by_int_combine,\n\
+        parallel = safe,\n\
+        serialfunc = toolkit_experimental.min_n_by_int_serialize,\n\
+        deserialfunc = toolkit_experimental.min_n_by_int_deserialize,\n\
         finalfunc = toolkit_experimental.min_n_by_int_final\n\
     );\n\
 ",
     name = "min_n_by_int",
-    requires = [min_n_by_int_trans, min_n_by_int_final],
+    requires = [
+        min_n_by_int_trans,
+        min_n_by_int_final,
+        min_n_by_int_combine,
+        min_n_by_int_serialize,
+        min_n_by_int_deserialize
+    ],
 );
 
 extension_sql!(
@@ -121,11 +159,21 @@ extension_sql!(
     ) (\n\
         sfunc = toolkit_experimental.min_n_by_int_rollup_trans,\n\
         stype = internal,\n\
+        combinefunc = toolkit_experimental.min_n_by_int_combine,\n\
+        parallel = safe,\n\
+        serialfunc = toolkit_experimental.min_n_by_int_serialize,\n\
+        deserialfunc = toolkit_experimental.min_n_by_int_deserialize,\n\
         finalfunc = toolkit_experimental.min_n_by_int_final\n\
     );\n\
 ",
     name = "min_n_by_int_rollup",
-    requires = [min_n_by_int_rollup_trans, min_n_by_int_final],
+    requires = [
+        min_n_by_int_rollup_trans,
+        min_n_by_int_final,
+        min_n_by_int_combine,
+        min_n_by_int_serialize,
+        min_n_by_int_deserialize
+    ],
 );
 
 #[cfg(any(test, feature = "pg_test"))]
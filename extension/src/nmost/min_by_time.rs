@@ -5,8 +5,10 @@ use crate::nmost::*;
 
 use crate::{
     build, flatten,
-    palloc::{Internal, InternalAsValue, ToInternal},
-    pg_type, ron_inout_funcs,
+    palloc::{Inner, Internal, InternalAsValue, ToInternal},
+    pg_type,
+    raw::bytea,
+    ron_inout_funcs,
 };
 
 type MinByTimeTransType = NMostByTransState<pg_sys::TimestampTz>;
@@ -76,6 +78,32 @@ pub fn min_n_by_time_rollup_trans(
     .internal()
 }
 
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn min_n_by_time_combine(
+    state1: Internal,
+    state2: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    nmost_by_trans_combine(
+        unsafe { state1.to_inner::<MinByTimeTransType>() },
+        unsafe { state2.to_inner::<MinByTimeTransType>() },
+        fcinfo,
+    )
+    .internal()
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn min_n_by_time_serialize(state: Internal) -> bytea {
+    let state: Inner<MinByTimeTransType> = unsafe { state.to_inner().unwrap() };
+    nmost_by_trans_serialize(state)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
+pub fn min_n_by_time_deserialize(bytes: bytea, _internal: Internal) -> Option<Internal> {
+    let i: MinByTimeTransType = nmost_by_trans_deserialize(bytes);
+    Internal::new(i).into()
+}
+
 #[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe)]
 pub fn min_n_by_time_final(state: Internal) -> toolkit_experimental::MinByTimes<'static> {
     unsafe { state.to_inner::<MinByTimeTransType>().unwrap().clone() }.into()
@@ -114,11 +142,21 @@ extension_sql!(
     ) (\n\
         sfunc = toolkit_experimental.min_n_by_time_trans,\n\
         stype = internal,\n\
+        combinefunc = toolkit_experimental.min_n_by_time_combine,\n\
+        parallel = safe,\n\
+        serialfunc = toolkit_experimental.min_n_by_time_serialize,\n\
+        deserialfunc = toolkit_experimental.min_n_by_time_deserialize,\n\
         finalfunc = toolkit_experimental.min_n_by_time_final\n\
     );\n\
 ",
     name = "min_n_by_time",
-    requires = [min_n_by_time_trans, min_n_by_time_final],
+    requires = [
+        min_n_by_time_trans,
+        min_n_by_time_final,
+        min_n_by_time_combine,
+        min_n_by_time_serialize,
+        min_n_by_time_deserialize
+    ],
 );
 
 extension_sql!(
@@ -128,11 +166,21 @@ extension_sql!(
     ) (\n\
         sfunc = toolkit_experimental.min_n_by_time_rollup_trans,\n\
         stype = internal,\n\
+        combinefunc = toolkit_experimental.min_n_by_time_combine,\n\
+        parallel = safe,\n\
+        serialfunc = toolkit_experimental.min_n_by_time_serialize,\n\
+        deserialfunc = toolkit_experimental.min_n_by_time_deserialize,\n\
         finalfunc = toolkit_experimental.min_n_by_time_final\n\
     );\n\
 ",
     name = "min_n_by_time_rollup",
-    requires = [min_n_by_time_rollup_trans, min_n_by_time_final],
+    requires = [
+        min_n_by_time_rollup_trans,
+        min_n_by_time_final,
+        min_n_by_time_combine,
+        min_n_by_time_serialize,
+        min_n_by_time_deserialize
+    ],
 );
 
 #[cfg(any(test, feature = "pg_test"))]
@@ -19,7 +19,8 @@ extension_sql!(
         Type(AnyElement),
         Type(tstzrange),
         Type(Interval),
-        Type(regproc)
+        Type(regproc),
+        Type(regclass)
     ],
     bootstrap,
 );
@@ -125,3 +126,7 @@ raw_type!(Interval, pg_sys::INTERVALOID, pg_sys::INTERVALARRAYOID);
 pub struct regproc(pub pg_sys::Datum);
 
 raw_type!(regproc, pg_sys::REGPROCOID, pg_sys::REGPROCARRAYOID);
+
+pub struct regclass(pub pg_sys::Datum);
+
+raw_type!(regclass, pg_sys::REGCLASSOID, pg_sys::REGCLASSARRAYOID);
@@ -0,0 +1,303 @@
+//! Array overloads of `rollup`
+//!
+//! Every toolkit aggregate type can be combined with the `rollup` aggregate,
+//! and with `toolkit_experimental.rollup(aggs[])` when the aggregates to
+//! combine are already collected into an array, e.g. by `array_agg` or from
+//! several columns.  The array forms run the aggregate over the elements in
+//! array order, so NULL elements are ignored as they would be by the
+//! aggregate, and an empty array returns NULL.
+
+use pgx::*;
+
+extension_sql!(
+    "\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs statssummary1d[]) RETURNS statssummary1d AS $$\n\
+    SELECT rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs statssummary2d[]) RETURNS statssummary2d AS $$\n\
+    SELECT rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs Hyperloglog[]) RETURNS Hyperloglog AS $$\n\
+    SELECT rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs HeartbeatAgg[]) RETURNS HeartbeatAgg AS $$\n\
+    SELECT rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs CounterSummary[]) RETURNS CounterSummary AS $$\n\
+    SELECT rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs tdigest[]) RETURNS tdigest AS $$\n\
+    SELECT rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs TimeWeightSummary[]) RETURNS TimeWeightSummary AS $$\n\
+    SELECT rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs timevector_tstz_f64[]) RETURNS timevector_tstz_f64 AS $$\n\
+    SELECT rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs uddsketch[]) RETURNS uddsketch AS $$\n\
+    SELECT rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs toolkit_experimental.Candlestick[]) RETURNS toolkit_experimental.Candlestick AS $$\n\
+    SELECT toolkit_experimental.rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs toolkit_experimental.GaugeSummary[]) RETURNS toolkit_experimental.GaugeSummary AS $$\n\
+    SELECT toolkit_experimental.rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs toolkit_experimental.CountMinSketch[]) RETURNS toolkit_experimental.CountMinSketch AS $$\n\
+    SELECT toolkit_experimental.rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs toolkit_experimental.BloomFilter[]) RETURNS toolkit_experimental.BloomFilter AS $$\n\
+    SELECT toolkit_experimental.rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs toolkit_experimental.KllSketch[]) RETURNS toolkit_experimental.KllSketch AS $$\n\
+    SELECT toolkit_experimental.rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs toolkit_experimental.SpaceSavingAggregate[]) RETURNS toolkit_experimental.SpaceSavingAggregate AS $$\n\
+    SELECT toolkit_experimental.rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs toolkit_experimental.SpaceSavingBigIntAggregate[]) RETURNS toolkit_experimental.SpaceSavingBigIntAggregate AS $$\n\
+    SELECT toolkit_experimental.rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs toolkit_experimental.SpaceSavingTextAggregate[]) RETURNS toolkit_experimental.SpaceSavingTextAggregate AS $$\n\
+    SELECT toolkit_experimental.rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs toolkit_experimental.StateAgg[]) RETURNS toolkit_experimental.StateAgg AS $$\n\
+    SELECT toolkit_experimental.rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs toolkit_experimental.MaxFloats[]) RETURNS toolkit_experimental.MaxFloats AS $$\n\
+    SELECT toolkit_experimental.rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs toolkit_experimental.MinFloats[]) RETURNS toolkit_experimental.MinFloats AS $$\n\
+    SELECT toolkit_experimental.rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs toolkit_experimental.MaxInts[]) RETURNS toolkit_experimental.MaxInts AS $$\n\
+    SELECT toolkit_experimental.rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs toolkit_experimental.MinInts[]) RETURNS toolkit_experimental.MinInts AS $$\n\
+    SELECT toolkit_experimental.rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs toolkit_experimental.MaxTimes[]) RETURNS toolkit_experimental.MaxTimes AS $$\n\
+    SELECT toolkit_experimental.rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs toolkit_experimental.MinTimes[]) RETURNS toolkit_experimental.MinTimes AS $$\n\
+    SELECT toolkit_experimental.rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs toolkit_experimental.MaxByFloats[]) RETURNS toolkit_experimental.MaxByFloats AS $$\n\
+    SELECT toolkit_experimental.rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs toolkit_experimental.MinByFloats[]) RETURNS toolkit_experimental.MinByFloats AS $$\n\
+    SELECT toolkit_experimental.rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs toolkit_experimental.MaxByInts[]) RETURNS toolkit_experimental.MaxByInts AS $$\n\
+    SELECT toolkit_experimental.rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs toolkit_experimental.MinByInts[]) RETURNS toolkit_experimental.MinByInts AS $$\n\
+    SELECT toolkit_experimental.rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs toolkit_experimental.MaxByTimes[]) RETURNS toolkit_experimental.MaxByTimes AS $$\n\
+    SELECT toolkit_experimental.rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+\n\
+CREATE FUNCTION toolkit_experimental.rollup(aggs toolkit_experimental.MinByTimes[]) RETURNS toolkit_experimental.MinByTimes AS $$\n\
+    SELECT toolkit_experimental.rollup(agg ORDER BY n) FROM unnest(aggs) WITH ORDINALITY AS a(agg, n)\n\
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE;\n\
+",
+    name = "rollup_arrays",
+    requires = [
+        "stats_1d_rollup",
+        "stats_2d_rollup",
+        "hll_rollup",
+        "heartbeat_agg_rollup",
+        "counter_rollup",
+        "tdigest_rollup",
+        "time_weight_agg",
+        "timevector_tstz_f64_rollup",
+        "udd_rollup",
+        "ohlc_rollup",
+        "gauge_rollup",
+        "count_min_sketch_rollup",
+        "bloom_rollup",
+        "kll_sketch_rollup",
+        "space_saving_rollup",
+        "space_saving_bigint_rollup",
+        "space_saving_text_rollup",
+        "state_agg_rollup",
+        "max_n_float_rollup",
+        "min_n_float_rollup",
+        "max_n_int_rollup",
+        "min_n_int_rollup",
+        "max_n_time_rollup",
+        "min_n_time_rollup",
+        "max_n_by_float_rollup",
+        "min_n_by_float_rollup",
+        "max_n_by_int_rollup",
+        "min_n_by_int_rollup",
+        "max_n_by_time_rollup",
+        "min_n_by_time_rollup"
+    ],
+);
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_every_rollup_has_array_overload() {
+        Spi::execute(|client| {
+            let missing = client
+                .select(
+                    "SELECT string_agg(p.oid::regprocedure::text, ', ')
+                    FROM pg_catalog.pg_proc p
+                    JOIN pg_catalog.pg_type t ON t.oid = p.proargtypes[0]
+                    WHERE p.proname = 'rollup'
+                        AND p.prokind = 'a'
+                        AND p.pronamespace IN (
+                            'toolkit_experimental'::regnamespace,
+                            (SELECT extnamespace FROM pg_catalog.pg_extension WHERE extname = 'timescaledb_toolkit')
+                        )
+                        AND NOT EXISTS (
+                            SELECT 1 FROM pg_catalog.pg_proc f
+                            WHERE f.proname = 'rollup'
+                                AND f.prokind = 'f'
+                                AND f.pronamespace = 'toolkit_experimental'::regnamespace
+                                AND f.proargtypes[0] = t.typarray
+                        )",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(missing, None);
+        });
+    }
+
+    #[pg_test]
+    fn test_every_rollup_is_partializable() {
+        Spi::execute(|client| {
+            let missing = client
+                .select(
+                    "SELECT string_agg(p.oid::regprocedure::text, ', ')
+                    FROM pg_catalog.pg_proc p
+                    JOIN pg_catalog.pg_aggregate a ON a.aggfnoid = p.oid
+                    WHERE p.proname = 'rollup'
+                        AND p.pronamespace IN (
+                            'toolkit_experimental'::regnamespace,
+                            (SELECT extnamespace FROM pg_catalog.pg_extension WHERE extname = 'timescaledb_toolkit')
+                        )
+                        AND a.aggcombinefn = 0",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(missing, None);
+        });
+    }
+
+    #[pg_test]
+    fn test_rollup_arrays() {
+        Spi::execute(|client| {
+            client.select("SET TIMEZONE to UTC", None, None);
+            client.select(
+                "CREATE TABLE test(ts TIMESTAMPTZ, val DOUBLE PRECISION)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO test
+                SELECT '2020-01-01 UTC'::timestamptz + v * '1 minute'::interval, v
+                FROM generate_series(0, 99) v",
+                None,
+                None,
+            );
+
+            // each array form agrees with the aggregate form over the same
+            // values
+            for (agg, rollup, accessor) in [
+                ("stats_agg(val)", "rollup", "average($)"),
+                ("percentile_agg(val)", "rollup", "approx_percentile(0.5, $)"),
+                ("tdigest(100, val)", "rollup", "approx_percentile(0.5, $)"),
+                ("hyperloglog(64, val)", "rollup", "distinct_count($)"),
+                ("counter_agg(ts, val)", "rollup", "delta($)"),
+                ("time_weight('Linear', ts, val)", "rollup", "average($)"),
+                (
+                    "heartbeat_agg(ts, '2020-01-01 UTC', '2h', '90s')",
+                    "rollup",
+                    "duration_live($)",
+                ),
+                (
+                    "toolkit_experimental.max_n(val, 3)",
+                    "toolkit_experimental.rollup",
+                    "toolkit_experimental.into_array($)",
+                ),
+            ] {
+                let (from_array, from_aggregate) = client
+                    .select(
+                        &format!(
+                            "SELECT {}::TEXT, {}::TEXT FROM (
+                                SELECT
+                                    array_agg(agg ORDER BY bucket) AS aggs,
+                                    {}(agg ORDER BY bucket) AS agg
+                                FROM (
+                                    SELECT date_trunc('hour', ts) AS bucket, {} AS agg
+                                    FROM test
+                                    GROUP BY 1
+                                ) buckets
+                            ) rolled",
+                            accessor.replace('$', "toolkit_experimental.rollup(aggs)"),
+                            accessor.replace('$', "agg"),
+                            rollup,
+                            agg,
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_two::<String, String>();
+                assert!(from_array.is_some(), "{}", agg);
+                assert_eq!(from_array, from_aggregate, "{}", agg);
+            }
+
+            let empty = client
+                .select(
+                    "SELECT toolkit_experimental.rollup(ARRAY[]::statssummary1d[])::TEXT",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(empty, None);
+        });
+    }
+}
@@ -165,4 +165,15 @@ pub(crate) mod serde_reference_adaptor {
     pub(crate) fn default_header() -> u32 {
         0
     }
+
+    pub(crate) fn default_slice<'a, T>() -> flat_serialize::Slice<'a, T> {
+        flat_serialize::Slice::Owned(vec![])
+    }
+
+    pub(crate) fn is_empty_slice<'a, T>(slice: &flat_serialize::Slice<'a, T>) -> bool
+    where
+        T: Clone + flat_serialize::FlatSerializable<'a>,
+    {
+        slice.is_empty()
+    }
 }
@@ -11,6 +11,23 @@
 
 crate::functions_stabilized_at! {
     STABLE_FUNCTIONS
+    "1.13.0" => {
+        heartbeatagg_in(cstring),
+        heartbeatagg_out(heartbeatagg),
+        heartbeat_trans(internal,timestamp with time zone,timestamp with time zone,interval,interval),
+        heartbeat_rollup_trans(internal,heartbeatagg),
+        heartbeat_combine(internal,internal),
+        heartbeat_trans_serialize(internal),
+        heartbeat_trans_deserialize(bytea,internal),
+        heartbeat_final(internal),
+        heartbeat_agg(timestamp with time zone,timestamp with time zone,interval,interval),
+        rollup(heartbeatagg),
+        live_ranges(heartbeatagg),
+        dead_ranges(heartbeatagg),
+        duration_live(heartbeatagg),
+        duration_dead(heartbeatagg),
+        live_at(heartbeatagg,timestamp with time zone),
+    }
     "1.12.0" => {
         stats1d_tf_inv_trans(internal,double precision),
         stats1d_tf_final(internal),
@@ -433,6 +450,9 @@ crate::functions_stabilized_at! {
 
 crate::types_stabilized_at! {
     STABLE_TYPES
+    "1.13.0" => {
+        heartbeatagg,
+    }
     "1.11.0" => {
         accessorfirsttime,
         accessorfirstval,
@@ -108,6 +108,20 @@ mod tests {
                         return Some(val);
                     }
 
+                    // tables, views, and their sequences are only ever created
+                    // in the experimental schema
+                    let relation = val
+                        .strip_prefix("table ")
+                        .or_else(|| val.strip_prefix("view "))
+                        .or_else(|| val.strip_prefix("sequence "));
+                    if let Some(relation) = relation {
+                        if relation.starts_with("toolkit_experimental.") {
+                            return None;
+                        }
+
+                        return Some(val);
+                    }
+
                     if let Some(cast) = val.strip_prefix("cast ") {
                         // casts cannot be schema-qualified, so we rely on one
                         // of the types involved being experimental and the
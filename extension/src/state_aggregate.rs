@@ -14,8 +14,9 @@ use flat_serialize::*;
 use flat_serialize_macro::FlatSerializable;
 
 use crate::{
+    aggregate_utils::in_aggregate_context,
     flatten,
-    palloc::{Inner, Internal},
+    palloc::{Inner, Internal, InternalAsValue, ToInternal},
     pg_type,
     raw::{bytea, TimestampTz},
     ron_inout_funcs,
@@ -32,7 +33,9 @@ pub mod toolkit_experimental {
         struct StateAgg<'input> {
             states_len: u64, // TODO JOSH this and durations_len can be 32
             durations_len: u64,
+            periods_len: u64,
             durations: [DurationInState; self.durations_len],
+            periods: [StatePeriod; self.periods_len],
             first_time: i64,
             last_time: i64,
             first_state: u32,
@@ -45,11 +48,14 @@ pub mod toolkit_experimental {
         pub(super) fn new(
             states: String,
             durations: Vec<DurationInState>,
+            periods: Vec<Period>,
             first: Option<Record>,
             last: Option<Record>,
         ) -> Self {
             if durations.is_empty() {
-                assert!(first.is_none() && last.is_none() && states.is_empty());
+                assert!(
+                    first.is_none() && last.is_none() && states.is_empty() && periods.is_empty()
+                );
 
                 return unsafe {
                     flatten!(StateAgg {
@@ -57,6 +63,8 @@ pub mod toolkit_experimental {
                         states: Slice::Slice(&[]),
                         durations_len: 0,
                         durations: Slice::Slice(&[]),
+                        periods_len: 0,
+                        periods: Slice::Slice(&[]),
                         first_time: 0,
                         last_time: 0,
                         first_state: 0,
@@ -91,12 +99,33 @@ pub mod toolkit_experimental {
             }
             assert!(first_state < durations.len() && last_state < durations.len());
 
+            // Periods refer to their state's name in `states` the same way durations do
+            let periods: Vec<StatePeriod> = periods
+                .into_iter()
+                .map(|period| {
+                    let d = durations
+                        .iter()
+                        .find(|d| {
+                            states[d.state_beg as usize..d.state_end as usize] == period.state
+                        })
+                        .expect("period in a state without a duration");
+                    StatePeriod {
+                        start: period.start,
+                        end: period.end,
+                        state_beg: d.state_beg,
+                        state_end: d.state_end,
+                    }
+                })
+                .collect();
+
             unsafe {
                 flatten!(StateAgg {
                     states_len,
                     states: states.into_bytes().into(),
                     durations_len,
                     durations: (&*durations).into(),
+                    periods_len: periods.len() as u64,
+                    periods: (&*periods).into(),
                     first_time: first.time,
                     last_time: last.time,
                     first_state: first_state as u32,
@@ -126,6 +155,32 @@ pub mod toolkit_experimental {
             &self.states_as_str()[beg..end]
         }
 
+        /// The periods spent in each state, in time order.
+        pub(super) fn periods(&self) -> impl Iterator<Item = Period> + '_ {
+            self.periods.iter().map(|period| Period {
+                state: self.states_as_str()[period.state_beg as usize..period.state_end as usize]
+                    .to_string(),
+                start: period.start,
+                end: period.end,
+            })
+        }
+
+        /// The state in effect at `time`, if it's within the aggregate.  At the
+        /// boundary between two periods, the later period's state is in effect.
+        pub(super) fn state_at(&self, time: i64) -> Option<&str> {
+            let periods = self.periods.as_slice();
+            // the last period starting at or before `time`
+            let idx = periods.partition_point(|period| period.start <= time);
+            if idx == 0 {
+                return None;
+            }
+            let period = &periods[idx - 1];
+            if time > period.end {
+                return None;
+            }
+            Some(&self.states_as_str()[period.state_beg as usize..period.state_end as usize])
+        }
+
         pub(super) fn interpolate(
             &self,
             interval_start: i64,
@@ -141,6 +196,7 @@ pub mod toolkit_experimental {
                 .unwrap()
                 .to_string();
             let mut durations: Vec<DurationInState> = self.durations.iter().collect();
+            let mut periods: Vec<Period> = self.periods().collect();
 
             let first = match prev {
                 Some(prev) if interval_start < self.first_time => {
@@ -161,6 +217,19 @@ pub mod toolkit_experimental {
                                 states += start_state;
                             }
                         };
+                        match periods.first_mut() {
+                            Some(period) if period.state == start_state => {
+                                period.start = interval_start
+                            }
+                            _ => periods.insert(
+                                0,
+                                Period {
+                                    state: start_state.to_string(),
+                                    start: interval_start,
+                                    end: self.first_time,
+                                },
+                            ),
+                        }
 
                         Record {
                             state: start_state.to_string(),
@@ -184,6 +253,9 @@ pub mod toolkit_experimental {
                     None => pgx::error!("poorly formed StateAgg, last_state out of starts"),
                     Some(dis) => {
                         dis.duration += last_interval;
+                        if let Some(period) = periods.last_mut() {
+                            period.end = interval_start + interval_len;
+                        }
                         Record {
                             state: states[dis.state_beg as usize..dis.state_end as usize]
                                 .to_string(),
@@ -200,7 +272,7 @@ pub mod toolkit_experimental {
                 }
             };
 
-            StateAgg::new(states, durations, Some(first), Some(last))
+            StateAgg::new(states, durations, periods, Some(first), Some(last))
         }
     }
 
@@ -213,6 +285,8 @@ impl toolkit_experimental::state_agg {
 
     const PARALLEL_SAFE: bool = true;
 
+    const BATCH_TRANSITION: bool = true;
+
     fn transition(
         state: Option<State>,
         #[sql_type("timestamptz")] ts: TimestampTz,
@@ -248,22 +322,7 @@ impl toolkit_experimental::state_agg {
     }
 
     fn finally(state: Option<&mut State>) -> Option<StateAgg<'static>> {
-        state.map(|s| {
-            let mut states = String::new();
-            let mut durations: Vec<DurationInState> = vec![];
-            let (map, first, last) = s.drain_to_duration_map_and_bounds();
-            for (state, duration) in map {
-                let state_beg = states.len() as u32;
-                let state_end = state_beg + state.len() as u32;
-                states.push_str(&state);
-                durations.push(DurationInState {
-                    duration,
-                    state_beg,
-                    state_end,
-                });
-            }
-            StateAgg::new(states, durations, first, last)
-        })
+        state.map(|s| s.drain_to_state_agg())
     }
 }
 
@@ -286,11 +345,18 @@ impl StateAggTransState {
         self.records.append(&mut other.records)
     }
 
-    /// Drain accumulated state, sort, and return tuple of map of states to durations along with first and last record.
+    fn drain_to_state_agg(&mut self) -> StateAgg<'static> {
+        let (map, periods, first, last) = self.drain_to_duration_map_and_bounds();
+        state_agg_from_duration_map(map, periods, first, last)
+    }
+
+    /// Drain accumulated state, sort, and return tuple of map of states to durations and the periods spent in each
+    /// state, along with first and last record.
     fn drain_to_duration_map_and_bounds(
         &mut self,
     ) -> (
         std::collections::HashMap<String, i64>,
+        Vec<Period>,
         Option<Record>,
         Option<Record>,
     ) {
@@ -319,10 +385,423 @@ impl StateAggTransState {
         }
         duration_state.finalize();
         // TODO BRIAN sort this by decreasing duration will make it easier to implement a TopN states
-        (duration_state.durations, first, last)
+        (
+            duration_state.durations,
+            duration_state.periods,
+            first,
+            last,
+        )
+    }
+}
+
+fn state_agg_from_duration_map(
+    map: std::collections::HashMap<String, i64>,
+    periods: Vec<Period>,
+    first: Option<Record>,
+    last: Option<Record>,
+) -> StateAgg<'static> {
+    let mut states = String::new();
+    let mut durations: Vec<DurationInState> = vec![];
+    for (state, duration) in map {
+        let state_beg = states.len() as u32;
+        let state_end = state_beg + state.len() as u32;
+        states.push_str(&state);
+        durations.push(DurationInState {
+            duration,
+            state_beg,
+            state_end,
+        });
+    }
+    StateAgg::new(states, durations, periods, first, last)
+}
+
+// Intermediate state for building a state aggregate from change events only.
+// Unlike the sample-based aggregate, the state in effect at `start` is known
+// (either `initial_state` or the most recent change before `start`), and the
+// last state seen is held until `end`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct StateChangesTransState {
+    changes: StateAggTransState,
+    initial_state: String,
+    start: i64,
+    end: i64,
+}
+
+impl StateChangesTransState {
+    fn new(initial_state: String, start: i64, end: i64) -> Self {
+        Self {
+            changes: StateAggTransState::new(),
+            initial_state,
+            start,
+            end,
+        }
+    }
+
+    fn combine(&mut self, other: &mut Self) {
+        if self.start != other.start
+            || self.end != other.end
+            || self.initial_state != other.initial_state
+        {
+            pgx::error!(
+                "state_agg arguments for start, duration, and initial state must be constant"
+            );
+        }
+        self.changes.append(&mut other.changes)
+    }
+
+    /// Convert the changes into the samples the regular state aggregate would
+    /// see: one at `start` for the state in effect then, the changes within
+    /// the range, and one at `end` repeating the final state.
+    fn drain_to_state_agg(&mut self) -> StateAgg<'static> {
+        let mut changes = std::mem::take(&mut self.changes.records);
+        changes.sort_by_key(|record| record.time);
+
+        let mut samples = StateAggTransState::new();
+        let mut current = self.initial_state.clone();
+        for record in changes {
+            if record.time < self.start {
+                current = record.state;
+            } else if record.time < self.end {
+                if samples.records.is_empty() && record.time > self.start {
+                    samples.record(current, self.start);
+                }
+                current = record.state.clone();
+                samples.records.push(record);
+            }
+        }
+        if samples.records.is_empty() {
+            samples.record(current.clone(), self.start);
+        }
+        samples.record(current, self.end);
+        samples.drain_to_state_agg()
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn state_changes_trans(
+    state: Internal,
+    changed_at: TimestampTz,
+    value: Option<String>,
+    initial_state: String,
+    agg_start: TimestampTz,
+    agg_duration: crate::raw::Interval,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    state_changes_trans_inner(
+        unsafe { state.to_inner() },
+        changed_at,
+        value,
+        initial_state,
+        agg_start,
+        agg_duration,
+        fcinfo,
+    )
+    .internal()
+}
+
+pub fn state_changes_trans_inner(
+    state: Option<Inner<StateChangesTransState>>,
+    changed_at: TimestampTz,
+    value: Option<String>,
+    initial_state: String,
+    agg_start: TimestampTz,
+    agg_duration: crate::raw::Interval,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<StateChangesTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = state.unwrap_or_else(|| {
+                let duration = crate::datum_utils::interval_to_ms(&agg_start, &agg_duration);
+                let start: i64 = agg_start.into();
+                StateChangesTransState::new(initial_state, start, start + duration).into()
+            });
+            if let Some(value) = value {
+                state.changes.record(value, changed_at.into());
+            }
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn state_changes_combine(
+    state1: Internal,
+    state2: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    unsafe { state_changes_combine_inner(state1.to_inner(), state2.to_inner(), fcinfo).internal() }
+}
+
+pub fn state_changes_combine_inner(
+    state1: Option<Inner<StateChangesTransState>>,
+    state2: Option<Inner<StateChangesTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<StateChangesTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || match (state1, state2) {
+            (None, None) => None,
+            (None, Some(only)) | (Some(only), None) => Some((*only).clone().into()),
+            (Some(a), Some(b)) => {
+                let (mut a, mut b) = ((*a).clone(), (*b).clone());
+                a.combine(&mut b);
+                Some(a.into())
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn state_changes_serialize(state: Internal) -> bytea {
+    let state: Inner<StateChangesTransState> = unsafe { state.to_inner().unwrap() };
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn state_changes_deserialize(bytes: bytea, _internal: Internal) -> Option<Internal> {
+    state_changes_deserialize_inner(bytes).internal()
+}
+
+pub fn state_changes_deserialize_inner(bytes: bytea) -> Inner<StateChangesTransState> {
+    let state: StateChangesTransState = crate::do_deserialize!(bytes, StateChangesTransState);
+    state.into()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn state_changes_final(
+    state: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<StateAgg<'static>> {
+    state_changes_final_inner(unsafe { state.to_inner() }, fcinfo)
+}
+
+pub fn state_changes_final_inner(
+    state: Option<Inner<StateChangesTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<StateAgg<'static>> {
+    unsafe { in_aggregate_context(fcinfo, || state.map(|mut state| state.drain_to_state_agg())) }
+}
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.state_agg(\n\
+        changed_at TIMESTAMPTZ, state TEXT, initial_state TEXT, agg_start TIMESTAMPTZ, agg_duration INTERVAL\n\
+    ) (\n\
+        sfunc = toolkit_experimental.state_changes_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.state_changes_final,\n\
+        combinefunc = toolkit_experimental.state_changes_combine,\n\
+        serialfunc = toolkit_experimental.state_changes_serialize,\n\
+        deserialfunc = toolkit_experimental.state_changes_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "state_agg_changes",
+    requires = [
+        state_changes_trans,
+        state_changes_final,
+        state_changes_combine,
+        state_changes_serialize,
+        state_changes_deserialize
+    ],
+);
+
+// Intermediate state for rolling up state aggregates.  Each input is kept
+// until the final function, since they have to be merged in time order.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct StateAggRollupTransState {
+    parts: Vec<StateAggPart>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct StateAggPart {
+    durations: Vec<(String, i64)>,
+    periods: Vec<Period>,
+    first: Record,
+    last: Record,
+}
+
+impl StateAggRollupTransState {
+    fn new() -> Self {
+        Self { parts: vec![] }
+    }
+
+    fn push(&mut self, agg: &StateAgg) {
+        if agg.durations.is_empty() {
+            return;
+        }
+        let states = agg.states_as_str();
+        let state = |record: &DurationInState| {
+            states[record.state_beg as usize..record.state_end as usize].to_string()
+        };
+        let durations: Vec<DurationInState> = agg.durations.iter().collect();
+        self.parts.push(StateAggPart {
+            first: Record {
+                state: state(&durations[agg.first_state as usize]),
+                time: agg.first_time,
+            },
+            last: Record {
+                state: state(&durations[agg.last_state as usize]),
+                time: agg.last_time,
+            },
+            durations: durations
+                .into_iter()
+                .map(|record| (state(&record), record.duration))
+                .collect(),
+            periods: agg.periods().collect(),
+        });
+    }
+
+    /// Merge the parts as if all of their samples had been aggregated
+    /// together: the time between one part's last sample and the next part's
+    /// first is spent in the state of that last sample.
+    fn drain_to_state_agg(&mut self) -> StateAgg<'static> {
+        self.parts.sort_by_key(|part| part.first.time);
+        let mut map = std::collections::HashMap::new();
+        let mut periods: Vec<Period> = vec![];
+        let mut prev: Option<&Record> = None;
+        for part in &self.parts {
+            if let Some(prev) = prev {
+                if part.first.time < prev.time {
+                    pgx::error!("state_aggs being rolled up must not overlap")
+                }
+                *map.entry(prev.state.clone()).or_insert(0) += part.first.time - prev.time;
+            }
+            for (state, duration) in &part.durations {
+                *map.entry(state.clone()).or_insert(0) += duration;
+            }
+            for period in &part.periods {
+                match periods.last_mut() {
+                    Some(last) if last.state == period.state => last.end = period.end,
+                    last => {
+                        if let Some(last) = last {
+                            last.end = period.start;
+                        }
+                        periods.push(period.clone());
+                    }
+                }
+            }
+            prev = Some(&part.last);
+        }
+        let first = self.parts.first().map(|part| part.first.clone());
+        let last = self.parts.last().map(|part| part.last.clone());
+        self.parts.clear();
+        state_agg_from_duration_map(map, periods, first, last)
     }
 }
 
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn state_agg_rollup_trans<'a>(
+    state: Internal,
+    value: Option<StateAgg<'a>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    state_agg_rollup_trans_inner(unsafe { state.to_inner() }, value, fcinfo).internal()
+}
+
+pub fn state_agg_rollup_trans_inner(
+    state: Option<Inner<StateAggRollupTransState>>,
+    value: Option<StateAgg>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<StateAggRollupTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = state.unwrap_or_else(|| StateAggRollupTransState::new().into());
+            if let Some(value) = value {
+                state.push(&value);
+            }
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn state_agg_rollup_combine(
+    state1: Internal,
+    state2: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    unsafe {
+        state_agg_rollup_combine_inner(state1.to_inner(), state2.to_inner(), fcinfo).internal()
+    }
+}
+
+pub fn state_agg_rollup_combine_inner(
+    state1: Option<Inner<StateAggRollupTransState>>,
+    state2: Option<Inner<StateAggRollupTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<StateAggRollupTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || match (state1, state2) {
+            (None, None) => None,
+            (None, Some(only)) | (Some(only), None) => Some((*only).clone().into()),
+            (Some(a), Some(b)) => {
+                let mut a = (*a).clone();
+                a.parts.extend(b.parts.iter().cloned());
+                Some(a.into())
+            }
+        })
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn state_agg_rollup_serialize(state: Internal) -> bytea {
+    let state: Inner<StateAggRollupTransState> = unsafe { state.to_inner().unwrap() };
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(immutable, parallel_safe, strict, schema = "toolkit_experimental")]
+pub fn state_agg_rollup_deserialize(bytes: bytea, _internal: Internal) -> Option<Internal> {
+    state_agg_rollup_deserialize_inner(bytes).internal()
+}
+
+pub fn state_agg_rollup_deserialize_inner(bytes: bytea) -> Inner<StateAggRollupTransState> {
+    let state: StateAggRollupTransState = crate::do_deserialize!(bytes, StateAggRollupTransState);
+    state.into()
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn state_agg_rollup_final(
+    state: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<StateAgg<'static>> {
+    state_agg_rollup_final_inner(unsafe { state.to_inner() }, fcinfo)
+}
+
+pub fn state_agg_rollup_final_inner(
+    state: Option<Inner<StateAggRollupTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<StateAgg<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            state.map(|state| (*state).clone().drain_to_state_agg())
+        })
+    }
+}
+
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.rollup(\n\
+        agg toolkit_experimental.StateAgg\n\
+    ) (\n\
+        sfunc = toolkit_experimental.state_agg_rollup_trans,\n\
+        stype = internal,\n\
+        finalfunc = toolkit_experimental.state_agg_rollup_final,\n\
+        combinefunc = toolkit_experimental.state_agg_rollup_combine,\n\
+        serialfunc = toolkit_experimental.state_agg_rollup_serialize,\n\
+        deserialfunc = toolkit_experimental.state_agg_rollup_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "state_agg_rollup",
+    requires = [
+        state_agg_rollup_trans,
+        state_agg_rollup_final,
+        state_agg_rollup_combine,
+        state_agg_rollup_serialize,
+        state_agg_rollup_deserialize
+    ],
+);
+
 #[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
 pub fn duration_in<'a>(state: String, aggregate: Option<StateAgg<'a>>) -> crate::raw::Interval {
     let time: i64 = aggregate
@@ -385,6 +864,42 @@ pub fn into_values<'a>(
     }))
 }
 
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn state_at<'a>(agg: StateAgg<'a>, ts: TimestampTz) -> Option<String> {
+    agg.state_at(ts.into()).map(str::to_string)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn state_timeline<'a>(
+    agg: StateAgg<'a>,
+) -> TableIterator<
+    'static,
+    (
+        pgx::name!(state, String),
+        pgx::name!(start, TimestampTz),
+        pgx::name!(end, TimestampTz),
+    ),
+> {
+    let periods: Vec<(String, TimestampTz, TimestampTz)> = agg
+        .periods()
+        .map(|period| (period.state, period.start.into(), period.end.into()))
+        .collect();
+    TableIterator::new(periods.into_iter())
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn state_periods<'a>(
+    state: String,
+    agg: StateAgg<'a>,
+) -> TableIterator<'static, (pgx::name!(start, TimestampTz), pgx::name!(end, TimestampTz))> {
+    let periods: Vec<(TimestampTz, TimestampTz)> = agg
+        .periods()
+        .filter(|period| period.state == state)
+        .map(|period| (period.start.into(), period.end.into()))
+        .collect();
+    TableIterator::new(periods.into_iter())
+}
+
 #[derive(Clone, Debug, Deserialize, Eq, FlatSerializable, PartialEq, Serialize)]
 #[repr(C)]
 pub struct DurationInState {
@@ -393,19 +908,44 @@ pub struct DurationInState {
     state_end: u32,
 }
 
+#[derive(Clone, Debug, Deserialize, Eq, FlatSerializable, PartialEq, Serialize)]
+#[repr(C)]
+pub struct StatePeriod {
+    start: i64,
+    end: i64,
+    state_beg: u32,
+    state_end: u32,
+}
+
 struct DurationState {
     last_state: Option<(String, i64)>,
     durations: std::collections::HashMap<String, i64>,
+    periods: Vec<Period>,
 }
 impl DurationState {
     fn new() -> Self {
         Self {
             last_state: None,
             durations: std::collections::HashMap::new(),
+            periods: vec![],
         }
     }
 
     fn handle_record(&mut self, state: String, time: i64) {
+        // each period lasts until the next record in a different state
+        match self.periods.last_mut() {
+            Some(period) if period.state == state => period.end = time,
+            last => {
+                if let Some(period) = last {
+                    period.end = time;
+                }
+                self.periods.push(Period {
+                    state: state.clone(),
+                    start: time,
+                    end: time,
+                });
+            }
+        }
         match self.last_state.take() {
             None => self.last_state = Some((state, time)),
             Some((last_state, last_time)) => {
@@ -440,6 +980,13 @@ struct Record {
     time: i64,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+struct Period {
+    state: String,
+    start: i64,
+    end: i64,
+}
+
 fn to_palloc<T>(value: T) -> *const T {
     unsafe {
         let ptr = pg_sys::palloc(std::mem::size_of::<T>()) as *mut T;
@@ -837,6 +1384,294 @@ SELECT toolkit_experimental.duration_in('one', toolkit_experimental.state_agg(ts
         });
     }
 
+    #[pg_test]
+    fn rollup() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE inttest(time TIMESTAMPTZ, state TEXT, bucket INT)",
+                None,
+                None,
+            );
+            client.select(
+                r#"INSERT INTO inttest VALUES
+                ('2020-1-1 10:00'::timestamptz, 'one', 1),
+                ('2020-1-1 12:00'::timestamptz, 'two', 1),
+                ('2020-1-1 16:00'::timestamptz, 'three', 1),
+                ('2020-1-2 2:00'::timestamptz, 'one', 2),
+                ('2020-1-2 12:00'::timestamptz, 'two', 2),
+                ('2020-1-2 20:00'::timestamptz, 'three', 2),
+                ('2020-1-3 10:00'::timestamptz, 'one', 3),
+                ('2020-1-3 12:00'::timestamptz, 'two', 3),
+                ('2020-1-3 16:00'::timestamptz, 'three', 3)"#,
+                None,
+                None,
+            );
+
+            // the time between buckets is spent in the last state of the earlier one
+            let mut durations = client.select(
+                r#"SELECT
+                    toolkit_experimental.duration_in('one', toolkit_experimental.rollup(agg))::TEXT,
+                    toolkit_experimental.duration_in('two', toolkit_experimental.rollup(agg))::TEXT,
+                    toolkit_experimental.duration_in('three', toolkit_experimental.rollup(agg))::TEXT
+                FROM (
+                    SELECT toolkit_experimental.state_agg(time, state) as agg
+                    FROM inttest
+                    GROUP BY bucket
+                ) s"#,
+                None,
+                None,
+            );
+            let row = durations.next().unwrap();
+            assert_eq!(row[1].value(), Some("14:00:00"));
+            assert_eq!(row[2].value(), Some("16:00:00"));
+            assert_eq!(row[3].value(), Some("1 day"));
+        });
+    }
+
+    #[pg_test]
+    fn timeline() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE test(ts timestamptz, state TEXT, bucket INT)",
+                None,
+                None,
+            );
+            client.select("SET TIME ZONE 'UTC'", None, None);
+            client.select(
+                r#"INSERT INTO test VALUES
+                    ('2020-01-01 00:00:00+00', 'one', 1),
+                    ('2020-01-01 00:01:00+00', 'one', 1),
+                    ('2020-01-01 00:02:00+00', 'two', 1),
+                    ('2020-01-01 00:04:00+00', 'two', 2),
+                    ('2020-01-01 00:05:00+00', 'one', 2),
+                    ('2020-01-01 00:06:00+00', 'three', 2)"#,
+                None,
+                None,
+            );
+
+            let expected = [
+                ("one", "2020-01-01 00:00:00+00", "2020-01-01 00:02:00+00"),
+                ("two", "2020-01-01 00:02:00+00", "2020-01-01 00:05:00+00"),
+                ("one", "2020-01-01 00:05:00+00", "2020-01-01 00:06:00+00"),
+                ("three", "2020-01-01 00:06:00+00", "2020-01-01 00:06:00+00"),
+            ];
+            for stmt in [
+                "SELECT state, start::TEXT, \"end\"::TEXT FROM toolkit_experimental.state_timeline(
+                    (SELECT toolkit_experimental.state_agg(ts, state) FROM test))",
+                // rolling up merges the periods which continue across aggregates
+                "SELECT state, start::TEXT, \"end\"::TEXT FROM toolkit_experimental.state_timeline(
+                    (SELECT toolkit_experimental.rollup(agg) FROM (
+                        SELECT toolkit_experimental.state_agg(ts, state) AS agg FROM test GROUP BY bucket
+                    ) s))",
+            ] {
+                let mut timeline = client.select(stmt, None, None);
+                for (state, start, end) in expected {
+                    let row = timeline.next().unwrap();
+                    assert_eq!(row[1].value(), Some(state));
+                    assert_eq!(row[2].value(), Some(start));
+                    assert_eq!(row[3].value(), Some(end));
+                }
+                assert!(timeline.next().is_none());
+            }
+
+            let mut periods = client.select(
+                "SELECT start::TEXT, \"end\"::TEXT FROM toolkit_experimental.state_periods(
+                    'one', (SELECT toolkit_experimental.state_agg(ts, state) FROM test))",
+                None,
+                None,
+            );
+            let row = periods.next().unwrap();
+            assert_eq!(row[1].value(), Some("2020-01-01 00:00:00+00"));
+            assert_eq!(row[2].value(), Some("2020-01-01 00:02:00+00"));
+            let row = periods.next().unwrap();
+            assert_eq!(row[1].value(), Some("2020-01-01 00:05:00+00"));
+            assert_eq!(row[2].value(), Some("2020-01-01 00:06:00+00"));
+            assert!(periods.next().is_none());
+        });
+    }
+
+    #[pg_test]
+    fn state_at() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(ts timestamptz, state TEXT)", None, None);
+            client.select(
+                r#"INSERT INTO test VALUES
+                    ('2020-01-01 00:00:00+00', 'one'),
+                    ('2020-01-01 00:02:00+00', 'two'),
+                    ('2020-01-01 00:04:00+00', 'one')"#,
+                None,
+                None,
+            );
+
+            for (ts, expected) in [
+                ("2019-12-31 23:59:00+00", None),
+                ("2020-01-01 00:00:00+00", Some("one")),
+                ("2020-01-01 00:01:00+00", Some("one")),
+                ("2020-01-01 00:02:00+00", Some("two")),
+                ("2020-01-01 00:03:59+00", Some("two")),
+                ("2020-01-01 00:04:00+00", Some("one")),
+                ("2020-01-01 00:04:01+00", None),
+            ] {
+                let stmt = format!(
+                    "SELECT toolkit_experimental.state_at(
+                        (SELECT toolkit_experimental.state_agg(ts, state) FROM test),
+                        '{}'
+                    )",
+                    ts
+                );
+                let state = client.select(&stmt, None, None).first().get_one::<String>();
+                assert_eq!(state.as_deref(), expected, "state at {}", ts);
+            }
+        });
+    }
+
+    #[pg_test(error = "state_aggs being rolled up must not overlap")]
+    fn rollup_overlapping() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE test(ts timestamptz, state TEXT, bucket INT)",
+                None,
+                None,
+            );
+            client.select(
+                r#"INSERT INTO test VALUES
+                    ('2020-01-01 00:00:00+00', 'one', 1),
+                    ('2020-01-01 00:02:00+00', 'two', 1),
+                    ('2020-01-01 00:01:00+00', 'two', 2),
+                    ('2020-01-01 00:03:00+00', 'one', 2)"#,
+                None,
+                None,
+            );
+            client.select(
+                "SELECT toolkit_experimental.rollup(agg) FROM (
+                    SELECT toolkit_experimental.state_agg(ts, state) as agg FROM test GROUP BY bucket
+                ) s",
+                None,
+                None,
+            );
+        })
+    }
+
+    #[pg_test]
+    fn state_agg_from_changes() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE changes(changed_at TIMESTAMPTZ, state TEXT)",
+                None,
+                None,
+            );
+            client.select(
+                r#"INSERT INTO changes VALUES
+                ('2020-01-01 02:00+00', 'OK'),
+                ('2020-01-01 05:00+00', 'ERROR'),
+                ('2020-01-01 06:00+00', 'OK'),
+                ('2020-01-02 01:00+00', 'STOP')"#,
+                None,
+                None,
+            );
+
+            let durations = |client: &pgx::SpiClient| {
+                client
+                    .select(
+                        r#"SELECT toolkit_experimental.duration_in('ERROR', agg)::TEXT,
+                                  toolkit_experimental.duration_in('OK', agg)::TEXT,
+                                  toolkit_experimental.duration_in('STOP', agg)::TEXT
+                        FROM (
+                            SELECT toolkit_experimental.state_agg(
+                                changed_at, state, 'ERROR', '2020-01-01 00:00+00', '1 day'
+                            ) AS agg FROM changes
+                        ) s"#,
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_three::<String, String, String>()
+            };
+
+            // the first period is spent in the initial state and the last
+            // state is held until the end of the range
+            assert_eq!(
+                durations(&client),
+                (
+                    Some("03:00:00".to_string()),
+                    Some("21:00:00".to_string()),
+                    Some("00:00:00".to_string())
+                )
+            );
+
+            // a change before the range overrides the initial state
+            client.select(
+                "INSERT INTO changes VALUES ('2019-12-31 22:00+00', 'STOP')",
+                None,
+                None,
+            );
+            assert_eq!(
+                durations(&client),
+                (
+                    Some("01:00:00".to_string()),
+                    Some("21:00:00".to_string()),
+                    Some("02:00:00".to_string())
+                )
+            );
+
+            // with no changes in the range it's all spent in one state
+            assert_eq!(
+                select_one!(
+                    client,
+                    "SELECT toolkit_experimental.duration_in('STOP', toolkit_experimental.state_agg(
+                        changed_at, state, 'ERROR', '2020-01-03 00:00+00', '12 hours'))::TEXT
+                    FROM changes",
+                    &str
+                ),
+                "12:00:00"
+            );
+        })
+    }
+
+    #[pg_test]
+    fn state_agg_batch() {
+        Spi::execute(|client| {
+            client.select(
+                "CREATE TABLE batches(times TIMESTAMPTZ[], states TEXT[])",
+                None,
+                None,
+            );
+            client.select(
+                r#"INSERT INTO batches VALUES
+                (ARRAY['2020-01-01 00:00:00+00', '2020-01-01 00:01:00+00']::TIMESTAMPTZ[], ARRAY['START', 'ERROR']),
+                (ARRAY['2020-01-01 00:02:00+00', '2020-01-01 00:03:00+00']::TIMESTAMPTZ[], ARRAY[NULL, 'STOPPED'])"#,
+                None,
+                None,
+            );
+            assert_eq!(
+                client
+                    .select(
+                        r#"SELECT toolkit_experimental.duration_in('ERROR', states)::TEXT as error,
+                                  toolkit_experimental.duration_in('START', states)::TEXT as start,
+                                  toolkit_experimental.duration_in('STOPPED', states)::TEXT as stopped
+                             FROM (SELECT toolkit_experimental.state_agg(times, states) as states FROM batches) as foo"#,
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_three::<&str, &str, &str>(),
+                (Some("00:02:00"), Some("00:01:00"), Some("00:00:00"))
+            );
+        })
+    }
+
+    #[pg_test(error = "all arrays passed to state_agg must have the same length")]
+    fn state_agg_batch_length_mismatch() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT toolkit_experimental.state_agg(
+                    ARRAY['2020-01-01 00:00:00+00']::TIMESTAMPTZ[], ARRAY['START', 'STOP'])",
+                None,
+                None,
+            );
+        })
+    }
+
     // TODO why doesn't this catch the error under github actions?
     //  https://github.com/timescale/timescaledb-toolkit/runs/4943786692?check_suite_focus=true
     // Retrieving Tests
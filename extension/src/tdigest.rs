@@ -368,6 +368,21 @@ pub fn tdigest_quantile<'a>(quantile: f64, digest: TDigest<'a>) -> f64 {
     digest.to_internal_tdigest().estimate_quantile(quantile)
 }
 
+// The value above which roughly `target_exceed_rate` (0.0-1.0) of the values
+// in the sketch fall, e.g. for setting alert thresholds.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "threshold_for_rate",
+    schema = "toolkit_experimental"
+)]
+pub fn tdigest_threshold_for_rate<'a>(sketch: TDigest<'a>, target_exceed_rate: f64) -> f64 {
+    if !(0.0..=1.0).contains(&target_exceed_rate) {
+        pgx::error!("target_exceed_rate must be between 0 and 1")
+    }
+    tdigest_quantile(1.0 - target_exceed_rate, sketch)
+}
+
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_tdigest_approx_rank<'a>(
@@ -385,6 +400,32 @@ pub fn tdigest_quantile_at_value<'a>(value: f64, digest: TDigest<'a>) -> f64 {
         .estimate_quantile_at_value(value)
 }
 
+// Estimated number of values in the sketch greater than `threshold`.  This is
+// subject to the same error as `approx_percentile_rank`, scaled by the number
+// of values in the sketch.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "approx_count_above",
+    schema = "toolkit_experimental"
+)]
+pub fn tdigest_approx_count_above<'a>(sketch: TDigest<'a>, threshold: f64) -> f64 {
+    let count = sketch.count as f64;
+    count * tdigest_approx_rate_above(sketch, threshold)
+}
+
+// Estimated fraction (0.0-1.0) of the values in the sketch greater than
+// `threshold`.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "approx_rate_above",
+    schema = "toolkit_experimental"
+)]
+pub fn tdigest_approx_rate_above<'a>(sketch: TDigest<'a>, threshold: f64) -> f64 {
+    1.0 - tdigest_quantile_at_value(threshold, sketch)
+}
+
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_tdigest_num_vals<'a>(sketch: TDigest<'a>, _accessor: AccessorNumVals<'a>) -> f64 {
@@ -729,4 +770,82 @@ mod tests {
             apx_eql(test_value.unwrap(), 9.0, 0.1);
         });
     }
+
+    #[pg_test]
+    fn test_tdigest_threshold_for_rate() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test (data DOUBLE PRECISION)", None, None);
+            client.select(
+                "INSERT INTO test SELECT generate_series(0.01, 100, 0.01)",
+                None,
+                None,
+            );
+
+            let (threshold, percentile) = client
+                .select(
+                    "SELECT \
+                        toolkit_experimental.threshold_for_rate(sketch, 0.005), \
+                        approx_percentile(0.995, sketch) \
+                    FROM (SELECT tdigest(100, data) AS sketch FROM test) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(threshold, percentile);
+            pct_eql(threshold.unwrap(), 99.5, 0.01);
+
+            let rate = client
+                .select(
+                    "SELECT approx_percentile_rank( \
+                        toolkit_experimental.threshold_for_rate(sketch, 0.2), sketch) \
+                    FROM (SELECT tdigest(100, data) AS sketch FROM test) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<f64>();
+            apx_eql(rate.unwrap(), 0.8, 0.01);
+        });
+    }
+
+    #[pg_test]
+    fn test_tdigest_count_above() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test (data DOUBLE PRECISION)", None, None);
+            client.select(
+                "INSERT INTO test SELECT generate_series(0.01, 100, 0.01)",
+                None,
+                None,
+            );
+
+            let (count, rate) = client
+                .select(
+                    "SELECT \
+                        toolkit_experimental.approx_count_above(sketch, 98), \
+                        toolkit_experimental.approx_rate_above(sketch, 98) \
+                    FROM (SELECT tdigest(100, data) AS sketch FROM test) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<f64, f64>();
+            apx_eql(rate.unwrap(), 0.02, 0.01);
+            assert_eq!(count.unwrap(), rate.unwrap() * 10000.0);
+
+            let (count, rate) = client
+                .select(
+                    "SELECT \
+                        toolkit_experimental.approx_count_above(sketch, 1000), \
+                        toolkit_experimental.approx_rate_above(sketch, 0) \
+                    FROM (SELECT tdigest(100, data) AS sketch FROM test) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(count, Some(0.0));
+            assert_eq!(rate, Some(1.0));
+        });
+    }
 }
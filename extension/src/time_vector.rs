@@ -23,6 +23,11 @@ use crate::raw::bytea;
 // Bit flags stored in Timevector flags
 pub const FLAG_IS_SORTED: u8 = 0x01;
 pub const FLAG_HAS_NULLS: u8 = 0x01 << 1;
+pub const FLAG_HAS_QUALITY: u8 = 0x01 << 2;
+
+// Quality code for points with no known problems, matching the OPC-UA
+// convention that a status of 0 is good
+pub const QUALITY_GOOD: u8 = 0;
 
 pg_type! {
     #[derive(Debug)]
@@ -32,7 +37,14 @@ pg_type! {
         flags: u8,         // extra information about the stored data
         internal_padding: [u8; 3],  // required to be aligned
         points: [TSPoint; self.num_points],
-        null_val: [u8; (self.num_points + 7)/ 8], // bit vector, must be last element for alignment purposes
+        null_val: [u8; (self.num_points + 7)/ 8], // bit vector, must be after all wider fields for alignment purposes
+        // per-point quality codes, only present if FLAG_HAS_QUALITY is set,
+        // older values without it can still be read
+        #[serde(
+            default = "crate::serialization::serde_reference_adaptor::default_slice",
+            skip_serializing_if = "crate::serialization::serde_reference_adaptor::is_empty_slice"
+        )]
+        quality: [u8; if self.flags & FLAG_HAS_QUALITY != 0 { self.num_points } else { 0 }],
     }
 }
 
@@ -72,6 +84,21 @@ impl<'input> Timevector_TSTZ_F64<'input> {
         self.null_val.as_slice()[byte_id] & (1 << byte_idx) != 0
     }
 
+    #[inline]
+    pub fn has_quality(&self) -> bool {
+        self.flags & FLAG_HAS_QUALITY != 0
+    }
+
+    // The quality code of the nth point, points in timevectors without
+    // quality information are considered good
+    pub fn quality(&self, index: usize) -> u8 {
+        assert!(index < self.num_points());
+        if !self.has_quality() {
+            return QUALITY_GOOD;
+        }
+        self.quality.as_slice()[index]
+    }
+
     fn clone_owned(&self) -> Timevector_TSTZ_F64<'static> {
         Timevector_TSTZ_F64Data::clone(self).into_owned().into()
     }
@@ -124,6 +151,28 @@ pub fn arrow_timevector_unnest<'a>(
     unnest(series)
 }
 
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn unnest_with_quality<'a>(
+    series: Timevector_TSTZ_F64<'a>,
+) -> TableIterator<
+    'a,
+    (
+        name!(time, crate::raw::TimestampTz),
+        name!(value, f64),
+        name!(quality, i16),
+    ),
+> {
+    let quality: Vec<_> = (0..series.num_points())
+        .map(|i| series.quality(i) as i16)
+        .collect();
+    TableIterator::new(
+        series
+            .into_iter()
+            .zip(quality)
+            .map(|(points, quality)| (points.ts.into(), points.val, quality)),
+    )
+}
+
 #[pg_extern(immutable, parallel_safe, strict)]
 pub fn timevector_serialize(state: Internal) -> bytea {
     // FIXME: This might duplicate the version and padding bits
@@ -152,6 +201,38 @@ pub fn timevector_trans_inner(
     time: Option<crate::raw::TimestampTz>,
     value: Option<f64>,
     fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Inner<Timevector_TSTZ_F64<'_>>> {
+    timevector_quality_trans_inner(state, time, value, None, fcinfo)
+}
+
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn timevector_tstz_f64_quality_trans(
+    state: Internal,
+    time: Option<crate::raw::TimestampTz>,
+    value: Option<f64>,
+    quality: Option<i16>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal> {
+    // a NULL quality is treated as good so that the point isn't lost
+    let quality = match quality {
+        None => QUALITY_GOOD,
+        Some(q) => u8::try_from(q)
+            .unwrap_or_else(|_| pgx::error!("quality must be between 0 and 255, got {}", q)),
+    };
+    unsafe {
+        timevector_quality_trans_inner(state.to_inner(), time, value, Some(quality), fcinfo)
+            .internal()
+    }
+}
+
+// Adds a point to the timevector, `quality` is only stored once some point
+// has provided one, earlier points are then marked as good
+pub fn timevector_quality_trans_inner(
+    state: Option<Inner<Timevector_TSTZ_F64<'_>>>,
+    time: Option<crate::raw::TimestampTz>,
+    value: Option<f64>,
+    quality: Option<u8>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Inner<Timevector_TSTZ_F64<'_>>> {
     unsafe {
         in_aggregate_context(fcinfo, || {
@@ -167,6 +248,7 @@ pub fn timevector_trans_inner(
                         internal_padding: [0; 3],
                         points: vec![].into(),
                         null_val: vec![].into(),
+                        quality: vec![].into(),
                     }
                 }),
                 Some(state) => state,
@@ -191,6 +273,16 @@ pub fn timevector_trans_inner(
                 }
                 Some(val) => state.points.as_owned().push(TSPoint { ts: time, val }),
             };
+            if quality.is_some() && !state.has_quality() {
+                state.flags |= FLAG_HAS_QUALITY;
+                state.quality = vec![QUALITY_GOOD; state.num_points as usize].into();
+            }
+            if state.has_quality() {
+                state
+                    .quality
+                    .as_owned()
+                    .push(quality.unwrap_or(QUALITY_GOOD));
+            }
             state.num_points += 1;
             Some(state)
         })
@@ -288,6 +380,16 @@ pub fn combine(
         v
     };
 
+    let quality: Vec<u8> = if first.has_quality() || second.has_quality() {
+        flags |= FLAG_HAS_QUALITY;
+        (0..first.num_points())
+            .map(|i| first.quality(i))
+            .chain((0..second.num_points()).map(|i| second.quality(i)))
+            .collect()
+    } else {
+        vec![]
+    };
+
     build! {
         Timevector_TSTZ_F64 {
             num_points: points.len() as _,
@@ -295,6 +397,7 @@ pub fn combine(
             internal_padding: [0; 3],
             points: points.into(),
             null_val: null_val.into(),
+            quality: quality.into(),
         }
     }
 }
@@ -344,6 +447,28 @@ extension_sql!(
     ],
 );
 
+extension_sql!(
+    "\n\
+    CREATE AGGREGATE toolkit_experimental.timevector(ts TIMESTAMPTZ, value DOUBLE PRECISION, quality SMALLINT) (\n\
+        sfunc = toolkit_experimental.timevector_tstz_f64_quality_trans,\n\
+        stype = internal,\n\
+        finalfunc = timevector_final,\n\
+        combinefunc = timevector_combine,\n\
+        serialfunc = timevector_serialize,\n\
+        deserialfunc = timevector_deserialize,\n\
+        parallel = safe\n\
+    );\n\
+",
+    name = "timevector_tstz_f64_quality_agg",
+    requires = [
+        timevector_tstz_f64_quality_trans,
+        timevector_final,
+        timevector_combine,
+        timevector_serialize,
+        timevector_deserialize
+    ],
+);
+
 extension_sql!(
     "\n\
 CREATE AGGREGATE rollup(\n\
@@ -644,4 +769,84 @@ mod tests {
             assert_eq!(tvec, expected);
         })
     }
+
+    #[pg_test]
+    fn test_timevector_quality() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            client.select(
+                "CREATE TABLE readings(time TIMESTAMPTZ, value DOUBLE PRECISION, quality SMALLINT)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO readings VALUES
+                    ('2020-1-1', 10.0, 0),
+                    ('2020-1-3', 30.0, 64),
+                    ('2020-1-2', 20.0, 0),
+                    ('2020-1-4', 40.0, NULL)",
+                None,
+                None,
+            );
+
+            let tvec = client
+                .select(
+                    "SELECT toolkit_experimental.timevector(time, value, quality)::TEXT FROM readings",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            let expected = r#"(version:1,num_points:4,flags:4,internal_padding:(0,0,0),points:[(ts:"2020-01-01 00:00:00+00",val:10),(ts:"2020-01-03 00:00:00+00",val:30),(ts:"2020-01-02 00:00:00+00",val:20),(ts:"2020-01-04 00:00:00+00",val:40)],null_val:[0],quality:[0,64,0,0])"#;
+            assert_eq!(tvec, expected);
+
+            let mut unnest = client.select(
+                "SELECT value, quality FROM toolkit_experimental.unnest_with_quality(
+                    (SELECT toolkit_experimental.timevector(time, value, quality) FROM readings))",
+                None,
+                None,
+            );
+            let mut next = || {
+                unnest
+                    .next()
+                    .map(|row| (row[1].value::<f64>(), row[2].value::<i16>()))
+            };
+            assert_eq!(next(), Some((Some(10.0), Some(0))));
+            assert_eq!(next(), Some((Some(30.0), Some(64))));
+            assert_eq!(next(), Some((Some(20.0), Some(0))));
+            assert_eq!(next(), Some((Some(40.0), Some(0))));
+            assert_eq!(next(), None);
+
+            // timevectors without quality information report every point as
+            // good, and values serialized before quality existed still parse
+            let quality = client
+                .select(
+                    r#"SELECT array_agg(quality)::TEXT FROM toolkit_experimental.unnest_with_quality(
+                        '(version:1,num_points:2,flags:1,internal_padding:(0,0,0),points:[(ts:"2020-01-01 00:00:00+00",val:1),(ts:"2020-01-02 00:00:00+00",val:2)],null_val:[0])'::timevector_tstz_f64)"#,
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(quality.as_deref(), Some("{0,0}"));
+
+            // rolling up a timevector with quality and one without keeps the
+            // quality of both
+            let quality = client
+                .select(
+                    "SELECT array_agg(quality)::TEXT FROM toolkit_experimental.unnest_with_quality(
+                        (SELECT rollup(tv) FROM (
+                            SELECT toolkit_experimental.timevector(time, value, quality) AS tv FROM readings
+                            UNION ALL
+                            SELECT timevector('2020-1-5', 50.0)
+                        ) tvs))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(quality.as_deref(), Some("{0,64,0,0,0}"));
+        })
+    }
 }
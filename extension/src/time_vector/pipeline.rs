@@ -1,11 +1,16 @@
 mod aggregation;
+mod anomalies;
 mod arithmetic;
+mod decompose;
 mod delta;
 mod expansion;
 mod fill_to;
 mod filter;
+mod indicators;
 mod lambda;
 mod map;
+mod moving_average;
+mod resample;
 mod sort;
 
 use std::convert::TryInto;
@@ -17,8 +22,11 @@ use super::*;
 use crate::{flatten, pg_type, ron_inout_funcs};
 
 use fill_to::{fill_to, FillToMethod};
+use indicators::timevector_rsi;
 
-use delta::timevector_delta;
+use delta::{timevector_delta, timevector_deriv};
+use moving_average::{timevector_ema, timevector_sma};
+use resample::{resample_to_rate, ResampleMethod};
 use sort::sort_timevector;
 
 pub use self::toolkit_experimental::*;
@@ -76,6 +84,27 @@ pub mod toolkit_experimental {
                 interval: i64,
                 fill_method: FillToMethod,
             },
+            FilterQuality: 12 {
+                // a boolean, stored as a u64 to keep the elements aligned
+                good_only: u64,
+            },
+            Deriv: 13 {
+            },
+            ResampleToRate: 14 {
+                interval: i64,
+                method: ResampleMethod,
+                // a boolean, stored as a u64 to keep the elements aligned
+                snap_to_rate: u64,
+            },
+            SMA: 15 {
+                window: u64,
+            },
+            EMA: 16 {
+                span: u64,
+            },
+            RSI: 17 {
+                period: u64,
+            },
         }
     }
 
@@ -137,12 +166,18 @@ pub fn execute_pipeline_element<'s>(
         Element::LTTB { resolution } => crate::lttb::lttb_ts(timevector, *resolution as _),
         Element::Sort { .. } => sort_timevector(timevector),
         Element::Delta { .. } => timevector_delta(&timevector),
+        Element::Deriv { .. } => timevector_deriv(&timevector),
         Element::MapData { function } => map::apply_to(timevector, function.0),
         Element::MapSeries { function } => map::apply_to_series(timevector, function.0),
         Element::MapLambda { lambda } => map::apply_lambda_to(timevector, lambda),
         Element::FilterLambda { lambda } => filter::apply_lambda_to(timevector, lambda),
         Element::Arithmetic { function, rhs } => arithmetic::apply(timevector, *function, *rhs),
         Element::FillTo { .. } => fill_to(timevector, element),
+        Element::FilterQuality { good_only } => filter::filter_quality(timevector, *good_only != 0),
+        Element::ResampleToRate { .. } => resample_to_rate(timevector, element),
+        Element::SMA { window } => timevector_sma(&timevector, *window as _),
+        Element::EMA { span } => timevector_ema(&timevector, *span as _),
+        Element::RSI { period } => timevector_rsi(&timevector, *period as _),
     }
 }
 
@@ -0,0 +1,216 @@
+use pgx::{iter::TableIterator, *};
+
+use super::*;
+
+// Scales the median absolute deviation of normally distributed values to
+// their standard deviation, so that the two methods' scores are comparable.
+const MAD_SCALE: f64 = 1.4826;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AnomalyMethod {
+    ZScore,
+    Mad,
+}
+
+impl AnomalyMethod {
+    // How many deviations `value` is from the center of `baseline`.
+    fn score(self, baseline: &[f64], value: f64) -> f64 {
+        let (center, spread) = match self {
+            AnomalyMethod::ZScore => {
+                let mean = baseline.iter().sum::<f64>() / baseline.len() as f64;
+                let variance = baseline
+                    .iter()
+                    .map(|v| (v - mean) * (v - mean))
+                    .sum::<f64>()
+                    / baseline.len() as f64;
+                (mean, variance.sqrt())
+            }
+            AnomalyMethod::Mad => {
+                let center = median(baseline.to_vec());
+                let deviations = baseline.iter().map(|v| (v - center).abs()).collect();
+                (center, MAD_SCALE * median(deviations))
+            }
+        };
+        let deviation = value - center;
+        if deviation == 0.0 {
+            0.0
+        } else {
+            // a baseline without any spread makes any other value infinitely
+            // unusual
+            deviation / spread
+        }
+    }
+}
+
+// `anomalies` rejects NaN values, so they all compare.
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let middle = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    }
+}
+
+/// Scores each point of a sorted timevector by how many deviations it is from
+/// the `window` points before it, either standard deviations from their mean
+/// (`'zscore'`) or scaled median absolute deviations from their median
+/// (`'mad'`), and flags those scoring more than `threshold` either way.  The
+/// first `window` points have no baseline to score them against, so their
+/// score and flag are NULL.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn anomalies<'a>(
+    series: Timevector_TSTZ_F64<'a>,
+    window: i32,
+    threshold: default!(f64, 3.0),
+    method: default!(&str, "'zscore'"),
+) -> TableIterator<
+    'a,
+    (
+        name!(time, crate::raw::TimestampTz),
+        name!(value, f64),
+        name!(score, Option<f64>),
+        name!(is_anomaly, Option<bool>),
+    ),
+> {
+    if window < 1 {
+        pgx::error!("anomalies requires a window of at least 1 point")
+    }
+    let method = match method.trim().to_lowercase().as_str() {
+        "zscore" => AnomalyMethod::ZScore,
+        "mad" => AnomalyMethod::Mad,
+        _ => pgx::error!("Invalid anomaly detection method: {}", method),
+    };
+    if !series.is_sorted() {
+        panic!("can only detect anomalies in sorted timevector");
+    }
+    if series.has_nulls() {
+        panic!("Unable to detect anomalies in timevector containing nulls");
+    }
+    if series.iter().any(|p| p.val.is_nan()) {
+        pgx::error!("Unable to detect anomalies in timevector containing NaN values");
+    }
+
+    let window = window as usize;
+    let values: Vec<f64> = series.iter().map(|p| p.val).collect();
+    let rows: Vec<_> = series
+        .iter()
+        .enumerate()
+        .map(|(i, point)| {
+            let score = (i >= window).then(|| method.score(&values[i - window..i], point.val));
+            let is_anomaly = score.map(|score| score.abs() > threshold);
+            (point.ts.into(), point.val, score, is_anomaly)
+        })
+        .collect();
+    TableIterator::new(rows.into_iter())
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_anomalies() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            client.select(
+                "CREATE TABLE series AS \
+                SELECT '2020-01-01 UTC'::timestamptz + i * '1 day'::interval AS time, \
+                    (ARRAY[10, 12, 11, 13, 9, 14, 11, 40, 12])[i + 1]::double precision AS value \
+                FROM generate_series(0, 8) i",
+                None,
+                None,
+            );
+
+            for method in ["zscore", "mad"] {
+                let anomalies = client
+                    .select(
+                        &format!(
+                            "SELECT string_agg(time::date::TEXT, ',' ORDER BY time) \
+                            FROM toolkit_experimental.anomalies( \
+                                (SELECT timevector(time, value) FROM series), 5, 3, '{}') \
+                            WHERE is_anomaly",
+                            method
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<String>();
+                assert_eq!(anomalies, Some("2020-01-08".to_string()));
+
+                let anomalies = client
+                    .select(
+                        &format!(
+                            "SELECT string_agg(time::date::TEXT, ',' ORDER BY time) \
+                            FROM toolkit_experimental.anomalies( \
+                                (SELECT timevector(time, value) FROM series), 5, 2, '{}') \
+                            WHERE is_anomaly",
+                            method
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<String>();
+                assert_eq!(anomalies, Some("2020-01-06,2020-01-08".to_string()));
+            }
+
+            // 14 against 10, 12, 11, 13, 9: their mean is 11 and standard
+            // deviation sqrt(2), while their median is 11 and the median of
+            // their distances from it is 1
+            let mut rows = client.select(
+                "SELECT z.score, m.score, z.is_anomaly \
+                FROM toolkit_experimental.anomalies( \
+                    (SELECT timevector(time, value) FROM series), 5) z \
+                JOIN toolkit_experimental.anomalies( \
+                    (SELECT timevector(time, value) FROM series), 5, 3, 'mad') m \
+                ON z.time = m.time \
+                ORDER BY z.time",
+                None,
+                None,
+            );
+            for _ in 0..5 {
+                let row = rows.next().unwrap();
+                assert_eq!(row[1].value::<f64>(), None);
+                assert_eq!(row[2].value::<f64>(), None);
+                assert_eq!(row[3].value::<bool>(), None);
+            }
+            let row = rows.next().unwrap();
+            let zscore = row[1].value::<f64>().unwrap();
+            let mad = row[2].value::<f64>().unwrap();
+            assert!((zscore - 3.0 / 2.0f64.sqrt()).abs() < 1e-9, "{}", zscore);
+            assert!((mad - 3.0 / 1.4826).abs() < 1e-9, "{}", mad);
+            assert_eq!(row[3].value::<bool>(), Some(false));
+        });
+    }
+
+    #[pg_test(error = "Invalid anomaly detection method: iqr")]
+    fn test_anomalies_method() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT * FROM toolkit_experimental.anomalies( \
+                    (SELECT timevector('2020-01-01'::timestamptz, 1.0)), 5, 3, 'iqr')",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test(error = "Unable to detect anomalies in timevector containing NaN values")]
+    fn test_anomalies_nan() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT * FROM toolkit_experimental.anomalies( \
+                    (SELECT timevector('2020-01-01'::timestamptz + i * '1 day'::interval, \
+                        CASE WHEN i = 3 THEN 'NaN'::double precision ELSE i END) \
+                    FROM generate_series(0, 5) i), 2, 3, 'mad')",
+                None,
+                None,
+            );
+        });
+    }
+}
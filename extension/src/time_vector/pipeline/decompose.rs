@@ -0,0 +1,182 @@
+use pgx::{iter::TableIterator, *};
+
+use super::*;
+
+/// Splits a sorted timevector into a trend, a seasonal component repeating
+/// every `period` points, and the residual left over, each of which add up to
+/// the points.  The trend is a moving average centered on each point, and so
+/// is missing for the first and last half period of points, as are their
+/// residuals.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn decompose<'a>(
+    series: Timevector_TSTZ_F64<'a>,
+    period: i32,
+) -> TableIterator<
+    'a,
+    (
+        name!(time, crate::raw::TimestampTz),
+        name!(value, f64),
+        name!(trend, Option<f64>),
+        name!(seasonal, f64),
+        name!(residual, Option<f64>),
+    ),
+> {
+    if period < 2 {
+        pgx::error!("decompose requires a period of at least 2 points")
+    }
+    if !series.is_sorted() {
+        panic!("can only decompose sorted timevector");
+    }
+    if series.has_nulls() {
+        panic!("Unable to decompose timevector containing nulls");
+    }
+
+    let period = period as usize;
+    let values: Vec<f64> = series.iter().map(|p| p.val).collect();
+    if values.len() < 2 * period {
+        pgx::error!("decompose needs at least two periods of points")
+    }
+    let (trend, seasonal) = seasonal_decomposition(&values, period);
+
+    let rows: Vec<_> = series
+        .iter()
+        .zip(trend)
+        .enumerate()
+        .map(|(i, (point, trend))| {
+            let seasonal = seasonal[i % period];
+            let residual = trend.map(|trend| point.val - trend - seasonal);
+            (point.ts.into(), point.val, trend, seasonal, residual)
+        })
+        .collect();
+    TableIterator::new(rows.into_iter())
+}
+
+/// Classical additive decomposition: the trend of each point with a full
+/// period around it, and the seasonal component of each point in the period,
+/// counting from the first point, which sum to zero over the period.
+pub fn seasonal_decomposition(values: &[f64], period: usize) -> (Vec<Option<f64>>, Vec<f64>) {
+    let half = period / 2;
+    let mut trend = vec![None; values.len()];
+    for (i, trend) in trend
+        .iter_mut()
+        .enumerate()
+        .take(values.len() - half)
+        .skip(half)
+    {
+        let window = &values[i - half..=i + half];
+        let sum: f64 = if period % 2 == 0 {
+            // an even period can't be centered on a point, so the ends of a
+            // window one point longer only count for half
+            window.iter().sum::<f64>() - (window[0] + window[period]) / 2.0
+        } else {
+            window.iter().sum()
+        };
+        *trend = Some(sum / period as f64);
+    }
+
+    let mut sums = vec![0.0; period];
+    let mut counts = vec![0; period];
+    for (i, (value, trend)) in values.iter().zip(&trend).enumerate() {
+        if let Some(trend) = trend {
+            sums[i % period] += value - trend;
+            counts[i % period] += 1;
+        }
+    }
+    let mut seasonal: Vec<f64> = sums
+        .iter()
+        .zip(&counts)
+        .map(|(sum, &count)| sum / count as f64)
+        .collect();
+    let mean = seasonal.iter().sum::<f64>() / period as f64;
+    for s in &mut seasonal {
+        *s -= mean;
+    }
+
+    (trend, seasonal)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_decompose() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // a line rising by 2 a day, plus a season of 4 days
+            client.select(
+                "CREATE TABLE series AS \
+                SELECT '2020-01-01 UTC'::timestamptz + i * '1 day'::interval AS time, \
+                    (2.0 * i + (ARRAY[5.0, -3.0, 1.0, -3.0])[i % 4 + 1])::double precision AS value \
+                FROM generate_series(0, 11) i",
+                None,
+                None,
+            );
+
+            let mut rows = client.select(
+                "SELECT time::TEXT, value, trend, seasonal, residual \
+                FROM toolkit_experimental.decompose( \
+                    (SELECT timevector(time, value) FROM series), 4)",
+                None,
+                None,
+            );
+            let mut next = || {
+                rows.next().map(|row| {
+                    (
+                        row[1].value::<String>().unwrap(),
+                        row[2].value::<f64>().unwrap(),
+                        row[3].value::<f64>(),
+                        row[4].value::<f64>().unwrap(),
+                        row[5].value::<f64>(),
+                    )
+                })
+            };
+            let season = [5.0, -3.0, 1.0, -3.0];
+            for i in 0..12 {
+                let (time, value, trend, seasonal, residual) = next().unwrap();
+                assert_eq!(time, format!("2020-01-{:02} 00:00:00+00", i + 1));
+                assert_eq!(value, 2.0 * i as f64 + season[i % 4]);
+                assert_eq!(seasonal, season[i % 4]);
+                // the trend needs two points either side
+                if (2..10).contains(&i) {
+                    assert_eq!(trend, Some(2.0 * i as f64));
+                    assert_eq!(residual, Some(0.0));
+                } else {
+                    assert_eq!(trend, None);
+                    assert_eq!(residual, None);
+                }
+            }
+            assert_eq!(next(), None);
+
+            // with an odd period the trend is a plain moving average
+            let (trend, seasonal) = client
+                .select(
+                    "SELECT trend, seasonal \
+                    FROM toolkit_experimental.decompose( \
+                        (SELECT timevector(time, value) FROM series), 3) \
+                    WHERE time = '2020-01-02 UTC'",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(trend, Some(3.0));
+            assert!(seasonal.is_some());
+        });
+    }
+
+    #[pg_test(error = "decompose needs at least two periods of points")]
+    fn test_decompose_too_short() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT * FROM toolkit_experimental.decompose( \
+                    (SELECT timevector('2020-01-01 UTC'::timestamptz + i * '1 day'::interval, i) \
+                    FROM generate_series(0, 6) i), 4)",
+                None,
+                None,
+            );
+        });
+    }
+}
@@ -27,24 +27,71 @@ extension_sql!(
     name = "accessor_delta_cast",
 );
 
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "deriv",
+    schema = "toolkit_experimental"
+)]
+pub fn deriv_pipeline_element<'e>() -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    Element::Deriv {}.flatten()
+}
+
 pub fn timevector_delta<'s>(series: &Timevector_TSTZ_F64<'s>) -> Timevector_TSTZ_F64<'s> {
+    timevector_differences(series, "deltas", |prev, pt| pt.val - prev.val)
+}
+
+/// The rate of change per second between each point and the preceding one.
+/// Points at the same time have no rate of change between them.
+pub fn timevector_deriv<'s>(series: &Timevector_TSTZ_F64<'s>) -> Timevector_TSTZ_F64<'s> {
+    timevector_differences(series, "derivatives", |prev, pt| {
+        if pt.ts == prev.ts {
+            pgx::error!(
+                "unable to compute derivatives over timevector with more than one point at {}",
+                crate::datum_utils::timestamptz_to_string(pt.ts)
+            )
+        }
+        (pt.val - prev.val) / ((pt.ts - prev.ts) as f64 / 1_000_000.0)
+    })
+}
+
+fn timevector_differences<'s>(
+    series: &Timevector_TSTZ_F64<'s>,
+    name: &str,
+    difference: impl Fn(&TSPoint, &TSPoint) -> f64,
+) -> Timevector_TSTZ_F64<'s> {
     if !series.is_sorted() {
-        panic!("can only compute deltas for sorted timevector");
+        panic!("can only compute {} for sorted timevector", name);
     }
     if series.has_nulls() {
-        panic!("Unable to compute deltas over timevector containing nulls");
+        panic!(
+            "Unable to compute {} over timevector containing nulls",
+            name
+        );
     }
 
     let mut it = series.iter();
-    let mut prev = it.next().unwrap().val;
+    let mut prev = it.next().unwrap();
     let mut delta_points = Vec::new();
+    let mut quality = Vec::new();
 
-    for pt in it {
+    for (i, pt) in it.enumerate() {
         delta_points.push(TSPoint {
             ts: pt.ts,
-            val: pt.val - prev,
+            val: difference(&prev, &pt),
         });
-        prev = pt.val;
+        prev = pt;
+        if series.has_quality() {
+            // a delta is only as good as the worse of the two points it's
+            // computed from
+            let current = series.quality(i + 1);
+            if current != QUALITY_GOOD {
+                quality.push(current);
+            } else {
+                quality.push(series.quality(i));
+            }
+        }
     }
 
     let nulls_len = (delta_points.len() + 7) / 8;
@@ -55,6 +102,7 @@ pub fn timevector_delta<'s>(series: &Timevector_TSTZ_F64<'s>) -> Timevector_TSTZ
         internal_padding: [0; 3],
         points: delta_points.into(),
         null_val: std::vec::from_elem(0_u8, nulls_len).into(),
+        quality: quality.into(),
     })
 }
 
@@ -125,4 +173,61 @@ mod tests {
             );
         });
     }
+
+    #[pg_test]
+    fn test_pipeline_deriv() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-01 00:00:00 UTC'::TIMESTAMPTZ, 0.0), \
+                    ('2020-01-01 00:00:10 UTC'::TIMESTAMPTZ, 50.0), \
+                    ('2020-01-01 00:00:30 UTC'::TIMESTAMPTZ, 30.0), \
+                    ('2020-01-01 00:01:30 UTC'::TIMESTAMPTZ, 330.0)",
+                None,
+                None,
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) \
+                        -> toolkit_experimental.deriv())::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:3,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:10+00\",val:5),\
+                (ts:\"2020-01-01 00:00:30+00\",val:-1),\
+                (ts:\"2020-01-01 00:01:30+00\",val:5)\
+            ],null_val:[0])"
+            );
+        });
+    }
+    #[pg_test(
+        error = "unable to compute derivatives over timevector with more than one point at 2020-01-01 00:00:10+00"
+    )]
+    fn test_pipeline_deriv_repeated_time() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            client.select(
+                "SELECT (timevector(time, value) -> toolkit_experimental.deriv())::TEXT \
+                FROM (VALUES \
+                    ('2020-01-01 00:00:00 UTC'::TIMESTAMPTZ, 0.0), \
+                    ('2020-01-01 00:00:10 UTC'::TIMESTAMPTZ, 50.0), \
+                    ('2020-01-01 00:00:10 UTC'::TIMESTAMPTZ, 60.0)) series(time, value)",
+                None,
+                None,
+            );
+        });
+    }
 }
@@ -13,6 +13,7 @@ pub enum FillToMethod {
     Locf,
     Interpolate,
     Nearest,
+    Zero,
 }
 
 impl FillToMethod {
@@ -44,6 +45,10 @@ impl FillToMethod {
                     }
                 }
             }
+            FillToMethod::Zero => TSPoint {
+                ts: target_ts,
+                val: 0.0,
+            },
         }
     }
 }
@@ -71,6 +76,7 @@ pub fn fillto_pipeline_element<'e>(
             "interpolate" => FillToMethod::Interpolate,
             "linear" => FillToMethod::Interpolate,
             "nearest" => FillToMethod::Nearest,
+            "zero" => FillToMethod::Zero,
             _ => panic!("Invalid fill method"),
         };
 
@@ -103,15 +109,18 @@ pub fn fill_to<'s>(
         panic!("Fill_to requires a timevector to not have NULL values")
     }
 
+    // filled points take the quality of the point they're filled from
+    let quality_of = |i: usize| series.quality(i);
+
     let mut result = vec![];
-    let mut it = series.iter().peekable();
+    let mut it = series.iter().enumerate().peekable();
     let mut current = it.next();
 
-    while let (Some(lhs), Some(rhs)) = (current, it.peek()) {
+    while let (Some((i, lhs)), Some((_, rhs))) = (current, it.peek()) {
         if rhs.ts - lhs.ts > interval {
             let mut target = lhs.ts + interval;
             while target < rhs.ts {
-                result.push(method.fill_point(&lhs, rhs, target));
+                result.push((method.fill_point(&lhs, rhs, target), quality_of(i)));
                 target += interval;
             }
         }
@@ -123,8 +132,19 @@ pub fn fill_to<'s>(
         return series;
     }
 
-    let mut result: Vec<TSPoint> = series.iter().chain(result.into_iter()).collect();
-    result.sort_by_key(|p| p.ts);
+    let mut result: Vec<(TSPoint, u8)> = series
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (p, quality_of(i)))
+        .chain(result.into_iter())
+        .collect();
+    result.sort_by_key(|(p, _)| p.ts);
+    let (result, quality): (Vec<TSPoint>, Vec<u8>) = result.into_iter().unzip();
+    let quality = if series.has_quality() {
+        quality
+    } else {
+        vec![]
+    };
 
     let nulls_len = (result.len() + 7) / 8;
     build! {
@@ -134,6 +154,7 @@ pub fn fill_to<'s>(
             internal_padding: [0; 3],
             points: result.into(),
             null_val: std::vec::from_elem(0_u8, nulls_len).into(),
+            quality: quality.into(),
         }
     }
 }
@@ -244,6 +265,28 @@ mod tests {
             ],null_val:[0,0])"
             );
 
+            let val = client.select(
+                "SELECT (timevector(time, value) -> fill_to('24 hours', 'zero'))::TEXT FROM series",
+                None,
+                None
+            )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:9,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-02 00:00:00+00\",val:0),\
+                (ts:\"2020-01-03 00:00:00+00\",val:20),\
+                (ts:\"2020-01-04 00:00:00+00\",val:90),\
+                (ts:\"2020-01-05 00:00:00+00\",val:0),\
+                (ts:\"2020-01-06 00:00:00+00\",val:30),\
+                (ts:\"2020-01-07 00:00:00+00\",val:0),\
+                (ts:\"2020-01-08 00:00:00+00\",val:0),\
+                (ts:\"2020-01-09 00:00:00+00\",val:40)\
+            ],null_val:[0,0])"
+            );
+
             let val = client.select(
                 "SELECT (timevector(time, value) -> fill_to('10 hours', 'nearest'))::TEXT FROM series",
                 None,
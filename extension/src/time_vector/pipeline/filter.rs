@@ -23,6 +23,21 @@ pub fn filter_lambda_pipeline_element<'l, 'e>(
     .flatten()
 }
 
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "filter_quality",
+    schema = "toolkit_experimental"
+)]
+pub fn filter_quality_pipeline_element<'e>(
+    good_only: default!(bool, true),
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    Element::FilterQuality {
+        good_only: good_only as u64,
+    }
+    .flatten()
+}
+
 pub fn apply_lambda_to<'a>(
     mut series: Timevector_TSTZ_F64<'a>,
     lambda: &lambda::LambdaData<'_>,
@@ -52,7 +67,43 @@ pub fn filter_lambda_over_series(
     series: &mut Timevector_TSTZ_F64<'_>,
     mut func: impl FnMut(i64, f64) -> bool,
 ) {
-    series.points.as_owned().retain(|p| func(p.ts, p.val));
+    retain_points(series, |p, _| func(p.ts, p.val));
+}
+
+// Keeps only the good points if `good_only`, otherwise only the points with
+// a quality problem
+pub fn filter_quality(
+    mut series: Timevector_TSTZ_F64<'_>,
+    good_only: bool,
+) -> Timevector_TSTZ_F64<'_> {
+    retain_points(&mut series, |_, quality| {
+        (quality == QUALITY_GOOD) == good_only
+    });
+    series
+}
+
+// Keeps the points, along with their quality codes, for which `keep` returns
+// true
+fn retain_points(series: &mut Timevector_TSTZ_F64<'_>, mut keep: impl FnMut(&TSPoint, u8) -> bool) {
+    let kept: Vec<bool> = series
+        .points
+        .as_slice()
+        .iter()
+        .enumerate()
+        .map(|(i, p)| keep(p, series.quality(i)))
+        .collect();
+    let mut is_kept = kept.iter();
+    series
+        .points
+        .as_owned()
+        .retain(|_| *is_kept.next().unwrap());
+    if series.has_quality() {
+        let mut is_kept = kept.iter();
+        series
+            .quality
+            .as_owned()
+            .retain(|_| *is_kept.next().unwrap());
+    }
     series.num_points = series.points.len() as _;
 }
 
@@ -131,4 +182,91 @@ mod tests {
             );
         });
     }
+
+    #[pg_test]
+    fn test_pipeline_filter_quality() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision, quality smallint)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 25.0, 0), \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 10.0, 0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 20.0, 64), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 15.0, 0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 30.0, 192)",
+                None,
+                None,
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value, quality) -> sort() -> filter_quality())::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:3,flags:5,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:10),\
+                (ts:\"2020-01-02 00:00:00+00\",val:15),\
+                (ts:\"2020-01-04 00:00:00+00\",val:25)\
+            ],null_val:[0],quality:[0,0,0])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value, quality) -> sort() -> filter_quality(false))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:2,flags:5,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-03 00:00:00+00\",val:20),\
+                (ts:\"2020-01-05 00:00:00+00\",val:30)\
+            ],null_val:[0],quality:[64,192])"
+            );
+
+            // filters other than filter_quality keep each point's quality
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value, quality) -> filter($$ $value > 15 $$))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:3,flags:4,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-04 00:00:00+00\",val:25),\
+                (ts:\"2020-01-03 00:00:00+00\",val:20),\
+                (ts:\"2020-01-05 00:00:00+00\",val:30)\
+            ],null_val:[0],quality:[0,64,192])"
+            );
+        });
+    }
 }
@@ -0,0 +1,391 @@
+use pgx::{iter::TableIterator, *};
+
+use super::*;
+
+use super::moving_average::{
+    exponential_moving_average, simple_moving_average, with_trailing_values,
+};
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "rsi",
+    schema = "toolkit_experimental"
+)]
+pub fn rsi_pipeline_element<'e>(
+    period: default!(i32, 14),
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    if period < 1 {
+        pgx::error!("rsi requires a period of at least 1 point")
+    }
+    Element::RSI {
+        period: period as u64,
+    }
+    .flatten()
+}
+
+pub fn timevector_rsi<'s>(
+    series: &Timevector_TSTZ_F64<'s>,
+    period: usize,
+) -> Timevector_TSTZ_F64<'s> {
+    with_trailing_values(
+        series,
+        "a relative strength index",
+        period,
+        relative_strength_index,
+    )
+}
+
+/// Wilder's relative strength index.  The average gain and loss start as the
+/// means over the first `period` changes, so the first index is at
+/// `values[period]`.
+pub fn relative_strength_index(values: &[f64], period: usize) -> Vec<f64> {
+    if values.len() <= period {
+        return vec![];
+    }
+    let changes: Vec<f64> = values.windows(2).map(|w| w[1] - w[0]).collect();
+    let gain = |change: f64| change.max(0.0);
+    let loss = |change: f64| (-change).max(0.0);
+    let index = |gain: f64, loss: f64| {
+        if loss == 0.0 {
+            // a series that doesn't move is neither overbought nor oversold
+            if gain == 0.0 {
+                50.0
+            } else {
+                100.0
+            }
+        } else {
+            100.0 - 100.0 / (1.0 + gain / loss)
+        }
+    };
+
+    let period_f = period as f64;
+    let mut avg_gain = changes[..period].iter().map(|&c| gain(c)).sum::<f64>() / period_f;
+    let mut avg_loss = changes[..period].iter().map(|&c| loss(c)).sum::<f64>() / period_f;
+    let mut indices = Vec::with_capacity(changes.len() - period + 1);
+    indices.push(index(avg_gain, avg_loss));
+    for &change in &changes[period..] {
+        avg_gain = (avg_gain * (period_f - 1.0) + gain(change)) / period_f;
+        avg_loss = (avg_loss * (period_f - 1.0) + loss(change)) / period_f;
+        indices.push(index(avg_gain, avg_loss));
+    }
+    indices
+}
+
+/// The moving average convergence/divergence of a sorted timevector: the
+/// difference between its `fast` and `slow` exponential moving averages, the
+/// `signal`-span exponential moving average of that difference, and the gap
+/// between the two.  Rows start once all three are available.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn macd<'a>(
+    series: Timevector_TSTZ_F64<'a>,
+    fast: default!(i32, 12),
+    slow: default!(i32, 26),
+    signal: default!(i32, 9),
+) -> TableIterator<
+    'a,
+    (
+        name!(time, crate::raw::TimestampTz),
+        name!(macd, f64),
+        name!(signal, f64),
+        name!(histogram, f64),
+    ),
+> {
+    if fast < 1 || signal < 1 {
+        pgx::error!("macd requires spans of at least 1 point")
+    }
+    if fast >= slow {
+        pgx::error!("macd requires the fast span to be shorter than the slow span")
+    }
+    if !series.is_sorted() {
+        panic!("can only compute macd for sorted timevector");
+    }
+    if series.has_nulls() {
+        panic!("Unable to compute macd over timevector containing nulls");
+    }
+
+    let values: Vec<f64> = series.iter().map(|p| p.val).collect();
+    let fast = exponential_moving_average(&values, fast as usize);
+    let slow = exponential_moving_average(&values, slow as usize);
+    let macd: Vec<f64> = fast[fast.len() - slow.len()..]
+        .iter()
+        .zip(&slow)
+        .map(|(fast, slow)| fast - slow)
+        .collect();
+    let signal = exponential_moving_average(&macd, signal as usize);
+
+    let skipped = values.len() - signal.len();
+    let rows: Vec<_> = series
+        .iter()
+        .skip(skipped)
+        .zip(&macd[macd.len() - signal.len()..])
+        .zip(signal)
+        .map(|((point, &macd), signal)| (point.ts.into(), macd, signal, macd - signal))
+        .collect();
+    TableIterator::new(rows.into_iter())
+}
+
+/// Bollinger bands over a sorted timevector: the mean of each `window`
+/// consecutive points, and the bands `num_stddev` population standard
+/// deviations of the window above and below it.
+#[pg_extern(immutable, parallel_safe, schema = "toolkit_experimental")]
+pub fn bollinger<'a>(
+    series: Timevector_TSTZ_F64<'a>,
+    window: default!(i32, 20),
+    num_stddev: default!(f64, 2.0),
+) -> TableIterator<
+    'a,
+    (
+        name!(time, crate::raw::TimestampTz),
+        name!(middle, f64),
+        name!(upper, f64),
+        name!(lower, f64),
+    ),
+> {
+    if window < 1 {
+        pgx::error!("bollinger requires a window of at least 1 point")
+    }
+    if !series.is_sorted() {
+        panic!("can only compute bollinger bands for sorted timevector");
+    }
+    if series.has_nulls() {
+        panic!("Unable to compute bollinger bands over timevector containing nulls");
+    }
+
+    let window = window as usize;
+    let values: Vec<f64> = series.iter().map(|p| p.val).collect();
+    let means = simple_moving_average(&values, window);
+    let skipped = values.len() - means.len();
+    let rows: Vec<_> = series
+        .iter()
+        .skip(skipped)
+        .zip(values.windows(window).zip(means))
+        .map(|(point, (values, mean))| {
+            let variance =
+                values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / window as f64;
+            let width = num_stddev * variance.sqrt();
+            (point.ts.into(), mean, mean + width, mean - width)
+        })
+        .collect();
+    TableIterator::new(rows.into_iter())
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_pipeline_rsi() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 1.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 2.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 4.0), \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 3.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 5.0)",
+                None,
+                None,
+            );
+
+            // gains average 1.5 with no losses, then -1 and +2 are smoothed in
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> rsi(2))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:3,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-03 00:00:00+00\",val:100),\
+                (ts:\"2020-01-04 00:00:00+00\",val:60),\
+                (ts:\"2020-01-05 00:00:00+00\",val:84.61538461538461)\
+            ],null_val:[0])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> rsi())::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:0,flags:1,internal_padding:(0,0,0),points:[],null_val:[])"
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_macd() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 2.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 4.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 6.0), \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 12.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 0.0), \
+                    ('2020-01-06 UTC'::TIMESTAMPTZ, 8.0)",
+                None,
+                None,
+            );
+
+            // the macd line is 2, 4, -4, 2 from the third point on, so the
+            // signal line starts at their first two's mean on the fourth
+            let mut rows = client.select(
+                "SELECT time::TEXT, macd, signal, histogram \
+                FROM toolkit_experimental.macd( \
+                    (SELECT timevector(time, value) FROM series), 1, 3, 2)",
+                None,
+                None,
+            );
+            let mut next = || {
+                rows.next().map(|row| {
+                    (
+                        row[1].value::<String>().unwrap(),
+                        row[2].value::<f64>().unwrap(),
+                        row[3].value::<f64>().unwrap(),
+                        row[4].value::<f64>().unwrap(),
+                    )
+                })
+            };
+            assert_eq!(
+                next(),
+                Some(("2020-01-04 00:00:00+00".to_string(), 4.0, 3.0, 1.0))
+            );
+            assert_eq!(
+                next(),
+                Some((
+                    "2020-01-05 00:00:00+00".to_string(),
+                    -4.0,
+                    -1.666666666666666,
+                    -2.333333333333334
+                ))
+            );
+            assert_eq!(
+                next(),
+                Some((
+                    "2020-01-06 00:00:00+00".to_string(),
+                    2.0,
+                    0.7777777777777777,
+                    1.2222222222222223
+                ))
+            );
+            assert_eq!(next(), None);
+        });
+    }
+
+    #[pg_test(error = "macd requires the fast span to be shorter than the slow span")]
+    fn test_macd_spans() {
+        Spi::execute(|client| {
+            client.select(
+                "SELECT * FROM toolkit_experimental.macd( \
+                    (SELECT timevector('2020-01-01'::timestamptz, 1.0)), 26, 12)",
+                None,
+                None,
+            );
+        });
+    }
+
+    #[pg_test]
+    fn test_bollinger() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 2.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 4.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 6.0), \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 12.0)",
+                None,
+                None,
+            );
+
+            let mut rows = client.select(
+                "SELECT time::TEXT, middle, upper, lower \
+                FROM toolkit_experimental.bollinger( \
+                    (SELECT timevector(time, value) FROM series), 2)",
+                None,
+                None,
+            );
+            let mut next = || {
+                rows.next().map(|row| {
+                    (
+                        row[1].value::<String>().unwrap(),
+                        row[2].value::<f64>().unwrap(),
+                        row[3].value::<f64>().unwrap(),
+                        row[4].value::<f64>().unwrap(),
+                    )
+                })
+            };
+            assert_eq!(
+                next(),
+                Some(("2020-01-02 00:00:00+00".to_string(), 3.0, 5.0, 1.0))
+            );
+            assert_eq!(
+                next(),
+                Some(("2020-01-03 00:00:00+00".to_string(), 5.0, 7.0, 3.0))
+            );
+            assert_eq!(
+                next(),
+                Some(("2020-01-04 00:00:00+00".to_string(), 9.0, 15.0, 3.0))
+            );
+            assert_eq!(next(), None);
+
+            // with no width the bands collapse onto the mean
+            let upper = client
+                .select(
+                    "SELECT upper \
+                    FROM toolkit_experimental.bollinger( \
+                        (SELECT timevector(time, value) FROM series), 4, 0)",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<f64>();
+            assert_eq!(upper, Some(6.0));
+        });
+    }
+}
@@ -0,0 +1,226 @@
+use pgx::*;
+
+use super::*;
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "sma",
+    schema = "toolkit_experimental"
+)]
+pub fn sma_pipeline_element<'e>(
+    window: i32,
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    if window < 1 {
+        pgx::error!("sma requires a window of at least 1 point")
+    }
+    Element::SMA {
+        window: window as u64,
+    }
+    .flatten()
+}
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "ema",
+    schema = "toolkit_experimental"
+)]
+pub fn ema_pipeline_element<'e>(span: i32) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    if span < 1 {
+        pgx::error!("ema requires a span of at least 1 point")
+    }
+    Element::EMA { span: span as u64 }.flatten()
+}
+
+/// The mean of each `window` consecutive values.  The first mean is that of
+/// `values[..window]`, so there are `window - 1` fewer means than values.
+pub fn simple_moving_average(values: &[f64], window: usize) -> Vec<f64> {
+    if values.len() < window {
+        return vec![];
+    }
+    let mut sum: f64 = values[..window].iter().sum();
+    let mut means = Vec::with_capacity(values.len() - window + 1);
+    means.push(sum / window as f64);
+    for i in window..values.len() {
+        sum += values[i] - values[i - window];
+        means.push(sum / window as f64);
+    }
+    means
+}
+
+/// The exponential moving average with smoothing factor `2 / (span + 1)`.
+/// Like the simple moving average, it starts from the mean of the first
+/// `span` values, so there are `span - 1` fewer averages than values.
+pub fn exponential_moving_average(values: &[f64], span: usize) -> Vec<f64> {
+    if values.len() < span {
+        return vec![];
+    }
+    let alpha = 2.0 / (span as f64 + 1.0);
+    let mut average = values[..span].iter().sum::<f64>() / span as f64;
+    let mut averages = Vec::with_capacity(values.len() - span + 1);
+    averages.push(average);
+    for value in &values[span..] {
+        average += alpha * (value - average);
+        averages.push(average);
+    }
+    averages
+}
+
+pub fn timevector_sma<'s>(
+    series: &Timevector_TSTZ_F64<'s>,
+    window: usize,
+) -> Timevector_TSTZ_F64<'s> {
+    with_trailing_values(series, "a moving average", window, simple_moving_average)
+}
+
+pub fn timevector_ema<'s>(
+    series: &Timevector_TSTZ_F64<'s>,
+    span: usize,
+) -> Timevector_TSTZ_F64<'s> {
+    with_trailing_values(
+        series,
+        "an exponential moving average",
+        span,
+        exponential_moving_average,
+    )
+}
+
+// Replaces the values of the points from the `n`th on with those computed by
+// `compute`, dropping the first `n - 1` points.  Each point keeps its quality.
+pub(super) fn with_trailing_values<'s>(
+    series: &Timevector_TSTZ_F64<'s>,
+    name: &str,
+    n: usize,
+    compute: impl Fn(&[f64], usize) -> Vec<f64>,
+) -> Timevector_TSTZ_F64<'s> {
+    if !series.is_sorted() {
+        panic!("can only compute {} for sorted timevector", name);
+    }
+    if series.has_nulls() {
+        panic!(
+            "Unable to compute {} over timevector containing nulls",
+            name
+        );
+    }
+
+    let values: Vec<f64> = series.iter().map(|p| p.val).collect();
+    let computed = compute(&values, n);
+    let skipped = values.len() - computed.len();
+    let points: Vec<TSPoint> = series
+        .iter()
+        .skip(skipped)
+        .zip(computed)
+        .map(|(p, val)| TSPoint { ts: p.ts, val })
+        .collect();
+    let quality: Vec<u8> = if series.has_quality() {
+        (skipped..values.len()).map(|i| series.quality(i)).collect()
+    } else {
+        vec![]
+    };
+
+    let nulls_len = (points.len() + 7) / 8;
+    build!(Timevector_TSTZ_F64 {
+        num_points: points.len() as u32,
+        flags: series.flags,
+        internal_padding: [0; 3],
+        points: points.into(),
+        null_val: std::vec::from_elem(0_u8, nulls_len).into(),
+        quality: quality.into(),
+    })
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_pipeline_moving_averages() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-01 UTC'::TIMESTAMPTZ, 2.0), \
+                    ('2020-01-02 UTC'::TIMESTAMPTZ, 4.0), \
+                    ('2020-01-03 UTC'::TIMESTAMPTZ, 6.0), \
+                    ('2020-01-04 UTC'::TIMESTAMPTZ, 12.0), \
+                    ('2020-01-05 UTC'::TIMESTAMPTZ, 0.0)",
+                None,
+                None,
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> sma(3))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:3,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-03 00:00:00+00\",val:4),\
+                (ts:\"2020-01-04 00:00:00+00\",val:7.333333333333333),\
+                (ts:\"2020-01-05 00:00:00+00\",val:6)\
+            ],null_val:[0])"
+            );
+
+            // starts from the mean of the first 3 values, then each step
+            // moves halfway to the next value
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> ema(3))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:3,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-03 00:00:00+00\",val:4),\
+                (ts:\"2020-01-04 00:00:00+00\",val:8),\
+                (ts:\"2020-01-05 00:00:00+00\",val:4)\
+            ],null_val:[0])"
+            );
+
+            // too short for a single average
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> sma(6))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:0,flags:1,internal_padding:(0,0,0),points:[],null_val:[])"
+            );
+        });
+    }
+}
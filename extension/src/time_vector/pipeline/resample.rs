@@ -0,0 +1,259 @@
+use pgx::*;
+
+use flat_serialize_macro::FlatSerializable;
+
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug, FlatSerializable)]
+#[repr(u64)]
+pub enum ResampleMethod {
+    Average,
+    Sum,
+    Min,
+    Max,
+}
+
+impl ResampleMethod {
+    fn combine(&self, values: &[f64]) -> f64 {
+        match *self {
+            ResampleMethod::Average => values.iter().sum::<f64>() / values.len() as f64,
+            ResampleMethod::Sum => values.iter().sum(),
+            ResampleMethod::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+            ResampleMethod::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        }
+    }
+}
+
+// TODO is (immutable, parallel_safe) correct?
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "resample_to_rate",
+    schema = "toolkit_experimental"
+)]
+pub fn resample_to_rate_pipeline_element<'e>(
+    method: String,
+    interval: crate::raw::Interval,
+    snap_to_rate: default!(bool, false),
+) -> toolkit_experimental::UnstableTimevectorPipeline<'e> {
+    unsafe {
+        let interval = interval.0.cast_mut_ptr::<pg_sys::Interval>() as *const pg_sys::Interval;
+        // TODO: store the postgres interval object and use postgres timestamp/interval functions
+        let interval =
+            ((*interval).month as i64 * 30 + (*interval).day as i64) * 24 * 60 * 60 * 1000000
+                + (*interval).time;
+        if interval <= 0 {
+            pgx::error!("resample_to_rate requires a positive interval")
+        }
+
+        let method = match method.to_lowercase().as_str() {
+            "average" | "avg" => ResampleMethod::Average,
+            "sum" => ResampleMethod::Sum,
+            "min" => ResampleMethod::Min,
+            "max" => ResampleMethod::Max,
+            _ => pgx::error!("Invalid resample method: {}", method),
+        };
+
+        Element::ResampleToRate {
+            interval,
+            method,
+            snap_to_rate: snap_to_rate as u64,
+        }
+        .flatten()
+    }
+}
+
+/// Replaces the points in each `interval`-long bucket with a single point at
+/// the start of the bucket.  Buckets start at multiples of `interval` from the
+/// epoch if `snap_to_rate`, otherwise from the first point.
+pub fn resample_to_rate<'s>(
+    series: Timevector_TSTZ_F64<'s>,
+    element: &toolkit_experimental::Element,
+) -> Timevector_TSTZ_F64<'s> {
+    let (interval, method, snap_to_rate) = match element {
+        Element::ResampleToRate {
+            interval,
+            method,
+            snap_to_rate,
+        } => (*interval, *method, *snap_to_rate != 0),
+        _ => unreachable!(),
+    };
+
+    if !series.is_sorted() {
+        panic!("Timevector must be sorted prior to passing to resample_to_rate")
+    }
+
+    if series.has_nulls() {
+        panic!("resample_to_rate requires a timevector to not have NULL values")
+    }
+
+    if series.num_points == 0 {
+        return series;
+    }
+
+    let origin = if snap_to_rate {
+        0
+    } else {
+        series.points.as_slice()[0].ts
+    };
+    let bucket_of = |ts: i64| origin + (ts - origin).div_euclid(interval) * interval;
+
+    let mut points = vec![];
+    let mut quality = vec![];
+    let mut values = vec![];
+    let mut bucket_quality = QUALITY_GOOD;
+    let mut bucket = None;
+    for (i, point) in series.iter().enumerate() {
+        let point_bucket = bucket_of(point.ts);
+        if let Some(bucket) = bucket.filter(|&bucket| bucket != point_bucket) {
+            points.push(TSPoint {
+                ts: bucket,
+                val: method.combine(&values),
+            });
+            quality.push(bucket_quality);
+            values.clear();
+            bucket_quality = QUALITY_GOOD;
+        }
+        bucket = Some(point_bucket);
+        values.push(point.val);
+        // a resampled point takes the first quality problem of its inputs
+        if bucket_quality == QUALITY_GOOD {
+            bucket_quality = series.quality(i);
+        }
+    }
+    points.push(TSPoint {
+        ts: bucket.unwrap(),
+        val: method.combine(&values),
+    });
+    quality.push(bucket_quality);
+
+    let quality = if series.has_quality() {
+        quality
+    } else {
+        vec![]
+    };
+
+    let nulls_len = (points.len() + 7) / 8;
+    build! {
+        Timevector_TSTZ_F64 {
+            num_points: points.len() as _,
+            flags: series.flags,
+            internal_padding: [0; 3],
+            points: points.into(),
+            null_val: std::vec::from_elem(0_u8, nulls_len).into(),
+            quality: quality.into(),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_pipeline_resample_to_rate() {
+        Spi::execute(|client| {
+            client.select("SET timezone TO 'UTC'", None, None);
+            // using the search path trick for this test b/c the operator is
+            // difficult to spot otherwise.
+            let sp = client
+                .select(
+                    "SELECT format(' %s, toolkit_experimental',current_setting('search_path'))",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>()
+                .unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", sp), None, None);
+
+            client.select(
+                "CREATE TABLE series(time timestamptz, value double precision)",
+                None,
+                None,
+            );
+            client.select(
+                "INSERT INTO series \
+                    VALUES \
+                    ('2020-01-01 00:30 UTC'::TIMESTAMPTZ, 1.0), \
+                    ('2020-01-01 00:50 UTC'::TIMESTAMPTZ, 2.0), \
+                    ('2020-01-01 01:10 UTC'::TIMESTAMPTZ, 3.0), \
+                    ('2020-01-01 01:40 UTC'::TIMESTAMPTZ, 4.0), \
+                    ('2020-01-01 02:20 UTC'::TIMESTAMPTZ, 5.0)",
+                None,
+                None,
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> resample_to_rate('avg', '1 hour', true))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:3,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:1.5),\
+                (ts:\"2020-01-01 01:00:00+00\",val:3.5),\
+                (ts:\"2020-01-01 02:00:00+00\",val:5)\
+            ],null_val:[0])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> resample_to_rate('min', '1 hour', true))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:3,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:00:00+00\",val:1),\
+                (ts:\"2020-01-01 01:00:00+00\",val:3),\
+                (ts:\"2020-01-01 02:00:00+00\",val:5)\
+            ],null_val:[0])"
+            );
+
+            // without snapping, the buckets start from the first point
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> resample_to_rate('sum', '1 hour'))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:2,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:30:00+00\",val:6),\
+                (ts:\"2020-01-01 01:30:00+00\",val:9)\
+            ],null_val:[0])"
+            );
+
+            let val = client
+                .select(
+                    "SELECT (timevector(time, value) -> resample_to_rate('max', '1 hour'))::TEXT FROM series",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<String>();
+            assert_eq!(
+                val.unwrap(),
+                "(version:1,num_points:2,flags:1,internal_padding:(0,0,0),points:[\
+                (ts:\"2020-01-01 00:30:00+00\",val:3),\
+                (ts:\"2020-01-01 01:30:00+00\",val:5)\
+            ],null_val:[0])"
+            );
+        });
+    }
+}
@@ -18,12 +18,12 @@ pub fn sort_timevector(mut series: Timevector_TSTZ_F64<'_>) -> Timevector_TSTZ_F
         return series;
     }
 
-    let (points, null_val) = if !series.has_nulls() {
+    let (points, null_val, quality) = if !series.has_nulls() && !series.has_quality() {
         // easy case
         let mut points = std::mem::take(series.points.as_owned());
         points.sort_by(|a, b| a.ts.cmp(&b.ts));
         let nulls_len = (points.len() + 7) / 8;
-        (points, std::vec::from_elem(0_u8, nulls_len))
+        (points, std::vec::from_elem(0_u8, nulls_len), vec![])
     } else {
         let mut points: Vec<(usize, TSPoint)> = std::mem::take(series.points.as_owned())
             .into_iter()
@@ -31,6 +31,7 @@ pub fn sort_timevector(mut series: Timevector_TSTZ_F64<'_>) -> Timevector_TSTZ_F
             .collect();
         points.sort_by(|(_, a), (_, b)| a.ts.cmp(&b.ts));
         let mut null_val = std::vec::from_elem(0_u8, (points.len() + 7) / 8);
+        let mut quality = Vec::new();
         let points = points
             .into_iter()
             .enumerate()
@@ -38,10 +39,13 @@ pub fn sort_timevector(mut series: Timevector_TSTZ_F64<'_>) -> Timevector_TSTZ_F
                 if series.is_null_val(old_idx) {
                     null_val[new_idx / 8] |= 1 << (new_idx % 8);
                 }
+                if series.has_quality() {
+                    quality.push(series.quality(old_idx));
+                }
                 ts
             })
             .collect();
-        (points, null_val)
+        (points, null_val, quality)
     };
 
     Timevector_TSTZ_F64Data {
@@ -53,6 +57,7 @@ pub fn sort_timevector(mut series: Timevector_TSTZ_F64<'_>) -> Timevector_TSTZ_F
         internal_padding: [0; 3],
         points: points.into(),
         null_val: null_val.into(),
+        quality: quality.into(),
     }
     .into()
 }
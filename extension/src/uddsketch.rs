@@ -582,6 +582,21 @@ pub fn uddsketch_approx_percentile<'a>(percentile: f64, sketch: UddSketch<'a>) -
     )
 }
 
+// The value above which roughly `target_exceed_rate` (0.0-1.0) of the values
+// in the sketch fall, e.g. for setting alert thresholds.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "threshold_for_rate",
+    schema = "toolkit_experimental"
+)]
+pub fn uddsketch_threshold_for_rate<'a>(sketch: UddSketch<'a>, target_exceed_rate: f64) -> f64 {
+    if !(0.0..=1.0).contains(&target_exceed_rate) {
+        pgx::error!("target_exceed_rate must be between 0 and 1")
+    }
+    uddsketch_approx_percentile(1.0 - target_exceed_rate, sketch)
+}
+
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_uddsketch_approx_rank<'a>(
@@ -602,6 +617,32 @@ pub fn uddsketch_approx_percentile_rank<'a>(value: f64, sketch: UddSketch<'a>) -
     )
 }
 
+// Estimated number of values in the sketch greater than `threshold`.  This is
+// subject to the same error as `approx_percentile_rank`, scaled by the number
+// of values in the sketch.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "approx_count_above",
+    schema = "toolkit_experimental"
+)]
+pub fn uddsketch_approx_count_above<'a>(sketch: UddSketch<'a>, threshold: f64) -> f64 {
+    let count = sketch.count as f64;
+    count * uddsketch_approx_rate_above(sketch, threshold)
+}
+
+// Estimated fraction (0.0-1.0) of the values in the sketch greater than
+// `threshold`.
+#[pg_extern(
+    immutable,
+    parallel_safe,
+    name = "approx_rate_above",
+    schema = "toolkit_experimental"
+)]
+pub fn uddsketch_approx_rate_above<'a>(sketch: UddSketch<'a>, threshold: f64) -> f64 {
+    1.0 - uddsketch_approx_percentile_rank(threshold, sketch)
+}
+
 #[pg_operator(immutable, parallel_safe)]
 #[opname(->)]
 pub fn arrow_uddsketch_num_vals<'a>(sketch: UddSketch<'a>, _accessor: AccessorNumVals<'a>) -> f64 {
@@ -1035,4 +1076,82 @@ mod tests {
             assert_eq!(output, None)
         })
     }
+
+    #[pg_test]
+    fn test_udd_threshold_for_rate() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test (data DOUBLE PRECISION)", None, None);
+            client.select(
+                "INSERT INTO test SELECT generate_series(0.01, 100, 0.01)",
+                None,
+                None,
+            );
+
+            let (threshold, percentile) = client
+                .select(
+                    "SELECT \
+                        toolkit_experimental.threshold_for_rate(sketch, 0.005), \
+                        approx_percentile(0.995, sketch) \
+                    FROM (SELECT uddsketch(100, 0.005, data) AS sketch FROM test) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(threshold, percentile);
+            pct_eql(threshold.unwrap(), 99.5, 0.01);
+
+            let rate = client
+                .select(
+                    "SELECT approx_percentile_rank( \
+                        toolkit_experimental.threshold_for_rate(sketch, 0.2), sketch) \
+                    FROM (SELECT uddsketch(100, 0.005, data) AS sketch FROM test) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<f64>();
+            apx_eql(rate.unwrap(), 0.8, 0.01);
+        });
+    }
+
+    #[pg_test]
+    fn test_udd_count_above() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test (data DOUBLE PRECISION)", None, None);
+            client.select(
+                "INSERT INTO test SELECT generate_series(0.01, 100, 0.01)",
+                None,
+                None,
+            );
+
+            let (count, rate) = client
+                .select(
+                    "SELECT \
+                        toolkit_experimental.approx_count_above(sketch, 98), \
+                        toolkit_experimental.approx_rate_above(sketch, 98) \
+                    FROM (SELECT uddsketch(1000, 0.005, data) AS sketch FROM test) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<f64, f64>();
+            apx_eql(rate.unwrap(), 0.02, 0.01);
+            assert_eq!(count.unwrap(), rate.unwrap() * 10000.0);
+
+            let (count, rate) = client
+                .select(
+                    "SELECT \
+                        toolkit_experimental.approx_count_above(sketch, 1000), \
+                        toolkit_experimental.approx_rate_above(sketch, 0) \
+                    FROM (SELECT uddsketch(1000, 0.005, data) AS sketch FROM test) s",
+                    None,
+                    None,
+                )
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(count, Some(0.0));
+            assert_eq!(rate, Some(1.0));
+        });
+    }
 }
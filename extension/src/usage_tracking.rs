@@ -0,0 +1,281 @@
+//! Opt-in counting of calls to toolkit functions
+//!
+//! SET timescaledb_toolkit.track_usage TO on;
+//! SELECT * FROM toolkit_experimental.function_usage WHERE calls > 0;
+//!
+//! When tracking is enabled we ask Postgres to route function calls through
+//! `fmgr_hook`, which counts them by function OID.  Only the extension's own
+//! functions are routed there, found by their extension dependency in
+//! `pg_depend`.  Aggregates aren't called that way, so they're counted by an
+//! `ExecutorStart_hook` instead, once for each time they appear in a query
+//! that's run.  The counts are kept in backend-local memory and are only ever
+//! exposed through the `function_usage` view; nothing is persisted or sent
+//! anywhere.  Routing calls through the hook has some overhead and prevents
+//! inlining of the extension's SQL functions, so tracking is off by default.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::{c_void, CString},
+};
+
+use pgx::{iter::TableIterator, *};
+
+static TRACK_USAGE: GucSetting<bool> = GucSetting::new(false);
+
+static mut PREV_NEEDS_FMGR_HOOK: pg_sys::needs_fmgr_hook_type = None;
+static mut PREV_FMGR_HOOK: pg_sys::fmgr_hook_type = None;
+static mut PREV_EXECUTOR_START_HOOK: pg_sys::ExecutorStart_hook_type = None;
+
+thread_local! {
+    static CALL_COUNTS: RefCell<HashMap<pg_sys::Oid, i64>> = RefCell::new(HashMap::new());
+    // Whether each function looked up so far belongs to the extension. It's
+    // cleared whenever pg_proc changes, so it stays right across extension
+    // updates, drops, and recreations.
+    static TOOLKIT_FUNCTIONS: RefCell<HashMap<pg_sys::Oid, bool>> = RefCell::new(HashMap::new());
+}
+
+pub(crate) fn init() {
+    GucRegistry::define_bool_guc(
+        "timescaledb_toolkit.track_usage",
+        "Count calls to toolkit functions.",
+        "Counts are kept per session and reported by toolkit_experimental.function_usage.",
+        &TRACK_USAGE,
+        GucContext::Userset,
+    );
+    unsafe {
+        PREV_NEEDS_FMGR_HOOK = pg_sys::needs_fmgr_hook;
+        pg_sys::needs_fmgr_hook = Some(needs_fmgr_hook);
+        PREV_FMGR_HOOK = pg_sys::fmgr_hook;
+        pg_sys::fmgr_hook = Some(fmgr_hook);
+        PREV_EXECUTOR_START_HOOK = pg_sys::ExecutorStart_hook;
+        pg_sys::ExecutorStart_hook = Some(executor_start_hook);
+        pg_sys::CacheRegisterSyscacheCallback(
+            pg_sys::SysCacheIdentifier_PROCOID as _,
+            Some(invalidate_toolkit_functions),
+            pg_sys::Datum::from(0),
+        );
+    }
+}
+
+fn count_call(fn_oid: pg_sys::Oid) {
+    if is_toolkit_function(fn_oid) {
+        CALL_COUNTS.with(|counts| *counts.borrow_mut().entry(fn_oid).or_insert(0) += 1);
+    }
+}
+
+// A function belongs to the extension when it has an extension dependency
+// on it in pg_depend, the same one the function_usage view lists them by.
+fn is_toolkit_function(fn_oid: pg_sys::Oid) -> bool {
+    if let Some(known) =
+        TOOLKIT_FUNCTIONS.with(|functions| functions.borrow().get(&fn_oid).copied())
+    {
+        return known;
+    }
+    let is_toolkit = unsafe {
+        let extension = pg_sys::getExtensionOfObject(pg_sys::ProcedureRelationId, fn_oid);
+        let name = CString::new("timescaledb_toolkit").unwrap();
+        extension != pg_sys::InvalidOid
+            && extension == pg_sys::get_extension_oid(name.as_ptr(), true)
+    };
+    TOOLKIT_FUNCTIONS.with(|functions| functions.borrow_mut().insert(fn_oid, is_toolkit));
+    is_toolkit
+}
+
+#[pg_guard]
+unsafe extern "C" fn invalidate_toolkit_functions(
+    _arg: pg_sys::Datum,
+    _cache_id: i32,
+    _hash_value: u32,
+) {
+    TOOLKIT_FUNCTIONS.with(|functions| functions.borrow_mut().clear());
+}
+
+// Consulted when a function is looked up, so only functions looked up while
+// tracking is enabled will be counted.
+#[pg_guard]
+unsafe extern "C" fn needs_fmgr_hook(fn_oid: pg_sys::Oid) -> bool {
+    if TRACK_USAGE.get() && is_toolkit_function(fn_oid) {
+        return true;
+    }
+    match PREV_NEEDS_FMGR_HOOK {
+        Some(prev) => prev(fn_oid),
+        None => false,
+    }
+}
+
+#[pg_guard]
+unsafe extern "C" fn fmgr_hook(
+    event: pg_sys::FmgrHookEventType,
+    flinfo: *mut pg_sys::FmgrInfo,
+    private: *mut pg_sys::Datum,
+) {
+    if event == pg_sys::FmgrHookEventType_FHET_START && TRACK_USAGE.get() {
+        count_call((*flinfo).fn_oid);
+    }
+    if let Some(prev) = PREV_FMGR_HOOK {
+        prev(event, flinfo, private)
+    }
+}
+
+#[pg_guard]
+unsafe extern "C" fn executor_start_hook(query_desc: *mut pg_sys::QueryDesc, eflags: i32) {
+    match PREV_EXECUTOR_START_HOOK {
+        Some(prev) => prev(query_desc, eflags),
+        None => pg_sys::standard_ExecutorStart(query_desc, eflags),
+    }
+    let explain_only = eflags & pg_sys::EXEC_FLAG_EXPLAIN_ONLY as i32 != 0;
+    if TRACK_USAGE.get() && !explain_only {
+        count_plan_aggregates((*query_desc).planstate, std::ptr::null_mut());
+    }
+}
+
+// Aggregates are only left in the expressions of the plan nodes computing
+// them, along with those of any subplans.
+#[pg_guard]
+unsafe extern "C" fn count_plan_aggregates(
+    planstate: *mut pg_sys::PlanState,
+    context: *mut c_void,
+) -> bool {
+    let plan = (*planstate).plan;
+    count_aggregates((*plan).targetlist as *mut pg_sys::Node, context);
+    count_aggregates((*plan).qual as *mut pg_sys::Node, context);
+    pg_sys::planstate_tree_walker(planstate, Some(count_plan_aggregates), context)
+}
+
+#[pg_guard]
+unsafe extern "C" fn count_aggregates(node: *mut pg_sys::Node, context: *mut c_void) -> bool {
+    if node.is_null() {
+        return false;
+    }
+    if is_a(node, pg_sys::NodeTag_T_Aggref) {
+        let aggref = node as *mut pg_sys::Aggref;
+        // a partial aggregate is finished by another, which is the one counted
+        if (*aggref).aggsplit & pg_sys::AGGSPLITOP_SKIPFINAL == 0 {
+            count_call((*aggref).aggfnoid);
+        }
+    } else if is_a(node, pg_sys::NodeTag_T_WindowFunc) {
+        count_call((*(node as *mut pg_sys::WindowFunc)).winfnoid);
+    }
+    pg_sys::expression_tree_walker(node, Some(count_aggregates), context)
+}
+
+#[pg_extern(volatile, parallel_restricted, schema = "toolkit_experimental")]
+pub fn usage_counts() -> TableIterator<'static, (name!(function, pg_sys::Oid), name!(calls, i64))> {
+    let counts: Vec<(pg_sys::Oid, i64)> = CALL_COUNTS.with(|counts| {
+        counts
+            .borrow()
+            .iter()
+            .map(|(oid, calls)| (*oid, *calls))
+            .collect()
+    });
+    TableIterator::new(counts.into_iter())
+}
+
+extension_sql!(
+    "\n\
+    CREATE VIEW toolkit_experimental.function_usage AS\n\
+        SELECT p.oid::regprocedure AS function,\n\
+            p.prokind = 'a' AS is_aggregate,\n\
+            p.pronamespace = 'toolkit_experimental'::regnamespace AS is_experimental,\n\
+            COALESCE(u.calls, 0) AS calls\n\
+        FROM pg_catalog.pg_depend d\n\
+        JOIN pg_catalog.pg_proc p ON p.oid = d.objid\n\
+        LEFT JOIN toolkit_experimental.usage_counts() u ON u.function = p.oid\n\
+        WHERE d.classid = 'pg_catalog.pg_proc'::regclass\n\
+            AND d.refclassid = 'pg_catalog.pg_extension'::regclass\n\
+            AND d.refobjid = (SELECT oid FROM pg_catalog.pg_extension WHERE extname = 'timescaledb_toolkit')\n\
+            AND d.deptype = 'e';\n\
+",
+    name = "function_usage_view",
+    requires = [usage_counts],
+);
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgx::*;
+    use pgx_macros::pg_test;
+
+    #[pg_test]
+    fn test_function_usage() {
+        Spi::execute(|client| {
+            let calls = |function: &str| {
+                client
+                    .select(
+                        &format!(
+                            "SELECT calls FROM toolkit_experimental.function_usage
+                            WHERE function = '{}'::regprocedure",
+                            function
+                        ),
+                        None,
+                        None,
+                    )
+                    .first()
+                    .get_one::<i64>()
+            };
+
+            // nothing is counted until tracking is turned on
+            client.select(
+                "SELECT average(stats_agg(v)) FROM generate_series(1, 5) v",
+                None,
+                None,
+            );
+            assert_eq!(calls("average(statssummary1d)"), Some(0));
+
+            client.select("SET timescaledb_toolkit.track_usage TO on", None, None);
+            client.select(
+                "SELECT average(stats_agg(v)) FROM generate_series(1, 5) v",
+                None,
+                None,
+            );
+            assert_eq!(calls("average(statssummary1d)"), Some(1));
+            assert_eq!(calls("stats_agg(double precision)"), Some(1));
+            assert_eq!(calls("stats1d_trans(internal,double precision)"), Some(5));
+            assert_eq!(calls("num_vals(statssummary1d)"), Some(0));
+
+            // rollup and rolling share their transition function, but only
+            // the one that's run is counted
+            client.select(
+                "SELECT average(rollup(s)) FROM (
+                    SELECT stats_agg(v) AS s FROM generate_series(1, 5) v GROUP BY v % 2
+                ) summaries",
+                None,
+                None,
+            );
+            assert_eq!(calls("rollup(statssummary1d)"), Some(1));
+            assert_eq!(calls("rolling(statssummary1d)"), Some(0));
+
+            // functions from outside the extension aren't counted
+            client.select(
+                "SELECT sum(abs(v)) FROM generate_series(1, 5) v",
+                None,
+                None,
+            );
+            let others = client
+                .select(
+                    "SELECT count(*) FROM toolkit_experimental.usage_counts() u
+                    WHERE NOT EXISTS (
+                        SELECT 1 FROM toolkit_experimental.function_usage f
+                        WHERE f.function = u.function
+                    )",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<i64>();
+            assert_eq!(others, Some(0));
+
+            let experimental = client
+                .select(
+                    "SELECT bool_and(is_experimental) FROM toolkit_experimental.function_usage
+                    WHERE function::text LIKE 'toolkit_experimental.%'",
+                    None,
+                    None,
+                )
+                .first()
+                .get_one::<bool>();
+            assert_eq!(experimental, Some(true));
+        });
+    }
+}
@@ -19,11 +19,17 @@ static ALTERABLE_PROPERTIES: [&str; 6] = [
 mod stabilization_info;
 
 // our update script is a copy of the install script with the following changes
-// 1. we drop the experimental schema so everything inside it is dropped.
-// 2. drop the event triggers in case we're coming from a version that had them
-// 3. for all CREATEs we check if the object is new in `current_version`
+// 1. we move any newly-stabilized types out of the experimental schema so
+//    that columns of those types survive the update.
+// 2. we copy the rows of the experimental tables into temporary tables, so
+//    that they can be restored once the tables are recreated.
+// 3. we drop the experimental schema so everything inside it is dropped.
+// 4. drop the event triggers in case we're coming from a version that had them
+// 5. for all CREATEs we check if the object is new in `current_version`
 //     a. if it is, we output the CREATE as-is
 //     b. if it's not, we output the equivalent REPLACE, if one is needed
+//     c. newly-stabilized types, and their I/O functions, may already exist
+//        if they were moved in step 1, so they're only created if needed
 pub(crate) fn generate_from_install(
     from_version: &str,
     current_version: &str,
@@ -32,6 +38,13 @@ pub(crate) fn generate_from_install(
 ) {
     let new_stabilizations = new_stabilizations(from_version, current_version);
 
+    let mut new_types: Vec<_> = new_stabilizations.new_types.iter().collect();
+    new_types.sort();
+    for type_name in new_types {
+        write_move_from_experimental(&mut upgrade_file, type_name);
+    }
+    write_save_experimental_tables(&mut upgrade_file);
+
     writeln!(
         &mut upgrade_file,
         "DROP SCHEMA IF EXISTS toolkit_experimental CASCADE;\n\
@@ -79,6 +92,16 @@ pub(crate) fn generate_from_install(
                 }
                 unimplemented!("unprepared for stable CAST: {}", create)
             }
+            Some(Create::View(create)) => {
+                // experimental views are dropped along with their schema, so
+                // they can always be recreated as-is
+                if create.starts_with("toolkit_experimental.") {
+                    writeln!(script_creator.upgrade_file, "CREATE VIEW {}", create).unwrap();
+                    continue;
+                }
+                unimplemented!("unprepared for stable VIEW: {}", create)
+            }
+            Some(Create::Table(create)) => script_creator.handle_create_table(create),
             None => continue,
         }
     }
@@ -101,6 +124,8 @@ enum Create {
     Operator(String),
     Schema(String),
     Cast(String),
+    View(String),
+    Table(String),
 }
 
 const MUST_FIND_MATCH: bool = false;
@@ -131,6 +156,8 @@ where
                         ("OPERATOR", &mut |l| Create::Operator(l.to_string())),
                         ("SCHEMA", &mut |l| Create::Schema(l.to_string())),
                         ("CAST", &mut |l| Create::Cast(l.to_string())),
+                        ("VIEW", &mut |l| Create::View(l.to_string())),
+                        ("TABLE", &mut |l| Create::Table(l.to_string())),
                     ],
                 );
                 if create.is_some() {
@@ -195,7 +222,9 @@ where
         let function = Function { name, types };
 
         // write
-        if self.new_stabilizations.new_functions.contains(&function) {
+        if self.new_stabilizations.new_functions.contains(&function)
+            && !self.is_new_type_io_function(&is_function, &function)
+        {
             writeln!(
                 self.upgrade_file,
                 "{} {}",
@@ -223,7 +252,45 @@ where
         }
 
         if self.new_stabilizations.new_types.contains(&type_name) {
-            writeln!(self.upgrade_file, "CREATE TYPE {}", create).unwrap();
+            // the type may have been moved out of the experimental schema
+            // already, in which case there's nothing to create
+            if create.trim_end().ends_with(';') {
+                writeln!(
+                    self.upgrade_file,
+                    "DO $$\n\
+                    BEGIN\n    \
+                        IF to_regtype('@extschema@.{name}') IS NULL THEN\n        \
+                            CREATE TYPE {create}\n    \
+                        END IF;\n\
+                    END\n\
+                    $$;",
+                    name = type_name,
+                    create = create,
+                )
+                .expect("cannot write CREATE TYPE");
+            } else {
+                let mut create = format!("CREATE TYPE {}", create);
+                for line in &mut self.lines {
+                    create.push('\n');
+                    create.push_str(&line);
+                    if line.trim_start().starts_with(')') {
+                        break;
+                    }
+                }
+                writeln!(
+                    self.upgrade_file,
+                    "DO $$\n\
+                    BEGIN\n    \
+                        IF NOT (SELECT typisdefined FROM pg_type WHERE oid = '@extschema@.{name}'::regtype) THEN\n\
+                            {create}\n    \
+                        END IF;\n\
+                    END\n\
+                    $$;",
+                    name = type_name,
+                    create = create,
+                )
+                .expect("cannot write CREATE TYPE");
+            }
             return;
         }
 
@@ -244,6 +311,22 @@ where
         }
     }
 
+    // the input and output functions of a newly-stabilized type are moved
+    // along with it, so they need to be replaced rather than created
+    fn is_new_type_io_function(&self, is_function: &FunctionLike, function: &Function) -> bool {
+        if !matches!(is_function, FunctionLike::Fn) {
+            return false;
+        }
+        let name = function.name.to_ascii_lowercase();
+        let type_name = name
+            .strip_suffix("_in")
+            .or_else(|| name.strip_suffix("_out"));
+        match type_name {
+            Some(type_name) => self.new_stabilizations.new_types.contains(type_name),
+            None => false,
+        }
+    }
+
     fn get_alterable_properties(&mut self) -> Vec<Option<String>> {
         self.get_properties(&ALTERABLE_PROPERTIES[..], ALLOW_NO_MATCH);
         // Should return alters here, except PG12 doesn't allow alterations to type properties.
@@ -290,16 +373,20 @@ where
         // CREATE OPERATOR <op> (
         //     PROCEDURE=...,
         //     LEFTARG=...,
-        //     RIGHTARG=...
+        //     RIGHTARG=...,
+        //     COMMUTATOR=...,
+        //     NEGATOR=...
         // );
         // ```
+        // where `COMMUTATOR` and `NEGATOR` are optional.
         // if any of `PROCEDURE`, `LEFTARG`, or `RIGHTARG` refer to and
         // experimental object the operator is experimental, otherwise it isn't
         let op = extract_name(&create);
 
-        let fields = self.get_properties(&["PROCEDURE", "LEFTARG", "RIGHTARG"], MUST_FIND_MATCH);
+        let properties = ["PROCEDURE", "LEFTARG", "RIGHTARG", "COMMUTATOR", "NEGATOR"];
+        let fields = self.get_properties(&properties, MUST_FIND_MATCH);
 
-        let is_experimental = fields
+        let is_experimental = fields[..3]
             .iter()
             .filter_map(|f| f.as_ref())
             .any(|f| f.contains("toolkit_experimental"));
@@ -330,22 +417,53 @@ where
             ],
         };
         if is_experimental || self.new_stabilizations.new_operators.contains(&operator) {
+            let definition: Vec<String> = properties
+                .iter()
+                .zip(&fields)
+                .filter_map(|(property, field)| {
+                    let value = field.as_ref()?.trim_end_matches(',');
+                    Some(format!("{}={}", property, value))
+                })
+                .collect();
             writeln!(
                 self.upgrade_file,
-                "CREATE OPERATOR {} (\n    \
-                    PROCEDURE={}\n    \
-                    LEFTARG={}\n    \
-                    RIGHTARG={}\n    \
-                );",
+                "CREATE OPERATOR {} (\n    {}\n);",
                 op,
-                fields[0].as_ref().unwrap(),
-                fields[1].as_ref().unwrap(),
-                fields[2].as_ref().unwrap(),
+                definition.join(",\n    "),
             )
             .expect("cannot write CREATE OPERATOR")
         }
     }
 
+    fn handle_create_table(&mut self, create: String) {
+        // found
+        // ```
+        // CREATE TABLE toolkit_experimental.<name> (
+        //     ...
+        // );
+        // ```
+        // experimental tables are dropped along with their schema, so they
+        // are recreated as-is, and then refilled with the rows saved before
+        // the schema was dropped
+        let table_name = extract_name(&create);
+        if !table_name.starts_with("toolkit_experimental.") {
+            unimplemented!("unprepared for stable TABLE: {}", create)
+        }
+
+        let mut create = format!("CREATE TABLE {}", create);
+        if !create.trim_end().ends_with(';') {
+            for line in &mut self.lines {
+                create.push('\n');
+                create.push_str(&line);
+                if line.trim_end().ends_with(';') {
+                    break;
+                }
+            }
+        }
+        writeln!(self.upgrade_file, "{}", create).expect("cannot write CREATE TABLE");
+        write_restore_experimental_table(&mut self.upgrade_file, &table_name);
+    }
+
     fn get_properties(&mut self, fields: &[&str], allow_no_match: bool) -> Vec<Option<String>> {
         let mut properties = vec![None; fields.len()];
         for line in &mut self.lines {
@@ -358,7 +476,8 @@ where
             if line.trim_start().starts_with(')') {
                 break;
             }
-            let mut split = line.split('=');
+            // values such as `OPERATOR(schema.=)` can contain `=` themselves
+            let mut split = line.splitn(2, '=');
             let field = split.next().unwrap().trim();
             let value = split
                 .next()
@@ -406,6 +525,106 @@ impl FunctionLike {
     }
 }
 
+// move a type, along with the functions it depends on, from the experimental
+// schema into the extension's schema, if it exists. Since the type is moved
+// rather than recreated, any columns of the type are preserved when the
+// experimental schema is dropped.
+fn write_move_from_experimental(upgrade_file: &mut impl Write, type_name: &str) {
+    writeln!(
+        upgrade_file,
+        "DO $$\n\
+        DECLARE\n    \
+            experimental_type regtype := to_regtype('toolkit_experimental.{name}');\n    \
+            support_function regprocedure;\n\
+        BEGIN\n    \
+            IF experimental_type IS NOT NULL THEN\n        \
+                FOR support_function IN\n            \
+                    SELECT p.oid::regprocedure\n            \
+                    FROM pg_type t\n            \
+                    JOIN pg_proc p ON p.oid IN (t.typinput, t.typoutput, t.typreceive, t.typsend)\n            \
+                    WHERE t.oid = experimental_type\n              \
+                      AND p.pronamespace = 'toolkit_experimental'::regnamespace\n        \
+                LOOP\n            \
+                    EXECUTE format('ALTER FUNCTION %s SET SCHEMA @extschema@', support_function);\n        \
+                END LOOP;\n        \
+                ALTER TYPE toolkit_experimental.{name} SET SCHEMA @extschema@;\n    \
+            END IF;\n\
+        END\n\
+        $$;",
+        name = type_name,
+    )
+    .expect("cannot write type move");
+}
+
+// copy the rows of every table in the experimental schema into a temporary
+// table, named `toolkit_saved_<table name>`, so that they survive the schema
+// being dropped
+fn write_save_experimental_tables(upgrade_file: &mut impl Write) {
+    writeln!(
+        upgrade_file,
+        "DO $$\n\
+        DECLARE\n    \
+            experimental_table record;\n\
+        BEGIN\n    \
+            FOR experimental_table IN\n        \
+                SELECT c.oid::regclass AS name, c.relname\n        \
+                FROM pg_class c\n        \
+                WHERE c.relnamespace = to_regnamespace('toolkit_experimental')\n          \
+                  AND c.relkind = 'r'\n    \
+            LOOP\n        \
+                EXECUTE format('DROP TABLE IF EXISTS pg_temp.%I', 'toolkit_saved_' || experimental_table.relname);\n        \
+                EXECUTE format('CREATE TEMPORARY TABLE %I AS SELECT * FROM %s', 'toolkit_saved_' || experimental_table.relname, experimental_table.name);\n    \
+            END LOOP;\n\
+        END\n\
+        $$;"
+    )
+    .expect("cannot write table save");
+}
+
+// refill a recreated experimental table from the rows saved by
+// `write_save_experimental_tables`, if there were any. Only the columns the
+// old and new tables share are copied, and any serial sequences are moved past
+// the restored values.
+fn write_restore_experimental_table(upgrade_file: &mut impl Write, table_name: &str) {
+    let relname = table_name.trim_start_matches("toolkit_experimental.");
+    writeln!(
+        upgrade_file,
+        "DO $$\n\
+        DECLARE\n    \
+            saved regclass := to_regclass('pg_temp.toolkit_saved_{relname}');\n    \
+            restored regclass := '{name}'::regclass;\n    \
+            columns text;\n    \
+            serial_column record;\n\
+        BEGIN\n    \
+            IF saved IS NULL THEN\n        \
+                RETURN;\n    \
+            END IF;\n    \
+            SELECT string_agg(quote_ident(n.attname), ', ' ORDER BY n.attnum) INTO columns\n    \
+            FROM pg_attribute n\n    \
+            JOIN pg_attribute o ON o.attname = n.attname\n    \
+            WHERE n.attrelid = restored AND n.attnum > 0 AND NOT n.attisdropped\n      \
+              AND o.attrelid = saved AND o.attnum > 0 AND NOT o.attisdropped;\n    \
+            IF columns IS NOT NULL THEN\n        \
+                EXECUTE format('INSERT INTO %s (%s) SELECT %s FROM %s', restored, columns, columns, saved);\n    \
+            END IF;\n    \
+            FOR serial_column IN\n        \
+                SELECT attname, pg_get_serial_sequence(restored::text, attname) AS sequence\n        \
+                FROM pg_attribute\n        \
+                WHERE attrelid = restored AND attnum > 0 AND NOT attisdropped\n    \
+            LOOP\n        \
+                IF serial_column.sequence IS NOT NULL THEN\n            \
+                    EXECUTE format('SELECT setval(%L, coalesce(max(%I), 0) + 1, false) FROM %s', serial_column.sequence, serial_column.attname, restored);\n        \
+                END IF;\n    \
+            END LOOP;\n    \
+            EXECUTE format('DROP TABLE %s', saved);\n\
+        END\n\
+        $$;",
+        relname = relname,
+        name = table_name,
+    )
+    .expect("cannot write table restore");
+}
+
 fn parse_arg_types(stmt: &str) -> Vec<Vec<String>> {
     // extract the types from a
     // `( <ident> <type segment>,* )`